@@ -7,13 +7,18 @@ use syn::{DeriveInput, ItemTrait, parse_macro_input};
 mod protocol;
 mod rw;
 
-#[proc_macro_derive(Read)]
+#[proc_macro_derive(Read, attributes(ipc))]
 pub fn derive_read(input: TokenStream) -> TokenStream {
 	let input = parse_macro_input!(input as DeriveInput);
-	TokenStream::from(rw::derive_read(&input))
+	let read_code = rw::derive_read(&input);
+	let describe_code = rw::derive_describe(&input);
+	TokenStream::from(quote! {
+		#read_code
+		#describe_code
+	})
 }
 
-#[proc_macro_derive(Write)]
+#[proc_macro_derive(Write, attributes(ipc))]
 pub fn derive_write(input: TokenStream) -> TokenStream {
 	let input = parse_macro_input!(input as DeriveInput);
 	TokenStream::from(rw::derive_write(&input))
@@ -21,7 +26,7 @@ pub fn derive_write(input: TokenStream) -> TokenStream {
 
 /// Function-like macro for implementing `Read` and `Write` on already declared types.
 ///
-/// Useful for implementing those traits on standard library types, like [`Result`] or [`Option`].
+/// Useful for implementing those traits, and `Describe`, on standard library types, like [`Result`] or [`Option`].
 ///
 /// # Example
 ///
@@ -39,9 +44,11 @@ pub fn __impl_rw_for_external(input: TokenStream) -> TokenStream {
 	let input = parse_macro_input!(input as DeriveInput);
 	let read_code = rw::derive_read(&input);
 	let write_code = rw::derive_write(&input);
+	let describe_code = rw::derive_describe(&input);
 	TokenStream::from(quote! {
 		#read_code
 		#write_code
+		#describe_code
 	})
 }
 
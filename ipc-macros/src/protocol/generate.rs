@@ -2,13 +2,46 @@ use std::iter;
 
 use proc_macro::{Diagnostic, Level};
 use proc_macro2::{Span, TokenStream};
-use quote::quote;
+use quote::{ToTokens, quote};
 use syn::{
-	FnArg, GenericParam, Ident, Pat, PatIdent, ReturnType, WherePredicate, parse_quote,
+	Attribute, Expr, ExprLit, FnArg, GenericParam, Ident, Lit, Meta, MetaNameValue, Pat, PatIdent,
+	ReturnType, Signature, TraitItemFn, Type, WhereClause, WherePredicate, parse_quote,
 	punctuated::Punctuated, spanned::Spanned,
 };
 
-use super::{Protocol, ProtocolMethod};
+use super::{Protocol, ProtocolMethod, stream_item_type};
+
+/// Kind of a client, which decides how its methods are generated
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ClientKind {
+	/// Using `ipc::__private::Client`, implementing the protocol trait
+	Async,
+	/// Using `ipc::BlockingClient`, generated with `blocking_client_name`
+	Blocking,
+	/// Using `ipc::LocalClient`, generated with `local_client_name`
+	Local,
+}
+
+impl ClientKind {
+	/// Type of the responses of a streamed method
+	fn stream_type(self, item: &dyn ToTokens, error: &dyn ToTokens) -> TokenStream {
+		match self {
+			Self::Async => {
+				quote!(impl ::ipc::futures::Stream<Item = ::ipc::Result<#item>> + ::core::marker::Send)
+			}
+			Self::Blocking => quote!(::ipc::BlockingStream<'_, #item, #error>),
+			Self::Local => quote!(impl ::ipc::futures::Stream<Item = ::ipc::Result<#item>>),
+		}
+	}
+
+	/// Awaits a call of the inner client, nothing if its calls aren't `async`
+	fn await_token(self) -> TokenStream {
+		match self {
+			Self::Blocking => TokenStream::new(),
+			Self::Async | Self::Local => quote!(.await),
+		}
+	}
+}
 
 impl Protocol {
 	pub fn generate(mut self) -> TokenStream {
@@ -19,10 +52,30 @@ impl Protocol {
 		let client_name = &self.client_name;
 		let visibility = &self.visibility;
 
+		let fingerprint = self.generate_fingerprint();
+		let schema = self.generate_schema();
 		let call_structs = self.generate_call_structs();
 		let server_trait = self.generate_server_trait();
 		let client_trait = self.generate_client_trait();
 		let client = self.generate_client();
+		let blocking_client = self
+			.blocking_client_name
+			.as_ref()
+			.map(|name| self.generate_inherent_client(name, ClientKind::Blocking));
+		let local_client = self
+			.local_client_name
+			.as_ref()
+			.map(|name| self.generate_inherent_client(name, ClientKind::Local));
+		let signals = self.generate_signals();
+		let signals_use = (!self.signals.is_empty()).then(|| {
+			let signals_name = &self.signals_name;
+			quote!(#visibility use #module_name::#signals_name;)
+		});
+		let inherent_clients_use = self
+			.blocking_client_name
+			.iter()
+			.chain(&self.local_client_name)
+			.map(|name| quote!(#visibility use #module_name::#name;));
 
 		quote! {
 			#[allow(non_snake_case, non_camel_case_types)]
@@ -30,36 +83,71 @@ impl Protocol {
 			mod #module_name {
 				use super::*;
 
+				/// Fingerprint of the protocol, exchanged when a connection is opened
+				const FINGERPRINT: u64 = #fingerprint;
+
+				#schema
 				#call_structs
 				#server_trait
 				#client_trait
 				#client
+				#blocking_client
+				#local_client
+				#signals
 			}
 
 			#visibility use #module_name::#server_name;
 			#visibility use #module_name::#client_name;
 			#visibility use #module_name::#name;
+			#signals_use
+			#(#inherent_clients_use)*
 		}
 	}
 
 	/// Emit errors for forbidden things and remove them from the code to avoid creating even more errors
 	fn sanitize(&mut self) {
-		if !self.generics.params.is_empty() {
-			Diagnostic::spanned(
-				self.generics.span().unwrap(),
-				Level::Error,
-				"protocols cannot have generics yet",
-			)
-			.emit();
+		for param in &self.generics.params {
+			if !matches!(param, GenericParam::Type(_)) {
+				Diagnostic::spanned(
+					param.span().unwrap(),
+					Level::Error,
+					"protocols can only have type parameters",
+				)
+				.emit();
+			}
+		}
+		self.generics.params = self
+			.generics
+			.params
+			.clone()
+			.into_iter()
+			.filter(|param| matches!(param, GenericParam::Type(_)))
+			.collect();
+		for param in self.generics.type_params_mut() {
+			if let Some(default) = param.default.take() {
+				Diagnostic::spanned(
+					default.span().unwrap(),
+					Level::Error,
+					"protocol type parameters cannot have defaults",
+				)
+				.emit();
+				param.eq_token = None;
+			}
 		}
 
-		for method in &mut self.methods {
-			let generics = &mut method.inner_mut().sig.generics;
+		let signatures = self
+			.methods
+			.iter_mut()
+			.map(ProtocolMethod::inner_mut)
+			.chain(&mut self.signals)
+			.map(|x| &mut x.sig);
+		for signature in signatures {
+			let generics = &mut signature.generics;
 			if !generics.params.is_empty() {
 				Diagnostic::spanned(
 					generics.span().unwrap(),
 					Level::Error,
-					"protocol methods cannot contain generics or lifetimes",
+					"protocol methods cannot contain generics or lifetimes, the server must know the types it decodes; add type parameters to the protocol instead",
 				)
 				.emit();
 			}
@@ -67,6 +155,178 @@ impl Protocol {
 			generics.params = Punctuated::new();
 			generics.where_clause = None;
 		}
+
+		for method in &mut self.methods {
+			// only keep the first stream argument
+			let inputs = &mut method.inner_mut().sig.inputs;
+			let mut has_stream = false;
+			*inputs = inputs
+				.clone()
+				.into_iter()
+				.filter(|arg| {
+					let FnArg::Typed(arg) = arg else {
+						return true;
+					};
+					if stream_item_type(&arg.ty).is_none() {
+						return true;
+					}
+					if has_stream {
+						Diagnostic::spanned(
+							arg.span().unwrap(),
+							Level::Error,
+							"protocol methods can only take one stream as argument",
+						)
+						.emit();
+						return false;
+					}
+					has_stream = true;
+					true
+				})
+				.collect();
+		}
+	}
+
+	/// Hash the signature of every method, so that client and server can detect
+	/// if they don't speak the same protocol
+	///
+	/// This uses FNV-1a, because the value must be the same across compilations.
+	///
+	/// Only the spelling of the signatures is hashed, since the macro can't see the
+	/// definition of the types. Spelling a type differently, like `String` and
+	/// `std::string::String`, changes the fingerprint without changing the encoding, and
+	/// changing the definition of a type used by a method, like adding a variant to an
+	/// enum, changes the encoding without changing the fingerprint. For the same reason,
+	/// every instantiation of a generic protocol has the same fingerprint.
+	fn generate_fingerprint(&self) -> u64 {
+		let mut signatures = String::new();
+		for method in &self.methods {
+			let signature = &method.inner().sig;
+			signatures.push_str(&signature.ident.to_string());
+			signatures.push('(');
+			for arg in &signature.inputs {
+				if let FnArg::Typed(arg) = arg {
+					signatures.push_str(&arg.ty.to_token_stream().to_string());
+					signatures.push(',');
+				}
+			}
+			signatures.push(')');
+			signatures.push_str(&signature.output.to_token_stream().to_string());
+			if let ProtocolMethod::LongCall {
+				early_error,
+				item_error,
+				..
+			} = method
+			{
+				signatures.push_str(" stream ");
+				signatures.push_str(&early_error.to_token_stream().to_string());
+				signatures.push(' ');
+				signatures.push_str(&item_error.to_token_stream().to_string());
+			}
+			signatures.push(';');
+		}
+		for signal in &self.signals {
+			signatures.push_str("signal ");
+			signatures.push_str(&signal.sig.ident.to_string());
+			signatures.push_str(&signal_type(signal).to_string());
+			signatures.push(';');
+		}
+
+		signatures
+			.bytes()
+			.fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+				(hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+			})
+	}
+
+	/// Generate the function building the `Schema` of the protocol
+	///
+	/// The types of the protocol are described with `Describe` if they implement it, and
+	/// as opaque types otherwise, which includes the type parameters.
+	fn generate_schema(&self) -> TokenStream {
+		let methods = self.methods.iter().map(|method| {
+			let signature = &method.inner().sig;
+			let name = signature.ident.to_string();
+			let docs = docs(&method.inner().attrs);
+			let arguments = method.arguments().filter_map(|arg| match &*arg.pat {
+				Pat::Ident(PatIdent { ident, .. }) => Some(describe_argument(ident, &arg.ty)),
+				// reported when generating the call structs
+				_ => None,
+			});
+			let input_stream = optional(method.input_stream().map(|(_, item)| describe(item)));
+			let output = match &signature.output {
+				ReturnType::Default => parse_quote!(()),
+				ReturnType::Type(_, output) => output.clone(),
+			};
+			let (output, stream, early_error, item_error) = match method {
+				ProtocolMethod::SimpleCall(_) => (output, false, None, None),
+				ProtocolMethod::LongCall {
+					early_error,
+					item_error,
+					..
+				} => (
+					stream_item(output, item_error.as_ref()),
+					true,
+					early_error.as_ref(),
+					item_error.as_ref(),
+				),
+			};
+			let output = describe(&output);
+			let early_error = optional(early_error.map(describe));
+			let item_error = optional(item_error.map(describe));
+			let timeout = optional(self.timeouts.get(&signature.ident).map(|timeout| {
+				let millis = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+				quote!(#millis)
+			}));
+
+			quote! {
+				::ipc::schema::Method {
+					name: ::std::borrow::ToOwned::to_owned(#name),
+					docs: ::std::borrow::ToOwned::to_owned(#docs),
+					arguments: ::std::vec![#(#arguments),*],
+					input_stream: #input_stream,
+					output: #output,
+					stream: #stream,
+					early_error: #early_error,
+					item_error: #item_error,
+					timeout: #timeout,
+				}
+			}
+		});
+
+		let signals = self.signals.iter().map(|signal| {
+			let name = signal.sig.ident.to_string();
+			let docs = docs(&signal.attrs);
+			let arguments = signal_arguments(signal).map(|(name, ty)| describe_argument(name, ty));
+			quote! {
+				::ipc::schema::Signal {
+					name: ::std::borrow::ToOwned::to_owned(#name),
+					docs: ::std::borrow::ToOwned::to_owned(#docs),
+					arguments: ::std::vec![#(#arguments),*],
+				}
+			}
+		});
+
+		let name = self.name.to_string();
+		let type_arguments = self.generics.type_params().map(|param| &param.ident);
+		quote! {
+			/// Description of the protocol, sent to clients making a describe call
+			fn schema<#(#type_arguments),*>() -> ::ipc::schema::Schema {
+				#[allow(unused_imports)]
+				use ::ipc::__private::{DescribeProbe as _, OpaqueProbe as _};
+
+				#[allow(unused_mut, reason = "protocols without methods or signals don't describe types")]
+				let mut types = ::ipc::schema::Types::new();
+				let methods = ::std::vec![#(#methods),*];
+				let signals = ::std::vec![#(#signals),*];
+				::ipc::schema::Schema {
+					name: ::std::borrow::ToOwned::to_owned(#name),
+					fingerprint: FINGERPRINT,
+					methods,
+					signals,
+					types: types.into_vec(),
+				}
+			}
+		}
 	}
 
 	fn generate_call_structs(&self) -> TokenStream {
@@ -77,14 +337,8 @@ impl Protocol {
 				let name = &method.inner().sig.ident;
 				let struct_name = Ident::new(&format!("{name}Call"), Span::mixed_site());
 				let (fields, generics): (Vec<_>, Vec<_>) = method
-					.inner()
-					.sig
-					.inputs
-					.iter()
+					.arguments()
 					.filter_map(|arg| {
-						let FnArg::Typed(arg) = arg else {
-							return None;
-						};
 						let name = match &*arg.pat {
 							Pat::Ident(PatIdent { ident, .. }) => ident,
 							x => {
@@ -116,6 +370,20 @@ impl Protocol {
 			})
 			.collect();
 
+		let (stream_variants, stream_generics): (Vec<_>, Vec<_>) = self
+			.methods
+			.iter()
+			.filter(|method| method.input_stream().is_some())
+			.map(|method| {
+				let name = &method.inner().sig.ident;
+				let generic_name = Ident::new(&format!("MethodStream_{name}"), Span::mixed_site());
+				(
+					quote!(#name(::ipc::__private::StreamPacket<#generic_name, !>)),
+					generic_name,
+				)
+			})
+			.unzip();
+
 		let generics = generics.iter().flatten();
 		quote! {
 			#[derive(::ipc::Read, ::ipc::Write)]
@@ -123,6 +391,12 @@ impl Protocol {
 				#(#variants),*
 			}
 
+			/// Values of the streams sent by the client as arguments
+			#[derive(::ipc::Read, ::ipc::Write)]
+			enum MethodStream<#(#stream_generics),*> {
+				#(#stream_variants),*
+			}
+
 			#(#structs)*
 		}
 	}
@@ -143,11 +417,11 @@ impl Protocol {
                         method.sig.output = parse_quote!(-> impl ::core::future::Future<Output = #output> + ::core::marker::Send);
                         method
                     }
-                    ProtocolMethod::LongCall { mut method, early_error } => {
-                        let output = match &method.sig.output {
+                    ProtocolMethod::LongCall { mut method, early_error, item_error } => {
+                        let output = stream_item(match &method.sig.output {
                             ReturnType::Default => parse_quote!(()),
                             ReturnType::Type(_, output) => (*output).clone(),
-                        };
+                        }, item_error.as_ref());
 
                         if let Some(error) = early_error {
                             method.sig.output = parse_quote!(-> impl ::core::future::Future<Output = ::core::result::Result<impl ::ipc::futures::Stream<Item = #output> + ::core::marker::Send, #error>> + ::core::marker::Send);
@@ -157,6 +431,13 @@ impl Protocol {
                         method
                     }
                 };
+                for arg in &mut method.sig.inputs {
+                    if let FnArg::Typed(arg) = arg
+                        && let Some(item) = stream_item_type(&arg.ty)
+                    {
+                        arg.ty = parse_quote!(::ipc::IncomingStream<#item>);
+                    }
+                }
                 method.sig.asyncness = None;
                 method
             })
@@ -165,22 +446,27 @@ impl Protocol {
 		let server_name = &self.server_name;
 		let attributes = &self.attributes;
 		let generics = &self.generics.params;
-		let where_clause = &self.generics.where_clause;
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		let where_clause = self.where_clause();
 		let supertraits = &self.supertraits;
 
-		let (serve_method, handle_client_method) = self
-			.abstract_socket
-			.as_ref()
-			.map(|socket_name| self.generate_serve_method(socket_name))
-			.map_or((None, None), |(serve, handle_client)| {
-				(Some(serve), Some(handle_client))
-			});
+		let signals_method = (!self.signals.is_empty()).then(|| {
+			let signals_name = &self.signals_name;
+			quote! {
+				/// Emitter of the signals, whose subscriptions are managed by [`Self::serve`]
+				fn signals(&self) -> &#signals_name #ty_generics;
+			}
+		});
+
+		let (serve_method, handle_client_method) = self.generate_serve_method();
 
 		quote! {
 			#(#attributes)*
 			pub trait #server_name<#generics>: #supertraits #where_clause {
 				#(#methods)*
 
+				#signals_method
+
 				#serve_method
 			}
 
@@ -188,9 +474,30 @@ impl Protocol {
 		}
 	}
 
-	fn generate_serve_method(&self, socket_name: &str) -> (TokenStream, TokenStream) {
+	fn generate_serve_method(&self) -> (TokenStream, TokenStream) {
 		let server_name = &self.server_name;
-		let (variable_creation, read_branch, select_branch, call_types): (Vec<_>, Vec<_>, Vec<_>, Vec<_>) = self
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		let generics = &self.generics.params;
+		let type_arguments: Vec<_> = self
+			.generics
+			.type_params()
+			.map(|param| &param.ident)
+			.collect();
+		let parameter_bounds = self.parameter_bounds();
+		let stream_types = self
+			.methods
+			.iter()
+			.filter_map(|method| method.input_stream().map(|(_, item)| item));
+		let running_calls = Ident::new("running_calls", Span::mixed_site());
+		let interceptor = Ident::new("interceptor", Span::mixed_site());
+		let call_info = Ident::new("call_info", Span::mixed_site());
+		let options = Ident::new("options", Span::mixed_site());
+		let draining = Ident::new("draining", Span::mixed_site());
+		let pending = Ident::new("pending", Span::mixed_site());
+		let queued = Ident::new("queued", Span::mixed_site());
+		let trace = Ident::new("trace", Span::mixed_site());
+		let handler = Ident::new("handler", Span::mixed_site());
+		let (variable_creation, read_branch, select_branch, call_types, inputs_names): (Vec<_>, Vec<_>, Vec<_>, Vec<_>, Vec<_>) = self
             .methods
             .iter()
             .map(|method| {
@@ -198,41 +505,128 @@ impl Protocol {
                 let calls_name = Ident::new(&format!("{name}_calls"), Span::mixed_site());
                 let call_struct_name = Ident::new(&format!("{name}Call"), Span::mixed_site());
 
-                let (args_name, args_types): (Vec<_>, Vec<_>) = method.inner().sig.inputs.iter().filter_map(|arg| if let FnArg::Typed(arg) = arg { Some((&arg.pat, &arg.ty)) } else { None }).collect();
+                let (args_name, args_types): (Vec<_>, Vec<_>) = method.arguments().map(|arg| (&arg.pat, &arg.ty)).collect();
+                let all_args_name = method.inner().sig.inputs.iter().filter_map(|arg| if let FnArg::Typed(arg) = arg { Some(&arg.pat) } else { None });
+                let inputs_name = method.input_stream().map(|_| Ident::new(&format!("{name}_inputs"), Span::mixed_site()));
 
-                let variable_creation = quote! {
+                let mut variable_creation = quote! {
                     let mut #calls_name = ::ipc::futures::prelude::stream::FuturesUnordered::new();
                 };
-                let read_branch = quote! {
-                    ::core::result::Result::Ok(::ipc::__private::Clientbound { call_id, payload: MethodCall::#name(#call_struct_name { #(#args_name),* }) }) => ::ipc::futures::prelude::stream::FuturesUnordered::push(&mut #calls_name, {
-                        async move { (call_id, #server_name::#name(server, #(#args_name),*).await) }
+                let create_stream = method.input_stream().zip(inputs_name.as_ref()).map(|((stream_arg, _), inputs_name)| {
+                    let stream_name = &stream_arg.pat;
+                    quote!(let #stream_name = #inputs_name.create(call_id);)
+                });
+                // streamed responses are polled in the call, so its info is kept with the stream
+                let output = match method {
+                    ProtocolMethod::SimpleCall(_) => quote!(result),
+                    ProtocolMethod::LongCall { .. } => quote!((result, #call_info)),
+                };
+                let mut read_branch = quote! {
+                    (call_id, ::core::result::Result::Ok(::ipc::__private::Request::Call(MethodCall::#name(#call_struct_name { #(#args_name),* })))) => ::ipc::futures::prelude::stream::FuturesUnordered::push(&mut #calls_name, {
+                        #create_stream
+                        let #call_info = ::ipc::interceptor::CallInfo {
+                            method: ::core::stringify!(#name),
+                            call_id,
+                            peer,
+                            deadline,
+                            trace: ::core::option::Option::unwrap_or_else(#trace, ::ipc::TraceContext::new),
+                            arguments: if ::ipc::interceptor::Interceptor::wants_arguments(#interceptor) {
+                                ::std::vec![#((::core::stringify!(#args_name), ::std::format!("{:?}", #args_name))),*]
+                            } else {
+                                ::std::vec::Vec::new()
+                            },
+                        };
+                        #running_calls.register(call_id, ::ipc::__private::with_deadline(deadline, async move {
+                            let #handler = ::ipc::interceptor::Interceptor::intercept(#interceptor, &#call_info, async move {
+                                ::core::result::Result::Ok(#server_name::#name(server, #(#all_args_name),*).await)
+                            });
+                            let result = ::ipc::__private::in_call_span(&#call_info, #handler).await;
+                            (call_id, #output)
+                        }))
                     })
                 };
+                if let Some(inputs_name) = &inputs_name {
+                    variable_creation = quote! {
+                        #variable_creation
+                        let mut #inputs_name = ::ipc::__private::IncomingStreams::new(limits);
+                    };
+                    read_branch = quote! {
+                        #read_branch,
+                        (call_id, ::core::result::Result::Ok(::ipc::__private::Request::Stream(MethodStream::#name(packet)))) => {
+                            if let ::core::result::Result::Err(e) = #inputs_name.push(call_id, packet) {
+                                ::ipc::log::error!("Client sent inputs faster than they are handled: {e}");
+                                #running_calls.cancel(call_id);
+                                send_packet!(tx, call_id, ::ipc::__private::Response::<!>::LimitExceeded(e));
+                            }
+                        }
+                    };
+                }
+                let finish_inputs = inputs_name.as_ref().map(|inputs_name| quote!(#inputs_name.finish(id);));
                 match method {
                     ProtocolMethod::SimpleCall(_) => {
                         let select_branch = quote! {
-                            ::core::option::Option::Some((id, result)) = ::ipc::futures::StreamExt::next(&mut #calls_name), if !::ipc::futures::prelude::stream::FuturesUnordered::is_empty(&#calls_name) => {
-                                send_packet!(tx, id, result);
+                            ::core::option::Option::Some(result) = ::ipc::futures::StreamExt::next(&mut #calls_name), if !::ipc::futures::prelude::stream::FuturesUnordered::is_empty(&#calls_name) => {
+                                // error if the call was cancelled
+                                if let ::core::result::Result::Ok((id, result)) = result {
+                                    #running_calls.finish(id);
+                                    #finish_inputs
+                                    match result {
+                                        ::core::result::Result::Ok(result) => {
+                                            send_packet!(tx, id, ::ipc::__private::Response::Value(result));
+                                        }
+                                        ::core::result::Result::Err(rejection) => {
+                                            send_packet!(tx, id, ::ipc::__private::Response::<!>::from(rejection));
+                                        }
+                                    }
+                                }
                             }
                         };
 
-                        (variable_creation, read_branch, select_branch, args_types)
+                        (variable_creation, read_branch, select_branch, args_types, inputs_name)
                     }
-                    ProtocolMethod::LongCall { early_error, .. } => {
+                    ProtocolMethod::LongCall { early_error, item_error, .. } => {
+                        // streams with an item error end after the first error
+                        let stream_with_id = if item_error.is_some() {
+                            quote!(fallible_stream_with_id)
+                        } else {
+                            quote!(stream_with_id)
+                        };
                         let streams_name = Ident::new(&format!("{name}_streams"), Span::mixed_site());
                         let select_branch = if early_error.is_some() {
                             quote! {
-                                ::core::option::Option::Some((id, stream)) = ::ipc::futures::StreamExt::next(&mut #calls_name), if !::ipc::futures::prelude::stream::FuturesUnordered::is_empty(&#calls_name) => {
-                                    match stream {
-                                        ::core::result::Result::Ok(x) => ::ipc::futures::prelude::stream::SelectAll::push(&mut #streams_name, ::std::boxed::Box::pin(::ipc::__private::stream_with_id(id, x))),
-                                        ::core::result::Result::Err(e) => { send_packet!(tx, id, ::ipc::__private::StreamPacket::<!, _>::Error(e)); }
+                                ::core::option::Option::Some(result) = ::ipc::futures::StreamExt::next(&mut #calls_name), if !::ipc::futures::prelude::stream::FuturesUnordered::is_empty(&#calls_name) => {
+                                    // error if the call was cancelled
+                                    if let ::core::result::Result::Ok((id, (stream, #call_info))) = result {
+                                        match stream {
+                                            ::core::result::Result::Ok(::core::result::Result::Ok(x)) => ::ipc::futures::prelude::stream::SelectAll::push(&mut #streams_name, #running_calls.register(id, ::std::boxed::Box::pin(::ipc::__private::#stream_with_id(id, ::ipc::__private::in_call_stream(#call_info, x))))),
+                                            ::core::result::Result::Ok(::core::result::Result::Err(e)) => {
+                                                #running_calls.finish(id);
+                                                #finish_inputs
+                                                send_packet!(tx, id, ::ipc::__private::Response::Value(::ipc::__private::StreamPacket::<!, _>::Error(e)));
+                                            }
+                                            ::core::result::Result::Err(rejection) => {
+                                                #running_calls.finish(id);
+                                                #finish_inputs
+                                                send_packet!(tx, id, ::ipc::__private::Response::<!>::from(rejection));
+                                            }
+                                        }
                                     }
                                 }
                             }
                         } else {
                             quote! {
-                                ::core::option::Option::Some((id, stream)) = ::ipc::futures::StreamExt::next(&mut #calls_name), if !::ipc::futures::prelude::stream::FuturesUnordered::is_empty(&#calls_name) => {
-                                    ::ipc::futures::prelude::stream::SelectAll::push(&mut #streams_name, ::std::boxed::Box::pin(::ipc::__private::stream_with_id(id, stream)));
+                                ::core::option::Option::Some(result) = ::ipc::futures::StreamExt::next(&mut #calls_name), if !::ipc::futures::prelude::stream::FuturesUnordered::is_empty(&#calls_name) => {
+                                    // error if the call was cancelled
+                                    if let ::core::result::Result::Ok((id, (stream, #call_info))) = result {
+                                        match stream {
+                                            ::core::result::Result::Ok(stream) => ::ipc::futures::prelude::stream::SelectAll::push(&mut #streams_name, #running_calls.register(id, ::std::boxed::Box::pin(::ipc::__private::#stream_with_id(id, ::ipc::__private::in_call_stream(#call_info, stream))))),
+                                            ::core::result::Result::Err(rejection) => {
+                                                #running_calls.finish(id);
+                                                #finish_inputs
+                                                send_packet!(tx, id, ::ipc::__private::Response::<!>::from(rejection));
+                                            }
+                                        }
+                                    }
                                 }
                             }
                         };
@@ -244,24 +638,46 @@ impl Protocol {
                         let select_branches = quote! {
                             #select_branch,
                             ::core::option::Option::Some((id, result)) = ::ipc::futures::StreamExt::next(&mut #streams_name), if !::ipc::futures::prelude::stream::SelectAll::is_empty(&#streams_name) => {
-                                send_packet!(tx, id, result);
+                                if ::core::matches!(result, ::ipc::__private::StreamPacket::EndOfStream) {
+                                    #running_calls.finish(id);
+                                    #finish_inputs
+                                }
+                                send_packet!(tx, id, ::ipc::__private::Response::Value(result));
                             }
                         };
 
-                        (variable_creation, read_branch, select_branches, args_types)
+                        (variable_creation, read_branch, select_branches, args_types, inputs_name)
                     }
                 }
             })
             .collect();
 		let call_types = call_types.iter().flatten();
+		let inputs_names = inputs_names.iter().flatten();
+
+		let subscriptions = Ident::new("subscriptions", Span::mixed_site());
+		let signals_count = self.signals.len() as u64;
+		let (subscriptions_creation, subscribe_branch, unsubscribe) = if self.signals.is_empty() {
+			(None, None, None)
+		} else {
+			(
+				Some(quote! {
+					let #subscriptions = ::ipc::__private::Subscribers::client(&#server_name::signals(server).0, ::core::clone::Clone::clone(&tx));
+				}),
+				Some(quote! {
+					(call_id, ::core::result::Result::Ok(::ipc::__private::Request::Subscribe(signal))) if signal < #signals_count => #subscriptions.subscribe(signal, call_id),
+				}),
+				Some(quote!(#subscriptions.unsubscribe(call_id);)),
+			)
+		};
 
 		let handle_client_method = quote! {
 			#[allow(clippy::future_not_send, reason = "isn't needed for callers to be send")]
-			async fn handle_client(
-				server: &impl #server_name,
-				rx: ::ipc::__private::PacketReceiver<::ipc::tokio::net::unix::OwnedReadHalf>,
-				mut tx: ::ipc::tokio::io::BufWriter<::ipc::tokio::net::unix::OwnedWriteHalf>,
-			) {
+			async fn handle_client<RX, TX, #generics>(server: &(impl #server_name #ty_generics + ::core::marker::Sync), rx: RX, tx: TX, shutdown: ::ipc::CancellationToken)
+			where
+				RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send,
+				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send + ::core::marker::Sync + 'static,
+				#(#parameter_bounds,)*
+			{
 				macro_rules! send_packet {
 					($tx:expr, $id:expr, $payload:expr) => {
 						let packet = ::ipc::__private::Serverbound {
@@ -269,8 +685,7 @@ impl Protocol {
 							payload: $payload,
 						};
 
-						let result = ::ipc::Write::write(&packet, &mut tx).await;
-						if let ::core::result::Result::Err(e) = result {
+						if let ::core::result::Result::Err(e) = $tx.write(packet).await {
 							if let ::core::option::Option::Some(e) =
 								::ipc::anyhow::Error::downcast_ref::<::std::io::Error>(&e)
 								&& ::std::io::Error::kind(&e) == ::std::io::ErrorKind::BrokenPipe
@@ -279,294 +694,1106 @@ impl Protocol {
 								return;
 							}
 							::ipc::log::error!("Error while sending packet to client: {e}");
-						} else {
-							// we don't really care if we can't flush
-							let _ = ::ipc::tokio::io::AsyncWriteExt::flush(&mut $tx).await;
+							if let ::core::option::Option::Some(e) =
+								::ipc::anyhow::Error::downcast_ref::<::ipc::LimitExceededError>(&e)
+							{
+								// nothing was sent, tell the client that this call failed
+								let packet = ::ipc::__private::Serverbound {
+									call_id: $id,
+									payload: ::ipc::__private::Response::<!>::LimitExceeded(*e),
+								};
+								if let ::core::result::Result::Err(e) = $tx.write(packet).await {
+									::ipc::log::error!("Error while sending packet to client: {e}");
+								}
+							}
 						}
 					};
 				}
 
+				let limits = #server_name::limits(server);
+				let mut rx = ::ipc::__private::PacketReceiver::new(rx);
+				rx.set_limits(limits);
+				let mut tx = ::ipc::__private::PacketSender::new(::std::boxed::Box::new(tx) as ::ipc::__private::ConnectionWriter);
+				tx.set_limits(limits);
+
+				if let ::core::result::Result::Err(e) = ::ipc::__private::handshake(&mut rx, &tx, FINGERPRINT, ::ipc::capture::Side::Server).await {
+					::ipc::log::error!("Handshake with client failed: {e}");
+					return;
+				}
+
+				let peer = ::ipc::peer_credentials();
+				if let ::core::option::Option::Some(peer) = peer
+					&& !#server_name::authorize(server, &peer).await
+				{
+					::ipc::log::warn!("Rejected client {peer:?}");
+					::ipc::__private::reject_client(rx, &tx).await;
+					return;
+				}
+
+				let #interceptor = #server_name::interceptor(server);
+				let #interceptor = &#interceptor;
+
+				let mut #running_calls = ::ipc::__private::RunningCalls::default();
+				#subscriptions_creation
 				#(#variable_creation)*
 
-				let read_stream = ::ipc::__private::PacketReceiver::receive_stream::<::ipc::__private::Clientbound<MethodCall<#(#call_types),*>>>(rx);
+				let read_stream = ::ipc::__private::PacketReceiver::receive_stream(rx);
 				::ipc::tokio::pin!(read_stream);
 
+				let #options = #server_name::server_options(server);
+				// set once the server is shut down, to stop once the running calls end
+				let mut #draining = false;
+				// the request that was just received
+				let mut #pending = ::core::option::Option::None;
+				// calls waiting for running ones to end, while the client can't start more of them
+				let mut #queued = ::std::collections::VecDeque::new();
+
 				loop {
-					::ipc::tokio::select! {
-						::core::option::Option::Some(packet) = ::ipc::futures::StreamExt::next(&mut read_stream) => match packet {
+					while let ::core::option::Option::Some((call, deadline, #trace)) = ::core::option::Option::take(&mut #pending).or_else(|| {
+						if !#draining && #options.accepts_call(#running_calls.len()) {
+							#queued.pop_front()
+						} else {
+							::core::option::Option::None
+						}
+					}) {
+						#[allow(unused_variables, reason = "only used by methods")]
+						let (deadline, #trace) = (deadline, #trace);
+						match call {
 							#(#read_branch,)*
-							::core::result::Result::Err(e) => {
-								if let ::core::option::Option::Some(e) = ::ipc::anyhow::Error::downcast_ref::<::std::io::Error>(&e)
-									&& ::std::io::Error::kind(e) == ::std::io::ErrorKind::UnexpectedEof
-								{
-									// client quitted normally
+							(call_id, ::core::result::Result::Ok(::ipc::__private::Request::Cancel)) => {
+								#running_calls.cancel(call_id);
+								#(#inputs_names.finish(call_id);)*
+								#unsubscribe
+							}
+							#subscribe_branch
+							(call_id, ::core::result::Result::Ok(::ipc::__private::Request::Subscribe(signal))) => {
+								::ipc::log::error!("Client subscribed to unknown signal {signal}");
+								send_packet!(tx, call_id, ::ipc::__private::Response::<!>::InvalidCall(::std::format!("unknown signal {signal}")));
+							}
+							(_, ::core::result::Result::Ok(::ipc::__private::Request::ContextCall(..))) => ::core::unreachable!("calls with a context were turned into calls"),
+							(call_id, ::core::result::Result::Ok(::ipc::__private::Request::Describe)) => {
+								send_packet!(tx, call_id, ::ipc::__private::Response::Value(schema::<#(#type_arguments),*>()));
+							}
+							(call_id, ::core::result::Result::Err(e)) => {
+								::ipc::log::error!("Received malformed call from client: {e:#}");
+								send_packet!(tx, call_id, ::ipc::__private::Response::<!>::InvalidCall(::std::format!("{e:#}")));
+							}
+						}
+					}
+					if #draining && #running_calls.is_empty() {
+						break;
+					}
+
+					::ipc::tokio::select! {
+						() = shutdown.cancelled(), if !#draining => {
+							#draining = true;
+							for ((call_id, _), _, _) in ::core::mem::take(&mut #queued) {
+								send_packet!(tx, call_id, ::ipc::__private::Response::<!>::ShuttingDown);
+							}
+						}
+						::core::option::Option::Some(frame) = ::ipc::futures::StreamExt::next(&mut read_stream) => {
+							let frame = match frame {
+								::core::result::Result::Ok(frame) => frame,
+								::core::result::Result::Err(e) => {
+									if let ::core::option::Option::Some(skipped) = ::ipc::__private::SkippedFrame::from_io(&e) {
+										::ipc::log::error!("Received too big packet from client: {skipped}");
+										// the frame was skipped, only the call it belongs to fails
+										if let ::core::option::Option::Some(call_id) = skipped.call_id {
+											send_packet!(tx, call_id, ::ipc::__private::Response::<!>::LimitExceeded(skipped.error));
+										}
+										continue;
+									}
+									if ::std::io::Error::kind(&e) != ::std::io::ErrorKind::UnexpectedEof {
+										::ipc::log::error!("Error receiving message from client: {e}");
+									}
+									// otherwise, client quitted normally
 									break;
 								}
-								::ipc::log::error!("Error receiving message from client: {e}");
-								break;
+							};
+
+							match ::ipc::__private::decode_call::<::ipc::__private::Request<MethodCall<#(#call_types),*>, MethodStream<#(#stream_types),*>>>(frame, limits).await {
+								::core::result::Result::Ok(call) => {
+									let request = ::ipc::__private::split_context(call);
+									let (call_id, _) = request.0;
+									let is_call = ::core::matches!(request.0, (_, ::core::result::Result::Ok(::ipc::__private::Request::Call(_))));
+									if !is_call || (!#draining && #queued.is_empty() && #options.accepts_call(#running_calls.len())) {
+										#pending = ::core::option::Option::Some(request);
+									} else if #draining {
+										send_packet!(tx, call_id, ::ipc::__private::Response::<!>::ShuttingDown);
+									} else {
+										match #options.check_queued_calls(#queued.len()) {
+											::core::result::Result::Ok(()) => #queued.push_back(request),
+											::core::result::Result::Err(e) => {
+												::ipc::log::warn!("Rejected call of a client with too many calls: {e}");
+												send_packet!(tx, call_id, ::ipc::__private::Response::<!>::LimitExceeded(e));
+											}
+										}
+									}
+								}
+								// there's no call id to answer to, the frame is simply skipped
+								::core::result::Result::Err(e) => ::ipc::log::error!("Received malformed packet from client: {e}"),
 							}
 						},
 						#(#select_branch,)*
+						// the connection ended without an error, while no call is running
+						else => break,
 					}
 				}
 			}
 		};
 
+		let abstract_socket_methods = self.abstract_socket.as_ref().map(|socket_name| {
+			quote! {
+				fn serve(&self) -> impl ::core::future::Future<Output = ::std::io::Result<!>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					self.serve_with_abstract_socket(#socket_name)
+				}
+
+				/// Serve clients on the socket of the protocol until `shutdown` is cancelled, see [`Self::server_options`]
+				fn serve_until(&self, shutdown: &::ipc::CancellationToken) -> impl ::core::future::Future<Output = ::std::io::Result<()>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					self.serve_with_abstract_socket_until(#socket_name, shutdown)
+				}
+
+				fn serve_with_abstract_socket(&self, socket: &str) -> impl ::core::future::Future<Output = ::std::io::Result<!>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					async move {
+						self.serve_with_abstract_socket_until(socket, &::ipc::CancellationToken::new()).await?;
+						::core::unreachable!("the server was never shut down")
+					}
+				}
+
+				fn serve_with_abstract_socket_until(&self, socket: &str, shutdown: &::ipc::CancellationToken) -> impl ::core::future::Future<Output = ::std::io::Result<()>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					async move {
+						let addr = <::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(socket)?;
+						let listener = ::std::os::unix::net::UnixListener::bind_addr(&addr)?;
+						::std::os::unix::net::UnixListener::set_nonblocking(&listener, true)?;
+						let listener = ::ipc::tokio::net::UnixListener::from_std(listener)?;
+
+						::ipc::__private::run_server(self, listener, handle_client::<_, _, #(#type_arguments),*>, #server_name::server_options(self), shutdown).await
+					}
+				}
+			}
+		});
+		let path_socket_methods = self.path_socket.as_ref().map(|path| {
+			quote! {
+				/// Serve clients on the socket file of the protocol, whose environment variables are expanded
+				fn serve(&self) -> impl ::core::future::Future<Output = ::std::io::Result<!>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					async move {
+						let path = ::ipc::__private::expand_path(#path)?;
+						self.serve_with_path_socket(&path).await
+					}
+				}
+
+				/// Like [`Self::serve`], until `shutdown` is cancelled, see [`Self::server_options`]
+				fn serve_until(&self, shutdown: &::ipc::CancellationToken) -> impl ::core::future::Future<Output = ::std::io::Result<()>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					async move {
+						let path = ::ipc::__private::expand_path(#path)?;
+						self.serve_with_path_socket_until(&path, shutdown).await
+					}
+				}
+
+				/// Serve clients on a socket file, which is removed when this future ends or is dropped
+				fn serve_with_path_socket(&self, path: &::std::path::Path) -> impl ::core::future::Future<Output = ::std::io::Result<!>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					async move {
+						self.serve_with_path_socket_until(path, &::ipc::CancellationToken::new()).await?;
+						::core::unreachable!("the server was never shut down")
+					}
+				}
+
+				/// Like [`Self::serve_with_path_socket`], until `shutdown` is cancelled
+				fn serve_with_path_socket_until(&self, path: &::std::path::Path, shutdown: &::ipc::CancellationToken) -> impl ::core::future::Future<Output = ::std::io::Result<()>> + ::core::marker::Send
+				where
+					Self: ::core::marker::Sized + ::core::marker::Sync,
+				{
+					async move {
+						let (listener, _file) = ::ipc::__private::bind_path_socket(path)?;
+						::ipc::__private::run_server(self, listener, handle_client::<_, _, #(#type_arguments),*>, #server_name::server_options(self), shutdown).await
+					}
+				}
+			}
+		});
+
 		let serve_method = quote! {
-			fn serve(&self) -> impl ::core::future::Future<Output = ::std::io::Result<!>> + ::core::marker::Send
-			where
-				Self: ::core::marker::Sized + ::core::marker::Sync,
-			{
-				self.serve_with_abstract_socket(#socket_name)
+			/// Limits of the values received from each client
+			fn limits(&self) -> ::ipc::Limits {
+				::ipc::Limits::default()
+			}
+
+			/// Limits on the clients and their calls, and how the server shuts down
+			fn server_options(&self) -> ::ipc::ServerOptions {
+				::ipc::ServerOptions::default()
 			}
 
-			fn serve_with_abstract_socket(&self, socket: &str) -> impl ::core::future::Future<Output = ::std::io::Result<!>> + ::core::marker::Send
+			/// Whether a client connected through a unix socket can make calls
+			///
+			/// By default, only processes of the same user are allowed. Rejected clients
+			/// get [`ipc::Error::Unauthorized`] for every call. Clients connected through
+			/// [`Self::serve_connection`] are always allowed.
+			fn authorize(&self, peer: &::ipc::PeerCredentials) -> impl ::core::future::Future<Output = bool> + ::core::marker::Send {
+				::core::future::ready(peer.uid == ::ipc::__private::current_uid())
+			}
+
+			/// Code running around every call of a client, see [`ipc::interceptor`]
+			///
+			/// Called once for every client, before its first call. Calls aren't intercepted by default.
+			fn interceptor(&self) -> impl ::ipc::interceptor::Interceptor {}
+
+			/// Serve a single client connected through `rx` and `tx`, until it disconnects
+			fn serve_connection<RX, TX>(&self, rx: RX, tx: TX) -> impl ::core::future::Future<Output = ()> + ::core::marker::Send
 			where
 				Self: ::core::marker::Sized + ::core::marker::Sync,
+				RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send,
+				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send + ::core::marker::Sync + 'static,
 			{
-				async move {
-					let addr = <::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(socket)?;
-					let listener = ::std::os::unix::net::UnixListener::bind_addr(&addr)?;
-					::std::os::unix::net::UnixListener::set_nonblocking(&listener, true)?;
-					let listener = ::ipc::tokio::net::UnixListener::from_std(listener)?;
-
-					::ipc::__private::run_server(self, listener, handle_client).await
-				}
+				handle_client::<RX, TX, #(#type_arguments),*>(self, rx, tx, ::ipc::CancellationToken::new())
 			}
+
+			#abstract_socket_methods
+			#path_socket_methods
 		};
 
 		(serve_method, handle_client_method)
 	}
 
 	fn generate_client_trait(&self) -> TokenStream {
-		let methods: Vec<_> = self
-            .methods
-            .iter()
-            .cloned()
-            .map(|method| {
-                let mut method = match method {
-                    ProtocolMethod::SimpleCall(mut method) => {
-                        let output = match &method.sig.output {
-                            ReturnType::Default => parse_quote!(()),
-                            ReturnType::Type(_, output) => (*output).clone(),
-                        };
-
-                        method.sig.output = parse_quote!(-> impl ::core::future::Future<Output = ::ipc::Result<#output>> + ::core::marker::Send);
-                        method
-                    }
-                    ProtocolMethod::LongCall { mut method, early_error } => {
-                        let output = match &method.sig.output {
-                            ReturnType::Default => parse_quote!(()),
-                            ReturnType::Type(_, output) => (*output).clone(),
-                        };
-
-                        if let Some(error) = early_error {
-                            method.sig.output = parse_quote!(-> impl ::core::future::Future<Output = ::ipc::Result<::core::result::Result<impl ::ipc::futures::Stream<Item = ::ipc::Result<#output>> + ::core::marker::Send, #error>>> + ::core::marker::Send);
-                        } else {
-                            method.sig.output = parse_quote!(-> impl ::core::future::Future<Output = ::ipc::Result<impl ::ipc::futures::Stream<Item = ::ipc::Result<#output>> + ::core::marker::Send>> + ::core::marker::Send);
-                        }
-                        method
-                    }
-                };
-
-                let mut generics: Vec<GenericParam> = Vec::new();
-                let mut where_clauses: Vec<WherePredicate> = Vec::new();
-                for arg in &mut method.sig.inputs {
-                    if let FnArg::Typed(arg) = arg {
-                        let name = &arg.pat;
-                        let ty = &arg.ty;
-                        generics.push(parse_quote!(#name: ::ipc::__private::Writable<#ty>));
-                        where_clauses.push(parse_quote!(#name: Sync + Send));
-                        where_clauses.push(parse_quote!(<#name as ::ipc::Write>::Error: Sync + Send + 'static));
-                        where_clauses.push(parse_quote!(::ipc::anyhow::Error: ::core::convert::From<<#name as ::ipc::Write>::Error>));
-
-                        arg.ty = parse_quote!(#name);
-                    }
-                }
-                let sig_mut = &mut method.sig;
-                sig_mut.generics.params.extend(generics);
-                let mut where_clause = sig_mut.generics.where_clause.clone().unwrap_or_else(|| parse_quote!(where));
-                where_clause.predicates.extend(where_clauses);
-                sig_mut.generics.where_clause = Some(where_clause);
+		// the methods of the async client, whose futures are `Send`
+		let methods = self.methods.iter().enumerate().map(|(i, method)| {
+			let attributes = &method.inner().attrs;
+			let (mut signature, _) = self.client_method(i, method, ClientKind::Async);
+			let output = match signature.output {
+				ReturnType::Default => parse_quote!(()),
+				ReturnType::Type(_, output) => output,
+			};
+			signature.asyncness = None;
+			signature.output = parse_quote!(-> impl ::core::future::Future<Output = #output> + ::core::marker::Send);
+			quote! {
+				#(#attributes)*
+				#signature;
+			}
+		});
 
-                method.sig.asyncness = None;
-                method
-            })
-            .collect();
+		let receive_methods = self.signals.iter().map(|signal| {
+			let attributes = &signal.attrs;
+			let name = Ident::new(&format!("receive_{}", signal.sig.ident), signal.sig.ident.span());
+			let item = signal_type(signal);
+			quote! {
+				#(#attributes)*
+				fn #name(&self) -> impl ::core::future::Future<Output = ::ipc::Result<impl ::ipc::futures::Stream<Item = ::ipc::Result<#item>> + ::core::marker::Send>> + ::core::marker::Send;
+			}
+		});
 
 		let name = &self.name;
 		let attributes = &self.attributes;
 		let generics = &self.generics;
+		let where_clause = &self.generics.where_clause;
 		let supertraits = &self.supertraits;
 
 		quote! {
 			#(#attributes)*
 			#[allow(clippy::multiple_bound_locations)]
-			pub trait #name #generics: #supertraits {
+			pub trait #name #generics: #supertraits #where_clause {
 				#(#methods)*
+				#(#receive_methods)*
 			}
 		}
 	}
 
 	fn generate_client(&self) -> TokenStream {
-		let generics_count: Vec<_> = self
-			.methods
-			.iter()
-			.map(|method| {
-				method
-					.inner()
-					.sig
-					.inputs
-					.iter()
-					.filter(|x| matches!(x, FnArg::Typed(_)))
-					.count()
-			})
+		let methods = self.methods.iter().enumerate().map(|(i, method)| {
+			let (signature, body) = self.client_method(i, method, ClientKind::Async);
+			quote!(#signature #body)
+		});
+
+		let receive_methods = self.signals.iter().enumerate().map(|(i, signal)| {
+			let name = Ident::new(&format!("receive_{}", signal.sig.ident), signal.sig.ident.span());
+			let item = signal_type(signal);
+			let index = i as u64;
+			quote! {
+				async fn #name(&self) -> ::ipc::Result<impl ::ipc::futures::Stream<Item = ::ipc::Result<#item>> + ::core::marker::Send> {
+					::ipc::__private::Client::subscribe::<#item>(&self.inner, #index).await
+				}
+			}
+		});
+
+		let name = &self.client_name;
+		let trait_name = &self.name;
+		let attributes = &self.attributes;
+		let generics = self.generics.params.iter().collect::<Vec<_>>();
+		let (_, ty_generics, _) = self.generics.split_for_impl();
+		let where_clause = self.where_clause();
+		let type_arguments: Vec<_> = self
+			.generics
+			.type_params()
+			.map(|param| &param.ident)
 			.collect();
-		let methods: Vec<_> = self
-            .methods
-            .iter()
-            .cloned()
-            .enumerate()
-            .map(|(i, mut method)| {
-                let output = match &method.inner().sig.output {
-                    ReturnType::Default => parse_quote!(()),
-                    ReturnType::Type(_, output) => (*output).clone(),
-                };
+		let client_type = quote!(#name<#(#type_arguments,)* RX, TX>);
+		let socket_client_type =
+			quote!(#name<#(#type_arguments,)* ::ipc::UnixReadHalf, ::ipc::UnixWriteHalf>);
 
-                match &mut method {
-                    ProtocolMethod::SimpleCall(method) => {
-                        method.sig.output = parse_quote!(-> ::ipc::Result<#output>);
-                    }
-                    ProtocolMethod::LongCall { method, early_error } => {
-                        if let Some(error) = early_error {
-                            method.sig.output =
-                                parse_quote!(-> ::ipc::Result<::core::result::Result<impl ::ipc::futures::Stream<Item = ::ipc::Result<#output>> + ::core::marker::Send, #error>>);
-                        } else {
-                            method.sig.output = parse_quote!(-> ::ipc::Result<impl ::ipc::futures::Stream<Item = ::ipc::Result<#output>> + ::core::marker::Send>);
-                        }
+		let socket_impl = self.abstract_socket.as_ref().map(|socket| {
+            quote! {
+                impl<#(#generics),*> #socket_client_type {
+                    /// Name of the abstract socket of the protocol
+                    pub const ABSTRACT_SOCKET: &str = #socket;
+
+                    pub async fn new() -> ::ipc::Result<Self> {
+                        Self::new_with_abstract_socket(#socket).await
                     }
-                }
 
-                let mut generics: Vec<GenericParam> = Vec::new();
-                let mut where_clauses: Vec<WherePredicate> = Vec::new();
-                for arg in &mut method.inner_mut().sig.inputs {
-                    if let FnArg::Typed(arg) = arg {
-                        let name = &arg.pat;
-                        let ty = &arg.ty;
-                        generics.push(parse_quote!(#name: ::ipc::__private::Writable<#ty>));
-                        where_clauses.push(parse_quote!(#name: Sync + Send));
-                        where_clauses.push(parse_quote!(<#name as ::ipc::Write>::Error: Sync + Send + 'static));
-                        where_clauses.push(parse_quote!(::ipc::anyhow::Error: ::core::convert::From<<#name as ::ipc::Write>::Error>));
-
-                        arg.ty = parse_quote!(#name);
+                    pub async fn new_with_abstract_socket(socket: &str) -> ::ipc::Result<Self> {
+                        let address = <::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(socket).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::__private::Client::from_unix_address(&address, FINGERPRINT).await?,
+                        })
                     }
-                }
-                let sig_mut = &mut method.inner_mut().sig;
-                sig_mut.generics.params.extend(generics);
-                let mut where_clause = sig_mut.generics.where_clause.clone().unwrap_or_else(|| parse_quote!(where));
-                where_clause.predicates.extend(where_clauses);
-                sig_mut.generics.where_clause = Some(where_clause);
-
-                let signature = &method.inner().sig;
-                let name = &signature.ident;
-                let struct_name = Ident::new(&format!("{name}Call"), Span::mixed_site());
-                let args: Vec<_> = signature
-                    .inputs
-                    .iter()
-                    .filter_map(|x| match x {
-                        FnArg::Receiver(_) => None,
-                        FnArg::Typed(x) => Some(&x.pat),
-                    })
-                    .collect();
-
-                let generics: Vec<_> = generics_count[..i]
-                    .iter()
-                    .flat_map(|count| iter::repeat_n(quote!(!), *count))
-                    .chain(args.iter().map(|ty| quote!(#ty)))
-                    .chain(generics_count[(i + 1)..].iter().flat_map(|count| iter::repeat_n(quote!(!), *count)))
-                    .collect();
-                match &method {
-                    ProtocolMethod::SimpleCall(_) => {
-                        quote! {
-                            #signature {
-                                ::ipc::__private::Client::call::<MethodCall<#(#generics),*>, #output>(
-                                    &self.inner,
-                                    MethodCall::<#(#generics),*>::#name(#struct_name { #(#args),* })
-                                ).await
-                            }
-                        }
+
+                    /// Same as [`Self::new`], but wait for at most `timeout` for the server to be up
+                    pub async fn connect_with_retry(timeout: ::core::time::Duration) -> ::ipc::Result<Self> {
+                        let address = <::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(#socket).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::__private::Client::from_unix_address_with_retry(&address, FINGERPRINT, timeout).await?,
+                        })
                     }
-                    ProtocolMethod::LongCall { early_error, .. } => {
-                        if early_error.is_some() {
-                            quote! {
-                                #signature {
-                                    ::ipc::__private::Client::long_call::<MethodCall<#(#generics),*>, #output, #early_error>(
-                                        &self.inner,
-                                        MethodCall::<#(#generics),*>::#name(#struct_name { #(#args),* })
-                                    ).await
-                                }
-                            }
-                        } else {
-                            quote! {
-                                #signature {
-                                    let ::core::result::Result::Ok(result) =::ipc::__private::Client::long_call::<MethodCall<#(#generics),*>, #output, !>(
-                                        &self.inner,
-                                        MethodCall::<#(#generics),*>::#name(#struct_name { #(#args),* })
-                                    ).await?;
-                                    Ok(result)
-                                }
-                            }
-                        }
+
+                    /// Same as [`Self::new`], but connect again when the connection breaks
+                    pub async fn new_reconnecting(options: ::ipc::Reconnect) -> ::ipc::Result<Self> {
+                        let address = <::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(#socket).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::__private::Client::from_unix_address_reconnecting(address, FINGERPRINT, options).await?,
+                        })
                     }
                 }
-            })
-            .collect();
-
-		let name = &self.client_name;
-		let trait_name = &self.name;
-		let attributes = &self.attributes;
-		let impl_generics = self.generics.params.iter();
-		let (_, ty_generics, where_clause) = self.generics.split_for_impl();
+            }
+        });
 
-		let socket_impl = self.abstract_socket.as_ref().map(|socket| {
+		let path_socket_impl = self.path_socket.as_ref().map(|path| {
             quote! {
-                impl #name<::ipc::tokio::net::unix::OwnedReadHalf, ::ipc::tokio::net::unix::OwnedWriteHalf> {
-                    pub fn new() -> ::std::io::Result<Self> {
-                        Self::new_with_abstract_socket(#socket)
+                impl<#(#generics),*> #socket_client_type {
+                    /// Socket file of the protocol, before its environment variables are expanded
+                    pub const PATH_SOCKET: &str = #path;
+
+                    /// Connect to the socket file of the protocol, whose environment variables are expanded
+                    pub async fn new() -> ::ipc::Result<Self> {
+                        let path = ::ipc::__private::expand_path(#path).map_err(::ipc::Error::Connect)?;
+                        Self::new_with_path_socket(&path).await
+                    }
+
+                    pub async fn new_with_path_socket(path: &::std::path::Path) -> ::ipc::Result<Self> {
+                        let address = ::std::os::unix::net::SocketAddr::from_pathname(path).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::__private::Client::from_unix_address(&address, FINGERPRINT).await?,
+                        })
+                    }
+
+                    /// Same as [`Self::new`], but wait for at most `timeout` for the server to be up
+                    pub async fn connect_with_retry(timeout: ::core::time::Duration) -> ::ipc::Result<Self> {
+                        let path = ::ipc::__private::expand_path(#path).map_err(::ipc::Error::Connect)?;
+                        let address = ::std::os::unix::net::SocketAddr::from_pathname(path).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::__private::Client::from_unix_address_with_retry(&address, FINGERPRINT, timeout).await?,
+                        })
                     }
 
-                    pub fn new_with_abstract_socket(socket: &str) -> ::std::io::Result<Self> {
+                    /// Same as [`Self::new`], but connect again when the connection breaks
+                    pub async fn new_reconnecting(options: ::ipc::Reconnect) -> ::ipc::Result<Self> {
+                        let path = ::ipc::__private::expand_path(#path).map_err(::ipc::Error::Connect)?;
+                        let address = ::std::os::unix::net::SocketAddr::from_pathname(path).map_err(::ipc::Error::Connect)?;
                         ::core::result::Result::Ok(Self {
-                            inner: ::ipc::__private::Client::from_unix_address(&<::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(socket)?)?,
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::__private::Client::from_unix_address_reconnecting(address, FINGERPRINT, options).await?,
                         })
                     }
                 }
             }
         });
 
+		let transport_impl = quote! {
+			impl<
+				#(#generics,)*
+				RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send + 'static,
+				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send + ::core::marker::Sync + 'static,
+			> #client_type {
+				/// Connect to a server over any transport, like the ones of [`ipc::testing::pair`]
+				pub async fn new_with_transport(rx: RX, tx: TX) -> ::ipc::Result<Self> {
+					::core::result::Result::Ok(Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: ::ipc::__private::Client::new(rx, tx, FINGERPRINT).await?,
+					})
+				}
+
+				/// Connect to a server with the transports returned by `connect`, which
+				/// is called again when the connection breaks
+				pub async fn new_with_connector<F, Fut>(connect: F, options: ::ipc::Reconnect) -> ::ipc::Result<Self>
+				where
+					F: ::core::ops::Fn() -> Fut + ::core::marker::Send + ::core::marker::Sync + 'static,
+					Fut: ::core::future::Future<Output = ::std::io::Result<(RX, TX)>> + ::core::marker::Send + 'static,
+				{
+					::core::result::Result::Ok(Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: ::ipc::__private::Client::new_reconnecting(connect, FINGERPRINT, options).await?,
+					})
+				}
+			}
+		};
+
 		// TODO: simplify, use less dyn, RwLock, ...
 		//
 		// Idea: pass function pointers pointing to reading functions instead of Box<dyn>
 		quote! {
 			#(#attributes)*
 			pub struct #name<
+				#(#type_arguments,)*
 				RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send + 'static,
 				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send,
-
 			> {
 				inner: ::ipc::__private::Client<RX, TX>,
+				_protocol: ::core::marker::PhantomData<fn() -> (#(#type_arguments,)*)>,
 			}
 
 			#[allow(clippy::multiple_bound_locations)]
 			impl<
 				RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send,
-				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send + ::core::marker::Sync,
-				#(#impl_generics,)*
-			> #trait_name #ty_generics for #name<RX, TX> #where_clause {
+				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send + ::core::marker::Sync + 'static,
+				#(#generics,)*
+			> #trait_name #ty_generics for #client_type #where_clause {
 				#(#methods)*
+				#(#receive_methods)*
+			}
+
+			impl<
+				#(#generics,)*
+				RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send + 'static,
+				TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + ::core::marker::Send,
+			> #client_type {
+				/// Fingerprint of the protocol, a server with a different fingerprint will be rejected
+				pub const FINGERPRINT: u64 = FINGERPRINT;
+
+				/// Description of the protocol, which servers send to clients making a describe call
+				#[must_use]
+				pub fn schema() -> ::ipc::schema::Schema {
+					schema::<#(#type_arguments),*>()
+				}
+
+				/// Set the limits of the values received from the server
+				#[must_use]
+				pub fn with_limits(self, limits: ::ipc::Limits) -> Self {
+					Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: self.inner.with_limits(limits),
+					}
+				}
+
+				/// Set the maximum time to wait for the response of the methods that
+				/// don't have a `#[timeout]` and aren't streamed
+				#[must_use]
+				pub fn with_timeout(self, timeout: ::core::time::Duration) -> Self {
+					Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: self.inner.with_timeout(timeout),
+					}
+				}
+
+				/// Stream of the states of the connection, starting with the current one
+				pub fn connection_states(&self) -> impl ::ipc::futures::Stream<Item = ::ipc::ConnectionState> + use<#(#type_arguments,)* RX, TX> {
+					self.inner.connection_states()
+				}
 			}
 
+			#transport_impl
 			#socket_impl
+			#path_socket_impl
 		}
 	}
+
+	/// Client of the protocol that doesn't implement the protocol trait, because its
+	/// methods aren't `async` or their futures aren't `Send`
+	fn generate_inherent_client(&self, name: &Ident, kind: ClientKind) -> TokenStream {
+		let (receiver, inner_type): (FnArg, TokenStream) = match kind {
+			ClientKind::Async => unreachable!("the async client implements the protocol trait"),
+			ClientKind::Blocking => (parse_quote!(&mut self), quote!(::ipc::BlockingClient)),
+			ClientKind::Local => (parse_quote!(&self), quote!(::ipc::LocalClient)),
+		};
+		let await_token = kind.await_token();
+		let methods = self.methods.iter().enumerate().map(|(i, method)| {
+			let attributes = &method.inner().attrs;
+			let (signature, body) = self.client_method(i, method, kind);
+			quote! {
+				#(#attributes)*
+				pub #signature #body
+			}
+		});
+
+		let receive_methods = self.signals.iter().enumerate().map(|(i, signal)| {
+			let attributes = &signal.attrs;
+			let name = Ident::new(
+				&format!("receive_{}", signal.sig.ident),
+				signal.sig.ident.span(),
+			);
+			let item = signal_type(signal);
+			let index = i as u64;
+			let stream = kind.stream_type(&item, &quote!(!));
+			let asyncness = (kind == ClientKind::Local).then(|| quote!(async));
+			quote! {
+				#(#attributes)*
+				pub #asyncness fn #name(#receiver) -> ::ipc::Result<#stream> {
+					self.inner.subscribe::<#item>(#index)#await_token
+				}
+			}
+		});
+
+		let attributes = &self.attributes;
+		let generics = self.generics.params.iter().collect::<Vec<_>>();
+		let where_clause = self.where_clause();
+		let type_arguments: Vec<_> = self
+			.generics
+			.type_params()
+			.map(|param| &param.ident)
+			.collect();
+		let client_type = quote!(#name<#(#type_arguments),*>);
+
+		// the future receiving the frames of a local client
+		let connection_type =
+			quote!(impl ::core::future::Future<Output = ()> + use<#(#type_arguments,)*>);
+
+		let socket_impl = self.abstract_socket.as_ref().map(|socket| {
+            let connect = match kind {
+                ClientKind::Blocking => Some(quote! {
+                    pub fn new() -> ::ipc::Result<Self> {
+                        Self::new_with_abstract_socket(#socket)
+                    }
+
+                    pub fn new_with_abstract_socket(socket: &str) -> ::ipc::Result<Self> {
+                        let address = <::std::os::unix::net::SocketAddr as ::std::os::linux::net::SocketAddrExt>::from_abstract_name(socket).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::BlockingClient::from_unix_address(&address, FINGERPRINT)?,
+                        })
+                    }
+                }),
+                ClientKind::Local if cfg!(feature = "gio") => Some(quote! {
+                    pub async fn new() -> ::ipc::Result<(Self, #connection_type)> {
+                        Self::new_with_abstract_socket(#socket).await
+                    }
+
+                    /// Connect to an abstract socket with gio, see [`Self::new_with_socket_connection`]
+                    pub async fn new_with_abstract_socket(socket: &str) -> ::ipc::Result<(Self, #connection_type)> {
+                        let address = ::ipc::gio::UnixSocketAddress::with_type(::ipc::gio::prelude::UnixSocketAddressPath::Abstract(socket.as_bytes()));
+                        let (inner, connection) = ::ipc::LocalClient::from_gio_address(address, FINGERPRINT).await?;
+                        ::core::result::Result::Ok((Self { _protocol: ::core::marker::PhantomData, inner }, connection))
+                    }
+                }),
+                _ => None,
+            };
+            quote! {
+                /// Name of the abstract socket of the protocol
+                pub const ABSTRACT_SOCKET: &str = #socket;
+
+                #connect
+            }
+        });
+
+		let path_socket_impl = self.path_socket.as_ref().map(|path| {
+            let connect = match kind {
+                ClientKind::Blocking => Some(quote! {
+                    /// Connect to the socket file of the protocol, whose environment variables are expanded
+                    pub fn new() -> ::ipc::Result<Self> {
+                        let path = ::ipc::__private::expand_path(#path).map_err(::ipc::Error::Connect)?;
+                        Self::new_with_path_socket(&path)
+                    }
+
+                    pub fn new_with_path_socket(path: &::std::path::Path) -> ::ipc::Result<Self> {
+                        let address = ::std::os::unix::net::SocketAddr::from_pathname(path).map_err(::ipc::Error::Connect)?;
+                        ::core::result::Result::Ok(Self {
+                            _protocol: ::core::marker::PhantomData,
+                            inner: ::ipc::BlockingClient::from_unix_address(&address, FINGERPRINT)?,
+                        })
+                    }
+                }),
+                ClientKind::Local if cfg!(feature = "gio") => Some(quote! {
+                    /// Connect to the socket file of the protocol, whose environment variables are expanded
+                    pub async fn new() -> ::ipc::Result<(Self, #connection_type)> {
+                        let path = ::ipc::__private::expand_path(#path).map_err(::ipc::Error::Connect)?;
+                        Self::new_with_path_socket(&path).await
+                    }
+
+                    /// Connect to a socket file with gio, see [`Self::new_with_socket_connection`]
+                    pub async fn new_with_path_socket(path: &::std::path::Path) -> ::ipc::Result<(Self, #connection_type)> {
+                        let address = ::ipc::gio::UnixSocketAddress::new(path);
+                        let (inner, connection) = ::ipc::LocalClient::from_gio_address(address, FINGERPRINT).await?;
+                        ::core::result::Result::Ok((Self { _protocol: ::core::marker::PhantomData, inner }, connection))
+                    }
+                }),
+                _ => None,
+            };
+            quote! {
+                /// Socket file of the protocol, before its environment variables are expanded
+                pub const PATH_SOCKET: &str = #path;
+
+                #connect
+            }
+        });
+
+		let gio_constructor = cfg!(feature = "gio").then(|| quote! {
+			/// Do the handshake with a server over a gio socket connection, the returned
+			/// future can be spawned with `glib::spawn_future_local`
+			pub async fn new_with_socket_connection(connection: ::ipc::gio::SocketConnection) -> ::ipc::Result<(Self, #connection_type)> {
+				let (inner, connection) = ::ipc::LocalClient::new_with_socket_connection(connection, FINGERPRINT).await?;
+				::core::result::Result::Ok((Self { _protocol: ::core::marker::PhantomData, inner }, connection))
+			}
+		});
+		let constructors = if kind == ClientKind::Blocking {
+			quote! {
+				/// Do the handshake with a server over an already connected socket
+				pub fn new_with_stream(stream: ::std::os::unix::net::UnixStream) -> ::ipc::Result<Self> {
+					::core::result::Result::Ok(Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: ::ipc::BlockingClient::new(stream, FINGERPRINT)?,
+					})
+				}
+			}
+		} else {
+			quote! {
+				/// Connect to a server over any transport, the returned future receiving the
+				/// frames of the connection must be spawned on the executor of the calls
+				pub async fn new_with_transport<RX, TX>(rx: RX, tx: TX) -> ::ipc::Result<(Self, impl ::core::future::Future<Output = ()> + use<#(#type_arguments,)* RX, TX>)>
+				where
+					RX: ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + 'static,
+					TX: ::ipc::tokio::io::AsyncWrite + ::core::marker::Unpin + 'static,
+				{
+					let (inner, connection) = ::ipc::LocalClient::new(rx, tx, FINGERPRINT).await?;
+					::core::result::Result::Ok((Self { _protocol: ::core::marker::PhantomData, inner }, connection))
+				}
+
+				/// Same as [`Self::new_with_transport`], with a transport implementing the
+				/// traits of `futures`, like the gio streams
+				pub async fn new_with_io<T>(io: T) -> ::ipc::Result<(Self, impl ::core::future::Future<Output = ()> + use<#(#type_arguments,)* T>)>
+				where
+					T: ::ipc::futures::AsyncRead + ::ipc::futures::AsyncWrite + 'static,
+				{
+					let (inner, connection) = ::ipc::LocalClient::new_with_io(io, FINGERPRINT).await?;
+					::core::result::Result::Ok((Self { _protocol: ::core::marker::PhantomData, inner }, connection))
+				}
+
+				#gio_constructor
+			}
+		};
+
+		quote! {
+			#(#attributes)*
+			#[derive(Debug)]
+			pub struct #name<#(#type_arguments),*> {
+				inner: #inner_type,
+				_protocol: ::core::marker::PhantomData<fn() -> (#(#type_arguments,)*)>,
+			}
+
+			#[allow(clippy::multiple_bound_locations)]
+			impl<#(#generics),*> #client_type #where_clause {
+				/// Fingerprint of the protocol, a server with a different fingerprint will be rejected
+				pub const FINGERPRINT: u64 = FINGERPRINT;
+
+				/// Description of the protocol, which servers send to clients making a describe call
+				#[must_use]
+				pub fn schema() -> ::ipc::schema::Schema {
+					schema::<#(#type_arguments),*>()
+				}
+
+				#constructors
+
+				/// Set the limits of the values sent to and received from the server
+				#[must_use]
+				pub fn with_limits(self, limits: ::ipc::Limits) -> Self {
+					Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: self.inner.with_limits(limits),
+					}
+				}
+
+				/// Set the maximum time to wait for the response of the methods that
+				/// don't have a `#[timeout]` and aren't streamed
+				#[must_use]
+				pub fn with_timeout(self, timeout: ::core::time::Duration) -> Self {
+					Self {
+						_protocol: ::core::marker::PhantomData,
+						inner: self.inner.with_timeout(timeout),
+					}
+				}
+
+				#socket_impl
+				#path_socket_impl
+
+				#(#methods)*
+				#(#receive_methods)*
+			}
+		}
+	}
+
+	/// Signature and body of the method of a client calling `method`, the `i`th method of the protocol
+	fn client_method(
+		&self,
+		i: usize,
+		method: &ProtocolMethod,
+		kind: ClientKind,
+	) -> (Signature, TokenStream) {
+		let is_long_call = matches!(method, ProtocolMethod::LongCall { .. });
+		let stream_arg = method.input_stream().map(|(arg, _)| &arg.pat);
+		let args: Vec<_> = method.arguments().map(|arg| &arg.pat).collect();
+		let mut output = match &method.inner().sig.output {
+			ReturnType::Default => parse_quote!(()),
+			ReturnType::Type(_, output) => (*output).clone(),
+		};
+		if let ProtocolMethod::LongCall { item_error, .. } = method {
+			output = stream_item(output, item_error.as_ref());
+		}
+
+		let mut signature = method.inner().sig.clone();
+		signature.output = match method {
+			ProtocolMethod::SimpleCall(_) => parse_quote!(-> ::ipc::Result<#output>),
+			ProtocolMethod::LongCall {
+				early_error: Some(error),
+				..
+			} => {
+				let stream = kind.stream_type(&output, error);
+				parse_quote!(-> ::ipc::Result<::core::result::Result<#stream, #error>>)
+			}
+			ProtocolMethod::LongCall {
+				early_error: None, ..
+			} => {
+				let stream = kind.stream_type(&output, &quote!(!));
+				parse_quote!(-> ::ipc::Result<#stream>)
+			}
+		};
+		if kind == ClientKind::Blocking {
+			signature.asyncness = None;
+		}
+
+		let mut generics: Vec<GenericParam> = Vec::new();
+		let mut where_clauses: Vec<WherePredicate> = Vec::new();
+		for arg in &mut signature.inputs {
+			match arg {
+				FnArg::Receiver(_) => {
+					if kind == ClientKind::Blocking {
+						*arg = parse_quote!(&mut self);
+					}
+				}
+				FnArg::Typed(arg) => {
+					let name = &arg.pat;
+					let ty = &arg.ty;
+					if let Some(item) = stream_item_type(ty) {
+						match kind {
+							ClientKind::Async => generics.push(
+								parse_quote!(#name: ::ipc::futures::Stream<Item = #item> + ::core::marker::Send),
+							),
+							ClientKind::Blocking => generics.push(
+								parse_quote!(#name: ::core::iter::IntoIterator<Item = #item>),
+							),
+							ClientKind::Local => generics
+								.push(parse_quote!(#name: ::ipc::futures::Stream<Item = #item>)),
+						}
+						// streamed responses keep sending the input after the method returns
+						if is_long_call && kind != ClientKind::Blocking {
+							where_clauses.push(parse_quote!(#name: 'static));
+						}
+						arg.ty = parse_quote!(#name);
+						continue;
+					}
+					generics.push(parse_quote!(#name: ::ipc::__private::Writable<#ty>));
+					// the futures of the local client keep the value without having to be `Send`,
+					// but writing it still borrows it in a `Send` future
+					if kind == ClientKind::Local {
+						where_clauses.push(parse_quote!(#name: Sync));
+					} else {
+						where_clauses.push(parse_quote!(#name: Sync + Send));
+					}
+					where_clauses
+						.push(parse_quote!(<#name as ::ipc::Write>::Error: Sync + Send + 'static));
+					where_clauses.push(
+						parse_quote!(::ipc::anyhow::Error: ::core::convert::From<<#name as ::ipc::Write>::Error>),
+					);
+
+					arg.ty = parse_quote!(#name);
+				}
+			}
+		}
+		signature.generics.params.extend(generics);
+		let mut where_clause = signature
+			.generics
+			.where_clause
+			.clone()
+			.unwrap_or_else(|| parse_quote!(where));
+		where_clause.predicates.extend(where_clauses);
+		signature.generics.where_clause = Some(where_clause);
+
+		let name = &signature.ident;
+		let struct_name = Ident::new(&format!("{name}Call"), Span::mixed_site());
+		let timeout = match self.timeouts.get(name) {
+			Some(timeout) => {
+				let nanos = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+				quote!(::core::option::Option::Some(::core::time::Duration::from_nanos(#nanos)))
+			}
+			None => quote!(::core::option::Option::None),
+		};
+
+		// the arguments of the other methods are never sent
+		let generics_count = |methods: &[ProtocolMethod]| {
+			methods
+				.iter()
+				.flat_map(|method| iter::repeat_n(quote!(!), method.arguments().count()))
+				.collect::<Vec<_>>()
+		};
+		let generics: Vec<_> = generics_count(&self.methods[..i])
+			.into_iter()
+			.chain(args.iter().map(|ty| quote!(#ty)))
+			.chain(generics_count(&self.methods[(i + 1)..]))
+			.collect();
+		// streams sent by the client are passed along the call
+		let (call, long_call, input_generics, input_args) = match stream_arg {
+			Some(stream) => (
+				quote!(call_with_input),
+				quote!(long_call_with_input),
+				Some(if is_long_call && kind != ClientKind::Blocking {
+					quote!(, _, _, _)
+				} else {
+					quote!(, _, _)
+				}),
+				Some({
+					// only the type of this method's stream is known
+					let stream_generics = self
+						.methods
+						.iter()
+						.filter(|method| method.input_stream().is_some())
+						.map(|method| {
+							if method.inner().sig.ident == *name {
+								quote!(_)
+							} else {
+								quote!(!)
+							}
+						});
+					quote!(, #stream, MethodStream::<#(#stream_generics),*>::#name)
+				}),
+			),
+			None => (quote!(call), quote!(long_call), None, None),
+		};
+		let call_args = quote! {
+			MethodCall::<#(#generics),*>::#name(#struct_name { #(#args),* })
+			#input_args,
+			#timeout
+		};
+		let await_token = kind.await_token();
+		let body = match method {
+			ProtocolMethod::SimpleCall(_) => quote! {{
+				self.inner.#call::<MethodCall<#(#generics),*>, #output #input_generics>(#call_args)#await_token
+			}},
+			ProtocolMethod::LongCall {
+				early_error: Some(error),
+				..
+			} => quote! {{
+				self.inner.#long_call::<MethodCall<#(#generics),*>, #output, #error #input_generics>(#call_args)#await_token
+			}},
+			ProtocolMethod::LongCall {
+				early_error: None, ..
+			} => quote! {{
+				let ::core::result::Result::Ok(result) = self.inner.#long_call::<MethodCall<#(#generics),*>, #output, ! #input_generics>(#call_args)#await_token?;
+				::core::result::Result::Ok(result)
+			}},
+		};
+
+		(signature, body)
+	}
+
+	/// Bounds needed to send the values of the type parameters of the protocol in both directions,
+	/// and to format them for interceptors
+	///
+	/// Types built from a parameter, like `Vec<T>`, may need more bounds, which must be
+	/// written in the where clause of the protocol.
+	fn parameter_bounds(&self) -> Vec<WherePredicate> {
+		self.generics
+			.type_params()
+			.flat_map(|param| {
+				let name = &param.ident;
+				[
+					parse_quote!(#name: ::ipc::Read + ::ipc::Write + ::core::fmt::Debug + ::core::marker::Send + ::core::marker::Sync + 'static),
+					parse_quote!(<#name as ::ipc::Read>::Error: ::core::marker::Send + ::core::marker::Sync + 'static),
+					parse_quote!(<#name as ::ipc::Write>::Error: ::core::marker::Send + ::core::marker::Sync + 'static),
+					parse_quote!(::ipc::anyhow::Error: ::core::convert::From<<#name as ::ipc::Read>::Error> + ::core::convert::From<<#name as ::ipc::Write>::Error>),
+				]
+			})
+			.collect()
+	}
+
+	/// Where clause of the protocol, with the bounds of [`Self::parameter_bounds`]
+	fn where_clause(&self) -> WhereClause {
+		let mut where_clause = self
+			.generics
+			.where_clause
+			.clone()
+			.unwrap_or_else(|| parse_quote!(where));
+		where_clause.predicates.extend(self.parameter_bounds());
+		where_clause
+	}
+
+	fn generate_signals(&self) -> TokenStream {
+		if self.signals.is_empty() {
+			return TokenStream::new();
+		}
+
+		let emit_methods = self.signals.iter().enumerate().map(|(i, signal)| {
+			let attributes = &signal.attrs;
+			let name = &signal.sig.ident;
+			let (args_name, args_types): (Vec<_>, Vec<_>) = signal_arguments(signal).unzip();
+			let index = i as u64;
+
+			quote! {
+				#(#attributes)*
+				pub async fn #name(&self, #(#args_name: #args_types),*) {
+					if let ::core::result::Result::Err(e) = self.0.emit(#index, &(#(#args_name),*)).await {
+						::ipc::log::error!("Error while encoding signal: {}", ::ipc::anyhow::Error::from(e));
+					}
+				}
+			}
+		});
+
+		let signals_name = &self.signals_name;
+		let (impl_generics, ty_generics, _) = self.generics.split_for_impl();
+		let where_clause = self.where_clause();
+		let type_arguments = self.generics.type_params().map(|param| &param.ident);
+		quote! {
+			/// Emitter of the signals of the protocol, sending them to every subscribed client
+			///
+			/// Clones share the same subscriptions.
+			pub struct #signals_name #impl_generics(::ipc::__private::Subscribers, ::core::marker::PhantomData<fn(#(#type_arguments),*)>);
+
+			impl #impl_generics #signals_name #ty_generics #where_clause {
+				#(#emit_methods)*
+			}
+
+			// not derived, to not require the type parameters to implement these traits
+			impl #impl_generics ::core::fmt::Debug for #signals_name #ty_generics {
+				fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+					f.debug_tuple(::core::stringify!(#signals_name)).field(&self.0).finish()
+				}
+			}
+
+			impl #impl_generics ::core::clone::Clone for #signals_name #ty_generics {
+				fn clone(&self) -> Self {
+					Self(::core::clone::Clone::clone(&self.0), ::core::marker::PhantomData)
+				}
+			}
+
+			impl #impl_generics ::core::default::Default for #signals_name #ty_generics {
+				fn default() -> Self {
+					Self(::core::default::Default::default(), ::core::marker::PhantomData)
+				}
+			}
+		}
+	}
+}
+
+/// Type of the items of a streamed call, which are results if the call has an item error
+fn stream_item(output: Box<Type>, item_error: Option<&Type>) -> Box<Type> {
+	match item_error {
+		Some(error) => parse_quote!(::core::result::Result<#output, #error>),
+		None => output,
+	}
+}
+
+/// Index of the description of `ty`, added to the `types` of the generated `schema` function
+fn describe(ty: &Type) -> TokenStream {
+	quote!((&::ipc::__private::Probe::<#ty>::new()).describe(&mut types))
+}
+
+fn describe_argument(name: &Ident, ty: &Type) -> TokenStream {
+	let name = name.to_string();
+	let ty = describe(ty);
+	quote! {
+		::ipc::schema::Argument {
+			name: ::std::borrow::ToOwned::to_owned(#name),
+			ty: #ty,
+		}
+	}
+}
+
+fn optional(value: Option<TokenStream>) -> TokenStream {
+	match value {
+		Some(value) => quote!(::core::option::Option::Some(#value)),
+		None => quote!(::core::option::Option::None),
+	}
+}
+
+/// Documentation written with `///` comments
+fn docs(attributes: &[Attribute]) -> String {
+	let lines: Vec<_> = attributes
+		.iter()
+		.filter_map(|attribute| match &attribute.meta {
+			Meta::NameValue(MetaNameValue {
+				path,
+				value: Expr::Lit(ExprLit {
+					lit: Lit::Str(line),
+					..
+				}),
+				..
+			}) if path.is_ident("doc") => Some(line.value()),
+			_ => None,
+		})
+		.collect();
+	lines
+		.iter()
+		.map(|line| line.strip_prefix(' ').unwrap_or(line))
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Arguments of a signal, which are sent to clients
+fn signal_arguments(signal: &TraitItemFn) -> impl Iterator<Item = (&Ident, &Type)> {
+	signal.sig.inputs.iter().filter_map(|arg| {
+		let FnArg::Typed(arg) = arg else {
+			return None;
+		};
+		match &*arg.pat {
+			Pat::Ident(PatIdent { ident, .. }) => Some((ident, &*arg.ty)),
+			x => {
+				Diagnostic::spanned(
+					x.span().unwrap(),
+					Level::Error,
+					"only simple identifiers are supported as arguments",
+				)
+				.emit();
+				None
+			}
+		}
+	})
+}
+
+/// Type of the values received by the clients for a signal
+///
+/// This is the type of the argument if there's only one, a tuple otherwise.
+fn signal_type(signal: &TraitItemFn) -> TokenStream {
+	let types = signal.sig.inputs.iter().filter_map(|arg| match arg {
+		FnArg::Typed(arg) => Some(&arg.ty),
+		FnArg::Receiver(_) => None,
+	});
+	quote!((#(#types),*))
 }
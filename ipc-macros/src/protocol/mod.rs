@@ -1,5 +1,8 @@
+use std::{collections::HashMap, time::Duration};
+
 use syn::{
-	Attribute, Generics, Ident, Token, TraitItemFn, Type, TypeParamBound, Visibility,
+	AngleBracketedGenericArguments, Attribute, FnArg, GenericArgument, Generics, Ident, PatType,
+	PathArguments, Token, TraitBound, TraitItemFn, Type, TypeImplTrait, TypeParamBound, Visibility,
 	punctuated::Punctuated,
 };
 
@@ -8,15 +11,26 @@ mod parse;
 
 pub struct Arguments {
 	abstract_socket: Option<String>,
+	/// Path of a socket file, which can contain environment variables
+	path_socket: Option<String>,
 	client_name: Option<Ident>,
+	/// Name of the client using a blocking socket, only generated if set
+	blocking_client_name: Option<Ident>,
+	/// Name of the client whose futures aren't `Send`, only generated if set
+	local_client_name: Option<Ident>,
 	server_name: Option<Ident>,
+	signals_name: Option<Ident>,
 }
 
 pub struct Protocol {
 	abstract_socket: Option<String>,
+	path_socket: Option<String>,
 	module_name: Ident,
 	client_name: Ident,
+	blocking_client_name: Option<Ident>,
+	local_client_name: Option<Ident>,
 	server_name: Ident,
+	signals_name: Ident,
 
 	attributes: Vec<Attribute>,
 	visibility: Visibility,
@@ -24,6 +38,9 @@ pub struct Protocol {
 	generics: Generics,
 	supertraits: Punctuated<TypeParamBound, Token![+]>,
 	methods: Vec<ProtocolMethod>,
+	/// Timeouts set with `#[timeout = "..."]`, by method name
+	timeouts: HashMap<Ident, Duration>,
+	signals: Vec<TraitItemFn>,
 }
 
 #[expect(
@@ -36,6 +53,7 @@ enum ProtocolMethod {
 	LongCall {
 		method: TraitItemFn,
 		early_error: Option<Type>,
+		item_error: Option<Type>,
 	},
 }
 
@@ -51,4 +69,47 @@ impl ProtocolMethod {
 			Self::SimpleCall(method) | Self::LongCall { method, .. } => method,
 		}
 	}
+
+	/// Arguments of the method that are sent in the method call
+	pub fn arguments(&self) -> impl Iterator<Item = &PatType> {
+		self.inner().sig.inputs.iter().filter_map(|arg| match arg {
+			FnArg::Typed(arg) if stream_item_type(&arg.ty).is_none() => Some(arg),
+			_ => None,
+		})
+	}
+
+	/// Argument of the method that is a stream sent by the client, and the type of its items
+	pub fn input_stream(&self) -> Option<(&PatType, &Type)> {
+		self.inner().sig.inputs.iter().find_map(|arg| match arg {
+			FnArg::Typed(arg) => stream_item_type(&arg.ty).map(|item| (arg, item)),
+			FnArg::Receiver(_) => None,
+		})
+	}
+}
+
+/// If `ty` is `impl Stream<Item = T>`, returns `T`
+fn stream_item_type(ty: &Type) -> Option<&Type> {
+	let Type::ImplTrait(TypeImplTrait { bounds, .. }) = ty else {
+		return None;
+	};
+
+	bounds.iter().find_map(|bound| {
+		let TypeParamBound::Trait(TraitBound { path, .. }) = bound else {
+			return None;
+		};
+		let segment = path.segments.last()?;
+		if segment.ident != "Stream" {
+			return None;
+		}
+		let PathArguments::AngleBracketed(AngleBracketedGenericArguments { args, .. }) =
+			&segment.arguments
+		else {
+			return None;
+		};
+
+		args.iter().find_map(|arg| match arg {
+			GenericArgument::AssocType(assoc) if assoc.ident == "Item" => Some(&assoc.ty),
+			_ => None,
+		})
+	})
 }
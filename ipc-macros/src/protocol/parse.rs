@@ -1,9 +1,11 @@
+use std::{collections::HashMap, time::Duration};
+
 use proc_macro::{Diagnostic, Level};
 use proc_macro2::{Span, TokenStream};
 use quote::ToTokens;
 use syn::{
-	Expr, ExprLit, ExprPath, Ident, ItemTrait, Lit, Meta, MetaNameValue, Path, Result, Token,
-	TraitItem, Type,
+	Expr, ExprLit, ExprPath, Ident, ItemTrait, Lit, Meta, MetaNameValue, Path, Result, ReturnType,
+	Token, TraitItem, TraitItemFn, Type,
 	parse::{Parse, ParseStream},
 	punctuated::Punctuated,
 	spanned::Spanned,
@@ -14,8 +16,12 @@ use super::{Arguments, Protocol, ProtocolMethod};
 impl Parse for Arguments {
 	fn parse(input: ParseStream) -> Result<Self> {
 		let mut abstract_socket = (None, Vec::new());
+		let mut path_socket = (None, Vec::new());
 		let mut client_name = (None, Vec::new());
+		let mut blocking_client_name = (None, Vec::new());
+		let mut local_client_name = (None, Vec::new());
 		let mut server_name = (None, Vec::new());
+		let mut signals_name = (None, Vec::new());
 
 		let pairs = Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)?;
 
@@ -36,6 +42,22 @@ impl Parse for Arguments {
 					.emit();
 				}
 				abstract_socket.1.push(pair);
+			} else if pair.path.is_ident("path_socket") {
+				if let Expr::Lit(ExprLit {
+					lit: Lit::Str(ref s),
+					..
+				}) = pair.value
+				{
+					path_socket.0 = Some(s.value());
+				} else {
+					Diagnostic::spanned(
+						pair.value.span().unwrap(),
+						Level::Error,
+						"path_socket must be a string literal",
+					)
+					.emit();
+				}
+				path_socket.1.push(pair);
 			} else if pair.path.is_ident("client_name") {
 				if let Expr::Path(ExprPath { ref path, .. }) = pair.value
 					&& let Some(ident) = path.get_ident()
@@ -50,6 +72,34 @@ impl Parse for Arguments {
 					.emit();
 				}
 				client_name.1.push(pair);
+			} else if pair.path.is_ident("blocking_client_name") {
+				if let Expr::Path(ExprPath { ref path, .. }) = pair.value
+					&& let Some(ident) = path.get_ident()
+				{
+					blocking_client_name.0 = Some(ident.clone());
+				} else {
+					Diagnostic::spanned(
+						pair.value.span().unwrap(),
+						Level::Error,
+						"blocking_client_name must be an identifier",
+					)
+					.emit();
+				}
+				blocking_client_name.1.push(pair);
+			} else if pair.path.is_ident("local_client_name") {
+				if let Expr::Path(ExprPath { ref path, .. }) = pair.value
+					&& let Some(ident) = path.get_ident()
+				{
+					local_client_name.0 = Some(ident.clone());
+				} else {
+					Diagnostic::spanned(
+						pair.value.span().unwrap(),
+						Level::Error,
+						"local_client_name must be an identifier",
+					)
+					.emit();
+				}
+				local_client_name.1.push(pair);
 			} else if pair.path.is_ident("server_name") {
 				if let Expr::Path(ExprPath { ref path, .. }) = pair.value
 					&& let Some(ident) = path.get_ident()
@@ -64,6 +114,20 @@ impl Parse for Arguments {
 					.emit();
 				}
 				server_name.1.push(pair);
+			} else if pair.path.is_ident("signals_name") {
+				if let Expr::Path(ExprPath { ref path, .. }) = pair.value
+					&& let Some(ident) = path.get_ident()
+				{
+					signals_name.0 = Some(ident.clone());
+				} else {
+					Diagnostic::spanned(
+						pair.value.span().unwrap(),
+						Level::Error,
+						"signals_name must be an identifier",
+					)
+					.emit();
+				}
+				signals_name.1.push(pair);
 			} else {
 				Diagnostic::spanned(
 					pair.path.span().unwrap(),
@@ -74,14 +138,37 @@ impl Parse for Arguments {
 			}
 		}
 
+		if let (Some(abstract_pair), Some(path_pair)) =
+			(abstract_socket.1.last(), path_socket.1.last())
+		{
+			Diagnostic::spanned(
+				path_pair.span().unwrap(),
+				Level::Error,
+				"a protocol cannot use both an abstract socket and a path socket",
+			)
+			.span_note(
+				abstract_pair.span().unwrap(),
+				"abstract socket declared here",
+			)
+			.emit();
+		}
+
 		emit_duplicate_warnings(&mut abstract_socket.1, "abstract_socket");
+		emit_duplicate_warnings(&mut path_socket.1, "path_socket");
 		emit_duplicate_warnings(&mut client_name.1, "client_name");
+		emit_duplicate_warnings(&mut blocking_client_name.1, "blocking_client_name");
+		emit_duplicate_warnings(&mut local_client_name.1, "local_client_name");
 		emit_duplicate_warnings(&mut server_name.1, "server_name");
+		emit_duplicate_warnings(&mut signals_name.1, "signals_name");
 
 		Ok(Self {
 			abstract_socket: abstract_socket.0,
+			path_socket: path_socket.0,
 			client_name: client_name.0,
+			blocking_client_name: blocking_client_name.0,
+			local_client_name: local_client_name.0,
 			server_name: server_name.0,
+			signals_name: signals_name.0,
 		})
 	}
 }
@@ -96,6 +183,9 @@ impl Protocol {
 		let server_name = args
 			.server_name
 			.unwrap_or_else(|| Ident::new(&(name.to_string() + "Server"), Span::mixed_site()));
+		let signals_name = args
+			.signals_name
+			.unwrap_or_else(|| Ident::new(&(name.to_string() + "Signals"), Span::mixed_site()));
 
 		if let Some(unsafety) = input.unsafety {
 			Diagnostic::spanned(
@@ -115,21 +205,43 @@ impl Protocol {
 			.emit();
 		}
 
+		let mut methods = Vec::new();
+		let mut timeouts = HashMap::new();
+		let mut signals = Vec::new();
+		for item in input.items {
+			match item {
+				TraitItem::Fn(item_fn)
+					if item_fn.attrs.iter().any(|x| x.path().is_ident("signal")) =>
+				{
+					signals.push(parse_signal(item_fn));
+				}
+				TraitItem::Fn(mut item_fn) => {
+					if let Some(timeout) = parse_timeout(&mut item_fn) {
+						timeouts.insert(item_fn.sig.ident.clone(), timeout);
+					}
+					methods.extend(ProtocolMethod::parse(TraitItem::Fn(item_fn)));
+				}
+				item => methods.extend(ProtocolMethod::parse(item)),
+			}
+		}
+
 		Self {
 			abstract_socket: args.abstract_socket,
+			path_socket: args.path_socket,
 			module_name,
 			client_name,
+			blocking_client_name: args.blocking_client_name,
+			local_client_name: args.local_client_name,
 			server_name,
+			signals_name,
 			attributes: input.attrs,
 			visibility: input.vis,
 			name: input.ident,
 			generics: input.generics,
 			supertraits: input.supertraits,
-			methods: input
-				.items
-				.into_iter()
-				.filter_map(ProtocolMethod::parse)
-				.collect(),
+			methods,
+			timeouts,
+			signals,
 		}
 	}
 }
@@ -157,6 +269,7 @@ impl ProtocolMethod {
 						Self::LongCall {
 							method: item_fn,
 							early_error: None,
+							item_error: None,
 						}
 					} else {
 						let pairs = match attribute.parse_args_with(
@@ -170,10 +283,14 @@ impl ProtocolMethod {
 						};
 
 						let mut early_error = (None, Vec::new());
+						let mut item_error = (None, Vec::new());
 						for pair in pairs {
 							if pair.path.is_ident("early_error") {
 								early_error.0 = Some(pair.r#type.clone());
 								early_error.1.push(pair);
+							} else if pair.path.is_ident("item_error") {
+								item_error.0 = Some(pair.r#type.clone());
+								item_error.1.push(pair);
 							} else {
 								Diagnostic::spanned(
 									pair.path.span().unwrap(),
@@ -185,10 +302,12 @@ impl ProtocolMethod {
 						}
 
 						emit_duplicate_warnings(&mut early_error.1, "early_error");
+						emit_duplicate_warnings(&mut item_error.1, "item_error");
 
 						Self::LongCall {
 							method: item_fn,
 							early_error: early_error.0,
+							item_error: item_error.0,
 						}
 					}
 				} else {
@@ -209,6 +328,90 @@ impl ProtocolMethod {
 	}
 }
 
+/// Parse the `#[timeout = "..."]` attribute of a method, and remove it
+fn parse_timeout(item_fn: &mut TraitItemFn) -> Option<Duration> {
+	let mut timeout = None;
+	for attribute in item_fn
+		.attrs
+		.extract_if(.., |x| x.path().is_ident("timeout"))
+	{
+		let value = match &attribute.meta {
+			Meta::NameValue(MetaNameValue {
+				value: Expr::Lit(ExprLit {
+					lit: Lit::Str(s), ..
+				}),
+				..
+			}) => s,
+			meta => {
+				Diagnostic::spanned(
+					meta.span().unwrap(),
+					Level::Error,
+					"timeout must be written as `#[timeout = \"5s\"]`",
+				)
+				.emit();
+				continue;
+			}
+		};
+
+		match humantime::parse_duration(&value.value()) {
+			Ok(x) => timeout = Some(x),
+			Err(e) => Diagnostic::spanned(
+				value.span().unwrap(),
+				Level::Error,
+				format!("invalid timeout: {e}"),
+			)
+			.emit(),
+		}
+	}
+
+	timeout
+}
+
+/// Check a method marked with `#[signal]`, and remove the attribute
+fn parse_signal(mut item_fn: TraitItemFn) -> TraitItemFn {
+	for attribute in item_fn
+		.attrs
+		.extract_if(.., |x| x.path().is_ident("signal"))
+	{
+		if !matches!(attribute.meta, Meta::Path(_)) {
+			Diagnostic::spanned(
+				attribute.meta.span().unwrap(),
+				Level::Error,
+				"signal attribute doesn't take arguments",
+			)
+			.emit();
+		}
+	}
+
+	if item_fn.sig.asyncness.is_none() {
+		Diagnostic::spanned(
+			item_fn.sig.span().unwrap(),
+			Level::Error,
+			"protocol signal must be async",
+		)
+		.emit();
+	}
+	if let ReturnType::Type(_, ty) = &item_fn.sig.output {
+		Diagnostic::spanned(
+			ty.span().unwrap(),
+			Level::Error,
+			"protocol signal cannot return a value",
+		)
+		.help("the arguments of the signal are sent to the clients")
+		.emit();
+	}
+	if let Some(block) = &item_fn.default {
+		Diagnostic::spanned(
+			block.span().unwrap(),
+			Level::Error,
+			"protocol signal cannot have a body",
+		)
+		.emit();
+	}
+
+	item_fn
+}
+
 struct MetaNameType {
 	pub path: Path,
 	pub eq_token: Token![=],
@@ -1,10 +1,207 @@
+use proc_macro::{Diagnostic, Level};
 use proc_macro2::TokenStream;
 use quote::{ToTokens, quote};
 use syn::{
-	Data, DeriveInput, Fields, GenericParam, Generics, Ident, Index, Path, TypeParamBound,
-	WherePredicate, parse_quote, spanned::Spanned,
+	Attribute, Data, DataEnum, DeriveInput, Field, Fields, GenericParam, Generics, Ident, Index,
+	LitInt, Path, TypeParamBound, WherePredicate, parse_quote, spanned::Spanned,
 };
 
+/// Options set with `#[ipc(...)]` on a field
+#[derive(Default)]
+struct FieldOptions {
+	/// The field isn't sent, and is set to its default value when reading
+	skip: bool,
+	/// Module containing the `read` and `write` functions used for the field
+	with: Option<Path>,
+	/// The field is set to its default value if the other side didn't send it
+	default: bool,
+}
+
+impl FieldOptions {
+	fn parse(field: &Field) -> Self {
+		let mut options = Self::default();
+
+		for attribute in field.attrs.iter().filter(|x| x.path().is_ident("ipc")) {
+			let result = attribute.parse_nested_meta(|meta| {
+				if meta.path.is_ident("skip") {
+					options.skip = true;
+				} else if meta.path.is_ident("with") {
+					options.with = Some(meta.value()?.parse()?);
+				} else if meta.path.is_ident("default") {
+					options.default = true;
+				} else {
+					return Err(meta.error(format!(
+						"unknown field attribute: {}",
+						meta.path.to_token_stream()
+					)));
+				}
+				Ok(())
+			});
+			if let Err(e) = result {
+				e.span().unwrap().error(e.to_string()).emit();
+			}
+		}
+
+		if options.skip && (options.with.is_some() || options.default) {
+			Diagnostic::spanned(
+				field.span().unwrap(),
+				Level::Error,
+				"skipped field cannot also use `with` or `default`",
+			)
+			.emit();
+		}
+
+		options
+	}
+
+	/// Parse the options of all fields, and check that default fields are the last ones
+	/// of an extensible type
+	fn parse_all(fields: &Fields, extensible: bool) -> Vec<Self> {
+		let options: Vec<_> = fields.iter().map(Self::parse).collect();
+
+		let mut first_default = None;
+		for (field, options) in fields.iter().zip(&options) {
+			if options.skip {
+				continue;
+			}
+			if options.default {
+				if !extensible {
+					Diagnostic::spanned(
+						field.span().unwrap(),
+						Level::Error,
+						"default fields can only be used in a type with `#[ipc(extensible)]`",
+					)
+					.emit();
+				}
+				first_default.get_or_insert(field);
+			} else if let Some(first_default) = first_default {
+				Diagnostic::spanned(
+					field.span().unwrap(),
+					Level::Error,
+					"fields after a default field must also be default",
+				)
+				.span_note(first_default.span().unwrap(), "first default field is here")
+				.emit();
+			}
+		}
+
+		options
+	}
+}
+
+/// Options set with `#[ipc(...)]` on a type
+struct ContainerOptions {
+	/// Type used to send discriminants, `u64` by default
+	repr: Ident,
+	/// Fields are prefixed by their total length, so default fields can be added
+	extensible: bool,
+}
+
+impl ContainerOptions {
+	fn parse(attrs: &[Attribute], data: &Data) -> Self {
+		let mut repr = None;
+		let mut extensible = false;
+
+		for attribute in attrs.iter().filter(|x| x.path().is_ident("ipc")) {
+			let result = attribute.parse_nested_meta(|meta| {
+				if meta.path.is_ident("repr") {
+					let ident: Ident = meta.value()?.parse()?;
+					if !["u8", "u16", "u32", "u64"].iter().any(|x| ident == x) {
+						return Err(syn::Error::new(
+							ident.span(),
+							"repr must be one of u8, u16, u32 or u64",
+						));
+					}
+					if !matches!(data, Data::Enum(_)) {
+						return Err(meta.error("repr can only be used on enums"));
+					}
+					repr = Some(ident);
+				} else if meta.path.is_ident("extensible") {
+					extensible = true;
+				} else {
+					return Err(meta.error(format!(
+						"unknown container attribute: {}",
+						meta.path.to_token_stream()
+					)));
+				}
+				Ok(())
+			});
+			if let Err(e) = result {
+				e.span().unwrap().error(e.to_string()).emit();
+			}
+		}
+
+		Self {
+			repr: repr.unwrap_or_else(|| parse_quote!(u64)),
+			extensible,
+		}
+	}
+}
+
+/// Compute the discriminant of each variant
+///
+/// Like Rust enums, a variant without `#[ipc(discriminant = N)]` uses the discriminant of
+/// the previous variant plus one.
+fn discriminants(data: &DataEnum, repr: &Ident) -> Vec<LitInt> {
+	let max = match repr.to_string().as_str() {
+		"u8" => u8::MAX.into(),
+		"u16" => u16::MAX.into(),
+		"u32" => u32::MAX.into(),
+		_ => u64::MAX,
+	};
+
+	let mut discriminants: Vec<(u64, &Ident)> = Vec::with_capacity(data.variants.len());
+	for variant in &data.variants {
+		let mut discriminant = None;
+		for attribute in variant.attrs.iter().filter(|x| x.path().is_ident("ipc")) {
+			let result = attribute.parse_nested_meta(|meta| {
+				if meta.path.is_ident("discriminant") {
+					discriminant = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u64>()?);
+				} else {
+					return Err(meta.error(format!(
+						"unknown variant attribute: {}",
+						meta.path.to_token_stream()
+					)));
+				}
+				Ok(())
+			});
+			if let Err(e) = result {
+				e.span().unwrap().error(e.to_string()).emit();
+			}
+		}
+
+		let discriminant = discriminant
+			.or_else(|| discriminants.last().map_or(Some(0), |x| x.0.checked_add(1)))
+			.filter(|x| *x <= max)
+			.unwrap_or_else(|| {
+				Diagnostic::spanned(
+					variant.span().unwrap(),
+					Level::Error,
+					format!("discriminant doesn't fit in {repr}"),
+				)
+				.emit();
+				0
+			});
+
+		if let Some((_, other)) = discriminants.iter().find(|x| x.0 == discriminant) {
+			Diagnostic::spanned(
+				variant.ident.span().unwrap(),
+				Level::Error,
+				format!("discriminant {discriminant} is already used"),
+			)
+			.span_note(other.span().unwrap(), "previously used here")
+			.emit();
+		}
+
+		discriminants.push((discriminant, &variant.ident));
+	}
+
+	discriminants
+		.into_iter()
+		.map(|(discriminant, ident)| LitInt::new(&format!("{discriminant}{repr}"), ident.span()))
+		.collect()
+}
+
 pub fn derive_read(input: &DeriveInput) -> TokenStream {
 	// Add a bound `T: Read` to every type parameter T.
 	let (generics, additional_where_predicates) = add_trait_bounds(
@@ -18,7 +215,8 @@ pub fn derive_read(input: &DeriveInput) -> TokenStream {
 	let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
 	where_clause.predicates.extend(additional_where_predicates);
 
-	let read_code = read_code(&input.ident, &input.data);
+	let options = ContainerOptions::parse(&input.attrs, &input.data);
+	let read_code = read_code(&input.ident, &input.data, &options);
 
 	let name = &input.ident;
 	quote! {
@@ -26,10 +224,15 @@ pub fn derive_read(input: &DeriveInput) -> TokenStream {
 		impl #impl_generics ::ipc::Read for #name #ty_generics #where_clause {
 			type Error = ::ipc::anyhow::Error;
 
-			async fn read(stream: &mut (impl ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send)) -> ::core::result::Result<Self, <Self as ::ipc::Read>::Error>
+			#[allow(unused_variables, reason = "limits aren't used by types without fields")]
+			async fn read_limited(
+				stream: &mut (impl ::ipc::tokio::io::AsyncRead + ::core::marker::Unpin + ::core::marker::Send),
+				limits: ::ipc::Limits,
+			) -> ::core::result::Result<Self, <Self as ::ipc::Read>::Error>
 			where
 				Self: ::core::marker::Sized,
 			{
+				let limits = ::ipc::Limits::nested(limits)?;
 				#read_code
 			}
 		}
@@ -49,7 +252,8 @@ pub fn derive_write(input: &DeriveInput) -> TokenStream {
 	let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
 	where_clause.predicates.extend(additional_where_predicates);
 
-	let write_code = write_code(&input.data);
+	let options = ContainerOptions::parse(&input.attrs, &input.data);
+	let write_code = write_code(&input.data, &options);
 
 	let name = &input.ident;
 	quote! {
@@ -70,6 +274,111 @@ pub fn derive_write(input: &DeriveInput) -> TokenStream {
 	}
 }
 
+/// Implement `Describe`, with the same encoding as [`derive_read`]
+///
+/// Fields whose type doesn't implement `Describe`, or which use `#[ipc(with)]`, are
+/// described as opaque types.
+pub fn derive_describe(input: &DeriveInput) -> TokenStream {
+	let mut generics = input.generics.clone();
+	for param in generics.type_params_mut() {
+		param.bounds.push(parse_quote!(::ipc::schema::Describe));
+	}
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	let options = ContainerOptions::parse(&input.attrs, &input.data);
+	let extensible = options.extensible;
+	let description = match &input.data {
+		Data::Struct(data) => {
+			let fields = describe_fields(&data.fields, extensible);
+			quote! {
+				::ipc::schema::Type::Struct {
+					name: ::ipc::schema::type_name::<Self>(),
+					fields: #fields,
+					extensible: #extensible,
+				}
+			}
+		}
+		Data::Enum(data) => {
+			let repr = Ident::new(
+				&options.repr.to_string().to_uppercase(),
+				options.repr.span(),
+			);
+			let variants = data
+				.variants
+				.iter()
+				.zip(discriminants(data, &options.repr))
+				.map(|(variant, discriminant)| {
+					let name = variant.ident.to_string();
+					let discriminant: u64 = discriminant.base10_parse().unwrap_or_default();
+					let fields = describe_fields(&variant.fields, extensible);
+					quote! {
+						::ipc::schema::Variant {
+							name: ::std::borrow::ToOwned::to_owned(#name),
+							discriminant: #discriminant,
+							fields: #fields,
+						}
+					}
+				});
+			quote! {
+				::ipc::schema::Type::Enum {
+					name: ::ipc::schema::type_name::<Self>(),
+					repr: ::ipc::schema::Repr::#repr,
+					variants: ::std::vec![#(#variants),*],
+					extensible: #extensible,
+				}
+			}
+		}
+		Data::Union(_) => unimplemented!(),
+	};
+
+	let name = &input.ident;
+	quote! {
+		impl #impl_generics ::ipc::schema::Describe for #name #ty_generics #where_clause {
+			fn describe(types: &mut ::ipc::schema::Types) -> u64 {
+				#[allow(unused_imports)]
+				use ::ipc::__private::{DescribeProbe as _, OpaqueProbe as _};
+
+				types.define::<Self>(|types| #description)
+			}
+		}
+	}
+}
+
+fn describe_fields(fields: &Fields, extensible: bool) -> TokenStream {
+	let options = FieldOptions::parse_all(fields, extensible);
+	let described = fields
+		.iter()
+		.zip(&options)
+		.enumerate()
+		.filter(|(_, (_, options))| !options.skip)
+		.map(|(i, (field, options))| {
+			let name = field
+				.ident
+				.as_ref()
+				.map_or_else(|| i.to_string(), ToString::to_string);
+			let ty = &field.ty;
+			let describe = if options.with.is_some() {
+				quote!(types.opaque::<#ty>())
+			} else {
+				quote!((&::ipc::__private::Probe::<#ty>::new()).describe(types))
+			};
+			let default = options.default;
+			quote! {
+				::ipc::schema::Field {
+					name: ::std::borrow::ToOwned::to_owned(#name),
+					ty: #describe,
+					default: #default,
+				}
+			}
+		});
+
+	match fields {
+		Fields::Named(_) => quote!(::ipc::schema::Fields::Named(::std::vec![#(#described),*])),
+		Fields::Unnamed(_) => quote!(::ipc::schema::Fields::Unnamed(::std::vec![#(#described),*])),
+		Fields::Unit => quote!(::ipc::schema::Fields::Unit),
+	}
+}
+
 fn add_trait_bounds(
 	mut generics: Generics,
 	r#trait: &TokenStream,
@@ -104,31 +413,42 @@ fn add_trait_bounds(
 	(generics, where_predicates)
 }
 
-fn read_code(name: &Ident, data: &Data) -> TokenStream {
+fn read_code(name: &Ident, data: &Data, options: &ContainerOptions) -> TokenStream {
+	let repr = &options.repr;
 	match *data {
 		Data::Struct(ref data) => {
-			let constructor = read_code_for_fields(&parse_quote!(Self), &data.fields);
+			let constructor =
+				read_code_for_fields(&parse_quote!(Self), &data.fields, options.extensible);
 			quote! {
 				::core::result::Result::Ok(#constructor)
 			}
 		}
 		Data::Enum(ref data) => {
-			let recurse = data.variants.iter().enumerate().map(|(i, v)| {
-				let name = &v.ident;
-				let constructor = read_code_for_fields(&parse_quote!(Self::#name), &v.fields);
-				quote! {
-					#i => ::core::result::Result::Ok(#constructor)
-				}
-			});
+			let discriminants = discriminants(data, repr);
+			let recurse = data
+				.variants
+				.iter()
+				.zip(discriminants)
+				.map(|(v, discriminant)| {
+					let name = &v.ident;
+					let constructor = read_code_for_fields(
+						&parse_quote!(Self::#name),
+						&v.fields,
+						options.extensible,
+					);
+					quote! {
+						#discriminant => ::core::result::Result::Ok(#constructor)
+					}
+				});
 			let name = name.to_string();
 			quote! {
-				let discriminant = <u64 as ::ipc::Read>::read(stream).await? as usize;
+				let discriminant = <#repr as ::ipc::Read>::read(stream).await?;
 				match discriminant {
 					#(#recurse,)*
 					value => {
 						::core::result::Result::Err(::ipc::anyhow::Error::from(::ipc::InvalidDiscriminantError {
 							type_name: #name,
-							value,
+							value: value as usize,
 						}))
 					}
 				}
@@ -138,14 +458,42 @@ fn read_code(name: &Ident, data: &Data) -> TokenStream {
 	}
 }
 
-fn read_code_for_fields(this: &Path, fields: &Fields) -> TokenStream {
-	match fields {
+fn read_code_for_field(field: &Field, options: &FieldOptions) -> TokenStream {
+	let ty = &field.ty;
+
+	if options.skip {
+		return quote!(::core::default::Default::default());
+	}
+
+	let read = if let Some(with) = &options.with {
+		quote!(#with::read(stream, limits).await?)
+	} else {
+		quote!(<#ty as ::ipc::Read>::read_limited(stream, limits).await?)
+	};
+
+	if options.default {
+		quote! {
+			if stream.limit() == 0 {
+				::core::default::Default::default()
+			} else {
+				#read
+			}
+		}
+	} else {
+		read
+	}
+}
+
+fn read_code_for_fields(this: &Path, fields: &Fields, extensible: bool) -> TokenStream {
+	let options = FieldOptions::parse_all(fields, extensible);
+
+	let constructor = match fields {
 		Fields::Named(fields) => {
-			let recurse = fields.named.iter().map(|f| {
+			let recurse = fields.named.iter().zip(&options).map(|(f, options)| {
 				let name = &f.ident;
-				let ty = &f.ty;
+				let code = read_code_for_field(f, options);
 				quote! {
-					#name: <#ty as ::ipc::Read>::read(stream).await?
+					#name: #code
 				}
 			});
 			quote! {
@@ -155,24 +503,45 @@ fn read_code_for_fields(this: &Path, fields: &Fields) -> TokenStream {
 			}
 		}
 		Fields::Unnamed(fields) => {
-			let recurse = fields.unnamed.iter().map(|f| {
-				let ty = &f.ty;
-				quote! {
-					<#ty as ::ipc::Read>::read(stream).await?
-				}
-			});
+			let recurse = fields
+				.unnamed
+				.iter()
+				.zip(&options)
+				.map(|(f, options)| read_code_for_field(f, options));
 			quote! {
 				#this(#(#recurse,)*)
 			}
 		}
 		Fields::Unit => this.to_token_stream(),
+	};
+
+	if extensible {
+		// fields are prefixed by their total length, so that missing ones can be detected
+		// and unknown ones can be skipped
+		quote! {
+			{
+				let length = <u64 as ::ipc::Read>::read(stream).await?;
+				let mut body = ::ipc::tokio::io::AsyncReadExt::take(&mut *stream, length);
+				let value = {
+					let stream = &mut body;
+					#constructor
+				};
+				::ipc::tokio::io::copy(&mut body, &mut ::ipc::tokio::io::sink()).await?;
+				value
+			}
+		}
+	} else {
+		constructor
 	}
 }
 
-fn write_code(data: &Data) -> TokenStream {
+fn write_code(data: &Data, options: &ContainerOptions) -> TokenStream {
+	let repr = &options.repr;
+	let extensible = options.extensible;
 	match data {
 		Data::Struct(data) => {
-			let code = write_code_for_fields(&data.fields, true);
+			let options = FieldOptions::parse_all(&data.fields, extensible);
+			let code = write_code_for_fields(&data.fields, &options, true, extensible);
 			quote! {
 				#code;
 				::core::result::Result::Ok(())
@@ -184,17 +553,23 @@ fn write_code(data: &Data) -> TokenStream {
 					::core::unreachable!("Cannot write empty enum because there is no valid discriminant");
 				};
 			}
-			let recurse = data.variants.iter().enumerate().map(|(i, v)| {
-				let name = &v.ident;
-				let fields = fields_to_pattern(&v.fields);
-				let code = write_code_for_fields(&v.fields, false);
-				quote! {
-					Self::#name #fields => {
-						::ipc::Write::write(&(#i as u64), stream).await?;
-						#code;
+			let discriminants = discriminants(data, repr);
+			let recurse = data
+				.variants
+				.iter()
+				.zip(discriminants)
+				.map(|(v, discriminant)| {
+					let name = &v.ident;
+					let options = FieldOptions::parse_all(&v.fields, extensible);
+					let fields = fields_to_pattern(&v.fields, &options);
+					let code = write_code_for_fields(&v.fields, &options, false, extensible);
+					quote! {
+						Self::#name #fields => {
+							::ipc::Write::write(&#discriminant, stream).await?;
+							#code;
+						}
 					}
-				}
-			});
+				});
 			quote! {
 				#[allow(redundant_semicolons)]
 				match self {
@@ -208,20 +583,31 @@ fn write_code(data: &Data) -> TokenStream {
 	}
 }
 
-fn write_code_for_fields(fields: &Fields, has_self: bool) -> TokenStream {
-	match fields {
+fn write_code_for_field(value: &TokenStream, options: &FieldOptions) -> TokenStream {
+	if options.skip {
+		TokenStream::new()
+	} else if let Some(with) = &options.with {
+		quote!(#with::write(#value, stream).await?)
+	} else {
+		quote!(::ipc::Write::write(#value, stream).await?)
+	}
+}
+
+fn write_code_for_fields(
+	fields: &Fields,
+	options: &[FieldOptions],
+	has_self: bool,
+	extensible: bool,
+) -> TokenStream {
+	let code = match fields {
 		Fields::Named(fields) => {
-			let recurse = fields.named.iter().map(|f| {
+			let recurse = fields.named.iter().zip(options).map(|(f, options)| {
 				let name = &f.ident;
 
 				if has_self {
-					quote! {
-						::ipc::Write::write(&self.#name, stream).await?
-					}
+					write_code_for_field(&quote!(&self.#name), options)
 				} else {
-					quote! {
-						::ipc::Write::write(#name, stream).await?
-					}
+					write_code_for_field(&quote!(#name), options)
 				}
 			});
 			quote! {
@@ -229,44 +615,74 @@ fn write_code_for_fields(fields: &Fields, has_self: bool) -> TokenStream {
 			}
 		}
 		Fields::Unnamed(fields) => {
-			let recurse = fields.unnamed.iter().enumerate().map(|(i, f)| {
-				if has_self {
-					let index = Index::from(i);
-					quote! {
-						::ipc::Write::write(&self.#index, stream).await?
-					}
-				} else {
-					let name = Ident::new(&format!("f{i}"), f.span());
-					quote! {
-						::ipc::Write::write(#name, stream).await?
-					}
-				}
-			});
+			let recurse =
+				fields
+					.unnamed
+					.iter()
+					.zip(options)
+					.enumerate()
+					.map(|(i, (f, options))| {
+						if has_self {
+							let index = Index::from(i);
+							write_code_for_field(&quote!(&self.#index), options)
+						} else {
+							let name = Ident::new(&format!("f{i}"), f.span());
+							write_code_for_field(&quote!(#name), options)
+						}
+					});
 			quote! {
 				#(#recurse;)*
 			}
 		}
 		Fields::Unit => TokenStream::new(),
+	};
+
+	if extensible {
+		// see `read_code_for_fields`
+		quote! {
+			let mut body = ::std::vec::Vec::<u8>::new();
+			{
+				let stream = &mut body;
+				#code
+			}
+			::ipc::Write::write(&(body.len() as u64), stream).await?;
+			::ipc::tokio::io::AsyncWriteExt::write_all(stream, &body).await?
+		}
+	} else {
+		code
 	}
 }
 
-fn fields_to_pattern(fields: &Fields) -> TokenStream {
+fn fields_to_pattern(fields: &Fields, options: &[FieldOptions]) -> TokenStream {
+	// skipped fields aren't bound, to avoid unused variable warnings
 	match fields {
 		Fields::Named(fields) => {
-			let recurse = fields
-				.named
-				.iter()
-				.map(|f| f.ident.as_ref().unwrap().clone());
+			let recurse = fields.named.iter().zip(options).map(|(f, options)| {
+				let name = f.ident.as_ref().unwrap();
+				if options.skip {
+					quote!(#name: _)
+				} else {
+					quote!(#name)
+				}
+			});
 			quote! {
 				{ #(#recurse,)* }
 			}
 		}
 		Fields::Unnamed(fields) => {
-			let recurse = fields
-				.unnamed
-				.iter()
-				.enumerate()
-				.map(|(i, f)| Ident::new(&format!("f{i}"), f.span()));
+			let recurse =
+				fields
+					.unnamed
+					.iter()
+					.zip(options)
+					.enumerate()
+					.map(|(i, (f, options))| {
+						if options.skip {
+							quote!(_)
+						} else {
+							Ident::new(&format!("f{i}"), f.span()).into_token_stream()
+						}
+					});
 			quote! {
 				(#(#recurse,)*)
 			}
@@ -244,3 +244,268 @@ async fn generic_enum() {
 
 	assert_eq!(x, result);
 }
+
+#[tokio::test]
+async fn skipped_field() {
+	#[derive(Read, Write, Debug, PartialEq)]
+	struct Cached {
+		id: u32,
+		#[ipc(skip)]
+		cache: Option<String>,
+	}
+
+	#[derive(Read, Write, Debug, PartialEq)]
+	enum Event {
+		Tick(#[ipc(skip)] u64, u8),
+	}
+
+	let x = Cached {
+		id: 42,
+		cache: Some("cached".to_string()),
+	};
+
+	let mut writer = BufWriter::new(Vec::new());
+	x.write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let data = writer.into_inner();
+	assert_eq!(data, 42u32.to_be_bytes());
+
+	let mut reader = BufReader::new(&data[..]);
+	let result = Cached::read(&mut reader).await.unwrap();
+
+	assert_eq!(
+		result,
+		Cached {
+			id: 42,
+			cache: None
+		}
+	);
+
+	let mut writer = BufWriter::new(Vec::new());
+	Event::Tick(1000, 3).write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let mut data = &writer.into_inner()[..];
+	let mut reader = BufReader::new(&mut data);
+	let result = Event::read(&mut reader).await.unwrap();
+
+	assert_eq!(result, Event::Tick(0, 3));
+}
+
+#[tokio::test]
+async fn field_with() {
+	mod as_string {
+		use ipc::{Limits, Read as _, Write as _};
+		use tokio::io::{AsyncRead, AsyncWrite};
+
+		pub async fn read(
+			stream: &mut (impl AsyncRead + Unpin + Send),
+			limits: Limits,
+		) -> ipc::anyhow::Result<u32> {
+			Ok(String::read_limited(stream, limits).await?.parse()?)
+		}
+
+		pub async fn write(
+			value: &u32,
+			stream: &mut (impl AsyncWrite + Unpin + Send),
+		) -> ipc::anyhow::Result<()> {
+			Ok(value.to_string().write(stream).await?)
+		}
+	}
+
+	#[derive(Read, Write, Debug, PartialEq)]
+	struct Version {
+		#[ipc(with = as_string)]
+		major: u32,
+		minor: u32,
+	}
+
+	let x = Version {
+		major: 12,
+		minor: 3,
+	};
+
+	let mut writer = BufWriter::new(Vec::new());
+	x.write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let data = writer.into_inner();
+	assert_eq!(&data[..10], b"\0\0\0\0\0\0\0\x0212");
+
+	let mut reader = BufReader::new(&data[..]);
+	let result = Version::read(&mut reader).await.unwrap();
+
+	assert_eq!(x, result);
+}
+
+#[tokio::test]
+async fn explicit_discriminants() {
+	#[derive(Read, Write, Debug, PartialEq)]
+	#[ipc(repr = u8)]
+	enum Status {
+		#[ipc(discriminant = 2)]
+		Running,
+		#[ipc(discriminant = 0)]
+		Stopped,
+		Crashed,
+		#[ipc(discriminant = 10)]
+		Restarting(u8),
+	}
+
+	let inputs = [
+		(Status::Running, vec![2]),
+		(Status::Stopped, vec![0]),
+		(Status::Crashed, vec![1]),
+		(Status::Restarting(5), vec![10, 5]),
+	];
+
+	for (x, expected) in inputs {
+		let mut writer = BufWriter::new(Vec::new());
+		x.write(&mut writer).await.unwrap();
+		writer.flush().await.unwrap();
+
+		let data = writer.into_inner();
+		assert_eq!(data, expected);
+
+		let mut reader = BufReader::new(&data[..]);
+		let result = Status::read(&mut reader).await.unwrap();
+
+		assert_eq!(x, result);
+	}
+
+	let data = [3];
+	let mut reader = BufReader::new(&data[..]);
+	assert!(Status::read(&mut reader).await.is_err());
+}
+
+#[tokio::test]
+async fn default_fields() {
+	// the first version doesn't have default fields yet
+	mod v1 {
+		#[derive(ipc_macros::Read, ipc_macros::Write, Debug, PartialEq)]
+		#[ipc(extensible)]
+		pub struct Settings {
+			pub name: String,
+		}
+	}
+
+	mod v2 {
+		#[derive(ipc_macros::Read, ipc_macros::Write, Debug, PartialEq)]
+		#[ipc(extensible)]
+		pub struct Settings {
+			pub name: String,
+			#[ipc(default)]
+			pub verbose: bool,
+			#[ipc(default)]
+			pub retries: Option<u8>,
+		}
+	}
+
+	#[derive(Read, Write, Debug, PartialEq)]
+	struct Pair<T>(T, u16);
+
+	// an older peer sends a value to a newer one
+	let x = Pair(
+		v1::Settings {
+			name: "old".to_string(),
+		},
+		7,
+	);
+
+	let mut writer = BufWriter::new(Vec::new());
+	x.write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let mut data = &writer.into_inner()[..];
+	let mut reader = BufReader::new(&mut data);
+	let result = Pair::<v2::Settings>::read(&mut reader).await.unwrap();
+
+	assert_eq!(
+		result,
+		Pair(
+			v2::Settings {
+				name: "old".to_string(),
+				verbose: false,
+				retries: None,
+			},
+			7
+		)
+	);
+
+	// a newer peer sends a value to an older one
+	let x = Pair(
+		v2::Settings {
+			name: "new".to_string(),
+			verbose: true,
+			retries: Some(3),
+		},
+		8,
+	);
+
+	let mut writer = BufWriter::new(Vec::new());
+	x.write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let mut data = &writer.into_inner()[..];
+	let mut reader = BufReader::new(&mut data);
+	let result = Pair::<v1::Settings>::read(&mut reader).await.unwrap();
+
+	assert_eq!(
+		result,
+		Pair(
+			v1::Settings {
+				name: "new".to_string(),
+			},
+			8
+		)
+	);
+}
+
+#[tokio::test]
+async fn extensible_variants() {
+	mod v1 {
+		#[derive(ipc_macros::Read, ipc_macros::Write, Debug, PartialEq)]
+		#[ipc(extensible)]
+		pub enum Event {
+			Started,
+			Stopped(String),
+		}
+	}
+
+	mod v2 {
+		#[derive(ipc_macros::Read, ipc_macros::Write, Debug, PartialEq)]
+		#[ipc(extensible)]
+		pub enum Event {
+			Started,
+			Stopped(String, #[ipc(default)] Option<i32>),
+		}
+	}
+
+	let mut writer = BufWriter::new(Vec::new());
+	v2::Event::Stopped("a".to_string(), Some(1))
+		.write(&mut writer)
+		.await
+		.unwrap();
+	v1::Event::Stopped("b".to_string())
+		.write(&mut writer)
+		.await
+		.unwrap();
+	v2::Event::Started.write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let mut data = &writer.into_inner()[..];
+	let mut reader = BufReader::new(&mut data);
+	assert_eq!(
+		v1::Event::read(&mut reader).await.unwrap(),
+		v1::Event::Stopped("a".to_string())
+	);
+	assert_eq!(
+		v2::Event::read(&mut reader).await.unwrap(),
+		v2::Event::Stopped("b".to_string(), None)
+	);
+	assert_eq!(
+		v1::Event::read(&mut reader).await.unwrap(),
+		v1::Event::Started
+	);
+}
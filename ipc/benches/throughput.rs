@@ -0,0 +1,128 @@
+//! Throughput of many concurrent calls and streams sharing one connection
+
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use futures::{Stream, StreamExt, future::join_all, stream};
+use ipc::{
+	__private::{Route, Routes},
+	LimitExceededError,
+};
+use tokio::{io::DuplexStream, runtime::Runtime, spawn};
+
+#[ipc::protocol]
+pub trait Bench {
+	async fn echo(&self, value: Vec<u8>) -> Vec<u8>;
+
+	#[stream]
+	async fn count(&self, to: u32) -> u32;
+}
+
+struct App;
+
+impl BenchServer for App {
+	async fn echo(&self, value: Vec<u8>) -> Vec<u8> {
+		value
+	}
+
+	async fn count(&self, to: u32) -> impl Stream<Item = u32> + Send {
+		stream::iter(0..to)
+	}
+}
+
+type Client = BenchClient<DuplexStream, DuplexStream>;
+
+/// Start a server on the runtime, and connect a client to it
+fn connect(runtime: &Runtime) -> Client {
+	let (client, server) = ipc::testing::pair();
+	runtime.spawn(async move { App.serve_connection(server.0, server.1).await });
+	runtime
+		.block_on(BenchClient::new_with_transport(client.0, client.1))
+		.unwrap()
+}
+
+fn concurrent_calls(c: &mut Criterion) {
+	let runtime = Runtime::new().unwrap();
+	let client = connect(&runtime);
+	let mut group = c.benchmark_group("concurrent_calls");
+
+	for (calls, size) in [(1, 64), (16, 64), (256, 64), (16, 32 << 10)] {
+		group.throughput(Throughput::Bytes((calls * size) as u64));
+		let value = vec![0u8; size];
+		group.bench_with_input(
+			BenchmarkId::new(format!("{size}B"), calls),
+			&calls,
+			|b, &calls| {
+				b.to_async(&runtime).iter(|| {
+					join_all((0..calls).map(|_| async {
+						black_box(client.echo(value.clone()).await.unwrap());
+					}))
+				});
+			},
+		);
+	}
+	group.finish();
+}
+
+fn concurrent_streams(c: &mut Criterion) {
+	const VALUES: u32 = 100;
+
+	let runtime = Runtime::new().unwrap();
+	let client = connect(&runtime);
+	let mut group = c.benchmark_group("concurrent_streams");
+
+	for streams in [1, 16, 64] {
+		group.throughput(Throughput::Elements(u64::from(streams * VALUES)));
+		group.bench_with_input(
+			BenchmarkId::from_parameter(streams),
+			&streams,
+			|b, &streams| {
+				b.to_async(&runtime).iter(|| {
+					join_all((0..streams).map(|_| async {
+						let values = client.count(VALUES).await.unwrap();
+						black_box(values.map(Result::unwrap).count().await);
+					}))
+				});
+			},
+		);
+	}
+	group.finish();
+}
+
+/// Calls adding their route and receiving their response from many tasks at once,
+/// without the connection, which would otherwise hide the cost of routing
+fn routing(c: &mut Criterion) {
+	const CALLS: u64 = 1000;
+
+	let runtime = Runtime::new().unwrap();
+	let mut group = c.benchmark_group("routing");
+	for tasks in [1, 4, 16] {
+		group.throughput(Throughput::Elements(tasks * CALLS));
+		group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+			b.to_async(&runtime).iter(|| async move {
+				let routes = Routes::default();
+				let tasks = (0..tasks).map(|task| {
+					let routes = routes.clone();
+					spawn(async move {
+						for call_id in (task * CALLS)..((task + 1) * CALLS) {
+							let (route, mut rx) = Route::response();
+							routes.insert(call_id, route);
+							routes.dispatch(call_id, Err(LimitExceededError::Depth { max: 0 }));
+							black_box(rx.try_recv().unwrap());
+						}
+					})
+				});
+				for result in join_all(tasks).await {
+					result.unwrap();
+				}
+			});
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, concurrent_calls, concurrent_streams, routing);
+criterion_main!(benches);
@@ -0,0 +1,477 @@
+//! Capture of the frames of every connection, to debug protocols
+//!
+//! When the `IPC_CAPTURE` environment variable is set to a path, every connection of
+//! the process appends the frames it sends and receives to that file, starting with
+//! the handshake. Several processes can capture to the same file, since every
+//! [`Record`] contains the id of its process and connection.
+//!
+//! The file is read with [`read`], and its records are printed with a [`Printer`]
+//! using the [`Schema`] of the protocol.
+
+use std::{
+	collections::HashMap,
+	env,
+	fmt::Write as _,
+	fs::{self, File, OpenOptions},
+	io::Write as _,
+	path::Path,
+	process,
+	sync::{
+		LazyLock, Mutex, PoisonError,
+		atomic::{AtomicU64, Ordering},
+	},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, bail, ensure};
+use futures::{FutureExt, executor::block_on};
+use log::{error, warn};
+
+use crate::{
+	LimitExceededError, Limits, Read, Write,
+	protocol::{
+		CallContext, Handshake, decode,
+		discriminant::{request, response, stream_packet},
+		split_frame,
+	},
+	schema::{Argument, Schema},
+};
+
+/// Environment variable containing the path of the capture file
+pub const CAPTURE_VAR: &str = "IPC_CAPTURE";
+
+/// The capture file, if frames are captured
+static CAPTURE: LazyLock<Option<Mutex<File>>> = LazyLock::new(|| {
+	let path = env::var_os(CAPTURE_VAR)?;
+	// records are written with a single write, so processes can share the file
+	let file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(&path)
+		.inspect_err(|e| {
+			error!(
+				"Couldn't open the capture file {}: {e}",
+				Path::new(&path).display()
+			);
+		})
+		.ok()?;
+	Some(Mutex::new(file))
+});
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+/// Side of the connection that captured a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Read, Write)]
+pub enum Side {
+	Client,
+	Server,
+}
+
+/// Whether a captured frame was sent or received
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Read, Write)]
+pub enum Direction {
+	Sent,
+	Received,
+}
+
+/// A captured frame
+#[derive(Debug, Clone, Read, Write)]
+pub struct Record {
+	/// Microseconds since the unix epoch
+	pub timestamp: u64,
+	/// Id of the process that captured the frame
+	pub pid: u32,
+	/// Id of the connection in its process
+	pub connection: u64,
+	pub side: Side,
+	pub direction: Direction,
+	/// Id of the call the frame belongs to, [`None`] for the handshake
+	pub call_id: Option<u64>,
+	/// The whole frame, starting with the call id
+	pub frame: Vec<u8>,
+}
+
+impl Record {
+	/// Whether the frame was sent by the client
+	pub fn is_clientbound(&self) -> bool {
+		(self.side == Side::Client) == (self.direction == Direction::Sent)
+	}
+}
+
+/// Records the frames going in one direction of a connection
+#[derive(Debug)]
+pub(crate) struct Tap {
+	connection: u64,
+	side: Side,
+	direction: Direction,
+	/// Whether the handshake frame was recorded
+	handshake_done: bool,
+}
+
+impl Tap {
+	/// Taps for the sent and received frames of a new connection, if frames are captured
+	pub(crate) fn new_pair(side: Side) -> Option<(Self, Self)> {
+		CAPTURE.as_ref()?;
+		let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+		let tap = |direction| Self {
+			connection,
+			side,
+			direction,
+			handshake_done: false,
+		};
+		Some((tap(Direction::Sent), tap(Direction::Received)))
+	}
+
+	/// Append a frame to the capture file
+	///
+	/// Errors are logged, they don't affect the connection.
+	pub(crate) fn record(&mut self, frame: &[u8]) {
+		let Some(capture) = CAPTURE.as_ref() else {
+			return;
+		};
+
+		let call_id = if self.handshake_done {
+			split_frame(frame).ok().map(|(call_id, _)| call_id)
+		} else {
+			self.handshake_done = true;
+			None
+		};
+		let record = Record {
+			timestamp: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map_or(0, |time| time.as_micros() as u64),
+			pid: process::id(),
+			connection: self.connection,
+			side: self.side,
+			direction: self.direction,
+			call_id,
+			frame: frame.to_vec(),
+		};
+
+		// the length of the record is written over the first 8 bytes
+		let mut bytes = vec![0; 8];
+		// writing to a vector never waits
+		let Some(Ok(())) = Write::write(&record, &mut bytes).now_or_never() else {
+			warn!("Couldn't encode a captured frame");
+			return;
+		};
+		let len = bytes.len() as u64 - 8;
+		bytes[..8].copy_from_slice(&len.to_be_bytes());
+
+		let mut file = capture.lock().unwrap_or_else(PoisonError::into_inner);
+		if let Err(e) = file.write_all(&bytes) {
+			warn!("Couldn't write a captured frame: {e}");
+		}
+	}
+}
+
+/// Read the records of a capture file
+///
+/// # Errors
+///
+/// This function will return an error if the file can't be read, or contains an
+/// invalid record.
+pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Vec<Record>> {
+	let bytes = fs::read(path)?;
+	let mut rest = bytes.as_slice();
+	let mut records = Vec::new();
+	while !rest.is_empty() {
+		let (len, tail) = split_u64(rest)?;
+		let len = usize::try_from(len)?;
+		ensure!(
+			tail.len() >= len,
+			"the capture file ends in the middle of a record"
+		);
+		let (record, tail) = tail.split_at(len);
+		records.push(decode_value(record)?);
+		rest = tail;
+	}
+	Ok(records)
+}
+
+/// Prints captured frames as text, using the schema of their protocol
+///
+/// Responses are decoded with the method of the call they answer, so the records of
+/// a connection must be printed in order, starting with the call.
+#[derive(Debug)]
+pub struct Printer<'a> {
+	schema: &'a Schema,
+	/// Running calls, by process, connection and call id
+	calls: HashMap<(u32, u64, u64), Call>,
+	/// Timestamp of the first printed record
+	start: Option<u64>,
+}
+
+/// What a call is waiting for
+#[derive(Debug, Clone, Copy)]
+enum Call {
+	Method(usize),
+	Signal(usize),
+	Describe,
+}
+
+impl<'a> Printer<'a> {
+	pub fn new(schema: &'a Schema) -> Self {
+		Self {
+			schema,
+			calls: HashMap::new(),
+			start: None,
+		}
+	}
+
+	/// Describe a record in a single line
+	///
+	/// Frames that can't be decoded are printed in hexadecimal, with the reason.
+	pub fn print(&mut self, record: &Record) -> String {
+		let start = *self.start.get_or_insert(record.timestamp);
+		let elapsed = record.timestamp.saturating_sub(start);
+		let side = match record.side {
+			Side::Client => "client",
+			Side::Server => "server",
+		};
+		let arrow = match record.direction {
+			Direction::Sent => "->",
+			Direction::Received => "<-",
+		};
+		let call = record
+			.call_id
+			.map_or_else(|| "handshake".to_owned(), |call_id| format!("#{call_id}"));
+		let payload = self.payload(record).unwrap_or_else(|e| {
+			let mut text = format!("undecodable frame ({e}):");
+			for byte in &record.frame {
+				let _ = write!(text, " {byte:02x}");
+			}
+			text
+		});
+
+		format!(
+			"{:4}.{:06} {side} {}:{} {arrow} {call} {payload}",
+			elapsed / 1_000_000,
+			elapsed % 1_000_000,
+			record.pid,
+			record.connection,
+		)
+	}
+
+	fn payload(&mut self, record: &Record) -> anyhow::Result<String> {
+		let Some(call_id) = record.call_id else {
+			let handshake = decode_value::<Handshake>(&record.frame)?;
+			return Ok(format!("fingerprint {:016X}", handshake.fingerprint));
+		};
+
+		let (_, payload) = split_frame(&record.frame)?;
+		let key = (record.pid, record.connection, call_id);
+		if record.is_clientbound() {
+			self.request(key, payload)
+		} else {
+			self.response(key, payload)
+		}
+	}
+
+	/// Describe a [`Request`](crate::protocol::Request)
+	fn request(&mut self, key: (u32, u64, u64), payload: &[u8]) -> anyhow::Result<String> {
+		let (variant, rest) = split_u64(payload)?;
+		Ok(match variant {
+			request::CALL => self.call(key, rest, false)?,
+			request::CANCEL => {
+				self.calls.remove(&key);
+				"cancel".to_owned()
+			}
+			request::STREAM => self.input(rest)?,
+			request::SUBSCRIBE => {
+				let (index, _) = split_u64(rest)?;
+				let index = usize::try_from(index)?;
+				let signal = self
+					.schema
+					.signals
+					.get(index)
+					.with_context(|| format!("unknown signal {index}"))?;
+				self.calls.insert(key, Call::Signal(index));
+				format!("subscribe {}", signal.name)
+			}
+			request::CONTEXT_CALL => self.call(key, rest, true)?,
+			request::DESCRIBE => {
+				self.calls.insert(key, Call::Describe);
+				"describe".to_owned()
+			}
+			_ => bail!("unknown request {variant}"),
+		})
+	}
+
+	fn call(
+		&mut self,
+		key: (u32, u64, u64),
+		payload: &[u8],
+		with_context: bool,
+	) -> anyhow::Result<String> {
+		let (index, rest) = split_u64(payload)?;
+		let index = usize::try_from(index)?;
+		let method = self
+			.schema
+			.methods
+			.get(index)
+			.with_context(|| format!("unknown method {index}"))?;
+		let types: Vec<_> = method.arguments.iter().map(|arg| arg.ty).collect();
+		let (values, rest) = self.schema.decode_prefix(&types, rest, file_limits())?;
+
+		let mut text = format!(
+			"call {}",
+			arguments(&method.name, &method.arguments, values)
+		);
+
+		if with_context {
+			let context = decode_value::<CallContext>(rest)?;
+			if let Some(timeout) = context.timeout {
+				let _ = write!(text, " timeout {timeout}ms");
+			}
+			if let Some(trace) = context.trace {
+				let _ = write!(text, " trace {:032x}", trace.trace_id);
+			}
+		} else {
+			ensure!(rest.is_empty(), "{} bytes left after the call", rest.len());
+		}
+
+		self.calls.insert(key, Call::Method(index));
+		Ok(text)
+	}
+
+	/// Describe a packet of an input stream
+	fn input(&self, payload: &[u8]) -> anyhow::Result<String> {
+		let (index, rest) = split_u64(payload)?;
+		let (method, ty) = usize::try_from(index)
+			.ok()
+			.and_then(|index| {
+				self.schema
+					.methods
+					.iter()
+					.filter_map(|method| Some((method, method.input_stream?)))
+					.nth(index)
+			})
+			.with_context(|| format!("unknown input stream {index}"))?;
+
+		let (packet, rest) = split_u64(rest)?;
+		Ok(match packet {
+			stream_packet::VALUE => format!(
+				"input of {}: {}",
+				method.name,
+				self.schema.decode(ty, rest, file_limits())?
+			),
+			stream_packet::END_OF_STREAM => format!("end of the input of {}", method.name),
+			_ => bail!("invalid input packet {packet}"),
+		})
+	}
+
+	/// Describe a [`Response`](crate::protocol::Response)
+	fn response(&mut self, key: (u32, u64, u64), payload: &[u8]) -> anyhow::Result<String> {
+		let (variant, rest) = split_u64(payload)?;
+		match variant {
+			response::VALUE => {}
+			response::INVALID_CALL => {
+				self.calls.remove(&key);
+				let reason = decode_value::<String>(rest)?;
+				return Ok(format!("invalid call: {reason}"));
+			}
+			response::LIMIT_EXCEEDED => {
+				self.calls.remove(&key);
+				let error = decode_value::<LimitExceededError>(rest)?;
+				return Ok(format!("limit exceeded: {error}"));
+			}
+			response::UNAUTHORIZED => return Ok("unauthorized".to_owned()),
+			response::RATE_LIMITED => return Ok("rate limited".to_owned()),
+			response::SHUTTING_DOWN => {
+				self.calls.remove(&key);
+				return Ok("shutting down".to_owned());
+			}
+			_ => bail!("unknown response {variant}"),
+		}
+
+		let call = *self
+			.calls
+			.get(&key)
+			.context("response to a call that wasn't captured")?;
+		match call {
+			Call::Describe => {
+				self.calls.remove(&key);
+				Ok(format!("schema ({} bytes)", rest.len()))
+			}
+			Call::Method(index) => {
+				let method = &self.schema.methods[index];
+				if !method.stream {
+					self.calls.remove(&key);
+					let value = self.schema.decode(method.output, rest, file_limits())?;
+					return Ok(format!("{} returned {value}", method.name));
+				}
+
+				let (packet, rest) = split_u64(rest)?;
+				match packet {
+					stream_packet::VALUE => {
+						let value = self.schema.decode(method.output, rest, file_limits())?;
+						Ok(format!("{} value {value}", method.name))
+					}
+					stream_packet::ERROR => {
+						self.calls.remove(&key);
+						let ty = method
+							.early_error
+							.context("the method has no early error")?;
+						let error = self.schema.decode(ty, rest, file_limits())?;
+						Ok(format!("{} failed with {error}", method.name))
+					}
+					stream_packet::END_OF_STREAM => {
+						self.calls.remove(&key);
+						Ok(format!("end of {}", method.name))
+					}
+					_ => bail!("invalid stream packet {packet}"),
+				}
+			}
+			Call::Signal(index) => {
+				let signal = &self.schema.signals[index];
+				let (packet, rest) = split_u64(rest)?;
+				match packet {
+					stream_packet::VALUE => {
+						let types: Vec<_> = signal.arguments.iter().map(|arg| arg.ty).collect();
+						let values = self.schema.decode_all(&types, rest, file_limits())?;
+						Ok(arguments(&signal.name, &signal.arguments, values))
+					}
+					stream_packet::END_OF_STREAM => {
+						self.calls.remove(&key);
+						Ok(format!("end of {}", signal.name))
+					}
+					_ => bail!("invalid stream packet {packet}"),
+				}
+			}
+		}
+	}
+}
+
+/// Write a call of a method or an emission of a signal, like `name(a: 1, b: 2)`
+fn arguments(name: &str, arguments: &[Argument], values: Vec<String>) -> String {
+	let mut text = format!("{name}(");
+	for (i, (arg, value)) in arguments.iter().zip(values).enumerate() {
+		let separator = if i == 0 { "" } else { ", " };
+		let _ = write!(text, "{separator}{}: {value}", arg.name);
+	}
+	text.push(')');
+	text
+}
+
+/// Limits for decoding captured values, which come from a trusted file
+fn file_limits() -> Limits {
+	Limits::default()
+		.with_max_length(usize::MAX)
+		.with_max_string_bytes(usize::MAX)
+}
+
+fn decode_value<T: Read>(bytes: &[u8]) -> anyhow::Result<T>
+where
+	anyhow::Error: From<T::Error>,
+{
+	Ok(block_on(decode(bytes, file_limits()))?)
+}
+
+/// Split the big-endian integer at the start of `bytes`, like a discriminant or a length
+fn split_u64(bytes: &[u8]) -> anyhow::Result<(u64, &[u8])> {
+	let (value, rest) = bytes
+		.split_first_chunk()
+		.context("unexpected end of the frame")?;
+	Ok((u64::from_be_bytes(*value), rest))
+}
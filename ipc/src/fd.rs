@@ -0,0 +1,398 @@
+//! Sending file descriptors over unix sockets
+//!
+//! An [`Fd`] is encoded as its index in the file descriptors of the frame containing
+//! it. These are sent as `SCM_RIGHTS` ancillary data with the first bytes of the frame,
+//! which requires the connection to use [`UnixReadHalf`] and [`UnixWriteHalf`].
+
+use std::{
+	cell::RefCell,
+	io::{self, ErrorKind, IoSlice, IoSliceMut},
+	mem::MaybeUninit,
+	os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+	pin::Pin,
+	task::{Context, Poll, ready},
+};
+
+use anyhow::anyhow;
+use rustix::net::{
+	RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, ReturnFlags, SendAncillaryBuffer,
+	SendAncillaryMessage, SendFlags, recvmsg, sendmsg,
+};
+use tokio::{
+	io::{AsyncRead, AsyncWrite, Interest, ReadBuf},
+	net::{
+		UnixStream,
+		unix::{OwnedReadHalf, OwnedWriteHalf},
+	},
+	task_local,
+};
+
+use crate::{
+	Limits, Read, Write,
+	schema::{Describe, Type, Types, type_name},
+};
+
+/// Maximum number of file descriptors in a frame, `SCM_MAX_FD` in linux
+const MAX_FDS: usize = 253;
+
+/// Size of the ancillary data of a frame with [`MAX_FDS`] file descriptors
+const CONTROL_SIZE: usize = rustix::cmsg_space!(ScmRights(MAX_FDS));
+
+task_local! {
+	/// File descriptors of the frame being sent, sent with its first write
+	static SENT: RefCell<Vec<OwnedFd>>;
+	/// File descriptors of the frame being received or decoded, taken by its [`Fd`]s
+	static RECEIVED: RefCell<Vec<Option<OwnedFd>>>;
+}
+
+/// A file descriptor sent to the other side of the connection
+///
+/// The receiver gets a new file descriptor referring to the same open file, like
+/// with `dup`. Only connections through a unix socket can send them, and they can't
+/// be sent in signals. At most 253 of them can be sent in a single packet.
+#[derive(Debug)]
+pub struct Fd(OwnedFd);
+
+impl Fd {
+	pub fn new(fd: impl Into<OwnedFd>) -> Self {
+		Self(fd.into())
+	}
+
+	#[must_use]
+	pub fn into_inner(self) -> OwnedFd {
+		self.0
+	}
+}
+
+impl From<OwnedFd> for Fd {
+	fn from(value: OwnedFd) -> Self {
+		Self(value)
+	}
+}
+
+impl From<Fd> for OwnedFd {
+	fn from(value: Fd) -> Self {
+		value.0
+	}
+}
+
+impl AsFd for Fd {
+	fn as_fd(&self) -> BorrowedFd<'_> {
+		self.0.as_fd()
+	}
+}
+
+impl AsRawFd for Fd {
+	fn as_raw_fd(&self) -> RawFd {
+		self.0.as_raw_fd()
+	}
+}
+
+impl Read for Fd {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let index = u32::read_limited(stream, limits).await?;
+		RECEIVED
+			.try_with(|fds| {
+				fds.borrow_mut()
+					.get_mut(index as usize)
+					.and_then(Option::take)
+			})
+			.ok()
+			.flatten()
+			.map(Self)
+			.ok_or_else(|| anyhow!("file descriptor {index} wasn't received with the packet"))
+	}
+}
+
+impl Write for Fd {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		let fd = self.0.try_clone()?;
+		let index = SENT
+			.try_with(|fds| {
+				let mut fds = fds.borrow_mut();
+				if fds.len() >= MAX_FDS {
+					return Err(io::Error::new(
+						ErrorKind::InvalidInput,
+						format!("more than {MAX_FDS} file descriptors in a packet"),
+					));
+				}
+				fds.push(fd);
+				Ok(fds.len() - 1)
+			})
+			.map_err(|_| io::Error::other("file descriptors can only be sent in packets"))??;
+
+		(index as u32).write(stream).await
+	}
+}
+
+impl Describe for Fd {
+	fn describe(types: &mut Types) -> u64 {
+		types.define::<Self>(|_| Type::Opaque(type_name::<Self>()))
+	}
+}
+
+/// Run `future`, sending the file descriptors of the [`Fd`]s it encodes with its
+/// first write to a [`UnixWriteHalf`]
+///
+/// The file descriptors that weren't sent are returned with the output.
+pub(crate) async fn sending_fds<F: Future>(future: F) -> (F::Output, Vec<OwnedFd>) {
+	SENT.scope(RefCell::default(), async {
+		let output = future.await;
+		(output, SENT.with(RefCell::take))
+	})
+	.await
+}
+
+/// Run `future`, collecting the file descriptors received by the [`UnixReadHalf`]s
+/// it reads from
+pub(crate) async fn receiving_fds<F: Future>(future: F) -> (F::Output, Vec<OwnedFd>) {
+	RECEIVED
+		.scope(RefCell::default(), async {
+			let output = future.await;
+			let fds = RECEIVED.with(RefCell::take).into_iter().flatten().collect();
+			(output, fds)
+		})
+		.await
+}
+
+/// Run `future`, whose [`Fd`]s are taken from `fds`
+pub(crate) async fn with_received_fds<F: Future>(fds: Vec<OwnedFd>, future: F) -> F::Output {
+	let fds = fds.into_iter().map(Some).collect();
+	RECEIVED.scope(RefCell::new(fds), future).await
+}
+
+/// Reading half of a unix socket, which also receives the file descriptors of [`Fd`]s
+#[derive(Debug)]
+pub struct UnixReadHalf(OwnedReadHalf);
+
+impl From<OwnedReadHalf> for UnixReadHalf {
+	fn from(value: OwnedReadHalf) -> Self {
+		Self(value)
+	}
+}
+
+impl AsyncRead for UnixReadHalf {
+	fn poll_read(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut ReadBuf<'_>,
+	) -> Poll<io::Result<()>> {
+		let socket: &UnixStream = self.0.as_ref();
+		loop {
+			ready!(socket.poll_read_ready(cx))?;
+			let bytes = buf.initialize_unfilled();
+			match socket.try_io(Interest::READABLE, || receive(socket, bytes)) {
+				Ok((read, fds)) => {
+					// file descriptors received outside of a frame are closed
+					let _ = RECEIVED.try_with(|received| {
+						received.borrow_mut().extend(fds.into_iter().map(Some))
+					});
+					buf.advance(read);
+					return Poll::Ready(Ok(()));
+				}
+				// the readiness was cleared, the next poll registers the waker
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+				Err(e) => return Poll::Ready(Err(e)),
+			}
+		}
+	}
+}
+
+/// Receive bytes from a socket, and the file descriptors sent with them
+pub(crate) fn receive(socket: impl AsFd, bytes: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+	let mut space = [MaybeUninit::uninit(); CONTROL_SIZE];
+	let mut control = RecvAncillaryBuffer::new(&mut space);
+	let message = recvmsg(
+		socket,
+		&mut [IoSliceMut::new(bytes)],
+		&mut control,
+		RecvFlags::CMSG_CLOEXEC,
+	)?;
+
+	let mut fds = Vec::new();
+	for message in control.drain() {
+		if let RecvAncillaryMessage::ScmRights(received) = message {
+			fds.extend(received);
+		}
+	}
+	if message.flags.contains(ReturnFlags::CTRUNC) {
+		return Err(io::Error::new(
+			ErrorKind::InvalidData,
+			"received too many file descriptors",
+		));
+	}
+	Ok((message.bytes, fds))
+}
+
+/// Writing half of a unix socket, which also sends the file descriptors of [`Fd`]s
+#[derive(Debug)]
+pub struct UnixWriteHalf(OwnedWriteHalf);
+
+impl From<OwnedWriteHalf> for UnixWriteHalf {
+	fn from(value: OwnedWriteHalf) -> Self {
+		Self(value)
+	}
+}
+
+impl AsyncWrite for UnixWriteHalf {
+	fn poll_write(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		let fds = SENT.try_with(RefCell::take).unwrap_or_default();
+		if fds.is_empty() {
+			return Pin::new(&mut self.0).poll_write(cx, buf);
+		}
+
+		let result = poll_send(self.0.as_ref(), cx, buf, &fds);
+		if result.is_pending() {
+			// sent with the next attempt
+			let _ = SENT.try_with(|sent| sent.replace(fds));
+		}
+		result
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.0).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.0).poll_shutdown(cx)
+	}
+}
+
+/// Send bytes to a socket, with file descriptors attached to them
+fn poll_send(
+	socket: &UnixStream,
+	cx: &mut Context<'_>,
+	bytes: &[u8],
+	fds: &[OwnedFd],
+) -> Poll<io::Result<usize>> {
+	loop {
+		ready!(socket.poll_write_ready(cx))?;
+		match socket.try_io(Interest::WRITABLE, || send(socket, bytes, fds)) {
+			Ok(written) => return Poll::Ready(Ok(written)),
+			Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+			Err(e) => return Poll::Ready(Err(e)),
+		}
+	}
+}
+
+/// Send bytes to a socket, with file descriptors attached to them
+///
+/// At most [`MAX_FDS`] can be sent, which is the most an encoded frame contains.
+pub(crate) fn send(socket: impl AsFd, bytes: &[u8], fds: &[OwnedFd]) -> io::Result<usize> {
+	let fds: Vec<_> = fds.iter().map(AsFd::as_fd).collect();
+	let mut space = [MaybeUninit::uninit(); CONTROL_SIZE];
+	let mut control = SendAncillaryBuffer::new(&mut space);
+	if !control.push(SendAncillaryMessage::ScmRights(&fds)) {
+		return Err(io::Error::new(
+			ErrorKind::InvalidInput,
+			format!("more than {MAX_FDS} file descriptors in a packet"),
+		));
+	}
+	Ok(sendmsg(
+		socket,
+		&[IoSlice::new(bytes)],
+		&mut control,
+		SendFlags::NOSIGNAL,
+	)?)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+	use std::{
+		fs::File,
+		io::{Read as _, Write as _, pipe},
+	};
+
+	use tokio::io::duplex;
+
+	use super::*;
+	use crate::protocol::{PacketReceiver, PacketSender, Serverbound};
+
+	#[tokio::test]
+	async fn test_send_fds() {
+		let (a, b) = UnixStream::pair().unwrap();
+		let (_, tx) = a.into_split();
+		let (rx, _) = b.into_split();
+		let sender = PacketSender::new(UnixWriteHalf::from(tx));
+		let mut receiver = PacketReceiver::new(UnixReadHalf::from(rx));
+
+		let (mut reader, writer) = pipe().unwrap();
+		let (other_reader, _) = pipe().unwrap();
+		sender
+			.write(Serverbound {
+				call_id: 2,
+				payload: ("first", Fd::new(other_reader), Fd::new(writer)),
+			})
+			.await
+			.unwrap();
+		sender
+			.write(Serverbound {
+				call_id: 3,
+				payload: "no file descriptors",
+			})
+			.await
+			.unwrap();
+
+		let frame = receiver.receive().await.unwrap();
+		let (call_id, payload) = frame
+			.decode_payload::<(String, Fd, Fd)>(Limits::default())
+			.await
+			.unwrap();
+		let (name, _, fd) = payload.unwrap();
+		assert_eq!((call_id, name.as_str()), (2, "first"));
+
+		// the received descriptor refers to the same pipe
+		let mut received = File::from(fd.into_inner());
+		received.write_all(b"hello").unwrap();
+		let mut read = [0; 5];
+		reader.read_exact(&mut read).unwrap();
+		assert_eq!(&read, b"hello");
+
+		let frame = receiver.receive().await.unwrap();
+		let (_, payload) = frame
+			.decode_payload::<String>(Limits::default())
+			.await
+			.unwrap();
+		assert_eq!(payload.unwrap(), "no file descriptors");
+	}
+
+	#[tokio::test]
+	async fn test_transport_without_fds() {
+		let (tx, rx) = duplex(1024);
+		let sender = PacketSender::new(tx);
+		let mut receiver = PacketReceiver::new(rx);
+
+		let (reader, _) = pipe().unwrap();
+		assert!(
+			sender
+				.write(Serverbound {
+					call_id: 1,
+					payload: Fd::new(reader),
+				})
+				.await
+				.is_err()
+		);
+
+		let frame = receiver.receive().await.unwrap();
+		let (_, fd) = frame.decode_payload::<Fd>(Limits::default()).await.unwrap();
+		assert!(fd.is_err());
+	}
+}
@@ -0,0 +1,343 @@
+//! Code running around every call handled by a server
+//!
+//! A server chooses its interceptor by overriding the `interceptor` method of
+//! its generated trait, which is called once for every client. Layers are
+//! combined with tuples, the first one being the outermost:
+//!
+//! ```ignore
+//! fn interceptor(&self) -> impl Interceptor {
+//!     (Log(|call, outcome| ...), RateLimit::new(100, Duration::from_secs(1)))
+//! }
+//! ```
+//!
+//! Subscriptions to signals aren't calls, they aren't intercepted.
+
+use std::{
+	sync::{Mutex, PoisonError},
+	time::Duration,
+};
+
+use thiserror::Error;
+use tokio::time::Instant;
+
+use crate::{PeerCredentials, TraceContext};
+
+/// A call received by a server
+#[derive(Debug, Clone)]
+pub struct CallInfo {
+	/// Name of the method called
+	pub method: &'static str,
+	pub call_id: u64,
+	/// [`None`] if the client isn't connected through a unix socket
+	pub peer: Option<PeerCredentials>,
+	/// Time after which the client stops waiting, if it has a timeout
+	pub deadline: Option<Instant>,
+	/// Trace sent by the client, or a new one
+	pub trace: TraceContext,
+	/// Arguments formatted with [`Debug`], by name
+	///
+	/// Empty unless [`Interceptor::wants_arguments`] returns `true`. Input
+	/// streams aren't included.
+	pub arguments: Vec<(&'static str, String)>,
+}
+
+impl CallInfo {
+	/// Argument called `name`, formatted with [`Debug`]
+	#[must_use]
+	pub fn argument(&self, name: &str) -> Option<&str> {
+		self.arguments
+			.iter()
+			.find(|(argument, _)| *argument == name)
+			.map(|(_, value)| value.as_str())
+	}
+}
+
+/// Reason a call was rejected by an interceptor, sent to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum Rejection {
+	/// The client gets [`Error::Unauthorized`](crate::Error::Unauthorized)
+	#[error("the client isn't allowed to make this call")]
+	Unauthorized,
+	/// The client gets [`Error::RateLimited`](crate::Error::RateLimited)
+	#[error("the client made too many calls")]
+	RateLimited,
+}
+
+/// Code running around every call handled by a server
+pub trait Interceptor: Send + Sync {
+	/// Handle a call by awaiting `handler`, or reject it without awaiting it
+	///
+	/// `handler` runs the next layers and then the method, it fails if one of
+	/// the next layers rejected the call. For streamed methods, `handler` ends once the stream is returned, not
+	/// when it ends.
+	fn intercept<T, F>(
+		&self,
+		call: &CallInfo,
+		handler: F,
+	) -> impl Future<Output = Result<T, Rejection>> + Send
+	where
+		F: Future<Output = Result<T, Rejection>> + Send;
+
+	/// Whether [`CallInfo::arguments`] should be filled, which formats them for every call
+	fn wants_arguments(&self) -> bool {
+		false
+	}
+}
+
+/// Handle every call
+impl Interceptor for () {
+	async fn intercept<T, F>(&self, _call: &CallInfo, handler: F) -> Result<T, Rejection>
+	where
+		F: Future<Output = Result<T, Rejection>> + Send,
+	{
+		handler.await
+	}
+}
+
+impl<I: Interceptor + ?Sized> Interceptor for &I {
+	fn intercept<T, F>(
+		&self,
+		call: &CallInfo,
+		handler: F,
+	) -> impl Future<Output = Result<T, Rejection>> + Send
+	where
+		F: Future<Output = Result<T, Rejection>> + Send,
+	{
+		(**self).intercept(call, handler)
+	}
+
+	fn wants_arguments(&self) -> bool {
+		(**self).wants_arguments()
+	}
+}
+
+impl<A: Interceptor> Interceptor for (A,) {
+	fn intercept<T, F>(
+		&self,
+		call: &CallInfo,
+		handler: F,
+	) -> impl Future<Output = Result<T, Rejection>> + Send
+	where
+		F: Future<Output = Result<T, Rejection>> + Send,
+	{
+		self.0.intercept(call, handler)
+	}
+
+	fn wants_arguments(&self) -> bool {
+		self.0.wants_arguments()
+	}
+}
+
+/// Implement [`Interceptor`] for a tuple, the first layer wrapping the other ones
+macro_rules! tuple_interceptor {
+	($first:ident, $($rest:ident),+) => {
+		impl<$first: Interceptor, $($rest: Interceptor),+> Interceptor for ($first, $($rest),+) {
+			#[allow(non_snake_case, reason = "layers are named after their type")]
+			async fn intercept<T, F>(&self, call: &CallInfo, handler: F) -> Result<T, Rejection>
+			where
+				F: Future<Output = Result<T, Rejection>> + Send,
+			{
+				let ($first, $($rest),+) = self;
+				$first.intercept(call, ($($rest,)+).intercept(call, handler)).await
+			}
+
+			#[allow(non_snake_case, reason = "layers are named after their type")]
+			fn wants_arguments(&self) -> bool {
+				let ($first, $($rest),+) = self;
+				$first.wants_arguments() $(|| $rest.wants_arguments())+
+			}
+		}
+	};
+}
+
+tuple_interceptor!(A, B);
+tuple_interceptor!(A, B, C);
+tuple_interceptor!(A, B, C, D);
+
+/// Reject the calls for which the function returns `false` with [`Rejection::Unauthorized`]
+///
+/// Unlike the `authorize` method of servers, which accepts or rejects a whole
+/// client, this can allow only some methods.
+pub struct Authorize<F>(pub F);
+
+impl<F> Interceptor for Authorize<F>
+where
+	F: Fn(&CallInfo) -> bool + Send + Sync,
+{
+	async fn intercept<T, H>(&self, call: &CallInfo, handler: H) -> Result<T, Rejection>
+	where
+		H: Future<Output = Result<T, Rejection>> + Send,
+	{
+		if !(self.0)(call) {
+			return Err(Rejection::Unauthorized);
+		}
+		handler.await
+	}
+}
+
+/// Call the function once every call ended, with its duration, or the reason it was rejected
+///
+/// The rejections seen are those of the layers after this one. Arguments are
+/// formatted, so that they can be logged.
+pub struct Log<F>(pub F);
+
+impl<F> Interceptor for Log<F>
+where
+	F: Fn(&CallInfo, Result<Duration, Rejection>) + Send + Sync,
+{
+	async fn intercept<T, H>(&self, call: &CallInfo, handler: H) -> Result<T, Rejection>
+	where
+		H: Future<Output = Result<T, Rejection>> + Send,
+	{
+		let start = Instant::now();
+		let result = handler.await;
+		(self.0)(
+			call,
+			result.as_ref().map(|_| start.elapsed()).map_err(|e| *e),
+		);
+		result
+	}
+
+	fn wants_arguments(&self) -> bool {
+		true
+	}
+}
+
+/// Reject the calls made after `max_calls` in the same period with [`Rejection::RateLimited`]
+///
+/// Calls of every client sharing this layer are counted together. To limit
+/// each client separately, create it in the `interceptor` method of the server.
+#[derive(Debug)]
+pub struct RateLimit {
+	max_calls: u32,
+	period: Duration,
+	window: Mutex<Window>,
+}
+
+/// Calls made in the current period of a [`RateLimit`]
+#[derive(Debug)]
+struct Window {
+	start: Instant,
+	calls: u32,
+}
+
+impl RateLimit {
+	#[must_use]
+	pub fn new(max_calls: u32, period: Duration) -> Self {
+		Self {
+			max_calls,
+			period,
+			window: Mutex::new(Window {
+				start: Instant::now(),
+				calls: 0,
+			}),
+		}
+	}
+
+	/// Count a call, or return `false` if there were too many of them
+	fn acquire(&self) -> bool {
+		let mut window = self.window.lock().unwrap_or_else(PoisonError::into_inner);
+		let now = Instant::now();
+		if now.duration_since(window.start) >= self.period {
+			window.start = now;
+			window.calls = 0;
+		}
+		if window.calls >= self.max_calls {
+			return false;
+		}
+		window.calls += 1;
+		true
+	}
+}
+
+impl Interceptor for RateLimit {
+	async fn intercept<T, F>(&self, _call: &CallInfo, handler: F) -> Result<T, Rejection>
+	where
+		F: Future<Output = Result<T, Rejection>> + Send,
+	{
+		if !self.acquire() {
+			return Err(Rejection::RateLimited);
+		}
+		handler.await
+	}
+}
+
+/// Log the end of every call at the debug level with its duration, and rejections as warnings
+///
+/// The events are in the `call` span the server runs each call in, with its
+/// method, call id, trace and peer credentials.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracing;
+
+#[cfg(feature = "tracing")]
+impl Interceptor for Tracing {
+	async fn intercept<T, F>(&self, _call: &CallInfo, handler: F) -> Result<T, Rejection>
+	where
+		F: Future<Output = Result<T, Rejection>> + Send,
+	{
+		let start = Instant::now();
+		let result = handler.await;
+		match &result {
+			Ok(_) => tracing::debug!(elapsed = ?start.elapsed(), "Call handled"),
+			Err(rejection) => tracing::warn!("Call rejected: {rejection}"),
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+	use std::sync::Mutex;
+
+	use super::*;
+
+	fn call(method: &'static str) -> CallInfo {
+		CallInfo {
+			method,
+			call_id: 0,
+			peer: None,
+			deadline: None,
+			trace: TraceContext::new(),
+			arguments: Vec::new(),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_rate_limit() {
+		let limit = RateLimit::new(2, Duration::from_millis(50));
+		for _ in 0..2 {
+			assert_eq!(limit.intercept(&call("a"), async { Ok(1) }).await, Ok(1));
+		}
+		assert_eq!(
+			limit.intercept(&call("a"), async { Ok(1) }).await,
+			Err(Rejection::RateLimited)
+		);
+
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		assert_eq!(limit.intercept(&call("a"), async { Ok(1) }).await, Ok(1));
+	}
+
+	#[tokio::test]
+	async fn test_layers() {
+		let logged = Mutex::new(Vec::new());
+		let layers = (
+			Log(|call: &CallInfo, outcome: Result<Duration, Rejection>| {
+				logged.lock().unwrap().push((call.method, outcome.is_ok()));
+			}),
+			Authorize(|call: &CallInfo| call.method != "stop"),
+		);
+		assert!(layers.wants_arguments());
+
+		assert_eq!(
+			layers.intercept(&call("start"), async { Ok(1) }).await,
+			Ok(1)
+		);
+		assert_eq!(
+			layers.intercept(&call("stop"), async { Ok(1) }).await,
+			Err(Rejection::Unauthorized)
+		);
+		assert_eq!(*logged.lock().unwrap(), [("start", true), ("stop", false)]);
+	}
+}
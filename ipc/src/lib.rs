@@ -10,31 +10,90 @@ use core::result::Result as StdResult;
 pub use anyhow;
 #[doc(hidden)]
 pub use futures;
+#[cfg(feature = "gio")]
+#[doc(hidden)]
+pub use gio;
 #[doc(hidden)]
 pub use log;
 #[doc(hidden)]
 pub use tokio;
 
+pub mod capture;
+mod fd;
+pub mod interceptor;
 mod protocol;
 mod rw;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod testing;
 
+pub use fd::{Fd, UnixReadHalf, UnixWriteHalf};
 /// Derive macro for implementing [`Read`].
+///
+/// The encoding can be customized with these attributes:
+/// - `#[ipc(skip)]` on a field: the field isn't sent, and is set to [`Default::default`]
+///   when reading.
+/// - `#[ipc(with = path)]` on a field: use `path::read(stream, limits)` and
+///   `path::write(&value, stream)` instead of [`Read`] and [`Write`].
+/// - `#[ipc(extensible)]` on a type: the fields of the struct or of each variant are
+///   prefixed by their total length, so that fields can be added without breaking older
+///   peers. Adding or removing this attribute changes the encoding.
+/// - `#[ipc(default)]` on a field of an extensible type: the field is set to
+///   [`Default::default`] if the other side didn't send it. Default fields must be the last
+///   ones.
+/// - `#[ipc(discriminant = N)]` on a variant: the value sent to identify the variant,
+///   defaults to the discriminant of the previous variant plus one.
+/// - `#[ipc(repr = u8)]` on an enum: the type used to send discriminants, one of `u8`,
+///   `u16`, `u32` or `u64` (the default).
+///
+/// This also implements [`Describe`](schema::Describe), with the type parameters
+/// required to implement it.
 #[doc(inline)]
 pub use ipc_macros::Read;
 /// Derive macro for implementing [`Write`].
+///
+/// See [`Read`](macro@Read) for the supported attributes.
 #[doc(inline)]
 pub use ipc_macros::Write;
 pub use ipc_macros::protocol;
-pub use protocol::Error;
-pub use rw::{InvalidDiscriminantError, Read, Write};
+pub use protocol::{
+	ANY_FINGERPRINT, Error,
+	client::{
+		BlockingClient, BlockingStream, ConnectionState, DynamicCallError, DynamicClient,
+		DynamicResponse, LocalClient, Reconnect,
+	},
+	expand_path,
+	server::{IncomingStream, PeerCredentials, ServerOptions, deadline, peer_credentials},
+	trace::{TraceContext, trace_id},
+};
+pub use rw::{InvalidDiscriminantError, LimitExceededError, Limits, Read, Write};
+/// Token shutting down a server, see `serve_until`
+pub use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "serde")]
+pub use self::serde::Serde;
 
 pub type Result<T> = StdResult<T, Error>;
 
 #[doc(hidden)]
 pub mod __private {
-	pub use super::protocol::{
-		Clientbound, PacketReceiver, Serverbound, StreamPacket, Writable,
-		client::Client,
-		server::{run_server, stream_with_id},
+	pub use super::{
+		protocol::{
+			Clientbound, PacketReceiver, PacketSender, Request, Response, Serverbound,
+			SkippedFrame, StreamPacket, Writable,
+			client::{
+				Client,
+				demux::{Route, Routes},
+			},
+			expand_path, handshake,
+			server::{
+				ConnectionWriter, IncomingStreams, RunningCalls, SocketFile, Subscribers,
+				bind_path_socket, current_uid, decode_call, fallible_stream_with_id, reject_client,
+				run_server, split_context, stream_with_id, with_deadline,
+			},
+			trace::{in_call_span, in_call_stream},
+		},
+		schema::{DescribeProbe, OpaqueProbe, Probe},
 	};
 }
@@ -1,110 +1,315 @@
 use std::{
-	collections::HashMap,
+	ffi::OsStr,
 	fmt::{self, Debug},
 	io::{self, ErrorKind},
-	os::unix::net::{SocketAddr, UnixStream as StdUnixStream},
-	pin::Pin,
+	marker::PhantomData,
+	os::{
+		linux::net::SocketAddrExt,
+		unix::{ffi::OsStrExt, net::SocketAddr},
+	},
+	path::PathBuf,
+	pin::pin,
 	result::Result as StdResult,
 	sync::{
-		Arc,
+		Arc, Mutex, PoisonError,
 		atomic::{AtomicU64, Ordering},
 	},
+	time::Duration,
 };
 
-use async_stream::try_stream;
-use futures::Stream;
+use async_stream::{stream, try_stream};
+use futures::{
+	Stream, StreamExt,
+	future::{AbortHandle, Abortable, Either, select},
+};
 use log::{error, trace};
 use tokio::{
-	io::{AsyncRead, AsyncWrite, BufReader},
-	net::{
-		UnixStream,
-		unix::{OwnedReadHalf, OwnedWriteHalf},
-	},
+	io::{AsyncRead, AsyncWrite},
+	net::UnixStream,
+	runtime::Handle,
 	spawn,
-	sync::{RwLock, mpsc},
+	sync::watch,
+	time::{Instant, sleep, timeout},
+};
+
+use super::{
+	CallContext, Clientbound, Error, PacketReceiver, PacketSender, Request, StreamPacket, handshake,
 };
+use crate::{
+	Limits, Read, Result, TraceContext, UnixReadHalf, UnixWriteHalf, Write, capture::Side,
+	schema::Schema,
+};
+
+mod blocking;
+pub mod demux;
+mod dynamic;
+mod local;
+mod reconnect;
 
-use super::{Clientbound, Error, PacketSender, StreamPacket};
-use crate::{Read, Result, Write};
+pub use blocking::{BlockingClient, BlockingStream};
+use demux::{Packets, Receiver, Route, Routes, receive_frames, response};
+pub use dynamic::{DynamicCallError, DynamicClient, DynamicResponse};
+pub use local::LocalClient;
+use reconnect::{Connect, Reconnector, Subscriptions};
+pub use reconnect::{ConnectionState, Reconnect};
 
-type Callback<RX> = Box<
-	dyn for<'a> Fn(&'a mut BufReader<RX>) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>>
-		+ Send
-		+ Sync,
->;
+/// Delay before trying again to connect to a server that isn't up yet, doubled after each attempt
+const RETRY_INITIAL_DELAY: Duration = Duration::from_millis(10);
+/// Maximum delay between two attempts to connect to a server that isn't up yet
+const RETRY_MAX_DELAY: Duration = Duration::from_millis(500);
 
 pub struct Client<RX: AsyncRead + Unpin + Send + 'static, TX: AsyncWrite + Unpin + Send> {
 	next_call_id: Arc<AtomicU64>,
 	packet_sender: PacketSender<TX>,
-	callbacks: Arc<RwLock<HashMap<u64, Callback<RX>>>>,
+	routes: Routes,
+	limits: Limits,
+	/// Limits used by the task receiving frames, shared by all clones
+	receiver_limits: Arc<Mutex<Limits>>,
+	/// Timeout of the calls that don't have their own
+	timeout: Option<Duration>,
+	state: watch::Receiver<ConnectionState>,
+	/// Signals to subscribe to again after reconnecting, if enabled
+	subscriptions: Option<Subscriptions>,
+	/// Keeps the connection being re-established while a clone of the client is alive
+	alive: Arc<()>,
+	_rx: PhantomData<fn() -> RX>,
 }
 
-impl Client<OwnedReadHalf, OwnedWriteHalf> {
+impl Client<UnixReadHalf, UnixWriteHalf> {
 	/// Create a connection from a unix socket address
 	///
 	/// # Errors
 	///
-	/// This function will return an error if the socket can't be created,
-	/// connected to the address, set in non-blocking mode or converted to
-	/// a tokio socket.
-	pub fn from_unix_address(address: &SocketAddr) -> io::Result<Self> {
-		let stream = StdUnixStream::connect_addr(address)?;
-		stream.set_nonblocking(true)?;
-		let (rx, tx) = UnixStream::from_std(stream)?.into_split();
+	/// This function will return an error if the socket can't be connected to
+	/// the address, or if the handshake with the server fails.
+	pub async fn from_unix_address(address: &SocketAddr, fingerprint: u64) -> Result<Self> {
+		let (rx, tx) = connect(address).await.map_err(Error::Connect)?;
+
+		Self::new(rx, tx, fingerprint).await
+	}
+
+	/// Same as [`Self::from_unix_address`], but wait for at most `timeout` for
+	/// the server to listen on the address
+	///
+	/// # Errors
+	///
+	/// See [`Self::from_unix_address`]. If the server isn't up before `timeout`,
+	/// the error of the last attempt is returned.
+	pub async fn from_unix_address_with_retry(
+		address: &SocketAddr,
+		fingerprint: u64,
+		timeout: Duration,
+	) -> Result<Self> {
+		let deadline = Instant::now() + timeout;
+		let mut delay = RETRY_INITIAL_DELAY;
+		let (rx, tx) = loop {
+			match connect(address).await {
+				Ok(x) => break x,
+				// the socket doesn't exist yet, or nobody listens on it yet
+				Err(e)
+					if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused)
+						&& Instant::now() + delay < deadline =>
+				{
+					trace!("Server is not up yet: {e}");
+					sleep(delay).await;
+					delay = (delay * 2).min(RETRY_MAX_DELAY);
+				}
+				Err(e) => return Err(Error::Connect(e)),
+			}
+		};
+
+		Self::new(rx, tx, fingerprint).await
+	}
+
+	/// Same as [`Self::from_unix_address`], but connect again to the address
+	/// when the connection breaks
+	///
+	/// # Errors
+	///
+	/// See [`Self::from_unix_address`].
+	pub async fn from_unix_address_reconnecting(
+		address: SocketAddr,
+		fingerprint: u64,
+		options: Reconnect,
+	) -> Result<Self> {
+		let address = Arc::new(address);
+		let reconnect = move || {
+			let address = Arc::clone(&address);
+			async move { connect(&address).await }
+		};
+		Self::new_reconnecting(reconnect, fingerprint, options).await
+	}
+}
+
+/// Connect to a unix socket without blocking
+async fn connect(address: &SocketAddr) -> io::Result<(UnixReadHalf, UnixWriteHalf)> {
+	// tokio only takes paths, where abstract names start with a null byte
+	let path = match (address.as_pathname(), address.as_abstract_name()) {
+		(Some(path), _) => path.to_owned(),
+		(None, Some(name)) => PathBuf::from(OsStr::from_bytes(&[b"\0", name].concat())),
+		(None, None) => {
+			return Err(io::Error::new(
+				ErrorKind::InvalidInput,
+				"cannot connect to an unnamed socket",
+			));
+		}
+	};
+	let (rx, tx) = UnixStream::connect(path).await?.into_split();
+	Ok((rx.into(), tx.into()))
+}
+
+impl<RX, TX> Client<RX, TX>
+where
+	RX: AsyncRead + Unpin + Send + 'static,
+	TX: AsyncWrite + Unpin + Send,
+{
+	/// Set the limits of the values sent to and received from the server
+	///
+	/// The maximum frame size is shared with the clones of this client, as
+	/// they use the same connection.
+	#[must_use]
+	pub fn with_limits(mut self, limits: Limits) -> Self {
+		self.limits = limits;
+		self.packet_sender.set_limits(limits);
+		*self
+			.receiver_limits
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = limits;
+		self
+	}
+
+	/// Set the maximum time to wait for the response of a call
+	///
+	/// This only applies to calls that aren't streamed and don't have their own
+	/// timeout, since streams can legitimately stay silent for a long time.
+	#[must_use]
+	pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
 
-		Ok(Self::new(rx, tx))
+	/// Stream of the states of the connection, starting with the current one
+	///
+	/// Only clients created with [`Self::new_reconnecting`] can be connected
+	/// again after being disconnected. Quick successive changes may be merged.
+	pub fn connection_states(&self) -> impl Stream<Item = ConnectionState> + use<RX, TX> {
+		let mut state = self.state.clone();
+		stream! {
+			loop {
+				let current = *state.borrow_and_update();
+				yield current;
+				if state.changed().await.is_err() {
+					// the connection won't change anymore
+					break;
+				}
+			}
+		}
 	}
 }
 
 impl<RX, TX> Client<RX, TX>
 where
 	RX: AsyncRead + Unpin + Send,
-	TX: AsyncWrite + Unpin + Send + Sync,
+	TX: AsyncWrite + Unpin + Send + Sync + 'static,
 {
-	fn new(rx: RX, tx: TX) -> Self {
-		let callbacks: Arc<RwLock<HashMap<u64, Callback<RX>>>> =
-			Arc::new(RwLock::new(HashMap::new()));
-		let callbacks_copy = Arc::clone(&callbacks);
-		let mut rx = BufReader::new(rx);
+	/// Create a connection over any transport, and do the handshake with the server
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the handshake with the server fails.
+	pub async fn new(rx: RX, tx: TX, fingerprint: u64) -> Result<Self> {
+		Self::start(rx, tx, fingerprint, None).await
+	}
+
+	/// Create a connection opened by `connect`, which is called again when the
+	/// connection breaks
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the first connection can't be
+	/// opened, or if the handshake with the server fails.
+	pub async fn new_reconnecting<F, Fut>(
+		connect: F,
+		fingerprint: u64,
+		options: Reconnect,
+	) -> Result<Self>
+	where
+		F: Fn() -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = io::Result<(RX, TX)>> + Send + 'static,
+	{
+		let (rx, tx) = connect().await.map_err(Error::Connect)?;
+		let connect: Connect<RX, TX> = Box::new(move || Box::pin(connect()));
+		Self::start(rx, tx, fingerprint, Some((connect, options))).await
+	}
+
+	async fn start(
+		rx: RX,
+		tx: TX,
+		fingerprint: u64,
+		reconnect: Option<(Connect<RX, TX>, Reconnect)>,
+	) -> Result<Self> {
+		let mut rx = PacketReceiver::new(rx);
+		let packet_sender = PacketSender::new(tx);
+		handshake(&mut rx, &packet_sender, fingerprint, Side::Client).await?;
+
+		let routes = Routes::default();
+		let routes_copy = routes.clone();
+		let receiver_limits = Arc::new(Mutex::new(Limits::default()));
+		let receiver_limits_copy = Arc::clone(&receiver_limits);
+		let (state_sender, state) = watch::channel(ConnectionState::Connected);
+		let alive = Arc::new(());
+		let subscriptions = reconnect
+			.as_ref()
+			.filter(|(_, options)| options.resubscribe())
+			.map(|_| Subscriptions::default());
+		let reconnector = reconnect.map(|(connect, options)| Reconnector {
+			connect,
+			options,
+			fingerprint,
+			alive: Arc::downgrade(&alive),
+			packet_sender: packet_sender.clone(),
+			routes: routes.clone(),
+			subscriptions: subscriptions.clone(),
+		});
 
 		spawn(async move {
-			let callbacks = callbacks_copy;
+			let routes = routes_copy;
 			loop {
-				match u64::read(&mut rx).await {
-					Ok(x) => {
-						let mut callbacks = callbacks.write().await;
-						// None if the caller was cancelled
-						if let Some(callback) = callbacks.get(&x)
-							&& callback(&mut rx).await
-						{
-							// callback has decided that it should be removed
-							callbacks.remove(&x);
-						}
-					}
-					Err(e) => {
-						if e.kind() != ErrorKind::UnexpectedEof {
-							error!("Error while receiving response: {e}");
-						}
-						// drop all callbacks (and it turn, channel receiving ends), allowing call to detect the crash
-						callbacks.write().await.clear();
-						break;
-					}
-				}
-				// putting behind explicit debug-only gate because it locks the RwLock
-				if cfg!(debug_assertions) {
-					trace!("Callbacks remaining: {:?}", callbacks.read().await.keys());
-				}
+				receive_frames(&mut rx, &routes, &receiver_limits_copy).await;
+				// error if every clone of the client is dropped, nobody cares anymore
+				let _ = state_sender.send(ConnectionState::Disconnected);
+
+				let Some(reconnector) = &reconnector else {
+					break;
+				};
+				let Some(new_rx) = reconnector.reconnect().await else {
+					break;
+				};
+				rx = new_rx;
+				let _ = state_sender.send(ConnectionState::Connected);
 			}
+			// drop all routes (and it turn, channel sending ends), allowing calls to detect the crash
+			routes.retain(|_| false);
 		});
 
-		Self {
+		Ok(Self {
 			next_call_id: Arc::new(AtomicU64::new(0)),
-			packet_sender: PacketSender::new(tx),
-			callbacks,
-		}
+			packet_sender,
+			routes,
+			limits: Limits::default(),
+			receiver_limits,
+			timeout: None,
+			state,
+			subscriptions,
+			alive,
+			_rx: PhantomData,
+		})
 	}
 
-	pub async fn call<T, R>(&self, method: T) -> Result<R>
+	/// Call a method and wait for its response
+	///
+	/// If `timeout` is [`None`], the timeout of the client is used.
+	pub async fn call<T, R>(&self, method: T, timeout: Option<Duration>) -> Result<R>
 	where
 		T: Write + Send + Sync,
 		T::Error: Send + Sync + 'static,
@@ -112,28 +317,66 @@ where
 		R::Error: Send + Sync,
 		anyhow::Error: From<T::Error> + From<R::Error>,
 	{
-		let (tx, mut rx) = mpsc::channel(1);
-		self.call_base(method, move |rx| {
-			let tx = tx.clone();
-			Box::pin(async move {
-				// error if receiving end is closed, i.e call is cancelled, ignore it
-				let _ = tx
-					.send(R::read(rx).await.map_err(|e| Error::Read(e.into())))
-					.await;
-				// remove this callback, we only expect one response
-				true
-			})
-		})
-		.await?;
+		let timeout = timeout.or(self.timeout);
+		let (call_id, rx) = self.start_call(call_request(method, timeout)).await?;
+
+		// None if the route is dropped, will happen if the receiving socket is closed
+		self.wait_response(call_id, timeout, response(rx, self.limits))
+			.await
+			.ok_or(Error::ConnectionBroken)
+			.flatten()
+	}
+
+	/// Same as [`Self::call`], but also send the values of `input` to the server
+	///
+	/// `wrap` is used to put the values in the packet type expected by the server.
+	/// Sending `input` stops as soon as the response is received.
+	pub async fn call_with_input<T, R, I, P>(
+		&self,
+		method: T,
+		input: impl Stream<Item = I> + Send,
+		wrap: fn(StreamPacket<I, !>) -> P,
+		timeout: Option<Duration>,
+	) -> Result<R>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		I: Send,
+		P: Write<Error = anyhow::Error> + Send + Sync,
+		anyhow::Error: From<T::Error> + From<R::Error>,
+	{
+		let timeout = timeout.or(self.timeout);
+		let (call_id, rx) = self.start_call(call_request(method, timeout)).await?;
 
-		// recv returns None if tx is dropped, will happen if the receiving socket
-		// is closed
-		rx.recv().await.ok_or(Error::ConnectionBroken).flatten()
+		let response = async {
+			let response = pin!(response(rx, self.limits));
+			let forward = pin!(forward_input(
+				self.packet_sender.clone(),
+				call_id,
+				input,
+				wrap
+			));
+			match select(response, forward).await {
+				Either::Left((response, _)) => response,
+				Either::Right(((), response)) => response.await,
+			}
+		};
+
+		self.wait_response(call_id, timeout, response)
+			.await
+			.ok_or(Error::ConnectionBroken)
+			.flatten()
 	}
 
+	/// Call a streamed method
+	///
+	/// `timeout` only applies to the first packet of the stream.
 	pub async fn long_call<T, R, E>(
 		&self,
 		method: T,
+		timeout: Option<Duration>,
 	) -> Result<StdResult<impl Stream<Item = Result<R>> + use<T, R, E, RX, TX>, E>>
 	where
 		T: Write + Send + Sync,
@@ -144,33 +387,180 @@ where
 		E::Error: Send + Sync + 'static,
 		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
 	{
-		let (tx, mut rx) = mpsc::unbounded_channel();
-		let _ = self
-			.call_base(method, move |rx| {
-				let tx = tx.clone();
-				Box::pin(async move {
-					let value = StreamPacket::<R, E>::read(rx).await.map_err(Error::Read);
-
-					let is_end_packet = matches!(
-						value,
-						Ok(StreamPacket::EndOfStream | StreamPacket::Error(_)) | Err(_)
-					);
-					if tx.send(value).is_err() {
-						// receiving end is closed, i.e call is cancelled, remove this callback
-						return true;
-					}
+		let (call_id, rx) = self
+			.start_long_call::<T, R, E>(call_request(method, timeout))
+			.await?;
+		self.receive_stream(call_id, rx, None, timeout).await
+	}
 
-					// remove the callback if we don't expect new packets
-					is_end_packet
-				})
-			})
+	/// Same as [`Self::long_call`], but also send the values of `input` to the server
+	///
+	/// `wrap` is used to put the values in the packet type expected by the server.
+	/// Sending `input` stops as soon as the returned stream ends or is dropped.
+	pub async fn long_call_with_input<T, R, E, S, I, P>(
+		&self,
+		method: T,
+		input: S,
+		wrap: fn(StreamPacket<I, !>) -> P,
+		timeout: Option<Duration>,
+	) -> Result<StdResult<impl Stream<Item = Result<R>> + use<T, R, E, S, I, P, RX, TX>, E>>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		S: Stream<Item = I> + Send + 'static,
+		I: Send + 'static,
+		P: Write<Error = anyhow::Error> + Send + Sync + 'static,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let (call_id, rx) = self
+			.start_long_call::<T, R, E>(call_request(method, timeout))
+			.await?;
+
+		let (handle, registration) = AbortHandle::new_pair();
+		spawn(Abortable::new(
+			forward_input(self.packet_sender.clone(), call_id, input, wrap),
+			registration,
+		));
+
+		self.receive_stream(call_id, rx, Some(handle), timeout)
+			.await
+	}
+
+	/// Subscribe to a signal of the server
+	///
+	/// The subscription is sent before this function returns, so calls made
+	/// afterwards are handled by the server after it. Dropping the returned
+	/// stream cancels the subscription.
+	pub async fn subscribe<R>(
+		&self,
+		signal: u64,
+	) -> Result<impl Stream<Item = Result<R>> + use<R, RX, TX>>
+	where
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		anyhow::Error: From<R::Error>,
+	{
+		let (call_id, mut rx) = self
+			.start_long_call::<!, R, !>(Request::Subscribe(signal))
 			.await?;
+		if let Some(subscriptions) = &self.subscriptions {
+			subscriptions
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.insert(call_id, signal);
+		}
+		let mut guard = CancelGuard {
+			call_id,
+			packet_sender: self.packet_sender.clone(),
+			input: None,
+			armed: true,
+		};
+
+		Ok(try_stream! {
+			// anything else is EndOfStream, as the server has no early error to send
+			while let StreamPacket::Value(x) = rx.recv().await.ok_or(Error::ConnectionBroken)?? {
+				yield x;
+			}
+			// the server won't send anything else, no need to cancel
+			guard.disarm();
+		})
+	}
+
+	/// Ask the server for the schema of its protocol
+	///
+	/// If `timeout` is [`None`], the timeout of the client is used.
+	pub async fn describe(&self, timeout: Option<Duration>) -> Result<Schema> {
+		let timeout = timeout.or(self.timeout);
+		let (call_id, rx) = self.start_call(Request::<!, !>::Describe).await?;
+
+		self.wait_response(call_id, timeout, response(rx, self.limits))
+			.await
+			.ok_or(Error::ConnectionBroken)
+			.flatten()
+	}
+
+	/// Send a method call, and return a channel receiving its response
+	async fn start_call<T>(&self, request: Request<T, !>) -> Result<(u64, Receiver)>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error>,
+	{
+		let (route, rx) = Route::response();
+		let call_id = self.call_base(request, route).await?;
+
+		Ok((call_id, rx))
+	}
+
+	/// Send a streamed method call (or a subscription), and return the packets received for it
+	async fn start_long_call<T, R, E>(
+		&self,
+		request: Request<T, !>,
+	) -> Result<(u64, Packets<StreamPacket<R, E>>)>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let (route, rx) = Route::stream();
+		let call_id = self.call_base(request, route).await?;
 
-		let first_packet = rx.recv().await.ok_or(Error::ConnectionBroken)??;
+		Ok((
+			call_id,
+			Packets::new(call_id, rx, self.routes.clone(), self.limits),
+		))
+	}
+
+	/// Wait for the first packet of a streamed call, then turn the following ones into a stream
+	///
+	/// `input` is the handle of the task sending the input stream, if any.
+	async fn receive_stream<R, E>(
+		&self,
+		call_id: u64,
+		mut rx: Packets<StreamPacket<R, E>>,
+		input: Option<AbortHandle>,
+		timeout_duration: Option<Duration>,
+	) -> Result<StdResult<impl Stream<Item = Result<R>> + use<R, E, RX, TX>, E>>
+	where
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<R::Error> + From<E::Error>,
+	{
+		// created right away so that the call is also cancelled if this future is dropped
+		let mut guard = CancelGuard {
+			call_id,
+			packet_sender: self.packet_sender.clone(),
+			input,
+			armed: true,
+		};
+
+		let first_packet = match timeout_duration {
+			Some(duration) => timeout(duration, rx.recv())
+				.await
+				.map_err(|_| Error::Timeout)?,
+			None => rx.recv().await,
+		};
+		let first_packet = first_packet.ok_or(Error::ConnectionBroken)??;
 		let first_value = match first_packet {
 			StreamPacket::Value(x) => Some(x),
-			StreamPacket::EndOfStream => None,
-			StreamPacket::Error(e) => return Ok(Err(e)),
+			StreamPacket::EndOfStream => {
+				guard.disarm();
+				None
+			}
+			StreamPacket::Error(e) => {
+				guard.disarm();
+				return Ok(Err(e));
+			}
 		};
 
 		Ok(Ok(try_stream! {
@@ -192,37 +582,62 @@ where
 				};
 				yield value;
 			}
+			// the server won't send anything else, no need to cancel
+			guard.disarm();
 		}))
 	}
 
-	async fn call_base<T, F>(&self, method: T, callback: F) -> Result<u64>
+	/// Wait for the response of a call for at most `timeout`, cancelling the call if it expires
+	async fn wait_response<T>(
+		&self,
+		call_id: u64,
+		timeout_duration: Option<Duration>,
+		response: impl Future<Output = Option<Result<T>>>,
+	) -> Option<Result<T>> {
+		let Some(duration) = timeout_duration else {
+			return response.await;
+		};
+
+		if let Ok(response) = timeout(duration, response).await {
+			return response;
+		}
+		self.routes.remove(call_id);
+		// error if the connection is broken, in which case there's nothing to cancel
+		let _ = self
+			.packet_sender
+			.write(Clientbound {
+				call_id,
+				payload: Request::<!, !>::Cancel,
+			})
+			.await;
+		Some(Err(Error::Timeout))
+	}
+
+	async fn call_base<T>(&self, request: Request<T, !>, route: Route) -> Result<u64>
 	where
 		T: Write + Send + Sync,
 		T::Error: Send + Sync + 'static,
 		anyhow::Error: From<T::Error>,
-		F: for<'a> Fn(&'a mut BufReader<RX>) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>>
-			+ Send
-			+ Sync
-			+ 'static,
 	{
 		let call_id = self.next_call_id.fetch_add(1, Ordering::Relaxed);
 
-		// add callback before writing, otherwise we could read the response
-		// before having the callback in place
-		self.callbacks
-			.write()
-			.await
-			.insert(call_id, Box::new(callback));
+		// add the route before writing, otherwise we could read the response
+		// before having the route in place
+		self.routes.insert(call_id, route);
 		if let Err(e) = self
 			.packet_sender
-			.write::<Clientbound<T>>(Clientbound {
+			.write::<Clientbound<Request<T, !>>>(Clientbound {
 				call_id,
-				payload: method,
+				payload: request,
 			})
 			.await
 		{
-			self.callbacks.write().await.remove(&call_id);
-			return Err(super::Error::Read(e));
+			self.routes.remove(call_id);
+			// the writer of a broken connection, until it's re-established
+			if e.is::<io::Error>() {
+				return Err(Error::ConnectionBroken);
+			}
+			return Err(super::Error::from_decode(e));
 		}
 
 		Ok(call_id)
@@ -238,9 +653,108 @@ where
 		Self {
 			next_call_id: Arc::clone(&self.next_call_id),
 			packet_sender: self.packet_sender.clone(),
-			callbacks: Arc::clone(&self.callbacks),
+			routes: self.routes.clone(),
+			limits: self.limits,
+			receiver_limits: Arc::clone(&self.receiver_limits),
+			timeout: self.timeout,
+			state: self.state.clone(),
+			subscriptions: self.subscriptions.clone(),
+			alive: Arc::clone(&self.alive),
+			_rx: PhantomData,
+		}
+	}
+}
+
+/// Request calling a method, with the time the server has to answer if there's a timeout,
+/// and the trace of the call
+fn call_request<T>(method: T, timeout: Option<Duration>) -> Request<T, !> {
+	let context = CallContext {
+		timeout: timeout.map(|timeout| u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX)),
+		trace: TraceContext::outgoing(),
+	};
+	if context.timeout.is_none() && context.trace.is_none() {
+		Request::Call(method)
+	} else {
+		Request::ContextCall(method, context)
+	}
+}
+
+/// Send the values of a stream as the input of a call
+async fn forward_input<TX, I, P>(
+	packet_sender: PacketSender<TX>,
+	call_id: u64,
+	input: impl Stream<Item = I>,
+	wrap: fn(StreamPacket<I, !>) -> P,
+) where
+	TX: AsyncWrite + Unpin,
+	P: Write<Error = anyhow::Error> + Sync,
+{
+	let mut input = pin!(input);
+	while let Some(value) = input.next().await {
+		let packet = Clientbound {
+			call_id,
+			payload: Request::<!, _>::Stream(wrap(StreamPacket::Value(value))),
+		};
+		if let Err(e) = packet_sender.write(packet).await {
+			error!("Error while sending input stream: {e}");
+			return;
 		}
 	}
+
+	let packet = Clientbound {
+		call_id,
+		payload: Request::<!, _>::Stream(wrap(StreamPacket::EndOfStream)),
+	};
+	if let Err(e) = packet_sender.write(packet).await {
+		error!("Error while sending end of input stream: {e}");
+	}
+}
+
+/// Cancel a streamed call when dropped
+///
+/// This tells the server to stop sending packets for this call, and removes
+/// its callback so that packets already in flight are skipped. The task
+/// sending the input stream of the call, if any, is always stopped.
+struct CancelGuard<TX: AsyncWrite + Unpin + Send + Sync + 'static> {
+	call_id: u64,
+	packet_sender: PacketSender<TX>,
+	input: Option<AbortHandle>,
+	armed: bool,
+}
+
+impl<TX: AsyncWrite + Unpin + Send + Sync + 'static> CancelGuard<TX> {
+	/// Don't cancel the call when dropped, because it has already ended
+	const fn disarm(&mut self) {
+		self.armed = false;
+	}
+}
+
+impl<TX: AsyncWrite + Unpin + Send + Sync + 'static> Drop for CancelGuard<TX> {
+	fn drop(&mut self) {
+		if let Some(input) = &self.input {
+			input.abort();
+		}
+		if !self.armed {
+			return;
+		}
+		// no runtime means the program is exiting, the server will notice the
+		// connection closing anyway
+		let Ok(runtime) = Handle::try_current() else {
+			return;
+		};
+
+		let call_id = self.call_id;
+		let packet_sender = self.packet_sender.clone();
+		runtime.spawn(async move {
+			// error if the connection is broken, in which case there's nothing to cancel
+			let _ = packet_sender
+				.write(Clientbound {
+					call_id,
+					payload: Request::<!, !>::Cancel,
+				})
+				.await;
+		});
+	}
 }
 
 impl<RX, TX> Debug for Client<RX, TX>
@@ -252,7 +766,459 @@ where
 		f.debug_struct("Client")
 			.field("next_call_id", &self.next_call_id)
 			.field("packet_sender", &self.packet_sender)
-			.field("callbacks", &"<callbacks>")
+			.field("routes", &"<routes>")
+			.field("limits", &self.limits)
+			.field("timeout", &self.timeout)
 			.finish()
 	}
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+	use tokio::io::{DuplexStream, ReadHalf, WriteHalf, duplex, split};
+
+	use super::*;
+	use crate::protocol::{ANY_FINGERPRINT, Response, Serverbound, decode, split_frame};
+
+	type TestClient = Client<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>;
+	type TestServer = (
+		PacketReceiver<ReadHalf<DuplexStream>>,
+		PacketSender<WriteHalf<DuplexStream>>,
+	);
+
+	async fn connect(
+		client_fingerprint: u64,
+		server_fingerprint: u64,
+	) -> (Result<TestClient>, TestServer) {
+		let (client_side, server_side) = duplex(1024);
+		let (rx, tx) = split(client_side);
+		let (server_rx, server_tx) = split(server_side);
+		let mut server_rx = PacketReceiver::new(server_rx);
+		let server_tx = PacketSender::new(server_tx);
+
+		let (client, _) = tokio::join!(
+			Client::new(rx, tx, client_fingerprint),
+			handshake(&mut server_rx, &server_tx, server_fingerprint, Side::Server)
+		);
+		(client, (server_rx, server_tx))
+	}
+
+	#[tokio::test]
+	async fn test_any_fingerprint() {
+		let (client, _server) = connect(ANY_FINGERPRINT, 2).await;
+		assert!(client.is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_incompatible_protocol() {
+		let (client, _server) = connect(1, 2).await;
+		assert!(matches!(
+			client,
+			Err(Error::IncompatibleProtocol {
+				local: 1,
+				remote: 2
+			})
+		));
+	}
+
+	#[tokio::test]
+	async fn test_skip_unknown_call_id() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client.unwrap();
+
+		let server = async move {
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, _) = split_frame(&frame).unwrap();
+
+			// response to a call that doesn't exist (e.g was cancelled), with a payload
+			// that isn't even valid for the real call
+			server_tx
+				.write(Serverbound {
+					call_id: call_id + 1,
+					payload: Response::Value("garbage".to_owned()),
+				})
+				.await
+				.unwrap();
+			server_tx
+				.write(Serverbound {
+					call_id,
+					payload: Response::Value(42u32),
+				})
+				.await
+				.unwrap();
+		};
+
+		let (result, ()) = tokio::join!(client.call::<_, u32>(0u8, None), server);
+		assert_eq!(result.unwrap(), 42);
+	}
+
+	#[tokio::test]
+	async fn test_malformed_response() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client.unwrap();
+
+		let server = async move {
+			for _ in 0..2 {
+				let frame = server_rx.receive().await.unwrap();
+				let (call_id, _) = split_frame(&frame).unwrap();
+
+				server_tx
+					.write(Serverbound {
+						call_id,
+						payload: Response::Value(if call_id == 0 { 2u8 } else { 1u8 }),
+					})
+					.await
+					.unwrap();
+			}
+		};
+
+		let calls = async {
+			// 2 isn't a valid boolean, only this call should fail
+			assert!(matches!(
+				client.call::<_, bool>(0u8, None).await,
+				Err(Error::Read(_))
+			));
+			assert!(client.call::<_, bool>(0u8, None).await.unwrap());
+		};
+		tokio::join!(calls, server);
+	}
+
+	#[tokio::test]
+	async fn test_call_with_input() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client.unwrap();
+
+		let server = async move {
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, _) = split_frame(&frame).unwrap();
+
+			let mut sum = 0;
+			loop {
+				let frame = server_rx.receive().await.unwrap();
+				let (id, payload) = split_frame(&frame).unwrap();
+				assert_eq!(id, call_id);
+				match decode::<Request<!, StreamPacket<u8, !>>>(payload, Limits::default())
+					.await
+					.unwrap()
+				{
+					Request::Stream(StreamPacket::Value(x)) => sum += x,
+					Request::Stream(StreamPacket::EndOfStream) => break,
+					request => panic!("unexpected request: {request:?}"),
+				}
+			}
+
+			server_tx
+				.write(Serverbound {
+					call_id,
+					payload: Response::Value(sum),
+				})
+				.await
+				.unwrap();
+		};
+
+		let (result, ()) = tokio::join!(
+			client.call_with_input::<_, u8, _, _>(
+				0u8,
+				futures::stream::iter([1u8, 2, 3]),
+				|x| x,
+				None
+			),
+			server
+		);
+		assert_eq!(result.unwrap(), 6);
+	}
+
+	#[tokio::test]
+	async fn test_response_exceeds_limits() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client
+			.unwrap()
+			.with_limits(Limits::default().with_max_string_bytes(4));
+
+		let server = async move {
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, _) = split_frame(&frame).unwrap();
+			server_tx
+				.write(Serverbound {
+					call_id,
+					payload: Response::Value("Hello".to_owned()),
+				})
+				.await
+				.unwrap();
+		};
+
+		let (result, ()) = tokio::join!(client.call::<_, String>(0u8, None), server);
+		assert!(matches!(
+			result,
+			Err(Error::LimitExceeded(
+				crate::LimitExceededError::StringBytes { length: 5, max: 4 }
+			))
+		));
+	}
+
+	#[tokio::test]
+	async fn test_frame_exceeds_limits() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client
+			.unwrap()
+			.with_limits(Limits::default().with_max_frame_bytes(128));
+
+		let server = async move {
+			for _ in 0..2 {
+				let frame = server_rx.receive().await.unwrap();
+				let (call_id, _) = split_frame(&frame).unwrap();
+				let payload = if call_id == 0 {
+					"x".repeat(256)
+				} else {
+					"ok".to_owned()
+				};
+				server_tx
+					.write(Serverbound {
+						call_id,
+						payload: Response::Value(payload),
+					})
+					.await
+					.unwrap();
+			}
+		};
+
+		let calls = async {
+			// only the call whose response is too big fails
+			assert!(matches!(
+				client.call::<_, String>(0u8, None).await,
+				Err(Error::LimitExceeded(
+					crate::LimitExceededError::FrameBytes { max: 128, .. }
+				))
+			));
+			assert_eq!(client.call::<_, String>(0u8, None).await.unwrap(), "ok");
+		};
+		tokio::join!(calls, server);
+	}
+
+	#[tokio::test]
+	async fn test_subscribe() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client.unwrap();
+
+		// returns before the server sends anything
+		let signals = client.subscribe::<u8>(3).await.unwrap();
+
+		let frame = server_rx.receive().await.unwrap();
+		let (call_id, payload) = split_frame(&frame).unwrap();
+		assert!(matches!(
+			decode::<Request<!, !>>(payload, Limits::default())
+				.await
+				.unwrap(),
+			Request::Subscribe(3)
+		));
+		for value in [1u8, 2] {
+			server_tx
+				.write(Serverbound {
+					call_id,
+					payload: Response::Value(StreamPacket::<_, !>::Value(value)),
+				})
+				.await
+				.unwrap();
+		}
+
+		let mut signals = Box::pin(signals);
+		assert_eq!(signals.next().await.unwrap().unwrap(), 1);
+		assert_eq!(signals.next().await.unwrap().unwrap(), 2);
+		drop(signals);
+
+		let frame = server_rx.receive().await.unwrap();
+		let (cancelled_id, payload) = split_frame(&frame).unwrap();
+		assert_eq!(cancelled_id, call_id);
+		assert!(matches!(
+			decode::<Request<!, !>>(payload, Limits::default())
+				.await
+				.unwrap(),
+			Request::Cancel
+		));
+	}
+
+	#[tokio::test]
+	async fn test_cancel_dropped_stream() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client.unwrap();
+
+		let server = async move {
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, _) = split_frame(&frame).unwrap();
+			server_tx
+				.write(Serverbound {
+					call_id,
+					payload: Response::Value(StreamPacket::<u8, !>::Value(1)),
+				})
+				.await
+				.unwrap();
+
+			let frame = server_rx.receive().await.unwrap();
+			let (cancelled_id, payload) = split_frame(&frame).unwrap();
+			assert_eq!(cancelled_id, call_id);
+			assert!(matches!(
+				decode::<Request<u8, !>>(payload, Limits::default())
+					.await
+					.unwrap(),
+				Request::Cancel
+			));
+		};
+
+		let call = async {
+			let stream = client
+				.long_call::<_, u8, !>(0u8, None)
+				.await
+				.unwrap()
+				.unwrap();
+			drop(stream);
+		};
+		tokio::join!(call, server);
+		assert!(client.routes.is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_routes_removed() {
+		let (client, (mut server_rx, server_tx)) = connect(0, 0).await;
+		let client = client.unwrap();
+
+		let server = async move {
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, _) = split_frame(&frame).unwrap();
+			server_tx
+				.write(Serverbound {
+					call_id,
+					payload: Response::Value(1u8),
+				})
+				.await
+				.unwrap();
+
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, _) = split_frame(&frame).unwrap();
+			for packet in [StreamPacket::<u8, !>::Value(2), StreamPacket::EndOfStream] {
+				server_tx
+					.write(Serverbound {
+						call_id,
+						payload: Response::Value(packet),
+					})
+					.await
+					.unwrap();
+			}
+		};
+
+		let calls = async {
+			assert_eq!(client.call::<_, u8>(0u8, None).await.unwrap(), 1);
+			assert!(client.routes.is_empty());
+
+			let stream = client
+				.long_call::<_, u8, !>(0u8, None)
+				.await
+				.unwrap()
+				.unwrap();
+			let values: Vec<_> = stream.map(Result::unwrap).collect().await;
+			assert_eq!(values, [2]);
+		};
+		tokio::join!(calls, server);
+		assert!(client.routes.is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_call_timeout() {
+		let (client, (mut server_rx, _server_tx)) = connect(0, 0).await;
+		let client = client.unwrap().with_timeout(Duration::from_secs(60));
+
+		let server = async move {
+			// the call's own timeout is used instead of the client's one
+			let frame = server_rx.receive().await.unwrap();
+			let (call_id, payload) = split_frame(&frame).unwrap();
+			assert!(matches!(
+				decode::<Request<u8, !>>(payload, Limits::default())
+					.await
+					.unwrap(),
+				Request::ContextCall(
+					0,
+					CallContext {
+						timeout: Some(50),
+						..
+					}
+				)
+			));
+
+			// the server never answers, the client gives up
+			let frame = server_rx.receive().await.unwrap();
+			let (cancelled_id, payload) = split_frame(&frame).unwrap();
+			assert_eq!(cancelled_id, call_id);
+			assert!(matches!(
+				decode::<Request<u8, !>>(payload, Limits::default())
+					.await
+					.unwrap(),
+				Request::Cancel
+			));
+		};
+
+		let (result, ()) = tokio::join!(
+			client.call::<_, u32>(0u8, Some(Duration::from_millis(50))),
+			server
+		);
+		assert!(matches!(result, Err(Error::Timeout)));
+	}
+
+	#[tokio::test]
+	async fn test_connect_with_retry() {
+		let path = std::env::temp_dir().join(format!("ipc-retry-{}.sock", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let address = SocketAddr::from_pathname(&path).unwrap();
+
+		// nobody listens yet, the client waits for the server to be up
+		let server = async {
+			sleep(Duration::from_millis(50)).await;
+			let listener = tokio::net::UnixListener::bind(&path).unwrap();
+			let (stream, _) = listener.accept().await.unwrap();
+			let (rx, tx) = stream.into_split();
+			handshake(
+				&mut PacketReceiver::new(rx),
+				&PacketSender::new(tx),
+				0,
+				Side::Server,
+			)
+			.await
+			.unwrap();
+		};
+		let (client, ()) = tokio::join!(
+			Client::from_unix_address_with_retry(&address, 0, Duration::from_secs(5)),
+			server
+		);
+		client.unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		// gives up once the timeout is expired
+		assert!(matches!(
+			Client::from_unix_address_with_retry(&address, 0, Duration::from_millis(50)).await,
+			Err(Error::Connect(e)) if e.kind() == ErrorKind::NotFound
+		));
+	}
+
+	#[tokio::test]
+	async fn test_connect_abstract_socket() {
+		let name = format!("ipc-abstract-{}", std::process::id());
+		let address = SocketAddr::from_abstract_name(&name).unwrap();
+		let listener = std::os::unix::net::UnixListener::bind_addr(&address).unwrap();
+		listener.set_nonblocking(true).unwrap();
+		let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+
+		let server = async {
+			let (stream, _) = listener.accept().await.unwrap();
+			let (rx, tx) = stream.into_split();
+			handshake(
+				&mut PacketReceiver::new(rx),
+				&PacketSender::new(tx),
+				0,
+				Side::Server,
+			)
+			.await
+			.unwrap();
+		};
+		let (client, ()) = tokio::join!(Client::from_unix_address(&address, 0), server);
+		client.unwrap();
+	}
+}
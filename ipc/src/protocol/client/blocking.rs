@@ -0,0 +1,569 @@
+//! Client making calls from synchronous code, without an async runtime
+//!
+//! The socket is only read while a call waits for its response, so calls are made
+//! one after the other. The frames of calls that were cancelled or timed out are
+//! skipped when they arrive.
+
+use std::{
+	fmt::{self, Debug},
+	io::{self, ErrorKind, Write as _},
+	marker::PhantomData,
+	os::{
+		fd::OwnedFd,
+		unix::net::{SocketAddr, UnixStream},
+	},
+	result::Result as StdResult,
+	time::{Duration, Instant},
+};
+
+use futures::executor::block_on;
+use log::error;
+
+use super::{call_request, demux::decode_response};
+use crate::{
+	Limits, Read, Result, Write,
+	capture::{Side, Tap},
+	fd,
+	protocol::{
+		Clientbound, Error, Frame, Handshake, Request, SkippedFrame, StreamPacket,
+		check_fingerprint, decode, split_frame,
+	},
+	schema::Schema,
+};
+
+/// Size of the buffer used to skip the frames exceeding the limits
+const SKIP_BUFFER_SIZE: usize = 4096;
+
+/// Client of a protocol using a blocking unix socket
+///
+/// Every call blocks the current thread until its response is received, so it
+/// mustn't be used from an async task.
+pub struct BlockingClient {
+	stream: UnixStream,
+	next_call_id: u64,
+	limits: Limits,
+	/// Timeout of the calls that don't have their own
+	timeout: Option<Duration>,
+	/// Record the sent and received frames, if they are captured
+	taps: Option<(Tap, Tap)>,
+}
+
+impl BlockingClient {
+	/// Create a connection from a unix socket address
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the socket can't be connected to
+	/// the address, or if the handshake with the server fails.
+	pub fn from_unix_address(address: &SocketAddr, fingerprint: u64) -> Result<Self> {
+		let stream = UnixStream::connect_addr(address).map_err(Error::Connect)?;
+		Self::new(stream, fingerprint)
+	}
+
+	/// Create a connection over a connected socket, and do the handshake with the server
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the handshake with the server fails.
+	pub fn new(stream: UnixStream, fingerprint: u64) -> Result<Self> {
+		let mut client = Self {
+			stream,
+			next_call_id: 0,
+			limits: Limits::default(),
+			timeout: None,
+			taps: Tap::new_pair(Side::Client),
+		};
+
+		client
+			.send(Handshake { fingerprint })
+			.map_err(|e| Error::Connect(io::Error::other(e)))?;
+		let frame = client.receive(None).map_err(Error::Connect)?;
+		let remote = block_on(decode::<Handshake>(&frame, Limits::default()))
+			.map_err(Error::Read)?
+			.fingerprint;
+		check_fingerprint(fingerprint, remote)?;
+
+		Ok(client)
+	}
+
+	/// Set the limits of the values sent to and received from the server
+	#[must_use]
+	pub const fn with_limits(mut self, limits: Limits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Set the maximum time to wait for the response of a call
+	///
+	/// This only applies to calls that aren't streamed and don't have their own
+	/// timeout, since streams can legitimately stay silent for a long time.
+	#[must_use]
+	pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	/// Call a method and wait for its response
+	///
+	/// If `timeout` is [`None`], the timeout of the client is used.
+	pub fn call<T, R>(&mut self, method: T, timeout: Option<Duration>) -> Result<R>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		anyhow::Error: From<T::Error> + From<R::Error>,
+	{
+		let timeout = timeout.or(self.timeout);
+		let deadline = deadline(timeout);
+		let call_id = self.start_call(call_request(method, timeout))?;
+		self.wait_packet(call_id, deadline)
+	}
+
+	/// Same as [`Self::call`], but also send the values of `input` to the server
+	///
+	/// `wrap` is used to put the values in the packet type expected by the server.
+	/// All of `input` is sent before waiting for the response.
+	pub fn call_with_input<T, R, I, P>(
+		&mut self,
+		method: T,
+		input: impl IntoIterator<Item = I>,
+		wrap: fn(StreamPacket<I, !>) -> P,
+		timeout: Option<Duration>,
+	) -> Result<R>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		P: Write<Error = anyhow::Error> + Send + Sync,
+		anyhow::Error: From<T::Error> + From<R::Error>,
+	{
+		let timeout = timeout.or(self.timeout);
+		let deadline = deadline(timeout);
+		let call_id = self.start_call(call_request(method, timeout))?;
+		self.send_input(call_id, input, wrap)?;
+		self.wait_packet(call_id, deadline)
+	}
+
+	/// Call a streamed method
+	///
+	/// `timeout` only applies to the first packet of the stream.
+	pub fn long_call<T, R, E>(
+		&mut self,
+		method: T,
+		timeout: Option<Duration>,
+	) -> Result<StdResult<BlockingStream<'_, R, E>, E>>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let deadline = deadline(timeout);
+		let call_id = self.start_call(call_request(method, timeout))?;
+		self.receive_stream(call_id, deadline)
+	}
+
+	/// Same as [`Self::long_call`], but also send the values of `input` to the server
+	///
+	/// `wrap` is used to put the values in the packet type expected by the server.
+	/// All of `input` is sent before waiting for the first packet of the stream.
+	pub fn long_call_with_input<T, R, E, I, P>(
+		&mut self,
+		method: T,
+		input: impl IntoIterator<Item = I>,
+		wrap: fn(StreamPacket<I, !>) -> P,
+		timeout: Option<Duration>,
+	) -> Result<StdResult<BlockingStream<'_, R, E>, E>>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		P: Write<Error = anyhow::Error> + Send + Sync,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let deadline = deadline(timeout);
+		let call_id = self.start_call(call_request(method, timeout))?;
+		self.send_input(call_id, input, wrap)?;
+		self.receive_stream(call_id, deadline)
+	}
+
+	/// Subscribe to a signal of the server
+	///
+	/// The emissions are received while iterating over the returned stream, and
+	/// dropping it cancels the subscription.
+	pub fn subscribe<R>(&mut self, signal: u64) -> Result<BlockingStream<'_, R, !>>
+	where
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		anyhow::Error: From<R::Error>,
+	{
+		let call_id = self.start_call::<!>(Request::Subscribe(signal))?;
+		Ok(BlockingStream::new(self, call_id, None))
+	}
+
+	/// Ask the server for the schema of its protocol
+	///
+	/// If `timeout` is [`None`], the timeout of the client is used.
+	pub fn describe(&mut self, timeout: Option<Duration>) -> Result<Schema> {
+		let deadline = deadline(timeout.or(self.timeout));
+		let call_id = self.start_call::<!>(Request::Describe)?;
+		self.wait_packet(call_id, deadline)
+	}
+
+	/// Send a request with a new call id, and return this id
+	fn start_call<T>(&mut self, request: Request<T, !>) -> Result<u64>
+	where
+		T: Write + Send + Sync,
+		T::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error>,
+	{
+		let call_id = self.next_call_id;
+		self.next_call_id += 1;
+		self.send::<Clientbound<Request<T, !>>>(Clientbound {
+			call_id,
+			payload: request,
+		})?;
+
+		Ok(call_id)
+	}
+
+	/// Send the values of an iterator as the input of a call
+	fn send_input<I, P>(
+		&mut self,
+		call_id: u64,
+		input: impl IntoIterator<Item = I>,
+		wrap: fn(StreamPacket<I, !>) -> P,
+	) -> Result<()>
+	where
+		P: Write<Error = anyhow::Error> + Send + Sync,
+	{
+		let packets = input
+			.into_iter()
+			.map(StreamPacket::Value)
+			.chain([StreamPacket::EndOfStream]);
+		for packet in packets {
+			self.send(Clientbound {
+				call_id,
+				payload: Request::<!, _>::Stream(wrap(packet)),
+			})?;
+		}
+
+		Ok(())
+	}
+
+	/// Wait for the first packet of a streamed call, and return the stream of the others
+	fn receive_stream<R, E>(
+		&mut self,
+		call_id: u64,
+		deadline: Option<Instant>,
+	) -> Result<StdResult<BlockingStream<'_, R, E>, E>>
+	where
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<R::Error> + From<E::Error>,
+	{
+		// created right away so that the call is also cancelled if the first packet is invalid
+		let mut stream = BlockingStream::new(self, call_id, None);
+		match stream.client.wait_packet(call_id, deadline)? {
+			StreamPacket::Value(x) => stream.first = Some(x),
+			StreamPacket::EndOfStream => stream.running = false,
+			StreamPacket::Error(e) => {
+				stream.running = false;
+				return Ok(Err(e));
+			}
+		}
+
+		Ok(Ok(stream))
+	}
+
+	/// Wait until `deadline` for the next packet of a call, and decode it
+	///
+	/// The call is cancelled if the deadline passes.
+	fn wait_packet<T>(&mut self, call_id: u64, deadline: Option<Instant>) -> Result<T>
+	where
+		T: Read + Send + Sync + 'static,
+		T::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error>,
+	{
+		loop {
+			let error = match self.receive(deadline) {
+				Ok(frame) => match split_frame(&frame) {
+					Ok((id, _)) if id == call_id => {
+						return block_on(decode_response(Ok(frame), self.limits));
+					}
+					// a call that was cancelled, or timed out
+					Ok(_) => continue,
+					Err(e) => {
+						error!("Received malformed packet: {e}");
+						continue;
+					}
+				},
+				Err(e) => e,
+			};
+
+			if let Some(skipped) = SkippedFrame::from_io(&error) {
+				error!("{skipped}");
+				if skipped.call_id == Some(call_id) {
+					return Err(Error::LimitExceeded(skipped.error));
+				}
+			} else if error.kind() == ErrorKind::TimedOut {
+				self.cancel(call_id);
+				return Err(Error::Timeout);
+			} else {
+				if error.kind() != ErrorKind::UnexpectedEof {
+					error!("Error while receiving response: {error}");
+				}
+				return Err(Error::ConnectionBroken);
+			}
+		}
+	}
+
+	/// Tell the server to stop sending packets for a call
+	fn cancel(&mut self, call_id: u64) {
+		// error if the connection is broken, in which case there's nothing to cancel
+		let _ = self.send(Clientbound {
+			call_id,
+			payload: Request::<!, !>::Cancel,
+		});
+	}
+
+	/// Send a packet as a single frame, like [`PacketSender::write`](crate::protocol::PacketSender::write)
+	fn send<T>(&mut self, packet: T) -> Result<()>
+	where
+		T: Write,
+		anyhow::Error: From<T::Error>,
+	{
+		let (frame, fds) = block_on(fd::sending_fds(async {
+			// the length of the frame is written over the first 8 bytes
+			let mut frame = vec![0; 8];
+			Write::write(&packet, &mut frame).await?;
+			anyhow::Ok(frame)
+		}));
+		let mut frame = frame.map_err(Error::from_decode)?;
+		let len = frame.len() as u64 - 8;
+		self.limits
+			.check_frame_bytes(len)
+			.map_err(Error::LimitExceeded)?;
+		frame[..8].copy_from_slice(&len.to_be_bytes());
+
+		// recorded first, like with async clients
+		if let Some((tap, _)) = &mut self.taps {
+			tap.record(&frame[8..]);
+		}
+		let sent = if fds.is_empty() {
+			0
+		} else {
+			fd::send(&self.stream, &frame, &fds).map_err(|_| Error::ConnectionBroken)?
+		};
+		(&self.stream)
+			.write_all(&frame[sent..])
+			.map_err(|_| Error::ConnectionBroken)?;
+		Ok(())
+	}
+
+	/// Receive the next frame, waiting until `deadline` for it to start
+	///
+	/// Errors are the same as the ones of
+	/// [`PacketReceiver::receive`](crate::protocol::PacketReceiver::receive), and
+	/// [`ErrorKind::TimedOut`] if the deadline passed.
+	fn receive(&mut self, deadline: Option<Instant>) -> io::Result<Frame> {
+		let mut fds = Vec::new();
+		let mut len = [0; 8];
+		let mut read = 0;
+		if let Some(deadline) = deadline {
+			let remaining = deadline
+				.checked_duration_since(Instant::now())
+				.filter(|x| !x.is_zero())
+				.ok_or(ErrorKind::TimedOut)?;
+			self.stream.set_read_timeout(Some(remaining))?;
+			let result = receive_some(&self.stream, &mut len, &mut fds);
+			// the rest of the frame is already on its way
+			self.stream.set_read_timeout(None)?;
+			read = result.map_err(|e| match e.kind() {
+				ErrorKind::WouldBlock => ErrorKind::TimedOut.into(),
+				_ => e,
+			})?;
+		}
+		receive_exact(&self.stream, &mut len[read..], &mut fds)?;
+
+		let len = u64::from_be_bytes(len);
+		let len = match self.limits.check_frame_bytes(len) {
+			Ok(len) => len,
+			Err(error) => {
+				// every frame starts with the call id, see `split_frame`
+				let call_id = if len >= 8 {
+					let mut call_id = [0; 8];
+					receive_exact(&self.stream, &mut call_id, &mut fds)?;
+					Some(u64::from_be_bytes(call_id))
+				} else {
+					None
+				};
+				let mut remaining = len.saturating_sub(8);
+				let mut buffer = [0; SKIP_BUFFER_SIZE];
+				while remaining > 0 {
+					let chunk = remaining.min(SKIP_BUFFER_SIZE as u64) as usize;
+					receive_exact(&self.stream, &mut buffer[..chunk], &mut fds)?;
+					remaining -= chunk as u64;
+				}
+
+				return Err(io::Error::new(
+					ErrorKind::InvalidData,
+					SkippedFrame { call_id, error },
+				));
+			}
+		};
+
+		let mut bytes = vec![0; len];
+		receive_exact(&self.stream, &mut bytes, &mut fds)?;
+		if let Some((_, tap)) = &mut self.taps {
+			tap.record(&bytes);
+		}
+
+		Ok(Frame { bytes, fds })
+	}
+}
+
+impl Debug for BlockingClient {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("BlockingClient")
+			.field("next_call_id", &self.next_call_id)
+			.field("limits", &self.limits)
+			.field("timeout", &self.timeout)
+			.finish_non_exhaustive()
+	}
+}
+
+/// Values of a streamed call or of a subscription, received while iterating
+///
+/// It borrows the client, so no other call can be made until it's dropped. Dropping
+/// it before the end of the stream cancels the call.
+pub struct BlockingStream<'a, R, E> {
+	client: &'a mut BlockingClient,
+	call_id: u64,
+	first: Option<R>,
+	/// Whether the server may still send packets for the call
+	running: bool,
+	/// Whether the stream failed, after which nothing else is returned
+	failed: bool,
+	_error: PhantomData<fn() -> E>,
+}
+
+impl<'a, R, E> BlockingStream<'a, R, E> {
+	const fn new(client: &'a mut BlockingClient, call_id: u64, first: Option<R>) -> Self {
+		Self {
+			client,
+			call_id,
+			first,
+			running: true,
+			failed: false,
+			_error: PhantomData,
+		}
+	}
+}
+
+impl<R, E> Iterator for BlockingStream<'_, R, E>
+where
+	R: Read + Send + Sync + 'static,
+	R::Error: Send + Sync,
+	E: Read + Send + Sync + 'static + Debug,
+	E::Error: Send + Sync + 'static,
+	anyhow::Error: From<R::Error> + From<E::Error>,
+{
+	type Item = Result<R>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if let Some(first) = self.first.take() {
+			return Some(Ok(first));
+		}
+		if !self.running || self.failed {
+			return None;
+		}
+
+		match self
+			.client
+			.wait_packet::<StreamPacket<R, E>>(self.call_id, None)
+		{
+			Ok(StreamPacket::Value(x)) => Some(Ok(x)),
+			Ok(StreamPacket::Error(e)) => {
+				error!("Unexpected early error after receiving value: {e:?}");
+				self.running = false;
+				None
+			}
+			Ok(StreamPacket::EndOfStream) => {
+				self.running = false;
+				None
+			}
+			Err(e) => {
+				self.failed = true;
+				Some(Err(e))
+			}
+		}
+	}
+}
+
+impl<R, E> Drop for BlockingStream<'_, R, E> {
+	fn drop(&mut self) {
+		if self.running {
+			self.client.cancel(self.call_id);
+		}
+	}
+}
+
+impl<R, E> Debug for BlockingStream<'_, R, E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("BlockingStream")
+			.field("call_id", &self.call_id)
+			.field("running", &self.running)
+			.finish_non_exhaustive()
+	}
+}
+
+/// Instant at which a call times out
+fn deadline(timeout: Option<Duration>) -> Option<Instant> {
+	timeout.map(|timeout| Instant::now() + timeout)
+}
+
+/// Receive some bytes, and the file descriptors sent with them
+///
+/// Returns [`ErrorKind::UnexpectedEof`] if the socket was closed.
+fn receive_some(
+	stream: &UnixStream,
+	bytes: &mut [u8],
+	fds: &mut Vec<OwnedFd>,
+) -> io::Result<usize> {
+	loop {
+		match fd::receive(stream, bytes) {
+			Ok((0, _)) if !bytes.is_empty() => return Err(ErrorKind::UnexpectedEof.into()),
+			Ok((read, received)) => {
+				fds.extend(received);
+				return Ok(read);
+			}
+			Err(e) if e.kind() == ErrorKind::Interrupted => {}
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Fill `bytes`, collecting the file descriptors sent with them
+fn receive_exact(
+	stream: &UnixStream,
+	mut bytes: &mut [u8],
+	fds: &mut Vec<OwnedFd>,
+) -> io::Result<()> {
+	while !bytes.is_empty() {
+		let read = receive_some(stream, bytes, fds)?;
+		bytes = &mut bytes[read..];
+	}
+
+	Ok(())
+}
@@ -0,0 +1,230 @@
+//! Sending the frames received by a client to the calls they belong to
+//!
+//! The task receiving frames only looks up the channel of their call, they are
+//! decoded by the call itself. This way, a slow decoding doesn't delay the
+//! other calls.
+
+use std::{
+	io::ErrorKind,
+	marker::PhantomData,
+	result::Result as StdResult,
+	sync::{Arc, Mutex, PoisonError},
+};
+
+use log::error;
+use tokio::{io::AsyncRead, sync::mpsc};
+
+use crate::{
+	LimitExceededError, Limits, Read, Result,
+	protocol::{Error, Frame, PacketReceiver, Response, SkippedFrame, split_frame},
+};
+
+/// A received frame, starting with its call id, or the reason it was skipped
+pub type RoutedFrame = StdResult<Frame, LimitExceededError>;
+
+/// What a call receives from its route
+pub enum Routed {
+	Frame(RoutedFrame),
+	/// The route was removed by [`Routes::retain`], no frame will be received
+	Closed,
+}
+
+/// Channel receiving the frames of a call
+pub type Receiver = mpsc::UnboundedReceiver<Routed>;
+
+/// Where the frames of a call are sent
+pub struct Route {
+	tx: mpsc::UnboundedSender<Routed>,
+	/// The route is removed after its first frame
+	once: bool,
+}
+
+impl Route {
+	/// Route of a call expecting a single response, removed once it's received
+	pub fn response() -> (Self, Receiver) {
+		let (tx, rx) = mpsc::unbounded_channel();
+		(Self { tx, once: true }, rx)
+	}
+
+	/// Route of a streamed call or a subscription, removed when its receiver is dropped
+	pub fn stream() -> (Self, Receiver) {
+		let (tx, rx) = mpsc::unbounded_channel();
+		(Self { tx, once: false }, rx)
+	}
+}
+
+/// Routes of the calls in progress, by call id
+///
+/// The map is lock-free, so dispatching a frame never waits for calls being
+/// started or finished. Removed routes are only dropped once no thread can
+/// still use them, which is why [`Self::retain`] tells the calls it fails.
+#[derive(Default, Clone)]
+pub struct Routes(Arc<papaya::HashMap<u64, Route>>);
+
+impl Routes {
+	pub fn insert(&self, call_id: u64, route: Route) {
+		self.0.pin().insert(call_id, route);
+	}
+
+	pub fn remove(&self, call_id: u64) {
+		self.0.pin().remove(&call_id);
+	}
+
+	#[cfg(test)]
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	pub fn contains(&self, call_id: u64) -> bool {
+		self.0.pin().contains_key(&call_id)
+	}
+
+	/// Only keep the routes of the calls for which `keep` returns `true`
+	///
+	/// The other calls fail with [`Error::ConnectionBroken`].
+	pub fn retain(&self, mut keep: impl FnMut(u64) -> bool) {
+		self.0.pin().retain(|&call_id, route| {
+			let keep = keep(call_id);
+			if !keep {
+				let _ = route.tx.send(Routed::Closed);
+			}
+			keep
+		});
+	}
+
+	/// Send a frame to its call, or skip it if the call was cancelled
+	pub fn dispatch(&self, call_id: u64, frame: RoutedFrame) {
+		let routes = self.0.pin();
+		let route = match routes.get(&call_id) {
+			// only the thread removing the route sends the response
+			Some(route) if route.once => routes.remove(&call_id),
+			route => route,
+		};
+		let Some(route) = route else {
+			return;
+		};
+		// error if the call was cancelled, the frame is skipped
+		if route.tx.send(Routed::Frame(frame)).is_err() && !route.once {
+			// the receiver is being dropped, it won't remove the route itself
+			routes.remove(&call_id);
+		}
+	}
+}
+
+/// Packets of a streamed call (or a subscription), decoded as they are received
+///
+/// The route of the call is removed when this is dropped.
+pub struct Packets<T> {
+	call_id: u64,
+	frames: Receiver,
+	routes: Routes,
+	limits: Limits,
+	_packet: PhantomData<fn() -> T>,
+}
+
+impl<T> Packets<T>
+where
+	T: Read + Send + Sync + 'static,
+	T::Error: Send + Sync + 'static,
+	anyhow::Error: From<T::Error>,
+{
+	pub fn new(call_id: u64, frames: Receiver, routes: Routes, limits: Limits) -> Self {
+		Self {
+			call_id,
+			frames,
+			routes,
+			limits,
+			_packet: PhantomData,
+		}
+	}
+
+	/// Receive the next packet, [`None`] if the connection broke
+	pub async fn recv(&mut self) -> Option<Result<T>> {
+		match self.frames.recv().await? {
+			Routed::Frame(frame) => Some(decode_response(frame, self.limits).await),
+			Routed::Closed => None,
+		}
+	}
+}
+
+impl<T> Drop for Packets<T> {
+	fn drop(&mut self) {
+		self.routes.remove(self.call_id);
+	}
+}
+
+/// Wait for the response of a call and decode it, [`None`] if the connection broke
+pub async fn response<T>(mut rx: Receiver, limits: Limits) -> Option<Result<T>>
+where
+	T: Read + Send + Sync + 'static,
+	T::Error: Send + Sync + 'static,
+	anyhow::Error: From<T::Error>,
+{
+	match rx.recv().await? {
+		Routed::Frame(frame) => Some(decode_response(frame, limits).await),
+		Routed::Closed => None,
+	}
+}
+
+/// Decode the payload of a [`Serverbound`](crate::protocol::Serverbound) packet
+pub async fn decode_response<T>(frame: RoutedFrame, limits: Limits) -> Result<T>
+where
+	T: Read + Send + Sync + 'static,
+	T::Error: Send + Sync + 'static,
+	anyhow::Error: From<T::Error>,
+{
+	let frame = frame.map_err(Error::LimitExceeded)?;
+	// the frame was already split once, before being routed
+	let (_, response) = frame
+		.decode_payload::<Response<T>>(limits)
+		.await
+		.map_err(|e| Error::from_decode(e.into()))?;
+	match response.map_err(Error::from_decode)? {
+		Response::Value(x) => Ok(x),
+		Response::InvalidCall(reason) => Err(Error::InvalidCall(reason)),
+		Response::LimitExceeded(e) => Err(Error::LimitExceeded(e)),
+		Response::Unauthorized => Err(Error::Unauthorized),
+		Response::RateLimited => Err(Error::RateLimited),
+		Response::ShuttingDown => Err(Error::ShuttingDown),
+	}
+}
+
+/// Send the frames received to the calls they belong to, until the connection breaks
+pub async fn receive_frames<RX: AsyncRead + Unpin>(
+	rx: &mut PacketReceiver<RX>,
+	routes: &Routes,
+	receiver_limits: &Mutex<Limits>,
+) {
+	loop {
+		let limits = *receiver_limits
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		rx.set_limits(limits);
+
+		let (call_id, frame) = match rx.receive().await {
+			Ok(frame) => match split_frame(&frame) {
+				Ok((call_id, _)) => (call_id, Ok(frame)),
+				Err(e) => {
+					error!("Received malformed packet: {e}");
+					continue;
+				}
+			},
+			Err(e) => match SkippedFrame::from_io(&e) {
+				Some(skipped) => {
+					error!("{skipped}");
+					let Some(call_id) = skipped.call_id else {
+						continue;
+					};
+					(call_id, Err(skipped.error))
+				}
+				None => {
+					if e.kind() != ErrorKind::UnexpectedEof {
+						error!("Error while receiving response: {e}");
+					}
+					return;
+				}
+			},
+		};
+		routes.dispatch(call_id, frame);
+	}
+}
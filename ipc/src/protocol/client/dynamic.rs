@@ -0,0 +1,294 @@
+//! Client of protocols only known at runtime, from the schema sent by the server
+
+use std::{
+	io,
+	os::{linux::net::SocketAddrExt, unix::net::SocketAddr},
+	path::Path,
+	result::Result as StdResult,
+	sync::Arc,
+	time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::Client;
+use crate::{
+	Error, Limits, Read, Result, UnixReadHalf, UnixWriteHalf, Write,
+	protocol::ANY_FINGERPRINT,
+	schema::{Schema, ValueError},
+};
+
+/// Client calling the methods of any protocol, with arguments and responses written as text
+///
+/// The client learns the protocol from the [`Schema`] sent by the server, and writes
+/// values with the syntax of [`Schema::encode`]. Methods taking an input stream can't
+/// be called.
+pub struct DynamicClient<RX = UnixReadHalf, TX = UnixWriteHalf>
+where
+	RX: AsyncRead + Unpin + Send + 'static,
+	TX: AsyncWrite + Unpin + Send,
+{
+	inner: Client<RX, TX>,
+	schema: Arc<Schema>,
+}
+
+/// Error of a call made by a [`DynamicClient`]
+#[derive(Debug, Error)]
+pub enum DynamicCallError {
+	#[error(transparent)]
+	Ipc(#[from] Error),
+	#[error("The protocol has no method named {0}")]
+	UnknownMethod(String),
+	#[error("The protocol has no signal named {0}")]
+	UnknownSignal(String),
+	#[error("{method} takes {expected} arguments, but {given} were given")]
+	ArgumentCount {
+		method: String,
+		expected: usize,
+		given: usize,
+	},
+	#[error("Invalid argument {name}: {error}")]
+	Argument {
+		name: String,
+		#[source]
+		error: ValueError,
+	},
+	#[error("{0} takes an input stream, which can't be written as text")]
+	InputStream(String),
+	#[error("Could not decode the response: {0}")]
+	Response(#[source] ValueError),
+}
+
+/// Response of a method called by a [`DynamicClient`], written as text
+#[derive(Debug)]
+pub enum DynamicResponse<S> {
+	/// Response of a method that isn't streamed
+	Value(String),
+	/// Values of a streamed method
+	Stream(S),
+	/// Early error of a streamed method, sent instead of the stream
+	Error(String),
+}
+
+impl DynamicClient {
+	/// Connect to a server listening on a socket file
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the server can't be connected to, or if it
+	/// doesn't send its schema.
+	pub async fn new_with_path_socket(path: &Path) -> Result<Self> {
+		let address = SocketAddr::from_pathname(path).map_err(Error::Connect)?;
+		Self::from_client(Client::from_unix_address(&address, ANY_FINGERPRINT).await?).await
+	}
+
+	/// Connect to a server listening on an abstract socket
+	///
+	/// # Errors
+	///
+	/// See [`Self::new_with_path_socket`].
+	pub async fn new_with_abstract_socket(name: &str) -> Result<Self> {
+		let address = SocketAddr::from_abstract_name(name).map_err(Error::Connect)?;
+		Self::from_client(Client::from_unix_address(&address, ANY_FINGERPRINT).await?).await
+	}
+}
+
+impl<RX, TX> DynamicClient<RX, TX>
+where
+	RX: AsyncRead + Unpin + Send + 'static,
+	TX: AsyncWrite + Unpin + Send + Sync + 'static,
+{
+	/// Connect to a server over any transport
+	///
+	/// # Errors
+	///
+	/// See [`Self::new_with_path_socket`].
+	pub async fn new_with_transport(rx: RX, tx: TX) -> Result<Self> {
+		Self::from_client(Client::new(rx, tx, ANY_FINGERPRINT).await?).await
+	}
+
+	async fn from_client(inner: Client<RX, TX>) -> Result<Self> {
+		let schema = inner.describe(None).await?;
+		Ok(Self {
+			inner,
+			schema: Arc::new(schema),
+		})
+	}
+
+	/// Schema of the protocol of the server
+	pub fn schema(&self) -> &Schema {
+		&self.schema
+	}
+
+	/// Set the limits of the values received from the server
+	#[must_use]
+	pub fn with_limits(self, limits: Limits) -> Self {
+		Self {
+			inner: self.inner.with_limits(limits),
+			schema: self.schema,
+		}
+	}
+
+	/// Set the maximum time to wait for the response of the methods that don't have a
+	/// `#[timeout]` and aren't streamed
+	#[must_use]
+	pub fn with_timeout(self, timeout: Duration) -> Self {
+		Self {
+			inner: self.inner.with_timeout(timeout),
+			schema: self.schema,
+		}
+	}
+
+	/// Call a method with its arguments written as text
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the method doesn't exist, if the arguments
+	/// are invalid, if the call fails or if the response can't be written as text.
+	pub async fn call(
+		&self,
+		method: &str,
+		arguments: &[&str],
+	) -> StdResult<
+		DynamicResponse<impl Stream<Item = StdResult<String, DynamicCallError>> + use<RX, TX>>,
+		DynamicCallError,
+	> {
+		let (index, method) = self
+			.schema
+			.methods
+			.iter()
+			.enumerate()
+			.find(|(_, x)| x.name == method)
+			.ok_or_else(|| DynamicCallError::UnknownMethod(method.to_owned()))?;
+		if method.input_stream.is_some() {
+			return Err(DynamicCallError::InputStream(method.name.clone()));
+		}
+		if arguments.len() != method.arguments.len() {
+			return Err(DynamicCallError::ArgumentCount {
+				method: method.name.clone(),
+				expected: method.arguments.len(),
+				given: arguments.len(),
+			});
+		}
+
+		// encoded like the `MethodCall` enum of the protocol
+		let mut call = (index as u64).to_be_bytes().to_vec();
+		for (argument, text) in method.arguments.iter().zip(arguments) {
+			let value = self.schema.encode(argument.ty, text).map_err(|error| {
+				DynamicCallError::Argument {
+					name: argument.name.clone(),
+					error,
+				}
+			})?;
+			call.extend(value);
+		}
+		let timeout = method.timeout.map(Duration::from_millis);
+		let limits = self.inner.limits;
+
+		if !method.stream {
+			let RawValue(response) = self.inner.call(RawValue(call), timeout).await?;
+			let response = self
+				.schema
+				.decode(method.output, &response, limits)
+				.map_err(DynamicCallError::Response)?;
+			return Ok(DynamicResponse::Value(response));
+		}
+
+		let stream = match self
+			.inner
+			.long_call::<_, RawValue, RawValue>(RawValue(call), timeout)
+			.await?
+		{
+			Ok(stream) => stream,
+			Err(RawValue(error)) => {
+				let error = method
+					.early_error
+					.ok_or_else(|| ValueError::Invalid("unexpected early error".to_owned()))
+					.and_then(|ty| self.schema.decode(ty, &error, limits))
+					.map_err(DynamicCallError::Response)?;
+				return Ok(DynamicResponse::Error(error));
+			}
+		};
+		let schema = Arc::clone(&self.schema);
+		let output = method.output;
+		Ok(DynamicResponse::Stream(stream.map(move |value| {
+			let RawValue(value) = value?;
+			schema
+				.decode(output, &value, limits)
+				.map_err(DynamicCallError::Response)
+		})))
+	}
+
+	/// Subscribe to a signal, whose arguments are written as text
+	///
+	/// The values are the argument if the signal has one, or a tuple of the arguments.
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the signal doesn't exist, or if the
+	/// subscription can't be sent.
+	pub async fn subscribe(
+		&self,
+		signal: &str,
+	) -> StdResult<
+		impl Stream<Item = StdResult<String, DynamicCallError>> + use<RX, TX>,
+		DynamicCallError,
+	> {
+		let (index, signal) = self
+			.schema
+			.signals
+			.iter()
+			.enumerate()
+			.find(|(_, x)| x.name == signal)
+			.ok_or_else(|| DynamicCallError::UnknownSignal(signal.to_owned()))?;
+		let types: Vec<_> = signal.arguments.iter().map(|x| x.ty).collect();
+
+		let values = self.inner.subscribe::<RawValue>(index as u64).await?;
+		let schema = Arc::clone(&self.schema);
+		let limits = self.inner.limits;
+		Ok(values.map(move |value| {
+			let RawValue(value) = value?;
+			let arguments = schema
+				.decode_all(&types, &value, limits)
+				.map_err(DynamicCallError::Response)?;
+			Ok(match <[_; 1]>::try_from(arguments) {
+				Ok([argument]) => argument,
+				Err(arguments) => format!("({})", arguments.join(", ")),
+			})
+		}))
+	}
+}
+
+/// An encoded value, sent as is, and read up to the end of the frame
+#[derive(Debug)]
+struct RawValue(Vec<u8>);
+
+impl Read for RawValue {
+	type Error = io::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> StdResult<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		// the size of frames is already limited
+		let mut bytes = Vec::new();
+		stream.read_to_end(&mut bytes).await?;
+		Ok(Self(bytes))
+	}
+}
+
+impl Write for RawValue {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> StdResult<(), Self::Error> {
+		stream.write_all(&self.0).await
+	}
+}
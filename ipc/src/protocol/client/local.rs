@@ -0,0 +1,602 @@
+//! Client whose futures and streams don't need to be `Send`
+//!
+//! Single-threaded executors, like GLib's main context, can run futures that aren't
+//! `Send`, but have nothing like `tokio::spawn` to run the task receiving frames. This
+//! task is instead returned with the client, and the executor is in charge of running it.
+
+use std::{
+	cell::Cell,
+	fmt::{self, Debug},
+	io,
+	pin::pin,
+	rc::Rc,
+	result::Result as StdResult,
+	sync::{Arc, Mutex, PoisonError},
+	time::Duration,
+};
+
+use async_stream::try_stream;
+use futures::{
+	AsyncReadExt as _, Stream, StreamExt,
+	channel::mpsc,
+	future::{AbortHandle, Abortable, Either, LocalBoxFuture, select},
+};
+use log::error;
+use tokio::{
+	io::{AsyncRead, AsyncWrite},
+	time::timeout,
+};
+use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
+
+use super::{
+	call_request,
+	demux::{Packets, Receiver, Route, Routes, receive_frames, response},
+	forward_input,
+};
+use crate::{
+	Limits, Read, Result, Write,
+	capture::Side,
+	protocol::{
+		Clientbound, Error, PacketReceiver, PacketSender, Request, StreamPacket, handshake,
+	},
+	schema::Schema,
+};
+
+type BoxedWriter = Box<dyn AsyncWrite + Unpin>;
+
+/// Client of a protocol usable from a single-threaded executor
+///
+/// It's created with the future receiving the frames of the connection, which must
+/// be spawned on the same executor as the calls, for example with
+/// `glib::spawn_future_local`. This future ends when the connection breaks, or when
+/// the client and all its streams are dropped.
+///
+/// Timeouts use `tokio::time`, so calls with a timeout must be made while a tokio
+/// runtime is entered.
+pub struct LocalClient {
+	next_call_id: Rc<Cell<u64>>,
+	packet_sender: PacketSender<BoxedWriter>,
+	routes: Routes,
+	limits: Limits,
+	/// Limits used by the task receiving frames, shared by all clones
+	receiver_limits: Arc<Mutex<Limits>>,
+	/// Timeout of the calls that don't have their own
+	timeout: Option<Duration>,
+	/// Tasks run alongside the reception of frames, like sending input streams
+	tasks: mpsc::UnboundedSender<LocalBoxFuture<'static, ()>>,
+}
+
+impl LocalClient {
+	/// Create a connection over any transport, and do the handshake with the server
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the handshake with the server fails.
+	pub async fn new<RX, TX>(
+		rx: RX,
+		tx: TX,
+		fingerprint: u64,
+	) -> Result<(Self, impl Future<Output = ()> + use<RX, TX>)>
+	where
+		RX: AsyncRead + Unpin + 'static,
+		TX: AsyncWrite + Unpin + 'static,
+	{
+		let mut rx = PacketReceiver::new(rx);
+		let packet_sender = PacketSender::new(Box::new(tx) as BoxedWriter);
+		handshake(&mut rx, &packet_sender, fingerprint, Side::Client).await?;
+
+		let routes = Routes::default();
+		let receiver_limits = Arc::new(Mutex::new(Limits::default()));
+		let (tasks, new_tasks) = mpsc::unbounded();
+		let connection = {
+			let routes = routes.clone();
+			let receiver_limits = Arc::clone(&receiver_limits);
+			async move {
+				let receive = pin!(receive_frames(&mut rx, &routes, &receiver_limits));
+				// ends once the client and all its streams are dropped
+				let tasks = pin!(new_tasks.for_each_concurrent(None, |task| task));
+				select(receive, tasks).await;
+				// drop all routes, allowing calls to detect the crash
+				routes.retain(|_| false);
+			}
+		};
+
+		let client = Self {
+			next_call_id: Rc::new(Cell::new(0)),
+			packet_sender,
+			routes,
+			limits: Limits::default(),
+			receiver_limits,
+			timeout: None,
+			tasks,
+		};
+		Ok((client, connection))
+	}
+
+	/// Same as [`Self::new`], with a transport implementing the traits of `futures`
+	///
+	/// This includes the gio streams, for example
+	/// `gio::SocketConnection::into_async_read_write`.
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the handshake with the server fails.
+	pub async fn new_with_io<T>(
+		io: T,
+		fingerprint: u64,
+	) -> Result<(Self, impl Future<Output = ()> + use<T>)>
+	where
+		T: futures::AsyncRead + futures::AsyncWrite + 'static,
+	{
+		let (rx, tx) = io.split();
+		Self::new(rx.compat(), tx.compat_write(), fingerprint).await
+	}
+
+	/// Set the limits of the values sent to and received from the server
+	///
+	/// The maximum frame size is shared with the clones of this client, as
+	/// they use the same connection.
+	#[must_use]
+	pub fn with_limits(mut self, limits: Limits) -> Self {
+		self.limits = limits;
+		self.packet_sender.set_limits(limits);
+		*self
+			.receiver_limits
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = limits;
+		self
+	}
+
+	/// Set the maximum time to wait for the response of a call
+	///
+	/// This only applies to calls that aren't streamed and don't have their own
+	/// timeout, since streams can legitimately stay silent for a long time.
+	#[must_use]
+	pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	/// Call a method and wait for its response
+	///
+	/// If `timeout` is [`None`], the timeout of the client is used.
+	pub async fn call<T, R>(&self, method: T, timeout: Option<Duration>) -> Result<R>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		anyhow::Error: From<T::Error> + From<R::Error>,
+	{
+		let timeout = timeout.or(self.timeout);
+		let (call_id, rx) = self.start_call(call_request(method, timeout)).await?;
+
+		// None if the route is dropped, will happen if the connection breaks
+		self.wait_response(call_id, timeout, response(rx, self.limits))
+			.await
+			.ok_or(Error::ConnectionBroken)
+			.flatten()
+	}
+
+	/// Same as [`Self::call`], but also send the values of `input` to the server
+	///
+	/// `wrap` is used to put the values in the packet type expected by the server.
+	/// Sending `input` stops as soon as the response is received.
+	pub async fn call_with_input<T, R, I, P>(
+		&self,
+		method: T,
+		input: impl Stream<Item = I>,
+		wrap: fn(StreamPacket<I, !>) -> P,
+		timeout: Option<Duration>,
+	) -> Result<R>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		P: Write<Error = anyhow::Error> + Sync,
+		anyhow::Error: From<T::Error> + From<R::Error>,
+	{
+		let timeout = timeout.or(self.timeout);
+		let (call_id, rx) = self.start_call(call_request(method, timeout)).await?;
+
+		let response = async {
+			let response = pin!(response(rx, self.limits));
+			let forward = pin!(forward_input(
+				self.packet_sender.clone(),
+				call_id,
+				input,
+				wrap
+			));
+			match select(response, forward).await {
+				Either::Left((response, _)) => response,
+				Either::Right(((), response)) => response.await,
+			}
+		};
+
+		self.wait_response(call_id, timeout, response)
+			.await
+			.ok_or(Error::ConnectionBroken)
+			.flatten()
+	}
+
+	/// Call a streamed method
+	///
+	/// `timeout` only applies to the first packet of the stream.
+	pub async fn long_call<T, R, E>(
+		&self,
+		method: T,
+		timeout: Option<Duration>,
+	) -> Result<StdResult<impl Stream<Item = Result<R>> + use<T, R, E>, E>>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let (call_id, rx) = self
+			.start_long_call::<T, R, E>(call_request(method, timeout))
+			.await?;
+		self.receive_stream(call_id, rx, None, timeout).await
+	}
+
+	/// Same as [`Self::long_call`], but also send the values of `input` to the server
+	///
+	/// `wrap` is used to put the values in the packet type expected by the server.
+	/// Sending `input` stops as soon as the returned stream ends or is dropped.
+	pub async fn long_call_with_input<T, R, E, S, I, P>(
+		&self,
+		method: T,
+		input: S,
+		wrap: fn(StreamPacket<I, !>) -> P,
+		timeout: Option<Duration>,
+	) -> Result<StdResult<impl Stream<Item = Result<R>> + use<T, R, E, S, I, P>, E>>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		S: Stream<Item = I> + 'static,
+		I: 'static,
+		P: Write<Error = anyhow::Error> + Sync + 'static,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let (call_id, rx) = self
+			.start_long_call::<T, R, E>(call_request(method, timeout))
+			.await?;
+
+		let (handle, registration) = AbortHandle::new_pair();
+		self.spawn(Abortable::new(
+			forward_input(self.packet_sender.clone(), call_id, input, wrap),
+			registration,
+		));
+
+		self.receive_stream(call_id, rx, Some(handle), timeout)
+			.await
+	}
+
+	/// Subscribe to a signal of the server
+	///
+	/// The subscription is sent before this function returns, so calls made
+	/// afterwards are handled by the server after it. Dropping the returned
+	/// stream cancels the subscription.
+	pub async fn subscribe<R>(&self, signal: u64) -> Result<impl Stream<Item = Result<R>> + use<R>>
+	where
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		anyhow::Error: From<R::Error>,
+	{
+		let (call_id, mut rx) = self
+			.start_long_call::<!, R, !>(Request::Subscribe(signal))
+			.await?;
+		let mut guard = self.cancel_guard(call_id, None);
+
+		Ok(try_stream! {
+			// anything else is EndOfStream, as the server has no early error to send
+			while let StreamPacket::Value(x) = rx.recv().await.ok_or(Error::ConnectionBroken)?? {
+				yield x;
+			}
+			// the server won't send anything else, no need to cancel
+			guard.disarm();
+		})
+	}
+
+	/// Ask the server for the schema of its protocol
+	///
+	/// If `timeout` is [`None`], the timeout of the client is used.
+	pub async fn describe(&self, timeout: Option<Duration>) -> Result<Schema> {
+		let timeout = timeout.or(self.timeout);
+		let (call_id, rx) = self.start_call(Request::<!, !>::Describe).await?;
+
+		self.wait_response(call_id, timeout, response(rx, self.limits))
+			.await
+			.ok_or(Error::ConnectionBroken)
+			.flatten()
+	}
+
+	/// Send a method call, and return a channel receiving its response
+	async fn start_call<T>(&self, request: Request<T, !>) -> Result<(u64, Receiver)>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error>,
+	{
+		let (route, rx) = Route::response();
+		let call_id = self.call_base(request, route).await?;
+
+		Ok((call_id, rx))
+	}
+
+	/// Send a streamed method call (or a subscription), and return the packets received for it
+	async fn start_long_call<T, R, E>(
+		&self,
+		request: Request<T, !>,
+	) -> Result<(u64, Packets<StreamPacket<R, E>>)>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error> + From<R::Error> + From<E::Error>,
+	{
+		let (route, rx) = Route::stream();
+		let call_id = self.call_base(request, route).await?;
+
+		Ok((
+			call_id,
+			Packets::new(call_id, rx, self.routes.clone(), self.limits),
+		))
+	}
+
+	/// Wait for the first packet of a streamed call, then turn the following ones into a stream
+	///
+	/// `input` is the handle of the task sending the input stream, if any.
+	async fn receive_stream<R, E>(
+		&self,
+		call_id: u64,
+		mut rx: Packets<StreamPacket<R, E>>,
+		input: Option<AbortHandle>,
+		timeout_duration: Option<Duration>,
+	) -> Result<StdResult<impl Stream<Item = Result<R>> + use<R, E>, E>>
+	where
+		R: Read + Send + Sync + 'static,
+		R::Error: Send + Sync,
+		E: Read + Send + Sync + 'static + Debug,
+		E::Error: Send + Sync + 'static,
+		anyhow::Error: From<R::Error> + From<E::Error>,
+	{
+		// created right away so that the call is also cancelled if this future is dropped
+		let mut guard = self.cancel_guard(call_id, input);
+
+		let first_packet = match timeout_duration {
+			Some(duration) => timeout(duration, rx.recv())
+				.await
+				.map_err(|_| Error::Timeout)?,
+			None => rx.recv().await,
+		};
+		let first_packet = first_packet.ok_or(Error::ConnectionBroken)??;
+		let first_value = match first_packet {
+			StreamPacket::Value(x) => Some(x),
+			StreamPacket::EndOfStream => {
+				guard.disarm();
+				None
+			}
+			StreamPacket::Error(e) => {
+				guard.disarm();
+				return Ok(Err(e));
+			}
+		};
+
+		Ok(Ok(try_stream! {
+			if let Some(first_value) = first_value {
+				yield first_value;
+			} else {
+				// first packet was EndOfStream
+				return;
+			}
+
+			loop {
+				let value = match rx.recv().await.ok_or(Error::ConnectionBroken)?? {
+					StreamPacket::Value(x) => x,
+					StreamPacket::Error(e) => {
+						error!("Unexpected early error after receiving value: {e:?}");
+						break;
+					},
+					StreamPacket::EndOfStream => break,
+				};
+				yield value;
+			}
+			// the server won't send anything else, no need to cancel
+			guard.disarm();
+		}))
+	}
+
+	/// Wait for the response of a call for at most `timeout`, cancelling the call if it expires
+	async fn wait_response<T>(
+		&self,
+		call_id: u64,
+		timeout_duration: Option<Duration>,
+		response: impl Future<Output = Option<Result<T>>>,
+	) -> Option<Result<T>> {
+		let Some(duration) = timeout_duration else {
+			return response.await;
+		};
+
+		if let Ok(response) = timeout(duration, response).await {
+			return response;
+		}
+		self.routes.remove(call_id);
+		// error if the connection is broken, in which case there's nothing to cancel
+		let _ = self
+			.packet_sender
+			.write(Clientbound {
+				call_id,
+				payload: Request::<!, !>::Cancel,
+			})
+			.await;
+		Some(Err(Error::Timeout))
+	}
+
+	async fn call_base<T>(&self, request: Request<T, !>, route: Route) -> Result<u64>
+	where
+		T: Write + Sync,
+		T::Error: Send + Sync + 'static,
+		anyhow::Error: From<T::Error>,
+	{
+		let call_id = self.next_call_id.get();
+		self.next_call_id.set(call_id + 1);
+
+		// add the route before writing, otherwise we could read the response
+		// before having the route in place
+		self.routes.insert(call_id, route);
+		if let Err(e) = self
+			.packet_sender
+			.write::<Clientbound<Request<T, !>>>(Clientbound {
+				call_id,
+				payload: request,
+			})
+			.await
+		{
+			self.routes.remove(call_id);
+			if e.is::<io::Error>() {
+				return Err(Error::ConnectionBroken);
+			}
+			return Err(Error::from_decode(e));
+		}
+
+		Ok(call_id)
+	}
+
+	/// Run a task alongside the reception of frames
+	fn spawn(&self, task: impl Future<Output = impl Sized> + 'static) {
+		// error if the connection is closed, the task has nothing left to do
+		let _ = self.tasks.unbounded_send(Box::pin(async {
+			task.await;
+		}));
+	}
+
+	fn cancel_guard(&self, call_id: u64, input: Option<AbortHandle>) -> LocalCancelGuard {
+		LocalCancelGuard {
+			call_id,
+			packet_sender: self.packet_sender.clone(),
+			tasks: self.tasks.clone(),
+			input,
+			armed: true,
+		}
+	}
+}
+
+#[cfg(feature = "gio")]
+impl LocalClient {
+	/// Same as [`Self::new_with_io`], over a gio socket connection
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the streams of the connection can't be
+	/// polled, or if the handshake with the server fails.
+	pub async fn new_with_socket_connection(
+		connection: gio::SocketConnection,
+		fingerprint: u64,
+	) -> Result<(Self, impl Future<Output = ()> + use<>)> {
+		use gio::prelude::IOStreamExtManual;
+
+		let io = connection.into_async_read_write().map_err(|_| {
+			Error::Connect(io::Error::new(
+				io::ErrorKind::Unsupported,
+				"the streams of the connection can't be polled",
+			))
+		})?;
+		Self::new_with_io(io, fingerprint).await
+	}
+
+	/// Connect to a unix socket with gio, see [`Self::new_with_socket_connection`]
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the connection fails, or if the
+	/// handshake with the server fails.
+	pub async fn from_gio_address(
+		address: gio::UnixSocketAddress,
+		fingerprint: u64,
+	) -> Result<(Self, impl Future<Output = ()> + use<>)> {
+		use gio::prelude::SocketClientExt;
+
+		let connection = gio::SocketClient::new()
+			.connect_future(&address)
+			.await
+			.map_err(|e| Error::Connect(io::Error::other(e)))?;
+		Self::new_with_socket_connection(connection, fingerprint).await
+	}
+}
+
+impl Clone for LocalClient {
+	fn clone(&self) -> Self {
+		Self {
+			next_call_id: Rc::clone(&self.next_call_id),
+			packet_sender: self.packet_sender.clone(),
+			routes: self.routes.clone(),
+			limits: self.limits,
+			receiver_limits: Arc::clone(&self.receiver_limits),
+			timeout: self.timeout,
+			tasks: self.tasks.clone(),
+		}
+	}
+}
+
+impl Debug for LocalClient {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("LocalClient")
+			.field("next_call_id", &self.next_call_id)
+			.field("limits", &self.limits)
+			.field("timeout", &self.timeout)
+			.finish_non_exhaustive()
+	}
+}
+
+/// Cancel a streamed call when dropped, like the guard of [`Client`](super::Client)
+///
+/// The cancellation is sent by the future receiving the frames of the connection.
+struct LocalCancelGuard {
+	call_id: u64,
+	packet_sender: PacketSender<BoxedWriter>,
+	tasks: mpsc::UnboundedSender<LocalBoxFuture<'static, ()>>,
+	input: Option<AbortHandle>,
+	armed: bool,
+}
+
+impl LocalCancelGuard {
+	/// Don't cancel the call when dropped, because it has already ended
+	const fn disarm(&mut self) {
+		self.armed = false;
+	}
+}
+
+impl Drop for LocalCancelGuard {
+	fn drop(&mut self) {
+		if let Some(input) = &self.input {
+			input.abort();
+		}
+		if !self.armed {
+			return;
+		}
+
+		let call_id = self.call_id;
+		let packet_sender = self.packet_sender.clone();
+		// error if the connection is closed, in which case there's nothing to cancel
+		let _ = self.tasks.unbounded_send(Box::pin(async move {
+			let _ = packet_sender
+				.write(Clientbound {
+					call_id,
+					payload: Request::<!, !>::Cancel,
+				})
+				.await;
+		}));
+	}
+}
@@ -0,0 +1,177 @@
+//! Re-establishing the connection of a client after it broke
+
+use std::{
+	collections::HashMap,
+	io,
+	pin::Pin,
+	sync::{Arc, Mutex, PoisonError, Weak},
+	time::Duration,
+};
+
+use log::{debug, error};
+use tokio::{
+	io::{AsyncRead, AsyncWrite},
+	time::sleep,
+};
+
+use super::Routes;
+use crate::{
+	Result,
+	capture::Side,
+	protocol::{Clientbound, Error, PacketReceiver, PacketSender, Request, handshake},
+};
+
+/// Open a new connection to the server
+pub type Connect<RX, TX> =
+	Box<dyn Fn() -> Pin<Box<dyn Future<Output = io::Result<(RX, TX)>> + Send>> + Send + Sync>;
+
+/// Signals subscribed to by the calls of a client, to subscribe again after reconnecting
+pub type Subscriptions = Arc<Mutex<HashMap<u64, u64>>>;
+
+/// State of the connection of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+	Connected,
+	/// The connection broke, calls fail until it's re-established
+	Disconnected,
+}
+
+/// How a client re-establishes its connection after it broke
+///
+/// Calls in progress when the connection breaks fail with
+/// [`Error::ConnectionBroken`], as well as the calls made until the client is
+/// connected again.
+#[derive(Debug, Clone, Copy)]
+pub struct Reconnect {
+	initial_delay: Duration,
+	max_delay: Duration,
+	resubscribe: bool,
+}
+
+impl Reconnect {
+	/// Set the delay before the first attempt, doubled after each failed one
+	#[must_use]
+	pub const fn with_initial_delay(mut self, delay: Duration) -> Self {
+		self.initial_delay = delay;
+		self
+	}
+
+	/// Set the maximum delay between two attempts
+	#[must_use]
+	pub const fn with_max_delay(mut self, delay: Duration) -> Self {
+		self.max_delay = delay;
+		self
+	}
+
+	/// Keep the signal streams alive across reconnections, and subscribe to
+	/// them again on the new connection
+	///
+	/// Values sent by the server while the client was disconnected are lost.
+	#[must_use]
+	pub const fn with_resubscribe(mut self, resubscribe: bool) -> Self {
+		self.resubscribe = resubscribe;
+		self
+	}
+
+	pub(super) const fn resubscribe(&self) -> bool {
+		self.resubscribe
+	}
+}
+
+impl Default for Reconnect {
+	fn default() -> Self {
+		Self {
+			initial_delay: Duration::from_millis(100),
+			max_delay: Duration::from_secs(30),
+			resubscribe: false,
+		}
+	}
+}
+
+/// State shared between the task receiving frames and the clients, used to reconnect
+pub struct Reconnector<RX, TX: AsyncWrite> {
+	pub connect: Connect<RX, TX>,
+	pub options: Reconnect,
+	pub fingerprint: u64,
+	/// Dropped with the last clone of the client, to stop reconnecting
+	pub alive: Weak<()>,
+	pub packet_sender: PacketSender<TX>,
+	pub routes: Routes,
+	/// [`None`] unless subscriptions are kept across reconnections
+	pub subscriptions: Option<Subscriptions>,
+}
+
+impl<RX, TX> Reconnector<RX, TX>
+where
+	RX: AsyncRead + Unpin + Send,
+	TX: AsyncWrite + Unpin + Send + Sync,
+{
+	/// Connect to the server again, retrying with an exponential backoff
+	///
+	/// Returns [`None`] once every clone of the client is dropped.
+	pub async fn reconnect(&self) -> Option<PacketReceiver<RX>> {
+		self.fail_calls();
+
+		let mut delay = self.options.initial_delay;
+		loop {
+			sleep(delay).await;
+			if self.alive.strong_count() == 0 {
+				return None;
+			}
+			match self.try_connect().await {
+				Ok(rx) => return Some(rx),
+				Err(e) => debug!("Could not reconnect to the server: {e}"),
+			}
+			delay = delay.saturating_mul(2).min(self.options.max_delay);
+		}
+	}
+
+	/// Drop the routes of the calls in progress, except the kept subscriptions
+	fn fail_calls(&self) {
+		match &self.subscriptions {
+			Some(subscriptions) => {
+				let subscriptions = subscriptions.lock().unwrap_or_else(PoisonError::into_inner);
+				self.routes
+					.retain(|call_id| subscriptions.contains_key(&call_id));
+			}
+			None => self.routes.retain(|_| false),
+		}
+	}
+
+	async fn try_connect(&self) -> Result<PacketReceiver<RX>> {
+		let (rx, tx) = (self.connect)().await.map_err(Error::Connect)?;
+		let mut rx = PacketReceiver::new(rx);
+		let packet_sender = PacketSender::new(tx);
+		handshake(&mut rx, &packet_sender, self.fingerprint, Side::Client).await?;
+
+		self.packet_sender.replace(packet_sender).await;
+		self.resubscribe().await;
+		Ok(rx)
+	}
+
+	/// Send the subscriptions whose stream is still alive on the new connection
+	async fn resubscribe(&self) {
+		let Some(subscriptions) = &self.subscriptions else {
+			return;
+		};
+		let subscriptions: Vec<_> = {
+			let mut subscriptions = subscriptions.lock().unwrap_or_else(PoisonError::into_inner);
+			// the stream of the other ones was dropped
+			subscriptions.retain(|&call_id, _| self.routes.contains(call_id));
+			subscriptions
+				.iter()
+				.map(|(&id, &signal)| (id, signal))
+				.collect()
+		};
+
+		for (call_id, signal) in subscriptions {
+			let packet = Clientbound {
+				call_id,
+				payload: Request::<!, !>::Subscribe(signal),
+			};
+			if let Err(e) = self.packet_sender.write(packet).await {
+				error!("Error while subscribing again to signal {signal}: {e}");
+			}
+		}
+	}
+}
@@ -1,17 +1,32 @@
-use std::sync::Arc;
+use std::{
+	env,
+	fmt::{self, Debug},
+	io::{self, ErrorKind},
+	ops::Deref,
+	os::fd::OwnedFd,
+	path::PathBuf,
+	sync::Arc,
+};
 
+use anyhow::bail;
 use async_stream::stream;
 use futures::Stream;
 use thiserror::Error;
 use tokio::{
-	io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufWriter},
+	io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, copy, sink},
 	sync::RwLock,
 };
 
-use crate::{Read, Write};
+use crate::{
+	LimitExceededError, Limits, Read, TraceContext, Write,
+	capture::{Side, Tap},
+	fd,
+	interceptor::Rejection,
+};
 
 pub mod client;
 pub mod server;
+pub mod trace;
 mod writable;
 
 pub use writable::Writable;
@@ -22,6 +37,41 @@ pub enum Error {
 	Read(#[source] anyhow::Error),
 	#[error("The connection to the server was broken or the server closed")]
 	ConnectionBroken,
+	#[error("The server could not decode the method call: {0}")]
+	InvalidCall(String),
+	#[error("Could not connect to the server: {0}")]
+	Connect(#[source] io::Error),
+	#[error(
+		"The server uses an incompatible protocol (local fingerprint: {local:016X}, remote fingerprint: {remote:016X})"
+	)]
+	IncompatibleProtocol { local: u64, remote: u64 },
+	#[error("A value sent by the server exceeded the limits of the connection: {0}")]
+	LimitExceeded(#[source] LimitExceededError),
+	#[error("The server doesn't allow this client to make calls")]
+	Unauthorized,
+	#[error("The server didn't answer in time")]
+	Timeout,
+	#[error("The client made too many calls, the server rejected this one")]
+	RateLimited,
+	#[error("The server is shutting down, and didn't start the call")]
+	ShuttingDown,
+}
+
+impl Error {
+	/// Wrap an error that happened while decoding a packet
+	fn from_decode(error: anyhow::Error) -> Self {
+		match error.downcast_ref::<LimitExceededError>() {
+			Some(e) => Self::LimitExceeded(*e),
+			None => Self::Read(error),
+		}
+	}
+}
+
+/// First packet sent by both sides when a connection is opened
+#[derive(Debug, Clone, Read, Write)]
+pub struct Handshake {
+	/// Fingerprint of the protocol, generated from the methods' signatures
+	pub fingerprint: u64,
 }
 
 /// Packet going from the client to the server
@@ -40,70 +90,640 @@ pub struct Serverbound<T> {
 	pub payload: T,
 }
 
+/// Payload of a [`Clientbound`] packet
+///
+/// The discriminants are pinned, since frames are read without knowing the protocol.
+#[derive(Debug, Clone, Read, Write)]
+pub enum Request<T, S> {
+	/// Call a method
+	#[ipc(discriminant = 0)]
+	Call(T),
+	/// Cancel a call, the server will stop sending packets for it
+	#[ipc(discriminant = 1)]
+	Cancel,
+	/// A packet of the input stream of a call
+	#[ipc(discriminant = 2)]
+	Stream(S),
+	/// Subscribe to a signal, identified by its index in the protocol
+	///
+	/// Every emission of the signal is then sent as a [`StreamPacket`] with the
+	/// call id of this packet, until the subscription is cancelled.
+	#[ipc(discriminant = 3)]
+	Subscribe(u64),
+	/// Call a method, with information about the call
+	#[ipc(discriminant = 4)]
+	ContextCall(T, CallContext),
+	/// Ask for the [`Schema`](crate::schema::Schema) of the protocol
+	#[ipc(discriminant = 5)]
+	Describe,
+}
+
+/// Information sent with a [`Request::ContextCall`]
+#[derive(Debug, Clone, Default, Read, Write)]
+#[ipc(extensible)]
+pub struct CallContext {
+	/// Milliseconds after which the client stops waiting for the response
+	pub timeout: Option<u64>,
+	#[ipc(default)]
+	pub trace: Option<TraceContext>,
+}
+
+/// Payload of a [`Serverbound`] packet
+///
+/// The discriminants are pinned, since frames are read without knowing the protocol.
+#[derive(Debug, Clone, Read, Write)]
+pub enum Response<T> {
+	/// The actual response to the method call
+	#[ipc(discriminant = 0)]
+	Value(T),
+	/// The server couldn't decode the method call, contains the reason
+	#[ipc(discriminant = 1)]
+	InvalidCall(String),
+	/// The response was too big to be sent
+	#[ipc(discriminant = 2)]
+	LimitExceeded(LimitExceededError),
+	/// The server doesn't allow this client to make calls, or this call
+	#[ipc(discriminant = 3)]
+	Unauthorized,
+	/// The call was rejected by [`RateLimit`](crate::interceptor::RateLimit)
+	#[ipc(discriminant = 4)]
+	RateLimited,
+	/// The server is shutting down, and doesn't start new calls
+	#[ipc(discriminant = 5)]
+	ShuttingDown,
+}
+
+impl From<Rejection> for Response<!> {
+	fn from(value: Rejection) -> Self {
+		match value {
+			Rejection::Unauthorized => Self::Unauthorized,
+			Rejection::RateLimited => Self::RateLimited,
+		}
+	}
+}
+
 /// Packet wrapper for streamed responses
+///
+/// The discriminants are pinned, since frames are read without knowing the protocol.
 #[derive(Debug, Clone, Read, Write)]
 pub enum StreamPacket<T, E> {
 	/// A value from the stream
+	#[ipc(discriminant = 0)]
 	Value(T),
 	/// The early error, if any (should only be present in the first packet)
+	#[ipc(discriminant = 1)]
 	Error(E),
 	/// The stream has ended normally
+	#[ipc(discriminant = 2)]
 	EndOfStream,
 }
 
-#[derive(Debug)]
+/// Discriminants of the packets, to read frames without knowing the types of a protocol
+///
+/// They are pinned on [`Request`], [`Response`] and [`StreamPacket`] with `#[ipc(discriminant = N)]`.
+pub(crate) mod discriminant {
+	/// Variants of [`Request`](super::Request)
+	pub mod request {
+		pub const CALL: u64 = 0;
+		pub const CANCEL: u64 = 1;
+		pub const STREAM: u64 = 2;
+		pub const SUBSCRIBE: u64 = 3;
+		pub const CONTEXT_CALL: u64 = 4;
+		pub const DESCRIBE: u64 = 5;
+	}
+
+	/// Variants of [`Response`](super::Response)
+	pub mod response {
+		pub const VALUE: u64 = 0;
+		pub const INVALID_CALL: u64 = 1;
+		pub const LIMIT_EXCEEDED: u64 = 2;
+		pub const UNAUTHORIZED: u64 = 3;
+		pub const RATE_LIMITED: u64 = 4;
+		pub const SHUTTING_DOWN: u64 = 5;
+	}
+
+	/// Variants of [`StreamPacket`](super::StreamPacket)
+	pub mod stream_packet {
+		pub const VALUE: u64 = 0;
+		pub const ERROR: u64 = 1;
+		pub const END_OF_STREAM: u64 = 2;
+	}
+}
+
 pub struct PacketSender<TX: AsyncWrite> {
-	inner: Arc<RwLock<BufWriter<TX>>>,
+	inner: Arc<RwLock<Writer<TX>>>,
+	limits: Limits,
+}
+
+/// Writer shared by a [`PacketSender`] and its clones
+struct Writer<TX: AsyncWrite> {
+	stream: BufWriter<TX>,
+	/// Records the sent frames, if they are captured
+	tap: Option<Tap>,
 }
 
-impl<TX: AsyncWrite + Unpin + Send + Sync> PacketSender<TX> {
+impl<TX: AsyncWrite + Unpin> PacketSender<TX> {
 	pub fn new(stream: TX) -> Self {
 		Self {
-			inner: Arc::new(RwLock::new(BufWriter::new(stream))),
+			inner: Arc::new(RwLock::new(Writer {
+				stream: BufWriter::new(stream),
+				tap: None,
+			})),
+			limits: Limits::default(),
 		}
 	}
 
+	/// Send a packet as a single frame
+	///
+	/// The packet is encoded before taking the lock on the socket, so that the
+	/// frame length can be written before it.
+	///
+	/// The file descriptors of the [`Fd`](crate::Fd)s in the packet are sent with the
+	/// first bytes of the frame.
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the packet can't be encoded or
+	/// written, or if it exceeds the maximum frame size, in which case nothing is sent.
+	/// If the packet contains file descriptors but the writer can't send them, the
+	/// frame is sent without them and an error is returned.
 	pub async fn write<T>(&self, payload: T) -> anyhow::Result<()>
 	where
 		T: Write,
 		anyhow::Error: From<T::Error>,
 	{
-		let mut inner = self.inner.write().await;
-		Write::write(&payload, &mut *inner).await?;
-		inner.flush().await?;
+		let (result, unsent) = fd::sending_fds(async {
+			let mut frame = Vec::new();
+			Write::write(&payload, &mut frame).await?;
+			self.limits.check_frame_bytes(frame.len() as u64)?;
 
-		// drop "early" to satisfy clippy
-		drop(inner);
+			let mut inner = self.inner.write().await;
+			// recorded first, the answer to a frame may be received before the write returns
+			if let Some(tap) = &mut inner.tap {
+				tap.record(&frame);
+			}
+			inner.stream.write_u64(frame.len() as u64).await?;
+			inner.stream.write_all(&frame).await?;
+			inner.stream.flush().await?;
+
+			// drop "early" to satisfy clippy
+			drop(inner);
+			anyhow::Ok(())
+		})
+		.await;
+		result?;
+
+		if !unsent.is_empty() {
+			bail!("the connection can't send file descriptors");
+		}
 		Ok(())
 	}
+
+	/// Send the next frames with the writer of `other`, from this sender and all its clones
+	///
+	/// Nothing is replaced if `other` has been cloned.
+	pub async fn replace(&self, other: Self) {
+		if let Some(writer) = Arc::into_inner(other.inner) {
+			*self.inner.write().await = writer.into_inner();
+		}
+	}
+
+	/// Record the sent frames with `tap`
+	async fn set_tap(&self, tap: Tap) {
+		self.inner.write().await.tap = Some(tap);
+	}
 }
 
-impl<TX: AsyncWrite + Unpin + Send> Clone for PacketSender<TX> {
+impl<TX: AsyncWrite> PacketSender<TX> {
+	/// Set the limits checked before sending a frame
+	///
+	/// This only affects this sender, not its clones.
+	pub const fn set_limits(&mut self, limits: Limits) {
+		self.limits = limits;
+	}
+}
+
+// the writer isn't required to implement `Debug`, to allow boxing it
+impl<TX: AsyncWrite> Debug for PacketSender<TX> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("PacketSender").finish_non_exhaustive()
+	}
+}
+
+impl<TX: AsyncWrite> Clone for PacketSender<TX> {
 	fn clone(&self) -> Self {
 		Self {
 			inner: Arc::clone(&self.inner),
+			limits: self.limits,
 		}
 	}
 }
 
+/// A received frame that exceeded the limits of the connection, and was skipped
+///
+/// Only the call the frame belongs to fails, the connection stays usable.
+#[derive(Debug, Error)]
+#[error("Skipped frame of call {call_id:?}: {error}")]
+pub struct SkippedFrame {
+	/// Id of the call the frame belongs to, if the frame was long enough to contain it
+	pub call_id: Option<u64>,
+	#[source]
+	pub error: LimitExceededError,
+}
+
+impl SkippedFrame {
+	/// Get the skipped frame from an error returned by [`PacketReceiver::receive`]
+	pub fn from_io(error: &io::Error) -> Option<&Self> {
+		error.get_ref()?.downcast_ref()
+	}
+}
+
+/// A frame received by a [`PacketReceiver`], with the file descriptors sent with it
+#[derive(Debug)]
+pub struct Frame {
+	bytes: Vec<u8>,
+	fds: Vec<OwnedFd>,
+}
+
+impl Frame {
+	/// Split the frame with [`split_frame`], and decode its payload
+	///
+	/// The [`Fd`](crate::Fd)s of the payload are taken from the file descriptors of the
+	/// frame, the others are closed.
+	///
+	/// # Errors
+	///
+	/// The outer result is an error if the frame is too short to contain a call id,
+	/// the inner one if the payload can't be decoded or exceeds `limits`.
+	pub async fn decode_payload<T: Read>(
+		self,
+		limits: Limits,
+	) -> io::Result<(u64, Result<T, T::Error>)> {
+		let (call_id, payload) = split_frame(&self.bytes)?;
+		let payload = fd::with_received_fds(self.fds, decode(payload, limits)).await;
+		Ok((call_id, payload))
+	}
+}
+
+impl Deref for Frame {
+	type Target = [u8];
+
+	fn deref(&self) -> &Self::Target {
+		&self.bytes
+	}
+}
+
 #[derive(Debug)]
 pub struct PacketReceiver<RX: AsyncRead> {
 	inner: RX,
+	limits: Limits,
+	/// Records the received frames, if they are captured
+	tap: Option<Tap>,
 }
 
-impl<RX: AsyncRead + Unpin + Send> PacketReceiver<RX> {
-	pub const fn new(stream: RX) -> Self {
-		Self { inner: stream }
+impl<RX: AsyncRead + Unpin> PacketReceiver<RX> {
+	pub fn new(stream: RX) -> Self {
+		Self {
+			inner: stream,
+			limits: Limits::default(),
+			tap: None,
+		}
 	}
 
-	pub fn receive_stream<T>(mut self) -> impl Stream<Item = Result<T, T::Error>>
-	where
-		T: Read,
-	{
+	/// Set the limits checked before receiving a frame
+	pub const fn set_limits(&mut self, limits: Limits) {
+		self.limits = limits;
+	}
+
+	/// Receive the next frame
+	///
+	/// Frames bigger than the maximum frame size are skipped without being
+	/// allocated, and reported with a [`SkippedFrame`] error.
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the frame couldn't be read from
+	/// the socket, including [`ErrorKind::UnexpectedEof`] if the socket was closed,
+	/// or if it was skipped.
+	pub async fn receive(&mut self) -> io::Result<Frame> {
+		let (bytes, fds) = fd::receiving_fds(self.receive_bytes()).await;
+		let bytes = bytes?;
+		if let Some(tap) = &mut self.tap {
+			tap.record(&bytes);
+		}
+		Ok(Frame { bytes, fds })
+	}
+
+	async fn receive_bytes(&mut self) -> io::Result<Vec<u8>> {
+		let len = self.inner.read_u64().await?;
+		let len = match self.limits.check_frame_bytes(len) {
+			Ok(len) => len,
+			Err(error) => {
+				// every frame starts with the call id, see `split_frame`
+				let call_id = if len >= 8 {
+					Some(self.inner.read_u64().await?)
+				} else {
+					None
+				};
+				let remaining = len.saturating_sub(8);
+				let skipped = copy(&mut (&mut self.inner).take(remaining), &mut sink()).await?;
+				if skipped < remaining {
+					return Err(ErrorKind::UnexpectedEof.into());
+				}
+
+				return Err(io::Error::new(
+					ErrorKind::InvalidData,
+					SkippedFrame { call_id, error },
+				));
+			}
+		};
+
+		let mut frame = vec![0; len];
+		self.inner.read_exact(&mut frame).await?;
+
+		Ok(frame)
+	}
+
+	pub fn receive_stream(mut self) -> impl Stream<Item = io::Result<Frame>> {
 		stream! {
 			loop {
-				yield T::read(&mut self.inner).await;
+				yield self.receive().await;
 			}
 		}
 	}
 }
+
+/// Fingerprint accepted by every peer, used by clients that learn the protocol from its
+/// [`Schema`](crate::schema::Schema)
+pub const ANY_FINGERPRINT: u64 = 0;
+
+/// Exchange protocol fingerprints with the other side of the connection
+///
+/// Both sides send their fingerprint before reading the other one, so that each
+/// side can report the mismatch. [`ANY_FINGERPRINT`] matches every fingerprint.
+///
+/// If frames are [captured](crate::capture), the connection is recorded from here
+/// as being on `side`.
+///
+/// # Errors
+///
+/// This function will return an error if the handshake couldn't be sent or
+/// received, or if the fingerprints don't match.
+pub async fn handshake<RX, TX>(
+	rx: &mut PacketReceiver<RX>,
+	tx: &PacketSender<TX>,
+	fingerprint: u64,
+	side: Side,
+) -> crate::Result<()>
+where
+	RX: AsyncRead + Unpin,
+	TX: AsyncWrite + Unpin,
+{
+	if let Some((sent, received)) = Tap::new_pair(side) {
+		tx.set_tap(sent).await;
+		rx.tap = Some(received);
+	}
+
+	tx.write(Handshake { fingerprint })
+		.await
+		.map_err(|e| Error::Connect(io::Error::other(e)))?;
+
+	let frame = rx.receive().await.map_err(Error::Connect)?;
+	let remote = decode::<Handshake>(&frame, Limits::default())
+		.await
+		.map_err(Error::Read)?
+		.fingerprint;
+
+	check_fingerprint(fingerprint, remote)
+}
+
+/// Check that the fingerprint of the other side matches the local one
+fn check_fingerprint(local: u64, remote: u64) -> crate::Result<()> {
+	if remote == local || remote == ANY_FINGERPRINT || local == ANY_FINGERPRINT {
+		Ok(())
+	} else {
+		Err(Error::IncompatibleProtocol { local, remote })
+	}
+}
+
+/// Replace the environment variables in a socket path, written as `$NAME` or `${NAME}`
+///
+/// # Errors
+///
+/// This function will return an error if a variable isn't set.
+pub fn expand_path(path: &str) -> io::Result<PathBuf> {
+	let mut expanded = String::with_capacity(path.len());
+	let mut rest = path;
+	while let Some(start) = rest.find('$') {
+		expanded.push_str(&rest[..start]);
+		rest = &rest[(start + 1)..];
+
+		let (name, remaining) = if let Some(braced) = rest.strip_prefix('{') {
+			let end = braced.find('}').ok_or_else(|| {
+				io::Error::new(ErrorKind::InvalidInput, "unclosed `${` in socket path")
+			})?;
+			(&braced[..end], &braced[(end + 1)..])
+		} else {
+			let end = rest
+				.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+				.unwrap_or(rest.len());
+			rest.split_at(end)
+		};
+
+		let value = env::var(name).map_err(|_| {
+			io::Error::new(
+				ErrorKind::NotFound,
+				format!("environment variable {name} is not set"),
+			)
+		})?;
+		expanded.push_str(&value);
+		rest = remaining;
+	}
+	expanded.push_str(rest);
+
+	Ok(expanded.into())
+}
+
+/// Split a frame into the id of the call it belongs to and the remaining payload
+///
+/// Both [`Clientbound`] and [`Serverbound`] packets start with the call id, which
+/// allows reading it without knowing the type of the payload.
+///
+/// # Errors
+///
+/// This function will return an error if the frame is too short to contain a call id.
+pub fn split_frame(frame: &[u8]) -> io::Result<(u64, &[u8])> {
+	let (call_id, payload) = frame.split_first_chunk().ok_or_else(|| {
+		io::Error::new(
+			ErrorKind::UnexpectedEof,
+			"frame is too short to contain a call id",
+		)
+	})?;
+
+	Ok((u64::from_be_bytes(*call_id), payload))
+}
+
+/// Decode a value from a frame (or part of a frame)
+///
+/// # Errors
+///
+/// This function will return an error if the value can't be decoded, or
+/// exceeds `limits`.
+pub async fn decode<T: Read>(mut payload: &[u8], limits: Limits) -> Result<T, T::Error> {
+	T::read_limited(&mut payload, limits).await
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+	use tokio::io::duplex;
+
+	use super::*;
+
+	/// Discriminant at the start of the encoding of `value`
+	async fn discriminant_of(value: impl Write<Error = anyhow::Error>) -> u64 {
+		let mut bytes = Vec::new();
+		value.write(&mut bytes).await.unwrap();
+		u64::from_be_bytes(*bytes.first_chunk().unwrap())
+	}
+
+	#[tokio::test]
+	async fn test_discriminants() {
+		use discriminant::{request, response, stream_packet};
+
+		type R = Request<u8, u8>;
+		for (value, expected) in [
+			(R::Call(0), request::CALL),
+			(R::Cancel, request::CANCEL),
+			(R::Stream(0), request::STREAM),
+			(R::Subscribe(0), request::SUBSCRIBE),
+			(
+				R::ContextCall(0, CallContext::default()),
+				request::CONTEXT_CALL,
+			),
+			(R::Describe, request::DESCRIBE),
+		] {
+			assert_eq!(discriminant_of(value).await, expected);
+		}
+		for (value, expected) in [
+			(Response::Value(0u8), response::VALUE),
+			(Response::InvalidCall(String::new()), response::INVALID_CALL),
+			(
+				Response::LimitExceeded(LimitExceededError::Depth { max: 0 }),
+				response::LIMIT_EXCEEDED,
+			),
+			(Response::Unauthorized, response::UNAUTHORIZED),
+			(Response::RateLimited, response::RATE_LIMITED),
+			(Response::ShuttingDown, response::SHUTTING_DOWN),
+		] {
+			assert_eq!(discriminant_of(value).await, expected);
+		}
+		for (value, expected) in [
+			(StreamPacket::<u8, u8>::Value(0), stream_packet::VALUE),
+			(StreamPacket::Error(0), stream_packet::ERROR),
+			(StreamPacket::EndOfStream, stream_packet::END_OF_STREAM),
+		] {
+			assert_eq!(discriminant_of(value).await, expected);
+		}
+	}
+
+	#[tokio::test]
+	async fn test_frame_roundtrip() {
+		let (tx, rx) = duplex(1024);
+		let sender = PacketSender::new(tx);
+		let mut receiver = PacketReceiver::new(rx);
+
+		sender
+			.write(Serverbound {
+				call_id: 7,
+				payload: "Hello".to_owned(),
+			})
+			.await
+			.unwrap();
+
+		let frame = receiver.receive().await.unwrap();
+		let (call_id, payload) = split_frame(&frame).unwrap();
+		assert_eq!(call_id, 7);
+		assert_eq!(
+			decode::<String>(payload, Limits::default()).await.unwrap(),
+			"Hello"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_skip_frame_exceeding_limits() {
+		let (tx, rx) = duplex(1024);
+		let sender = PacketSender::new(tx);
+		let mut receiver = PacketReceiver::new(rx);
+		receiver.set_limits(Limits::default().with_max_frame_bytes(24));
+
+		for payload in ["This frame is too big", "Small"] {
+			sender
+				.write(Serverbound {
+					call_id: 3,
+					payload: payload.to_owned(),
+				})
+				.await
+				.unwrap();
+		}
+
+		let error = receiver.receive().await.unwrap_err();
+		let skipped = SkippedFrame::from_io(&error).unwrap();
+		assert_eq!(skipped.call_id, Some(3));
+		assert!(matches!(
+			skipped.error,
+			LimitExceededError::FrameBytes { max: 24, .. }
+		));
+
+		// the connection is still usable
+		let frame = receiver.receive().await.unwrap();
+		let (_, payload) = split_frame(&frame).unwrap();
+		assert_eq!(
+			decode::<String>(payload, Limits::default()).await.unwrap(),
+			"Small"
+		);
+	}
+
+	#[tokio::test]
+	async fn test_send_frame_exceeding_limits() {
+		let (tx, mut rx) = duplex(1024);
+		let mut sender = PacketSender::new(tx);
+		sender.set_limits(Limits::default().with_max_frame_bytes(16));
+
+		let error = sender
+			.write(Serverbound {
+				call_id: 3,
+				payload: "This frame is too big".to_owned(),
+			})
+			.await
+			.unwrap_err();
+		assert!(error.downcast_ref::<LimitExceededError>().is_some());
+
+		// nothing was sent
+		drop(sender);
+		let mut buf = Vec::new();
+		rx.read_to_end(&mut buf).await.unwrap();
+		assert!(buf.is_empty());
+	}
+
+	#[test]
+	fn test_expand_path() {
+		let home = env::var("HOME").unwrap();
+		assert_eq!(
+			expand_path("$HOME/a.sock").unwrap(),
+			PathBuf::from(format!("{home}/a.sock"))
+		);
+		assert_eq!(
+			expand_path("/x/${HOME}_b").unwrap(),
+			PathBuf::from(format!("/x/{home}_b"))
+		);
+		assert_eq!(expand_path("/no/vars").unwrap(), PathBuf::from("/no/vars"));
+		assert!(expand_path("$IPC_TEST_UNSET_VARIABLE/a").is_err());
+		assert!(expand_path("${HOME").is_err());
+	}
+
+	#[tokio::test]
+	async fn test_split_short_frame() {
+		assert!(split_frame(&[0, 0, 0]).is_err());
+	}
+}
@@ -1,37 +1,319 @@
-use std::{io, pin::pin};
+use std::{
+	collections::HashMap,
+	fs::{self, DirBuilder, Permissions},
+	io::{self, ErrorKind},
+	os::unix::{
+		fs::{DirBuilderExt, PermissionsExt},
+		net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream},
+	},
+	path::{Path, PathBuf},
+	pin::{Pin, pin},
+	process,
+	sync::{
+		Arc, Mutex, MutexGuard, PoisonError,
+		atomic::{AtomicU64, Ordering},
+	},
+	task::{Context, Poll},
+	time::Duration,
+};
 
 use async_stream::stream;
-use futures::{Stream, StreamExt, stream::FuturesUnordered};
-use log::error;
+use futures::{
+	Stream, StreamExt,
+	future::{AbortHandle, Abortable},
+	stream::FuturesUnordered,
+};
+use log::{error, warn};
 use tokio::{
-	io::BufWriter,
-	net::{
-		UnixListener,
-		unix::{OwnedReadHalf, OwnedWriteHalf},
-	},
-	select,
+	io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+	net::{UnixListener, unix::UCred},
+	select, spawn,
+	sync::mpsc::{self, error::TrySendError},
+	task_local,
+	time::{Instant, timeout},
 };
+use tokio_util::sync::CancellationToken;
+
+use super::{
+	Frame, PacketReceiver, PacketSender, Request, Response, Serverbound, SkippedFrame,
+	StreamPacket, split_frame,
+};
+use crate::{LimitExceededError, Limits, Read, TraceContext, UnixReadHalf, UnixWriteHalf, Write};
+
+/// Writing half of a connection, boxed so that clients using different transports
+/// can be subscribed to the same signals
+pub type ConnectionWriter = Box<dyn AsyncWrite + Unpin + Send + Sync>;
+
+task_local! {
+	/// Credentials of the client whose connection is being handled
+	static PEER_CREDENTIALS: PeerCredentials;
+	/// Time after which the client stops waiting for the call being handled
+	static DEADLINE: Option<Instant>;
+}
+
+/// Credentials of the process on the other side of a unix socket, as checked by the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+	pub uid: u32,
+	pub gid: u32,
+	/// Not available on every platform
+	pub pid: Option<i32>,
+}
+
+impl From<UCred> for PeerCredentials {
+	fn from(value: UCred) -> Self {
+		Self {
+			uid: value.uid(),
+			gid: value.gid(),
+			pid: value.pid(),
+		}
+	}
+}
+
+/// Credentials of the client whose call is being handled
+///
+/// Returns [`None`] outside of a call, or if the client isn't connected through
+/// a unix socket, like with [`crate::testing::pair`].
+#[must_use]
+pub fn peer_credentials() -> Option<PeerCredentials> {
+	PEER_CREDENTIALS.try_with(|x| *x).ok()
+}
+
+/// Time after which the client stops waiting for the call being handled
+///
+/// Handlers can use it to abort early, as their result would be ignored. Returns
+/// [`None`] outside of a call, or if the client has no timeout.
+#[must_use]
+pub fn deadline() -> Option<Instant> {
+	DEADLINE.try_with(|x| *x).ok().flatten()
+}
+
+/// Id and request of a call decoded by [`decode_call`]
+type DecodedRequest<T, S> = (u64, anyhow::Result<Request<T, S>>);
+
+/// Turn a [`Request::ContextCall`] into a [`Request::Call`], and compute its deadline
+pub fn split_context<T, S>(
+	(call_id, request): DecodedRequest<T, S>,
+) -> (DecodedRequest<T, S>, Option<Instant>, Option<TraceContext>) {
+	match request {
+		Ok(Request::ContextCall(call, context)) => (
+			(call_id, Ok(Request::Call(call))),
+			context
+				.timeout
+				.and_then(|timeout| Instant::now().checked_add(Duration::from_millis(timeout))),
+			context.trace,
+		),
+		request => ((call_id, request), None, None),
+	}
+}
+
+/// Make [`deadline`] return `deadline` while `future` runs
+pub fn with_deadline<F: Future>(
+	deadline: Option<Instant>,
+	future: F,
+) -> impl Future<Output = F::Output> {
+	DEADLINE.scope(deadline, future)
+}
+
+/// User id of the current process, the only one allowed by default
+#[must_use]
+pub fn current_uid() -> u32 {
+	rustix::process::getuid().as_raw()
+}
+
+/// Socket file of a server, removed when dropped
+#[derive(Debug)]
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+	fn drop(&mut self) {
+		if let Err(e) = fs::remove_file(&self.0) {
+			error!("Could not remove socket file {}: {e}", self.0.display());
+		}
+	}
+}
 
-use super::{PacketReceiver, StreamPacket};
+/// Listen on a socket file that only the current user can access
+///
+/// The socket is created in a private directory and then moved to `path`, so that
+/// other users can't connect to it before its permissions are restricted.
+///
+/// A file left by a server that didn't exit cleanly is replaced, but not the
+/// socket of a running server.
+///
+/// # Errors
+///
+/// This function will return an error if the socket can't be created, or
+/// if another server is listening on it.
+pub fn bind_path_socket(path: &Path) -> io::Result<(UnixListener, SocketFile)> {
+	if StdUnixStream::connect(path).is_ok() {
+		return Err(io::Error::new(
+			ErrorKind::AddrInUse,
+			format!("a server is already listening on {}", path.display()),
+		));
+	}
+
+	let name = path.file_name().ok_or(ErrorKind::InvalidInput)?;
+	let mut private = path.to_owned();
+	private.set_file_name(format!(".{}.{}", name.display(), process::id()));
+	// left by a server of a previous process with the same id
+	let _ = fs::remove_dir_all(&private);
+	DirBuilder::new().mode(0o700).create(&private)?;
+
+	let bind = || {
+		let temporary = private.join(name);
+		let listener = StdUnixListener::bind(&temporary)?;
+		fs::set_permissions(&temporary, Permissions::from_mode(0o600))?;
+		fs::rename(&temporary, path)?;
+		io::Result::Ok(listener)
+	};
+	let listener = bind();
+	if let Err(e) = fs::remove_dir_all(&private) {
+		error!("Could not remove directory {}: {e}", private.display());
+	}
+	let listener = listener?;
+	let file = SocketFile(path.to_owned());
+
+	listener.set_nonblocking(true)?;
+	Ok((UnixListener::from_std(listener)?, file))
+}
 
+/// Answer every call of a client that wasn't authorized with [`Response::Unauthorized`],
+/// until it disconnects
+pub async fn reject_client<RX, TX>(mut rx: PacketReceiver<RX>, tx: &PacketSender<TX>)
+where
+	RX: AsyncRead + Unpin + Send,
+	TX: AsyncWrite + Unpin + Send + Sync,
+{
+	loop {
+		let call_id = match rx.receive().await {
+			Ok(frame) => match split_frame(&frame) {
+				Ok((call_id, _)) => call_id,
+				Err(_) => continue,
+			},
+			Err(e) => match SkippedFrame::from_io(&e) {
+				// skipped frames still belong to a call
+				Some(skipped) => match skipped.call_id {
+					Some(call_id) => call_id,
+					None => continue,
+				},
+				None => return,
+			},
+		};
+
+		let packet = Serverbound {
+			call_id,
+			payload: Response::<!>::Unauthorized,
+		};
+		if tx.write(packet).await.is_err() {
+			return;
+		}
+	}
+}
+
+/// Settings of the connections accepted by a server
+///
+/// By default, there is no limit on the number of clients nor on the number of
+/// calls each of them runs at once.
+#[derive(Debug, Clone, Copy)]
+pub struct ServerOptions {
+	max_clients: Option<usize>,
+	max_calls_per_client: Option<usize>,
+	drain_timeout: Duration,
+}
+
+impl ServerOptions {
+	/// Stop accepting connections while `max` clients are connected
+	///
+	/// The next clients wait for one of them to disconnect.
+	#[must_use]
+	pub const fn with_max_clients(mut self, max: usize) -> Self {
+		self.max_clients = Some(max);
+		self
+	}
+
+	/// Queue the calls of a client while `max` of them are running
+	///
+	/// Calls and streamed responses count until they end. Up to `max` calls wait for
+	/// a running one to end, the next ones fail with
+	/// [`LimitExceededError::QueuedCalls`]. Cancellations and the inputs of the
+	/// running calls are still handled while calls are queued.
+	#[must_use]
+	pub const fn with_max_calls_per_client(mut self, max: usize) -> Self {
+		self.max_calls_per_client = Some(max);
+		self
+	}
+
+	/// Set how long the calls in progress have to end once the server is shut down,
+	/// before their clients are disconnected
+	#[must_use]
+	pub const fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+		self.drain_timeout = timeout;
+		self
+	}
+
+	/// Whether a client with `running` calls in progress can start another one
+	#[doc(hidden)]
+	#[must_use]
+	pub fn accepts_call(&self, running: usize) -> bool {
+		self.max_calls_per_client.is_none_or(|max| running < max)
+	}
+
+	/// Check that a client with `queued` calls waiting to start can queue another one
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the queue of the client is full.
+	#[doc(hidden)]
+	pub fn check_queued_calls(&self, queued: usize) -> Result<(), LimitExceededError> {
+		match self.max_calls_per_client {
+			Some(max) if queued >= max => Err(LimitExceededError::QueuedCalls { max }),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl Default for ServerOptions {
+	fn default() -> Self {
+		Self {
+			max_clients: None,
+			max_calls_per_client: None,
+			drain_timeout: Duration::from_secs(10),
+		}
+	}
+}
+
+/// Accept clients on `listener` until `shutdown` is cancelled
+///
+/// Once it is, the clients finish their calls in progress without starting new
+/// ones, and the ones still connected after the drain timeout are disconnected.
 pub async fn run_server<'a, S, F>(
 	server: &'a S,
 	listener: UnixListener,
-	handle_client: fn(&'a S, PacketReceiver<OwnedReadHalf>, BufWriter<OwnedWriteHalf>) -> F,
-) -> io::Result<!>
+	handle_client: fn(&'a S, UnixReadHalf, UnixWriteHalf, CancellationToken) -> F,
+	options: ServerOptions,
+	shutdown: &CancellationToken,
+) -> io::Result<()>
 where
 	S: Sync,
 	F: Future<Output = ()> + 'a,
 {
 	let mut client_tasks = FuturesUnordered::new();
 	loop {
+		let accepts_client = options
+			.max_clients
+			.is_none_or(|max| client_tasks.len() < max);
 		select! {
-			result = listener.accept() => {
+			() = shutdown.cancelled() => break,
+			result = listener.accept(), if accepts_client => {
 				match result {
-					Ok((stream, _)) => {
-						let (rx, tx) = stream.into_split();
-						client_tasks.push(handle_client(server, PacketReceiver::new(rx), BufWriter::new(tx)));
-					}
+					Ok((stream, _)) => match stream.peer_cred() {
+						Ok(credentials) => {
+							let (rx, tx) = stream.into_split();
+							client_tasks.push(PEER_CREDENTIALS.scope(credentials.into(), handle_client(server, rx.into(), tx.into(), shutdown.clone())));
+						}
+						Err(e) => error!("Could not get the credentials of a client: {e}"),
+					},
 					Err(e) => {
 						error!("Error accepting client: {e}");
 					}
@@ -40,6 +322,317 @@ where
 			Some(()) = client_tasks.next(), if !client_tasks.is_empty() => {}
 		}
 	}
+
+	drop(listener);
+	let drain = async { while client_tasks.next().await.is_some() {} };
+	if timeout(options.drain_timeout, drain).await.is_err() {
+		warn!(
+			"Disconnecting {} clients whose calls didn't end in time",
+			client_tasks.len()
+		);
+	}
+	Ok(())
+}
+
+/// Calls of a client that are still running, and can therefore be cancelled
+#[derive(Debug, Default)]
+pub struct RunningCalls(HashMap<u64, AbortHandle>);
+
+impl RunningCalls {
+	/// Make the future or stream of a call cancellable
+	///
+	/// If the call was already registered (e.g a streamed call whose stream
+	/// was just created), the previous handle is replaced.
+	pub fn register<T>(&mut self, id: u64, inner: T) -> Abortable<T> {
+		let (handle, registration) = AbortHandle::new_pair();
+		self.0.insert(id, handle);
+		Abortable::new(inner, registration)
+	}
+
+	/// Number of calls and streamed responses in progress
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// Forget about a call that has ended
+	pub fn finish(&mut self, id: u64) {
+		self.0.remove(&id);
+	}
+
+	/// Stop a call, if it is still running
+	pub fn cancel(&mut self, id: u64) {
+		if let Some(handle) = self.0.remove(&id) {
+			handle.abort();
+		}
+	}
+}
+
+/// Stream of values sent by the client, given to methods taking a stream as argument
+///
+/// The stream ends when the client has sent all values, or when the call is
+/// cancelled.
+#[derive(Debug)]
+pub struct IncomingStream<T>(mpsc::Receiver<T>);
+
+impl<T> Stream for IncomingStream<T> {
+	type Item = T;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		self.0.poll_recv(cx)
+	}
+}
+
+/// Input streams of the running calls of a method
+#[derive(Debug)]
+pub struct IncomingStreams<T> {
+	streams: HashMap<u64, mpsc::Sender<T>>,
+	max_pending: usize,
+}
+
+impl<T> IncomingStreams<T> {
+	pub fn new(limits: Limits) -> Self {
+		Self {
+			streams: HashMap::new(),
+			// a channel can't have a capacity of 0
+			max_pending: limits.max_pending_inputs().max(1),
+		}
+	}
+
+	/// Create the input stream of a call
+	pub fn create(&mut self, id: u64) -> IncomingStream<T> {
+		let (tx, rx) = mpsc::channel(self.max_pending);
+		self.streams.insert(id, tx);
+		IncomingStream(rx)
+	}
+
+	/// Forward a packet received from the client to the input stream of a call
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the method didn't handle enough
+	/// of the previous values, in which case the input stream is closed and the
+	/// call should be cancelled.
+	pub fn push(&mut self, id: u64, packet: StreamPacket<T, !>) -> Result<(), LimitExceededError> {
+		match packet {
+			StreamPacket::Value(x) => {
+				// None if the call has ended
+				if let Some(tx) = self.streams.get(&id) {
+					match tx.try_send(x) {
+						Ok(()) => {}
+						// the method dropped the stream
+						Err(TrySendError::Closed(_)) => self.finish(id),
+						Err(TrySendError::Full(_)) => {
+							self.finish(id);
+							return Err(LimitExceededError::PendingInputs {
+								max: self.max_pending,
+							});
+						}
+					}
+				}
+			}
+			StreamPacket::EndOfStream => self.finish(id),
+		}
+		Ok(())
+	}
+
+	/// Close the input stream of a call
+	pub fn finish(&mut self, id: u64) {
+		self.streams.remove(&id);
+	}
+}
+
+/// Number of signal packets queued for a client before it's considered too slow
+const SIGNAL_QUEUE_SIZE: usize = 256;
+
+/// Clients subscribed to the signals of a protocol, shared by all connections
+#[derive(Debug, Clone, Default)]
+pub struct Subscribers {
+	next_client_id: Arc<AtomicU64>,
+	subscriptions: Arc<Mutex<Vec<Subscription>>>,
+}
+
+#[derive(Debug)]
+struct Subscription {
+	client_id: u64,
+	signal: u64,
+	call_id: u64,
+	queue: mpsc::Sender<Outgoing>,
+}
+
+/// Signal packet waiting to be sent to a client
+#[derive(Debug)]
+enum Outgoing {
+	/// An emission of a signal, already encoded
+	Value(u64, Arc<[u8]>),
+	/// The subscription was removed by the server
+	End(u64),
+}
+
+impl Subscribers {
+	/// Register a newly connected client, its subscriptions are removed when
+	/// the returned value is dropped
+	///
+	/// Signals are sent to the client by a separate task, so that a client that
+	/// stops reading never blocks the emitter.
+	pub fn client(&self, packet_sender: PacketSender<ConnectionWriter>) -> ClientSubscriptions {
+		let (queue, receiver) = mpsc::channel(SIGNAL_QUEUE_SIZE);
+		spawn(send_signals(receiver, packet_sender));
+
+		ClientSubscriptions {
+			client_id: self.next_client_id.fetch_add(1, Ordering::Relaxed),
+			subscribers: self.clone(),
+			queue,
+		}
+	}
+
+	/// Send a value to every client subscribed to `signal`
+	///
+	/// This never waits for the clients, a client whose queue is full is
+	/// unsubscribed from all signals instead.
+	///
+	/// # Errors
+	///
+	/// This function will return an error if `value` can't be encoded.
+	pub async fn emit<T>(&self, signal: u64, value: &T) -> Result<(), T::Error>
+	where
+		T: Write,
+	{
+		// encoded once for every client
+		let mut payload = Vec::new();
+		value.write(&mut payload).await?;
+		self.emit_encoded(signal, &payload.into());
+		Ok(())
+	}
+
+	fn emit_encoded(&self, signal: u64, payload: &Arc<[u8]>) {
+		let mut subscriptions = self.lock();
+
+		let mut lagging = Vec::new();
+		for subscription in subscriptions.iter().filter(|x| x.signal == signal) {
+			if lagging.contains(&subscription.client_id) {
+				continue;
+			}
+			match subscription
+				.queue
+				.try_send(Outgoing::Value(subscription.call_id, Arc::clone(payload)))
+			{
+				// closed if the client is disconnecting, its subscriptions will be removed
+				Ok(()) | Err(TrySendError::Closed(_)) => {}
+				Err(TrySendError::Full(_)) => lagging.push(subscription.client_id),
+			}
+		}
+
+		if lagging.is_empty() {
+			return;
+		}
+		let removed: Vec<_> = subscriptions
+			.extract_if(.., |x| lagging.contains(&x.client_id))
+			.collect();
+		drop(subscriptions);
+
+		for client_id in lagging {
+			warn!("Client {client_id} is too slow to receive signals, unsubscribing it");
+		}
+		for subscription in removed {
+			// the queue is full, the end is sent once the client has caught up
+			spawn(async move {
+				let _ = subscription
+					.queue
+					.send(Outgoing::End(subscription.call_id))
+					.await;
+			});
+		}
+	}
+
+	fn lock(&self) -> MutexGuard<'_, Vec<Subscription>> {
+		// the lock is never held across a panic point, ignore poisoning
+		self.subscriptions
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+/// Write the signal packets queued for a client, until it disconnects
+async fn send_signals(
+	mut queue: mpsc::Receiver<Outgoing>,
+	packet_sender: PacketSender<ConnectionWriter>,
+) {
+	while let Some(outgoing) = queue.recv().await {
+		let (call_id, payload) = match outgoing {
+			Outgoing::Value(call_id, payload) => (call_id, Some(payload)),
+			Outgoing::End(call_id) => (call_id, None),
+		};
+		let packet = Serverbound {
+			call_id,
+			payload: Response::Value(match &payload {
+				Some(payload) => StreamPacket::<_, !>::Value(Encoded(payload)),
+				None => StreamPacket::EndOfStream,
+			}),
+		};
+
+		if let Err(e) = packet_sender.write(packet).await {
+			if let Some(e) = e.downcast_ref::<io::Error>()
+				&& e.kind() == io::ErrorKind::BrokenPipe
+			{
+				// client is disconnecting, its subscriptions will be removed
+				return;
+			}
+			error!("Error while sending signal to client: {e}");
+		}
+	}
+}
+
+/// Subscriptions of a single client
+#[derive(Debug)]
+pub struct ClientSubscriptions {
+	client_id: u64,
+	subscribers: Subscribers,
+	queue: mpsc::Sender<Outgoing>,
+}
+
+impl ClientSubscriptions {
+	/// Send the emissions of `signal` to the client, with the id of the call that subscribed
+	pub fn subscribe(&self, signal: u64, call_id: u64) {
+		self.subscribers.lock().push(Subscription {
+			client_id: self.client_id,
+			signal,
+			call_id,
+			queue: self.queue.clone(),
+		});
+	}
+
+	/// Stop sending a signal to the client, if `call_id` was a subscription
+	pub fn unsubscribe(&self, call_id: u64) {
+		self.subscribers
+			.lock()
+			.retain(|x| x.client_id != self.client_id || x.call_id != call_id);
+	}
+}
+
+impl Drop for ClientSubscriptions {
+	fn drop(&mut self) {
+		self.subscribers
+			.lock()
+			.retain(|x| x.client_id != self.client_id);
+	}
+}
+
+/// Value that was already encoded, written as is
+struct Encoded<'a>(&'a [u8]);
+
+impl Write for Encoded<'_> {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_all(self.0).await
+	}
 }
 
 pub fn stream_with_id<T>(
@@ -56,3 +649,81 @@ pub fn stream_with_id<T>(
 		yield (id, StreamPacket::EndOfStream);
 	}
 }
+
+/// Same as [`stream_with_id`], but the stream ends after sending the first error
+pub fn fallible_stream_with_id<T, E>(
+	id: u64,
+	stream: impl Stream<Item = Result<T, E>>,
+) -> impl Stream<Item = (u64, StreamPacket<Result<T, E>, !>)> {
+	stream! {
+		let mut stream = pin!(stream);
+
+		while let Some(item) = stream.next().await {
+			let is_error = item.is_err();
+			yield (id, StreamPacket::Value(item));
+			if is_error {
+				break;
+			}
+		}
+
+		yield (id, StreamPacket::EndOfStream);
+	}
+}
+
+/// Decode a [`Clientbound`](super::Clientbound) packet from a frame
+///
+/// The outer result is an error if the call id couldn't be read, in which case
+/// there's no way to answer the client. The inner result is an error if the
+/// payload couldn't be decoded or exceeds `limits`, which only affects this call.
+///
+/// # Errors
+///
+/// This function will return an error if the frame is too short to contain a call id.
+pub async fn decode_call<T>(frame: Frame, limits: Limits) -> io::Result<(u64, anyhow::Result<T>)>
+where
+	T: Read,
+	anyhow::Error: From<T::Error>,
+{
+	let (call_id, call) = frame.decode_payload::<T>(limits).await?;
+	Ok((call_id, call.map_err(anyhow::Error::from)))
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+	use futures::stream;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn test_slow_subscriber_doesnt_block_emit() {
+		// nobody reads the other side, writes block once the buffer is full
+		let (tx, _rx) = tokio::io::duplex(64);
+		let subscribers = Subscribers::default();
+		let client = subscribers.client(PacketSender::new(Box::new(tx)));
+		client.subscribe(0, 1);
+
+		let emit_all = async {
+			for i in 0..(SIGNAL_QUEUE_SIZE as u64 * 2) {
+				subscribers.emit(0, &i).await.unwrap();
+			}
+		};
+		tokio::time::timeout(std::time::Duration::from_secs(5), emit_all)
+			.await
+			.unwrap();
+
+		assert!(subscribers.lock().is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_fallible_stream_ends_after_error() {
+		let items = stream::iter([Ok(1u8), Err(2u8), Ok(3)]);
+		let packets: Vec<_> = fallible_stream_with_id(5, items).collect().await;
+
+		assert_eq!(packets.len(), 3);
+		assert!(packets.iter().all(|(id, _)| *id == 5));
+		assert!(matches!(packets[0].1, StreamPacket::Value(Ok(1))));
+		assert!(matches!(packets[1].1, StreamPacket::Value(Err(2))));
+		assert!(matches!(packets[2].1, StreamPacket::EndOfStream));
+	}
+}
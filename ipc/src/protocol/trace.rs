@@ -0,0 +1,168 @@
+//! Tying the calls made on behalf of the same request together, across processes
+//!
+//! Every call handled by a server belongs to a trace, either the one sent by
+//! the client or a new one. Calls made while handling it belong to the same
+//! trace. With the `tracing` feature, clients send the span they call from,
+//! and servers run each call in a `call` span with its trace id.
+
+use std::{
+	hash::{BuildHasher, RandomState},
+	pin::pin,
+};
+
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use tokio::task_local;
+
+use super::server::with_deadline;
+use crate::{Read, Write, interceptor::CallInfo};
+
+task_local! {
+	/// Trace of the call being handled
+	static TRACE_ID: u128;
+}
+
+/// Trace of a call, sent by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Read, Write)]
+pub struct TraceContext {
+	/// Shared by every call made on behalf of the same request
+	pub trace_id: u128,
+	/// Id of the span the client made the call from, only meaningful to the client
+	pub parent_span_id: Option<u64>,
+}
+
+impl TraceContext {
+	/// Start a new trace, not caused by another call
+	#[must_use]
+	pub fn new() -> Self {
+		// the keys of each `RandomState` are different, so are the hashes
+		let state = RandomState::new();
+		Self {
+			trace_id: u128::from(state.hash_one(0u8)) << 64 | u128::from(state.hash_one(1u8)),
+			parent_span_id: None,
+		}
+	}
+
+	/// Trace of a call made from the current task, continuing the trace of the call being handled
+	#[must_use]
+	pub fn current() -> Self {
+		Self {
+			trace_id: trace_id().unwrap_or_else(|| Self::new().trace_id),
+			#[cfg(feature = "tracing")]
+			parent_span_id: tracing::Span::current().id().map(|id| id.into_u64()),
+			#[cfg(not(feature = "tracing"))]
+			parent_span_id: None,
+		}
+	}
+
+	/// Trace sent with a call, [`None`] if there's nothing to propagate
+	///
+	/// Without the `tracing` feature, calls only continue the trace of the call being handled.
+	pub(crate) fn outgoing() -> Option<Self> {
+		if cfg!(feature = "tracing") || trace_id().is_some() {
+			Some(Self::current())
+		} else {
+			None
+		}
+	}
+}
+
+impl Default for TraceContext {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Id of the trace of the call being handled
+///
+/// Returns [`None`] outside of a call.
+#[must_use]
+pub fn trace_id() -> Option<u128> {
+	TRACE_ID.try_with(|x| *x).ok()
+}
+
+/// The `call` span of `call`
+#[cfg(feature = "tracing")]
+fn call_span(call: &CallInfo) -> tracing::Span {
+	use tracing::{field, info_span};
+
+	let span = info_span!(
+		"call",
+		method = call.method,
+		call_id = call.call_id,
+		trace_id = %format_args!("{:032x}", call.trace.trace_id),
+		parent_span_id = field::Empty,
+		uid = field::Empty,
+		pid = field::Empty,
+	);
+	if let Some(id) = call.trace.parent_span_id {
+		span.record("parent_span_id", id);
+	}
+	if let Some(peer) = call.peer {
+		span.record("uid", peer.uid);
+		if let Some(pid) = peer.pid {
+			span.record("pid", pid);
+		}
+	}
+	span
+}
+
+/// Run `future` in the trace of `call`, and in its `call` span with the `tracing` feature
+pub fn in_call_span<F: Future>(call: &CallInfo, future: F) -> impl Future<Output = F::Output> {
+	#[cfg(feature = "tracing")]
+	let future = tracing::Instrument::instrument(future, call_span(call));
+	TRACE_ID.scope(call.trace.trace_id, future)
+}
+
+/// Poll the stream returned by `call` like the call itself, see [`in_call_span`]
+///
+/// The [`deadline`](super::server::deadline) of the call is also kept while the stream is polled.
+pub fn in_call_stream<S: Stream>(call: CallInfo, stream: S) -> impl Stream<Item = S::Item> {
+	#[cfg(feature = "tracing")]
+	let span = call_span(&call);
+	stream! {
+		let mut stream = pin!(stream);
+		loop {
+			let next = stream.next();
+			#[cfg(feature = "tracing")]
+			let next = tracing::Instrument::instrument(next, span.clone());
+			match with_deadline(call.deadline, TRACE_ID.scope(call.trace.trace_id, next)).await {
+				Some(item) => yield item,
+				None => break,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn test_propagation() {
+		assert_ne!(TraceContext::new(), TraceContext::new());
+		assert_eq!(trace_id(), None);
+
+		let trace = TraceContext::new();
+		let call = CallInfo {
+			method: "call",
+			call_id: 0,
+			peer: None,
+			deadline: None,
+			trace,
+			arguments: Vec::new(),
+		};
+		in_call_span(&call, async {
+			assert_eq!(trace_id(), Some(trace.trace_id));
+			// calls made by the handler continue the trace
+			assert_eq!(TraceContext::outgoing().unwrap().trace_id, trace.trace_id);
+		})
+		.await;
+
+		// so does the stream it returns, while it's polled
+		let items = in_call_stream(call, futures::stream::repeat_with(trace_id).take(2));
+		assert_eq!(items.collect::<Vec<_>>().await, [Some(trace.trace_id); 2]);
+		assert_eq!(trace_id(), None);
+	}
+}
@@ -1,3 +1,8 @@
+use std::{
+	ffi::{OsStr, OsString},
+	path::{Path, PathBuf},
+};
+
 use anyhow::Context;
 
 use crate::Write;
@@ -25,3 +30,7 @@ where
 	Result<(), T::Error>: Context<(), T::Error>,
 {
 }
+
+impl Writable<OsString> for &OsStr {}
+
+impl Writable<PathBuf> for &Path {}
@@ -6,12 +6,26 @@ use anyhow::{Context, bail};
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+mod stdlib;
+
 pub trait Read {
 	type Error;
 
+	/// Read a value, with the default [`Limits`]
 	fn read(
 		stream: &mut (impl AsyncRead + Unpin + Send),
 	) -> impl Future<Output = Result<Self, Self::Error>> + Send
+	where
+		Self: Sized,
+	{
+		Self::read_limited(stream, Limits::default())
+	}
+
+	/// Read a value, failing if it's bigger or more nested than allowed by `limits`
+	fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> impl Future<Output = Result<Self, Self::Error>> + Send
 	where
 		Self: Sized;
 }
@@ -32,10 +46,172 @@ pub struct InvalidDiscriminantError {
 	pub value: usize,
 }
 
+/// Limits on the values decoded from a connection
+///
+/// Lengths are sent by the other side of the connection, and would otherwise
+/// be trusted when allocating memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+	max_length: usize,
+	max_string_bytes: usize,
+	max_depth: usize,
+	max_frame_bytes: usize,
+	max_pending_inputs: usize,
+	/// Nesting depth of the value being read
+	depth: usize,
+}
+
+/// A decoded value exceeded the [`Limits`] of the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error, ipc_macros::Read, ipc_macros::Write)]
+pub enum LimitExceededError {
+	#[error("Collection has {length} elements, but at most {max} are allowed")]
+	Length { length: u64, max: usize },
+	#[error("String has {length} bytes, but at most {max} are allowed")]
+	StringBytes { length: u64, max: usize },
+	#[error("Value is nested more than {max} times")]
+	Depth { max: usize },
+	#[error("Frame has {length} bytes, but at most {max} are allowed")]
+	FrameBytes { length: u64, max: usize },
+	#[error("More than {max} values of an input stream are waiting to be handled")]
+	PendingInputs { max: usize },
+	#[error("More than {max} calls are waiting for the running ones to end")]
+	QueuedCalls { max: usize },
+}
+
+impl Limits {
+	/// Maximum number of elements of a collection
+	#[must_use]
+	pub const fn with_max_length(mut self, max: usize) -> Self {
+		self.max_length = max;
+		self
+	}
+
+	/// Maximum number of bytes of a string
+	#[must_use]
+	pub const fn with_max_string_bytes(mut self, max: usize) -> Self {
+		self.max_string_bytes = max;
+		self
+	}
+
+	/// Maximum nesting depth of a value, each struct, enum, tuple or collection being a level
+	#[must_use]
+	pub const fn with_max_depth(mut self, max: usize) -> Self {
+		self.max_depth = max;
+		self
+	}
+
+	/// Maximum size of a frame, i.e a whole packet
+	///
+	/// This applies to both sent and received frames, the other side of the
+	/// connection is expected to use the same limit.
+	#[must_use]
+	pub const fn with_max_frame_bytes(mut self, max: usize) -> Self {
+		self.max_frame_bytes = max;
+		self
+	}
+
+	/// Maximum number of values of an input stream received but not yet handled
+	///
+	/// A call whose input stream exceeds this limit is cancelled.
+	#[must_use]
+	pub const fn with_max_pending_inputs(mut self, max: usize) -> Self {
+		self.max_pending_inputs = max;
+		self
+	}
+
+	pub(crate) const fn max_pending_inputs(self) -> usize {
+		self.max_pending_inputs
+	}
+
+	/// Limits for the values contained in the one being read
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the maximum depth is exceeded.
+	pub const fn nested(self) -> Result<Self, LimitExceededError> {
+		if self.depth >= self.max_depth {
+			return Err(LimitExceededError::Depth {
+				max: self.max_depth,
+			});
+		}
+		Ok(Self {
+			depth: self.depth + 1,
+			..self
+		})
+	}
+
+	/// Check the length of a collection before reading it
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the length exceeds the maximum.
+	pub fn check_length(self, length: u64) -> Result<usize, LimitExceededError> {
+		usize::try_from(length)
+			.ok()
+			.filter(|x| *x <= self.max_length)
+			.ok_or(LimitExceededError::Length {
+				length,
+				max: self.max_length,
+			})
+	}
+
+	/// Check the length of a string before reading it
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the length exceeds the maximum.
+	pub fn check_string_bytes(self, length: u64) -> Result<usize, LimitExceededError> {
+		usize::try_from(length)
+			.ok()
+			.filter(|x| *x <= self.max_string_bytes)
+			.ok_or(LimitExceededError::StringBytes {
+				length,
+				max: self.max_string_bytes,
+			})
+	}
+
+	/// Check the size of a frame before reading or sending it
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the size exceeds the maximum.
+	pub fn check_frame_bytes(self, length: u64) -> Result<usize, LimitExceededError> {
+		usize::try_from(length)
+			.ok()
+			.filter(|x| *x <= self.max_frame_bytes)
+			.ok_or(LimitExceededError::FrameBytes {
+				length,
+				max: self.max_frame_bytes,
+			})
+	}
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			max_length: 1 << 20,
+			max_string_bytes: 16 << 20,
+			max_depth: 64,
+			max_frame_bytes: 64 << 20,
+			max_pending_inputs: 1024,
+			depth: 0,
+		}
+	}
+}
+
+/// Maximum number of elements allocated before reading a collection
+///
+/// Bigger collections grow while they're read, so that a forged length
+/// doesn't allocate memory for elements that are never sent.
+const MAX_PREALLOCATION: usize = 1024;
+
 impl Read for () {
 	type Error = !;
 
-	async fn read(_stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+	async fn read_limited(
+		_stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
 	where
 		Self: Sized,
 	{
@@ -46,7 +222,10 @@ impl Read for () {
 impl Read for ! {
 	type Error = !;
 
-	async fn read(_stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+	async fn read_limited(
+		_stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
 	where
 		Self: Sized,
 	{
@@ -57,7 +236,10 @@ impl Read for ! {
 impl Read for bool {
 	type Error = anyhow::Error;
 
-	async fn read(stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
 	where
 		Self: Sized,
 	{
@@ -74,7 +256,10 @@ macro_rules! simple_read_impl {
 		impl Read for $type {
 			type Error = io::Error;
 
-			async fn read(stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+			async fn read_limited(
+				stream: &mut (impl AsyncRead + Unpin + Send),
+				_limits: Limits,
+			) -> Result<Self, Self::Error>
 			where
 				Self: Sized,
 			{
@@ -88,11 +273,59 @@ simple_read_impl!(u8, read_u8);
 simple_read_impl!(u16, read_u16);
 simple_read_impl!(u32, read_u32);
 simple_read_impl!(u64, read_u64);
+simple_read_impl!(u128, read_u128);
 
 simple_read_impl!(i8, read_i8);
 simple_read_impl!(i16, read_i16);
 simple_read_impl!(i32, read_i32);
 simple_read_impl!(i64, read_i64);
+simple_read_impl!(i128, read_i128);
+
+simple_read_impl!(f32, read_f32);
+simple_read_impl!(f64, read_f64);
+
+impl Read for usize {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		Self::try_from(stream.read_u64().await?).context("value exceeds platform capacity")
+	}
+}
+
+impl Read for isize {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		Self::try_from(stream.read_i64().await?).context("value exceeds platform capacity")
+	}
+}
+
+impl Read for char {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let value = stream.read_u32().await?;
+		Self::from_u32(value).with_context(|| format!("Invalid char value: {value}"))
+	}
+}
 
 macro_rules! tuple_read_impl {
     ($t:ident $($ts:ident)*) => {
@@ -105,11 +338,15 @@ macro_rules! tuple_read_impl {
             anyhow::Error: From<$t::Error> $(+ From<$ts::Error>)*,
         {
             type Error = anyhow::Error;
-            async fn read(stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+            async fn read_limited(
+                stream: &mut (impl AsyncRead + Unpin + Send),
+                limits: Limits,
+            ) -> Result<Self, Self::Error>
             where
                 Self: Sized,
             {
-                Ok(($t::read(stream).await?, $($ts::read(stream).await?,)*))
+                let limits = limits.nested()?;
+                Ok(($t::read_limited(stream, limits).await?, $($ts::read_limited(stream, limits).await?,)*))
             }
         }
 
@@ -123,14 +360,14 @@ tuple_read_impl!(A B C D E F G H I J K L M N O P);
 impl Read for String {
 	type Error = anyhow::Error;
 
-	async fn read(stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
 	where
 		Self: Sized,
 	{
-		let len = u64::read(stream)
-			.await?
-			.try_into()
-			.context("length exceeds platform capacity")?;
+		let len = limits.check_string_bytes(u64::read(stream).await?)?;
 
 		let mut buf = vec![0; len];
 		stream.read_exact(&mut buf).await?;
@@ -146,11 +383,14 @@ where
 {
 	type Error = <U as Read>::Error;
 
-	async fn read(stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
 	where
 		Self: Sized,
 	{
-		U::read(stream).await.map(Cow::Owned)
+		U::read_limited(stream, limits).await.map(Cow::Owned)
 	}
 }
 
@@ -162,17 +402,20 @@ where
 {
 	type Error = anyhow::Error;
 
-	async fn read(stream: &mut (impl AsyncRead + Unpin + Send)) -> Result<Self, Self::Error>
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
 	where
 		Self: Sized,
 	{
-		let len: usize = usize::try_from(u64::read(stream).await?)
-			.context("length exceeds platform capacity")?;
+		let len = limits.check_length(u64::read(stream).await?)?;
+		let limits = limits.nested()?;
 
-		let mut result = Self::with_capacity(len);
+		let mut result = Self::with_capacity(len.min(MAX_PREALLOCATION));
 		for i in 0..len {
 			result.push(
-				T::read(stream)
+				T::read_limited(stream, limits)
 					.await
 					.with_context(|| format!("while reading element {i}"))?,
 			);
@@ -253,11 +496,49 @@ simple_write_impl!(u8, write_u8);
 simple_write_impl!(u16, write_u16);
 simple_write_impl!(u32, write_u32);
 simple_write_impl!(u64, write_u64);
+simple_write_impl!(u128, write_u128);
 
 simple_write_impl!(i8, write_i8);
 simple_write_impl!(i16, write_i16);
 simple_write_impl!(i32, write_i32);
 simple_write_impl!(i64, write_i64);
+simple_write_impl!(i128, write_i128);
+
+simple_write_impl!(f32, write_f32);
+simple_write_impl!(f64, write_f64);
+
+impl Write for usize {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_u64(*self as u64).await
+	}
+}
+
+impl Write for isize {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_i64(*self as i64).await
+	}
+}
+
+impl Write for char {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_u32(u32::from(*self)).await
+	}
+}
 
 macro_rules! tuple_write_impl {
     ($t:ident $($ts:ident)*) => {
@@ -503,6 +784,44 @@ mod tests {
 		assert_eq!(result, vec![1, 2, 3]);
 	}
 
+	#[tokio::test]
+	async fn test_read_string_too_long() {
+		let data = &[0, 0, 0, 0, 0, 0, 0, 5, 72, 101, 108, 108, 111];
+		let mut reader = BufReader::new(&data[..]);
+		let limits = Limits::default().with_max_string_bytes(4);
+		let error = String::read_limited(&mut reader, limits).await.unwrap_err();
+		assert_eq!(
+			error.downcast_ref::<LimitExceededError>(),
+			Some(&LimitExceededError::StringBytes { length: 5, max: 4 })
+		);
+	}
+
+	#[tokio::test]
+	async fn test_read_vec_too_long() {
+		// the length is checked before allocating, the elements are never read
+		let data = &[255, 255, 255, 255, 255, 255, 255, 255];
+		let mut reader = BufReader::new(&data[..]);
+		let error = <Vec<u8>>::read(&mut reader).await.unwrap_err();
+		assert!(matches!(
+			error.downcast_ref::<LimitExceededError>(),
+			Some(LimitExceededError::Length { .. })
+		));
+	}
+
+	#[tokio::test]
+	async fn test_read_too_nested() {
+		let data = &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 42];
+		let mut reader = BufReader::new(&data[..]);
+		let limits = Limits::default().with_max_depth(1);
+		let error = <Vec<Vec<u8>>>::read_limited(&mut reader, limits)
+			.await
+			.unwrap_err();
+		assert!(matches!(
+			error.downcast_ref::<LimitExceededError>(),
+			Some(LimitExceededError::Depth { max: 1 })
+		));
+	}
+
 	#[tokio::test]
 	async fn test_write_slice() {
 		let mut writer = BufWriter::new(Vec::new());
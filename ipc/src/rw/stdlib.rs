@@ -0,0 +1,569 @@
+//! Read / Write implementations for standard library types that aren't primitives
+
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	ffi::{OsStr, OsString},
+	hash::{BuildHasher, Hash},
+	io,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr},
+	num::NonZero,
+	os::unix::ffi::{OsStrExt, OsStringExt},
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, anyhow, bail};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{Limits, Read, Write};
+
+impl<T: Read> Read for Box<T> {
+	type Error = T::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		T::read_limited(stream, limits).await.map(Self::new)
+	}
+}
+
+impl<T: Write + ?Sized + Sync> Write for Box<T> {
+	type Error = T::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		(**self).write(stream).await
+	}
+}
+
+impl<T: Read> Read for Arc<T> {
+	type Error = T::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		T::read_limited(stream, limits).await.map(Self::new)
+	}
+}
+
+impl<T: Write + ?Sized + Send + Sync> Write for Arc<T> {
+	type Error = T::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		(**self).write(stream).await
+	}
+}
+
+/// Arrays are encoded like slices, the length is checked when reading
+impl<T, const N: usize> Read for [T; N]
+where
+	T: Read + Send,
+	anyhow::Error: From<T::Error>,
+	Result<T, T::Error>: Context<T, T::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let elements = Vec::<T>::read_limited(stream, limits).await?;
+		let len = elements.len();
+		elements
+			.try_into()
+			.map_err(|_| anyhow!("Expected {N} elements, got {len}"))
+	}
+}
+
+impl<T, const N: usize> Write for [T; N]
+where
+	T: Write + Sync,
+	anyhow::Error: From<T::Error>,
+	Result<(), T::Error>: Context<(), T::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		self.as_slice().write(stream).await
+	}
+}
+
+/// Write the length of a collection, then its elements
+async fn write_collection<'a, T>(
+	stream: &mut (impl AsyncWrite + Unpin + Send),
+	len: usize,
+	elements: impl Iterator<Item = &'a T> + Send,
+) -> anyhow::Result<()>
+where
+	T: Write + Sync + 'a,
+	anyhow::Error: From<T::Error>,
+{
+	(len as u64).write(stream).await?;
+
+	for (i, e) in elements.enumerate() {
+		e.write(stream)
+			.await
+			.map_err(anyhow::Error::from)
+			.with_context(|| format!("while writing element {i}"))?;
+	}
+
+	Ok(())
+}
+
+/// Write the length of a map, then its key-value pairs
+async fn write_map<'a, K, V>(
+	stream: &mut (impl AsyncWrite + Unpin + Send),
+	len: usize,
+	pairs: impl Iterator<Item = (&'a K, &'a V)> + Send,
+) -> anyhow::Result<()>
+where
+	K: Write + Sync + 'a,
+	V: Write + Sync + 'a,
+	anyhow::Error: From<K::Error> + From<V::Error>,
+{
+	(len as u64).write(stream).await?;
+
+	for (i, (k, v)) in pairs.enumerate() {
+		k.write(stream)
+			.await
+			.map_err(anyhow::Error::from)
+			.with_context(|| format!("while writing key {i}"))?;
+		v.write(stream)
+			.await
+			.map_err(anyhow::Error::from)
+			.with_context(|| format!("while writing value {i}"))?;
+	}
+
+	Ok(())
+}
+
+/// Maps are encoded like a [`Vec`] of key-value pairs
+impl<K, V, S> Read for HashMap<K, V, S>
+where
+	K: Read + Eq + Hash + Send,
+	K::Error: Send,
+	V: Read + Send,
+	V::Error: Send,
+	S: BuildHasher + Default,
+	anyhow::Error: From<K::Error> + From<V::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let pairs = Vec::<(K, V)>::read_limited(stream, limits).await?;
+		Ok(pairs.into_iter().collect())
+	}
+}
+
+impl<K, V, S> Write for HashMap<K, V, S>
+where
+	K: Write + Sync,
+	V: Write + Sync,
+	S: Sync,
+	anyhow::Error: From<K::Error> + From<V::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		write_map(stream, self.len(), self.iter()).await
+	}
+}
+
+impl<K, V> Read for BTreeMap<K, V>
+where
+	K: Read + Ord + Send,
+	K::Error: Send,
+	V: Read + Send,
+	V::Error: Send,
+	anyhow::Error: From<K::Error> + From<V::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let pairs = Vec::<(K, V)>::read_limited(stream, limits).await?;
+		Ok(pairs.into_iter().collect())
+	}
+}
+
+impl<K, V> Write for BTreeMap<K, V>
+where
+	K: Write + Sync,
+	V: Write + Sync,
+	anyhow::Error: From<K::Error> + From<V::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		write_map(stream, self.len(), self.iter()).await
+	}
+}
+
+/// Sets are encoded like a [`Vec`]
+impl<T, S> Read for HashSet<T, S>
+where
+	T: Read + Eq + Hash + Send,
+	S: BuildHasher + Default,
+	anyhow::Error: From<T::Error>,
+	Result<T, T::Error>: Context<T, T::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let elements = Vec::<T>::read_limited(stream, limits).await?;
+		Ok(elements.into_iter().collect())
+	}
+}
+
+impl<T, S> Write for HashSet<T, S>
+where
+	T: Write + Sync,
+	S: Sync,
+	anyhow::Error: From<T::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		write_collection(stream, self.len(), self.iter()).await
+	}
+}
+
+impl<T> Read for BTreeSet<T>
+where
+	T: Read + Ord + Send,
+	anyhow::Error: From<T::Error>,
+	Result<T, T::Error>: Context<T, T::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let elements = Vec::<T>::read_limited(stream, limits).await?;
+		Ok(elements.into_iter().collect())
+	}
+}
+
+impl<T> Write for BTreeSet<T>
+where
+	T: Write + Sync,
+	anyhow::Error: From<T::Error>,
+{
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		write_collection(stream, self.len(), self.iter()).await
+	}
+}
+
+/// Encoded as whole seconds, then nanoseconds
+impl Read for Duration {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let secs = stream.read_u64().await?;
+		let nanos = stream.read_u32().await?;
+		if nanos >= 1_000_000_000 {
+			bail!("Invalid nanoseconds value: {nanos}");
+		}
+
+		Ok(Self::new(secs, nanos))
+	}
+}
+
+impl Write for Duration {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_u64(self.as_secs()).await?;
+		stream.write_u32(self.subsec_nanos()).await
+	}
+}
+
+/// Encoded as the duration since [`UNIX_EPOCH`], which is an error if the time is before it
+impl Read for SystemTime {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let time = match Result::<Duration, Duration>::read_limited(stream, limits).await? {
+			Ok(x) => UNIX_EPOCH.checked_add(x),
+			Err(x) => UNIX_EPOCH.checked_sub(x),
+		};
+
+		time.context("time is out of range")
+	}
+}
+
+impl Write for SystemTime {
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		self.duration_since(UNIX_EPOCH)
+			.map_err(|e| e.duration())
+			.write(stream)
+			.await
+	}
+}
+
+/// Encoded like a [`String`], but the bytes don't have to be valid UTF-8
+impl Read for OsString {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		let len = limits.check_string_bytes(stream.read_u64().await?)?;
+
+		let mut buf = vec![0; len];
+		stream.read_exact(&mut buf).await?;
+
+		Ok(Self::from_vec(buf))
+	}
+}
+
+impl Write for OsStr {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_u64(self.len() as u64).await?;
+		stream.write_all(self.as_bytes()).await
+	}
+}
+
+impl Write for &OsStr {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		OsStr::write(self, stream).await
+	}
+}
+
+impl Write for OsString {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		self.as_os_str().write(stream).await
+	}
+}
+
+impl Read for PathBuf {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		OsString::read_limited(stream, limits).await.map(Self::from)
+	}
+}
+
+impl Write for Path {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		self.as_os_str().write(stream).await
+	}
+}
+
+impl Write for &Path {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		self.as_os_str().write(stream).await
+	}
+}
+
+impl Write for PathBuf {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		self.as_os_str().write(stream).await
+	}
+}
+
+impl Read for Ipv4Addr {
+	type Error = io::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		stream.read_u32().await.map(Self::from_bits)
+	}
+}
+
+impl Write for Ipv4Addr {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_u32(self.to_bits()).await
+	}
+}
+
+impl Read for Ipv6Addr {
+	type Error = io::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		_limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		stream.read_u128().await.map(Self::from_bits)
+	}
+}
+
+impl Write for Ipv6Addr {
+	type Error = io::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		stream.write_u128(self.to_bits()).await
+	}
+}
+
+ipc_macros::__impl_rw_for_external! {
+	enum IpAddr {
+		V4(Ipv4Addr),
+		V6(Ipv6Addr)
+	}
+}
+
+macro_rules! non_zero_impl {
+	($($type:ty)*) => {
+		$(
+			impl Read for NonZero<$type> {
+				type Error = anyhow::Error;
+
+				async fn read_limited(
+					stream: &mut (impl AsyncRead + Unpin + Send),
+					limits: Limits,
+				) -> Result<Self, Self::Error>
+				where
+					Self: Sized,
+				{
+					Self::new(<$type>::read_limited(stream, limits).await?)
+						.context("Invalid zero value for non-zero integer")
+				}
+			}
+
+			impl Write for NonZero<$type> {
+				type Error = <$type as Write>::Error;
+
+				async fn write(
+					&self,
+					stream: &mut (impl AsyncWrite + Unpin + Send),
+				) -> Result<(), Self::Error> {
+					self.get().write(stream).await
+				}
+			}
+		)*
+	};
+}
+
+non_zero_impl!(u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize);
@@ -0,0 +1,448 @@
+//! Description of protocols, sent to clients that don't know them at compile time
+//!
+//! Every protocol has a [`Schema`], listing its methods and signals with the types of
+//! their arguments and responses. Servers send it to clients making a describe call,
+//! which lets [`DynamicClient`](crate::DynamicClient) call methods with arguments written
+//! as text, see [`Schema::encode`].
+//!
+//! Types are described by [`Describe`], which is implemented by the
+//! [`Read`](macro@crate::Read) derive. Types that don't implement it, like the type
+//! parameters of generic protocols or types with a hand-written [`Read`](crate::Read)
+//! implementation, are described as [`Type::Opaque`].
+
+use std::{any, collections::HashMap, marker::PhantomData};
+
+use crate::{Read, Write};
+
+mod stdlib;
+mod text;
+
+pub use text::ValueError;
+
+/// Description of a protocol
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+#[ipc(extensible)]
+pub struct Schema {
+	/// Name of the protocol trait
+	pub name: String,
+	/// Fingerprint of the protocol, exchanged when a connection is opened
+	pub fingerprint: u64,
+	pub methods: Vec<Method>,
+	pub signals: Vec<Signal>,
+	/// Types used by the methods and signals, referenced by their index
+	pub types: Vec<Type>,
+}
+
+/// Description of a method of a protocol
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+#[ipc(extensible)]
+pub struct Method {
+	pub name: String,
+	/// Documentation of the method
+	pub docs: String,
+	/// Arguments sent with the call, without the input stream
+	pub arguments: Vec<Argument>,
+	/// Type of the values of the input stream, if the method takes one
+	pub input_stream: Option<u64>,
+	/// Type of the response, or of the values of a streamed response, which are
+	/// results if there's an item error
+	pub output: u64,
+	/// The response is a stream
+	pub stream: bool,
+	/// Error sent instead of the stream
+	pub early_error: Option<u64>,
+	/// Error sent instead of a value of the stream
+	pub item_error: Option<u64>,
+	/// Milliseconds after which clients stop waiting, if set with `#[timeout]`
+	pub timeout: Option<u64>,
+}
+
+/// Description of a signal of a protocol
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+#[ipc(extensible)]
+pub struct Signal {
+	pub name: String,
+	/// Documentation of the signal
+	pub docs: String,
+	/// Arguments of the signal, sent one after the other
+	pub arguments: Vec<Argument>,
+}
+
+/// Argument of a method or of a signal
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+pub struct Argument {
+	pub name: String,
+	/// Index of the type in [`Schema::types`]
+	pub ty: u64,
+}
+
+/// Encoding of a type
+///
+/// Other types are referenced by their index in [`Schema::types`], which allows
+/// recursive types.
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+pub enum Type {
+	Unit,
+	Never,
+	Bool,
+	U8,
+	U16,
+	U32,
+	U64,
+	U128,
+	I8,
+	I16,
+	I32,
+	I64,
+	I128,
+	F32,
+	F64,
+	Char,
+	String,
+	/// Length, then the values
+	Sequence(u64),
+	/// Values one after the other
+	Tuple(Vec<u64>),
+	Struct {
+		name: String,
+		fields: Fields,
+		/// The fields are prefixed by their total length
+		extensible: bool,
+	},
+	Enum {
+		name: String,
+		/// Type of the discriminant, sent before the fields of the variant
+		repr: Repr,
+		variants: Vec<Variant>,
+		/// The fields of each variant are prefixed by their total length
+		extensible: bool,
+	},
+	/// A type whose encoding is unknown, with its name
+	Opaque(String),
+}
+
+/// Fields of a struct or of a variant, sent one after the other
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+pub enum Fields {
+	Unit,
+	Named(Vec<Field>),
+	/// Fields named by their position
+	Unnamed(Vec<Field>),
+}
+
+impl Fields {
+	/// All the fields, empty for [`Fields::Unit`]
+	#[must_use]
+	pub fn as_slice(&self) -> &[Field] {
+		match self {
+			Self::Unit => &[],
+			Self::Named(fields) | Self::Unnamed(fields) => fields,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+pub struct Field {
+	pub name: String,
+	/// Index of the type in [`Schema::types`]
+	pub ty: u64,
+	/// The field can be left out of an extensible type, see `#[ipc(default)]`
+	pub default: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+pub struct Variant {
+	pub name: String,
+	pub discriminant: u64,
+	pub fields: Fields,
+}
+
+/// Type of the discriminants of an enum, see `#[ipc(repr)]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Read, Write)]
+pub enum Repr {
+	U8,
+	U16,
+	U32,
+	U64,
+}
+
+impl Schema {
+	/// Find a method by its name
+	#[must_use]
+	pub fn method(&self, name: &str) -> Option<&Method> {
+		self.methods.iter().find(|method| method.name == name)
+	}
+
+	/// Find a signal by its name
+	#[must_use]
+	pub fn signal(&self, name: &str) -> Option<&Signal> {
+		self.signals.iter().find(|signal| signal.name == name)
+	}
+
+	/// Name of a type, as written in Rust
+	#[must_use]
+	pub fn type_name(&self, ty: u64) -> String {
+		let Some(ty) = self.ty(ty) else {
+			return "<unknown>".to_owned();
+		};
+		match ty {
+			Type::Unit => "()".to_owned(),
+			Type::Never => "!".to_owned(),
+			Type::Bool => "bool".to_owned(),
+			Type::U8 => "u8".to_owned(),
+			Type::U16 => "u16".to_owned(),
+			Type::U32 => "u32".to_owned(),
+			Type::U64 => "u64".to_owned(),
+			Type::U128 => "u128".to_owned(),
+			Type::I8 => "i8".to_owned(),
+			Type::I16 => "i16".to_owned(),
+			Type::I32 => "i32".to_owned(),
+			Type::I64 => "i64".to_owned(),
+			Type::I128 => "i128".to_owned(),
+			Type::F32 => "f32".to_owned(),
+			Type::F64 => "f64".to_owned(),
+			Type::Char => "char".to_owned(),
+			Type::String => "String".to_owned(),
+			Type::Sequence(item) => format!("[{}]", self.type_name(*item)),
+			Type::Tuple(items) => {
+				let items: Vec<_> = items.iter().map(|item| self.type_name(*item)).collect();
+				if items.len() == 1 {
+					format!("({},)", items[0])
+				} else {
+					format!("({})", items.join(", "))
+				}
+			}
+			Type::Struct { name, .. } | Type::Enum { name, .. } | Type::Opaque(name) => {
+				name.clone()
+			}
+		}
+	}
+
+	/// Get a type by its index
+	#[must_use]
+	pub fn ty(&self, ty: u64) -> Option<&Type> {
+		self.types.get(usize::try_from(ty).ok()?)
+	}
+}
+
+/// Types that can describe their encoding in a [`Schema`]
+///
+/// This is implemented by the [`Read`](macro@crate::Read) derive.
+pub trait Describe {
+	/// Add the description of this type, and of the types it contains, to `types`
+	///
+	/// Returns the index of the type.
+	fn describe(types: &mut Types) -> u64;
+}
+
+/// Types described while building a [`Schema`]
+#[derive(Debug, Default)]
+pub struct Types {
+	types: Vec<Type>,
+	/// Index of the types already described, by their full name
+	indices: HashMap<&'static str, u64>,
+}
+
+impl Types {
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Describe `T` with `describe`, unless it was already described, and return its index
+	///
+	/// `T` is described as [`Type::Opaque`] while `describe` runs, so that a recursive
+	/// type refers to itself.
+	pub fn define<T: ?Sized>(&mut self, describe: impl FnOnce(&mut Self) -> Type) -> u64 {
+		let full_name = any::type_name::<T>();
+		if let Some(index) = self.indices.get(full_name) {
+			return *index;
+		}
+
+		let index = self.types.len() as u64;
+		self.indices.insert(full_name, index);
+		self.types.push(Type::Opaque(type_name::<T>()));
+		let ty = describe(self);
+		self.types[index as usize] = ty;
+		index
+	}
+
+	/// Add a type whose encoding is unknown
+	pub fn opaque<T: ?Sized>(&mut self) -> u64 {
+		self.types.push(Type::Opaque(type_name::<T>()));
+		self.types.len() as u64 - 1
+	}
+
+	#[must_use]
+	pub fn into_vec(self) -> Vec<Type> {
+		self.types
+	}
+}
+
+/// Name of a type, without the paths of the modules
+///
+/// For example, `Result<(), StartError>` instead of
+/// `core::result::Result<(), tryfol_ipc::daemon_control::StartError>`.
+#[must_use]
+pub fn type_name<T: ?Sized>() -> String {
+	let full_name = any::type_name::<T>();
+	let mut name = String::with_capacity(full_name.len());
+	let mut segment_start = 0;
+	let mut chars = full_name.chars().peekable();
+	while let Some(c) = chars.next() {
+		if c == ':' && chars.next_if_eq(&':').is_some() {
+			// drop the module, only keep what follows the last `::`
+			name.truncate(segment_start);
+		} else {
+			name.push(c);
+			if !(c.is_alphanumeric() || c == '_') {
+				segment_start = name.len();
+			}
+		}
+	}
+	name
+}
+
+/// Describe `T` if it implements [`Describe`], or as an opaque type otherwise
+///
+/// Calling `(&Probe::<T>::new()).describe(types)` with [`DescribeProbe`] and
+/// [`OpaqueProbe`] in scope picks the implementation of `DescribeProbe` if it
+/// applies, because it doesn't need an extra reference.
+#[doc(hidden)]
+pub struct Probe<T: ?Sized>(PhantomData<fn() -> PhantomData<T>>);
+
+impl<T: ?Sized> Probe<T> {
+	#[must_use]
+	pub const fn new() -> Self {
+		Self(PhantomData)
+	}
+}
+
+impl<T: ?Sized> Default for Probe<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[doc(hidden)]
+pub trait DescribeProbe {
+	fn describe(&self, types: &mut Types) -> u64;
+}
+
+impl<T: Describe + ?Sized> DescribeProbe for Probe<T> {
+	fn describe(&self, types: &mut Types) -> u64 {
+		T::describe(types)
+	}
+}
+
+#[doc(hidden)]
+pub trait OpaqueProbe {
+	fn describe(&self, types: &mut Types) -> u64;
+}
+
+impl<T: ?Sized> OpaqueProbe for &Probe<T> {
+	fn describe(&self, types: &mut Types) -> u64 {
+		types.opaque::<T>()
+	}
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(Read, Write)]
+	#[ipc(extensible)]
+	struct Node {
+		value: Option<u32>,
+		#[ipc(skip)]
+		_cache: u8,
+		#[ipc(default)]
+		children: Vec<Option<u32>>,
+	}
+
+	#[derive(Read, Write)]
+	#[ipc(repr = u8)]
+	enum Shape {
+		Circle(f64),
+		#[ipc(discriminant = 4)]
+		Rectangle {
+			width: f64,
+			height: f64,
+		},
+		Empty,
+	}
+
+	struct Manual;
+
+	#[test]
+	fn test_type_name() {
+		assert_eq!(type_name::<u8>(), "u8");
+		assert_eq!(
+			type_name::<Result<Vec<String>, std::io::Error>>(),
+			"Result<Vec<String>, Error>"
+		);
+		assert_eq!(type_name::<(Node, &str)>(), "(Node, &str)");
+	}
+
+	#[test]
+	fn test_describe_struct() {
+		let mut types = Types::new();
+		let node = Node::describe(&mut types);
+		let types = types.into_vec();
+
+		let Type::Struct {
+			name,
+			fields: Fields::Named(fields),
+			extensible: true,
+		} = &types[node as usize]
+		else {
+			panic!("invalid description {:?}", types[node as usize]);
+		};
+		assert_eq!(name, "Node");
+		assert_eq!(fields.len(), 2);
+		assert_eq!(fields[0].name, "value");
+		assert!(!fields[0].default);
+		assert!(
+			matches!(&types[fields[0].ty as usize], Type::Enum { name, .. } if name == "Option<u32>")
+		);
+		assert!(fields[1].default);
+		// the same type is only described once
+		assert_eq!(types[fields[1].ty as usize], Type::Sequence(fields[0].ty));
+	}
+
+	#[test]
+	fn test_describe_enum() {
+		let mut types = Types::new();
+		let shape = Shape::describe(&mut types);
+		let types = types.into_vec();
+
+		let Type::Enum {
+			repr: Repr::U8,
+			variants,
+			..
+		} = &types[shape as usize]
+		else {
+			panic!("invalid description {:?}", types[shape as usize]);
+		};
+		let discriminants: Vec<_> = variants.iter().map(|x| x.discriminant).collect();
+		assert_eq!(discriminants, [0, 4, 5]);
+		assert_eq!(variants[1].fields.as_slice()[1].name, "height");
+		assert_eq!(variants[2].fields, Fields::Unit);
+	}
+
+	#[test]
+	#[allow(
+		clippy::needless_borrow,
+		reason = "the borrow selects the implementation"
+	)]
+	fn test_probe() {
+		let mut types = Types::new();
+		let described = (&Probe::<Vec<u8>>::new()).describe(&mut types);
+		let opaque = (&Probe::<Manual>::new()).describe(&mut types);
+		let types = types.into_vec();
+
+		assert!(matches!(types[described as usize], Type::Sequence(_)));
+		assert_eq!(types[opaque as usize], Type::Opaque("Manual".to_owned()));
+	}
+}
@@ -0,0 +1,180 @@
+//! Describe implementations for primitives and standard library types
+//!
+//! Types without a derived implementation are described with the same encoding as
+//! their [`Read`](crate::Read) implementation.
+
+use std::{
+	borrow::Cow,
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	ffi::{OsStr, OsString},
+	net::{Ipv4Addr, Ipv6Addr},
+	num::NonZero,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, SystemTime},
+};
+
+use super::{Describe, Field, Fields, Type, Types};
+
+macro_rules! simple_describe_impl {
+	($($type:ty => $variant:ident),* $(,)?) => {
+		$(
+			impl Describe for $type {
+				fn describe(types: &mut Types) -> u64 {
+					types.define::<Self>(|_| Type::$variant)
+				}
+			}
+		)*
+	};
+}
+
+simple_describe_impl!(
+	() => Unit,
+	! => Never,
+	bool => Bool,
+	u8 => U8,
+	u16 => U16,
+	u32 => U32,
+	u64 => U64,
+	u128 => U128,
+	usize => U64,
+	i8 => I8,
+	i16 => I16,
+	i32 => I32,
+	i64 => I64,
+	i128 => I128,
+	isize => I64,
+	f32 => F32,
+	f64 => F64,
+	char => Char,
+	str => String,
+	String => String,
+	OsStr => String,
+	OsString => String,
+	Path => String,
+	PathBuf => String,
+	Ipv4Addr => U32,
+	Ipv6Addr => U128,
+);
+
+macro_rules! tuple_describe_impl {
+	($t:ident $($ts:ident)*) => {
+		impl<$t: Describe, $($ts: Describe),*> Describe for ($t, $($ts,)*) {
+			fn describe(types: &mut Types) -> u64 {
+				types.define::<Self>(|types| Type::Tuple(vec![$t::describe(types), $($ts::describe(types)),*]))
+			}
+		}
+
+		tuple_describe_impl!($($ts)*);
+	};
+	() => {}
+}
+
+tuple_describe_impl!(A B C D E F G H I J K L M N O P);
+
+/// Types encoded like the type they contain
+macro_rules! transparent_describe_impl {
+	($($type:ty),*) => {
+		$(
+			impl<T: Describe + ?Sized> Describe for $type {
+				fn describe(types: &mut Types) -> u64 {
+					T::describe(types)
+				}
+			}
+		)*
+	};
+}
+
+transparent_describe_impl!(&T, Box<T>, Arc<T>);
+
+impl<T> Describe for Cow<'_, T>
+where
+	T: ToOwned + ?Sized,
+	T::Owned: Describe,
+{
+	fn describe(types: &mut Types) -> u64 {
+		T::Owned::describe(types)
+	}
+}
+
+macro_rules! sequence_describe_impl {
+	($($type:ty),*) => {
+		$(
+			impl<T: Describe> Describe for $type {
+				fn describe(types: &mut Types) -> u64 {
+					types.define::<Self>(|types| Type::Sequence(T::describe(types)))
+				}
+			}
+		)*
+	};
+}
+
+sequence_describe_impl!([T], Vec<T>, BTreeSet<T>);
+
+impl<T: Describe, const N: usize> Describe for [T; N] {
+	fn describe(types: &mut Types) -> u64 {
+		types.define::<Self>(|types| Type::Sequence(T::describe(types)))
+	}
+}
+
+impl<T: Describe, S> Describe for HashSet<T, S> {
+	fn describe(types: &mut Types) -> u64 {
+		types.define::<Self>(|types| Type::Sequence(T::describe(types)))
+	}
+}
+
+/// Encoded as a sequence of key and value pairs
+impl<K: Describe, V: Describe, S> Describe for HashMap<K, V, S> {
+	fn describe(types: &mut Types) -> u64 {
+		types.define::<Self>(|types| Type::Sequence(<(K, V)>::describe(types)))
+	}
+}
+
+/// Encoded as a sequence of key and value pairs
+impl<K: Describe, V: Describe> Describe for BTreeMap<K, V> {
+	fn describe(types: &mut Types) -> u64 {
+		types.define::<Self>(|types| Type::Sequence(<(K, V)>::describe(types)))
+	}
+}
+
+impl Describe for Duration {
+	fn describe(types: &mut Types) -> u64 {
+		types.define::<Self>(|types| Type::Struct {
+			name: "Duration".to_owned(),
+			fields: Fields::Named(vec![
+				Field {
+					name: "secs".to_owned(),
+					ty: u64::describe(types),
+					default: false,
+				},
+				Field {
+					name: "nanos".to_owned(),
+					ty: u32::describe(types),
+					default: false,
+				},
+			]),
+			extensible: false,
+		})
+	}
+}
+
+/// Encoded as the duration since the unix epoch, which is an error if the time is before it
+impl Describe for SystemTime {
+	fn describe(types: &mut Types) -> u64 {
+		Result::<Duration, Duration>::describe(types)
+	}
+}
+
+macro_rules! non_zero_describe_impl {
+	($($type:ty)*) => {
+		$(
+			impl Describe for NonZero<$type> {
+				fn describe(types: &mut Types) -> u64 {
+					<$type>::describe(types)
+				}
+			}
+		)*
+	};
+}
+
+non_zero_describe_impl!(u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize);
@@ -0,0 +1,819 @@
+//! Values written as text, encoded and decoded with the description of their type
+
+use std::{fmt::Write as _, mem};
+
+use thiserror::Error;
+
+use super::{Field, Fields, Repr, Schema, Type, Variant};
+use crate::{LimitExceededError, Limits};
+
+/// Error while encoding a value written as text, or while decoding a value as text
+#[derive(Debug, Error)]
+pub enum ValueError {
+	#[error("{message} {position}")]
+	Syntax { message: String, position: String },
+	#[error("The encoding of {0} is unknown")]
+	Opaque(String),
+	#[error("The schema has no type {0}")]
+	UnknownType(u64),
+	#[error("Unexpected end of the encoded value")]
+	UnexpectedEnd,
+	#[error("Invalid encoded value: {0}")]
+	Invalid(String),
+	#[error(transparent)]
+	LimitExceeded(#[from] LimitExceededError),
+}
+
+impl Schema {
+	/// Encode a value of type `ty`, written as text
+	///
+	/// The syntax is close to Rust's:
+	/// - numbers, `true`, `false` and chars like `'a'`
+	/// - strings, quoted with escapes like `"a\n"`, or a bare word like `abc`, which is
+	///   everything up to a space or a delimiter (the whole text for a string value)
+	/// - `[1, 2]` for sequences and maps (as pairs), `(1, "a")` for tuples
+	/// - `{ a: 1, b: 2 }` or `(1, 2)` for the fields of structs, optionally preceded by
+	///   the name of the struct
+	/// - `Variant`, `Variant(1)` or `Variant { a: 1 }` for enums, and for an [`Option`],
+	///   the value itself as `Some`
+	///
+	/// Default fields of extensible types can be left out.
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the text isn't a valid value of the type, or
+	/// if the type contains an opaque type.
+	pub fn encode(&self, ty: u64, text: &str) -> Result<Vec<u8>, ValueError> {
+		let mut encoder = Encoder {
+			schema: self,
+			text,
+			output: Vec::new(),
+		};
+		if matches!(self.ty(ty), Some(Type::String)) && !text.starts_with('"') {
+			encoder.string_bytes(text);
+			return Ok(encoder.output);
+		}
+
+		encoder.value(ty)?;
+		encoder.skip_whitespace();
+		if !encoder.text.is_empty() {
+			return Err(encoder.error("unexpected text after the value"));
+		}
+		Ok(encoder.output)
+	}
+
+	/// Decode a value of type `ty`, and write it as text like [`Debug`]
+	///
+	/// # Errors
+	///
+	/// This function will return an error if the value is invalid, exceeds `limits`, isn't
+	/// entirely read, or if the type contains an opaque type.
+	pub fn decode(&self, ty: u64, bytes: &[u8], limits: Limits) -> Result<String, ValueError> {
+		self.decode_all(&[ty], bytes, limits)
+			.map(|mut values| values.remove(0))
+	}
+
+	/// Decode values sent one after the other, like the arguments of a signal
+	///
+	/// # Errors
+	///
+	/// See [`Self::decode`].
+	pub fn decode_all(
+		&self,
+		types: &[u64],
+		bytes: &[u8],
+		limits: Limits,
+	) -> Result<Vec<String>, ValueError> {
+		let (values, rest) = self.decode_prefix(types, bytes, limits)?;
+		if !rest.is_empty() {
+			return Err(ValueError::Invalid(format!(
+				"{} bytes left after the value",
+				rest.len()
+			)));
+		}
+		Ok(values)
+	}
+
+	/// Decode values at the start of `bytes`, and return the bytes after them
+	///
+	/// # Errors
+	///
+	/// See [`Self::decode`], except that bytes can be left.
+	pub fn decode_prefix<'b>(
+		&self,
+		types: &[u64],
+		bytes: &'b [u8],
+		limits: Limits,
+	) -> Result<(Vec<String>, &'b [u8]), ValueError> {
+		let mut decoder = Decoder {
+			schema: self,
+			bytes,
+			output: String::new(),
+		};
+		let values = types
+			.iter()
+			.map(|ty| {
+				decoder.value(*ty, limits)?;
+				Ok(mem::take(&mut decoder.output))
+			})
+			.collect::<Result<_, ValueError>>()?;
+		Ok((values, decoder.bytes))
+	}
+
+	fn get(&self, ty: u64) -> Result<&Type, ValueError> {
+		self.ty(ty).ok_or(ValueError::UnknownType(ty))
+	}
+}
+
+/// Name of a struct or enum without its generics, as written in values
+fn base_name(name: &str) -> &str {
+	name.split_once('<').map_or(name, |(base, _)| base)
+}
+
+fn is_delimiter(c: char) -> bool {
+	c.is_whitespace() || "()[]{},:\"'".contains(c)
+}
+
+struct Encoder<'a> {
+	schema: &'a Schema,
+	/// Text that remains to be parsed
+	text: &'a str,
+	output: Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+	fn value(&mut self, ty: u64) -> Result<(), ValueError> {
+		macro_rules! number {
+			($type:ty) => {{
+				let word = self.word();
+				let value: $type = word
+					.parse()
+					.map_err(|_| self.error(format!("invalid {} `{word}`", stringify!($type))))?;
+				self.output.extend(value.to_be_bytes());
+			}};
+		}
+
+		self.skip_whitespace();
+		match self.schema.get(ty)? {
+			Type::Unit => {
+				self.expect('(')?;
+				self.expect(')')?;
+			}
+			Type::Never => return Err(self.error("no value has type `!`")),
+			Type::Bool => match self.word() {
+				"true" => self.output.push(1),
+				"false" => self.output.push(0),
+				word => return Err(self.error(format!("invalid bool `{word}`"))),
+			},
+			Type::U8 => number!(u8),
+			Type::U16 => number!(u16),
+			Type::U32 => number!(u32),
+			Type::U64 => number!(u64),
+			Type::U128 => number!(u128),
+			Type::I8 => number!(i8),
+			Type::I16 => number!(i16),
+			Type::I32 => number!(i32),
+			Type::I64 => number!(i64),
+			Type::I128 => number!(i128),
+			Type::F32 => number!(f32),
+			Type::F64 => number!(f64),
+			Type::Char => {
+				let c = if self.eat('\'') {
+					let c = self.quoted_char('\'')?;
+					self.expect('\'')?;
+					c
+				} else {
+					let word = self.word();
+					let mut chars = word.chars();
+					match (chars.next(), chars.next()) {
+						(Some(c), None) => c,
+						_ => return Err(self.error(format!("invalid char `{word}`"))),
+					}
+				};
+				self.output.extend(u32::from(c).to_be_bytes());
+			}
+			Type::String => {
+				let string = self.string()?;
+				self.string_bytes(&string);
+			}
+			Type::Sequence(item) => {
+				self.expect('[')?;
+				let start = self.output.len();
+				self.output.extend(0u64.to_be_bytes());
+				let mut length = 0u64;
+				loop {
+					self.skip_whitespace();
+					if self.eat(']') {
+						break;
+					}
+					self.value(*item)?;
+					length += 1;
+					self.skip_whitespace();
+					if !self.eat(',') {
+						self.expect(']')?;
+						break;
+					}
+				}
+				self.output[start..(start + 8)].copy_from_slice(&length.to_be_bytes());
+			}
+			Type::Tuple(items) => {
+				self.expect('(')?;
+				for (i, item) in items.iter().enumerate() {
+					if i > 0 {
+						self.expect(',')?;
+					}
+					self.value(*item)?;
+				}
+				self.skip_whitespace();
+				self.eat(',');
+				self.expect(')')?;
+			}
+			Type::Struct {
+				name,
+				fields,
+				extensible,
+			} => {
+				if self.text.starts_with(char::is_alphabetic) {
+					let word = self.word();
+					if word != base_name(name) {
+						return Err(self.error(format!("expected a value of {name}, got `{word}`")));
+					}
+				}
+				self.fields(fields, *extensible)?;
+			}
+			Type::Enum {
+				name,
+				repr,
+				variants,
+				extensible,
+			} => {
+				let word = self.text.split(is_delimiter).next().unwrap_or_default();
+				// an option can be written as the value it contains
+				if base_name(name) == "Option"
+					&& !variants.iter().any(|variant| variant.name == word)
+					&& let Some(some) = variants.iter().find(|variant| variant.name == "Some")
+					&& let [field] = some.fields.as_slice()
+				{
+					self.discriminant(*repr, some);
+					return self.value(field.ty);
+				}
+
+				let word = self.word();
+				let variant = variants
+					.iter()
+					.find(|variant| variant.name == word)
+					.ok_or_else(|| {
+						let names: Vec<_> = variants.iter().map(|x| x.name.as_str()).collect();
+						self.error(format!(
+							"unknown variant `{word}` of {name}, expected one of {}",
+							names.join(", ")
+						))
+					})?;
+				self.discriminant(*repr, variant);
+				self.fields(&variant.fields, *extensible)?;
+			}
+			Type::Opaque(name) => return Err(ValueError::Opaque(name.clone())),
+		}
+		Ok(())
+	}
+
+	/// Encode the fields of a struct or of a variant
+	fn fields(&mut self, fields: &Fields, extensible: bool) -> Result<(), ValueError> {
+		let start = self.output.len();
+		if extensible {
+			self.output.extend(0u64.to_be_bytes());
+		}
+
+		match fields {
+			Fields::Unit => {}
+			Fields::Unnamed(fields) => {
+				self.expect('(')?;
+				let mut given = 0;
+				while given < fields.len() {
+					self.skip_whitespace();
+					if self.text.starts_with(')') {
+						break;
+					}
+					if given > 0 {
+						self.expect(',')?;
+						self.skip_whitespace();
+						if self.text.starts_with(')') {
+							break;
+						}
+					}
+					self.value(fields[given].ty)?;
+					given += 1;
+				}
+				self.skip_whitespace();
+				self.eat(',');
+				self.expect(')')?;
+				self.check_missing(&fields[given..], extensible)?;
+			}
+			Fields::Named(fields) => {
+				self.expect('{')?;
+				let mut values: Vec<Option<Vec<u8>>> = vec![None; fields.len()];
+				loop {
+					self.skip_whitespace();
+					if self.eat('}') {
+						break;
+					}
+					let name = self.word();
+					let index = fields
+						.iter()
+						.position(|field| field.name == name)
+						.ok_or_else(|| self.error(format!("unknown field `{name}`")))?;
+					if values[index].is_some() {
+						return Err(self.error(format!("field `{name}` is given twice")));
+					}
+					self.expect(':')?;
+					let value_start = self.output.len();
+					self.value(fields[index].ty)?;
+					values[index] = Some(self.output.split_off(value_start));
+					self.skip_whitespace();
+					if !self.eat(',') {
+						self.expect('}')?;
+						break;
+					}
+				}
+
+				let given = values.iter().take_while(|value| value.is_some()).count();
+				if let Some(index) = values[given..].iter().position(Option::is_some) {
+					// a field can't be left out if a field after it is sent
+					let missing = &fields[given];
+					return Err(self.error(format!(
+						"missing field `{}`, needed to send `{}`",
+						missing.name,
+						fields[given + index].name
+					)));
+				}
+				self.check_missing(&fields[given..], extensible)?;
+				self.output.extend(values.into_iter().flatten().flatten());
+			}
+		}
+
+		if extensible {
+			let length = (self.output.len() - start - 8) as u64;
+			self.output[start..(start + 8)].copy_from_slice(&length.to_be_bytes());
+		}
+		Ok(())
+	}
+
+	/// Fail if a field that wasn't given can't be left out
+	fn check_missing(&self, missing: &[Field], extensible: bool) -> Result<(), ValueError> {
+		match missing.iter().find(|field| !(extensible && field.default)) {
+			Some(field) => Err(self.error(format!("missing field `{}`", field.name))),
+			None => Ok(()),
+		}
+	}
+
+	fn discriminant(&mut self, repr: Repr, variant: &Variant) {
+		// the discriminants come from the derive, which checked that they fit
+		#[allow(clippy::cast_possible_truncation)]
+		match repr {
+			Repr::U8 => self.output.push(variant.discriminant as u8),
+			Repr::U16 => self
+				.output
+				.extend((variant.discriminant as u16).to_be_bytes()),
+			Repr::U32 => self
+				.output
+				.extend((variant.discriminant as u32).to_be_bytes()),
+			Repr::U64 => self.output.extend(variant.discriminant.to_be_bytes()),
+		}
+	}
+
+	fn string_bytes(&mut self, string: &str) {
+		self.output.extend((string.len() as u64).to_be_bytes());
+		self.output.extend(string.as_bytes());
+	}
+
+	/// A quoted string, or a bare word
+	fn string(&mut self) -> Result<String, ValueError> {
+		if !self.eat('"') {
+			let word = self.word();
+			if word.is_empty() {
+				return Err(self.error("expected a string"));
+			}
+			return Ok(word.to_owned());
+		}
+
+		let mut string = String::new();
+		loop {
+			// not `eat`, which would skip whitespace in the string
+			if let Some(rest) = self.text.strip_prefix('"') {
+				self.text = rest;
+				return Ok(string);
+			}
+			string.push(self.quoted_char('"')?);
+		}
+	}
+
+	/// A char of a quoted string or char, which can be an escape sequence
+	fn quoted_char(&mut self, quote: char) -> Result<char, ValueError> {
+		let mut chars = self.text.chars();
+		let c = match chars.next() {
+			None => return Err(self.error(format!("missing closing {quote}"))),
+			Some(c) if c == quote => return Err(self.error("expected a char")),
+			Some('\\') => match chars.next() {
+				Some('n') => '\n',
+				Some('r') => '\r',
+				Some('t') => '\t',
+				Some('0') => '\0',
+				Some('u') => chars
+					.as_str()
+					.strip_prefix('{')
+					.and_then(|rest| rest.split_once('}'))
+					.and_then(|(code, rest)| {
+						chars = rest.chars();
+						u32::from_str_radix(code, 16).ok()
+					})
+					.and_then(char::from_u32)
+					.ok_or_else(|| self.error("invalid unicode escape"))?,
+				Some(c @ ('\\' | '"' | '\'')) => c,
+				_ => return Err(self.error("invalid escape")),
+			},
+			Some(c) => c,
+		};
+		self.text = chars.as_str();
+		Ok(c)
+	}
+
+	/// Everything up to the next whitespace or delimiter
+	fn word(&mut self) -> &'a str {
+		self.skip_whitespace();
+		let end = self.text.find(is_delimiter).unwrap_or(self.text.len());
+		let (word, rest) = self.text.split_at(end);
+		self.text = rest;
+		word
+	}
+
+	fn skip_whitespace(&mut self) {
+		self.text = self.text.trim_start();
+	}
+
+	/// Skip `c` if it's the next char
+	fn eat(&mut self, c: char) -> bool {
+		self.skip_whitespace();
+		match self.text.strip_prefix(c) {
+			Some(rest) => {
+				self.text = rest;
+				true
+			}
+			None => false,
+		}
+	}
+
+	fn expect(&mut self, c: char) -> Result<(), ValueError> {
+		if self.eat(c) {
+			Ok(())
+		} else {
+			Err(self.error(format!("expected `{c}`")))
+		}
+	}
+
+	fn error(&self, message: impl Into<String>) -> ValueError {
+		let position = if self.text.is_empty() {
+			"at the end".to_owned()
+		} else {
+			let rest: String = self.text.chars().take(20).collect();
+			format!("at `{rest}`")
+		};
+		ValueError::Syntax {
+			message: message.into(),
+			position,
+		}
+	}
+}
+
+struct Decoder<'s, 'a> {
+	schema: &'s Schema,
+	/// Bytes that remain to be decoded
+	bytes: &'a [u8],
+	output: String,
+}
+
+impl<'a> Decoder<'_, 'a> {
+	#[allow(clippy::too_many_lines)]
+	fn value(&mut self, ty: u64, limits: Limits) -> Result<(), ValueError> {
+		macro_rules! number {
+			($type:ty) => {{
+				let value = <$type>::from_be_bytes(self.take_array()?);
+				let _ = write!(self.output, "{value:?}");
+			}};
+		}
+
+		let limits = limits.nested()?;
+		match self.schema.get(ty)? {
+			Type::Unit => self.output.push_str("()"),
+			Type::Never => return Err(ValueError::Invalid("no value has type `!`".to_owned())),
+			Type::Bool => match self.take_array::<1>()? {
+				[0] => self.output.push_str("false"),
+				[1] => self.output.push_str("true"),
+				[x] => return Err(ValueError::Invalid(format!("invalid bool {x}"))),
+			},
+			Type::U8 => number!(u8),
+			Type::U16 => number!(u16),
+			Type::U32 => number!(u32),
+			Type::U64 => number!(u64),
+			Type::U128 => number!(u128),
+			Type::I8 => number!(i8),
+			Type::I16 => number!(i16),
+			Type::I32 => number!(i32),
+			Type::I64 => number!(i64),
+			Type::I128 => number!(i128),
+			Type::F32 => number!(f32),
+			Type::F64 => number!(f64),
+			Type::Char => {
+				let code = u32::from_be_bytes(self.take_array()?);
+				let c = char::from_u32(code)
+					.ok_or_else(|| ValueError::Invalid(format!("invalid char {code}")))?;
+				let _ = write!(self.output, "{c:?}");
+			}
+			Type::String => {
+				let length = limits.check_string_bytes(self.u64()?)?;
+				let string = String::from_utf8_lossy(self.take(length)?);
+				let _ = write!(self.output, "{string:?}");
+			}
+			Type::Sequence(item) => {
+				let length = limits.check_length(self.u64()?)?;
+				self.output.push('[');
+				for i in 0..length {
+					if i > 0 {
+						self.output.push_str(", ");
+					}
+					self.value(*item, limits)?;
+				}
+				self.output.push(']');
+			}
+			Type::Tuple(items) => {
+				self.output.push('(');
+				for (i, item) in items.iter().enumerate() {
+					if i > 0 {
+						self.output.push_str(", ");
+					}
+					self.value(*item, limits)?;
+				}
+				if items.len() == 1 {
+					self.output.push(',');
+				}
+				self.output.push(')');
+			}
+			Type::Struct {
+				name,
+				fields,
+				extensible,
+			} => {
+				self.output.push_str(base_name(name));
+				self.fields(fields, *extensible, limits)?;
+			}
+			Type::Enum {
+				name,
+				repr,
+				variants,
+				extensible,
+			} => {
+				let discriminant = match repr {
+					Repr::U8 => u8::from_be_bytes(self.take_array()?).into(),
+					Repr::U16 => u16::from_be_bytes(self.take_array()?).into(),
+					Repr::U32 => u32::from_be_bytes(self.take_array()?).into(),
+					Repr::U64 => self.u64()?,
+				};
+				let variant = variants
+					.iter()
+					.find(|variant| variant.discriminant == discriminant)
+					.ok_or_else(|| {
+						ValueError::Invalid(format!(
+							"invalid discriminant for {name}: {discriminant}"
+						))
+					})?;
+				self.output.push_str(&variant.name);
+				self.fields(&variant.fields, *extensible, limits)?;
+			}
+			Type::Opaque(name) => return Err(ValueError::Opaque(name.clone())),
+		}
+		Ok(())
+	}
+
+	/// Decode the fields of a struct or of a variant
+	///
+	/// Fields that an extensible type doesn't contain are left out.
+	fn fields(
+		&mut self,
+		fields: &Fields,
+		extensible: bool,
+		limits: Limits,
+	) -> Result<(), ValueError> {
+		let outer = if extensible {
+			let length = usize::try_from(self.u64()?).unwrap_or(usize::MAX);
+			let body = self.take(length)?;
+			Some(mem::replace(&mut self.bytes, body))
+		} else {
+			None
+		};
+
+		match fields {
+			Fields::Unit => {}
+			Fields::Unnamed(fields) => {
+				self.output.push('(');
+				for (i, field) in fields.iter().enumerate() {
+					if extensible && self.bytes.is_empty() {
+						break;
+					}
+					if i > 0 {
+						self.output.push_str(", ");
+					}
+					self.value(field.ty, limits)?;
+				}
+				self.output.push(')');
+			}
+			Fields::Named(fields) => {
+				self.output.push_str(" {");
+				for (i, field) in fields.iter().enumerate() {
+					if extensible && self.bytes.is_empty() {
+						break;
+					}
+					self.output.push_str(if i > 0 { ", " } else { " " });
+					self.output.push_str(&field.name);
+					self.output.push_str(": ");
+					self.value(field.ty, limits)?;
+				}
+				self.output.push_str(" }");
+			}
+		}
+
+		// unknown fields, sent by a newer peer, are skipped
+		if let Some(outer) = outer {
+			self.bytes = outer;
+		}
+		Ok(())
+	}
+
+	fn take(&mut self, length: usize) -> Result<&'a [u8], ValueError> {
+		let (taken, rest) = self
+			.bytes
+			.split_at_checked(length)
+			.ok_or(ValueError::UnexpectedEnd)?;
+		self.bytes = rest;
+		Ok(taken)
+	}
+
+	fn take_array<const N: usize>(&mut self) -> Result<[u8; N], ValueError> {
+		let (taken, rest) = self
+			.bytes
+			.split_first_chunk()
+			.ok_or(ValueError::UnexpectedEnd)?;
+		self.bytes = rest;
+		Ok(*taken)
+	}
+
+	fn u64(&mut self) -> Result<u64, ValueError> {
+		Ok(u64::from_be_bytes(self.take_array()?))
+	}
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		Read, Write,
+		schema::{Describe, Types},
+	};
+
+	#[derive(Debug, PartialEq, Read, Write)]
+	#[ipc(extensible)]
+	struct Window {
+		title: String,
+		size: (u32, u32),
+		#[ipc(default)]
+		workspace: Option<u8>,
+		#[ipc(default)]
+		tags: Vec<Tag>,
+	}
+
+	#[derive(Debug, PartialEq, Read, Write)]
+	#[ipc(repr = u8)]
+	enum Tag {
+		Urgent,
+		Named(String),
+		Color { red: u8, green: u8, blue: u8 },
+	}
+
+	fn schema<T: Describe>() -> (Schema, u64) {
+		let mut types = Types::new();
+		let ty = T::describe(&mut types);
+		let schema = Schema {
+			name: "Test".to_owned(),
+			fingerprint: 0,
+			methods: Vec::new(),
+			signals: Vec::new(),
+			types: types.into_vec(),
+		};
+		(schema, ty)
+	}
+
+	async fn encode<T: Write<Error: std::fmt::Debug>>(value: &T) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		value.write(&mut bytes).await.unwrap();
+		bytes
+	}
+
+	#[tokio::test]
+	async fn test_encode() {
+		let (schema, ty) = schema::<Window>();
+		let window = Window {
+			title: "a \"b\"".to_owned(),
+			size: (800, 600),
+			workspace: Some(2),
+			tags: vec![
+				Tag::Urgent,
+				Tag::Named("x".to_owned()),
+				Tag::Color {
+					red: 1,
+					green: 2,
+					blue: 3,
+				},
+			],
+		};
+		let text = r#"Window { size: (800, 600), title: "a \"b\"", workspace: 2,
+			tags: [Urgent, Named(x), Color { red: 1, green: 2, blue: 3 }] }"#;
+		assert_eq!(schema.encode(ty, text).unwrap(), encode(&window).await);
+
+		// default fields can be left out
+		let window = Window {
+			title: "c".to_owned(),
+			size: (1, 2),
+			workspace: None,
+			tags: Vec::new(),
+		};
+		let bytes = schema.encode(ty, "{ title: c, size: (1, 2) }").unwrap();
+		assert_eq!(Window::read(&mut bytes.as_slice()).await.unwrap(), window);
+		assert!(schema.encode(ty, "{ title: c }").is_err());
+		assert!(
+			schema
+				.encode(ty, "{ title: c, size: (1, 2), tags: [] }")
+				.is_err()
+		);
+	}
+
+	#[tokio::test]
+	async fn test_encode_top_level_string() {
+		let (schema, ty) = schema::<String>();
+		let bytes = schema.encode(ty, "some text, with (delimiters)").unwrap();
+		assert_eq!(bytes, encode(&"some text, with (delimiters)").await);
+		assert_eq!(
+			schema.encode(ty, r#""quoted\u{e9}""#).unwrap(),
+			encode(&"quotedé").await
+		);
+	}
+
+	#[tokio::test]
+	async fn test_decode() {
+		let (schema, ty) = schema::<Window>();
+		let window = Window {
+			title: "t".to_owned(),
+			size: (3, 4),
+			workspace: None,
+			tags: vec![
+				Tag::Named("n".to_owned()),
+				Tag::Color {
+					red: 0,
+					green: 1,
+					blue: 2,
+				},
+			],
+		};
+		let bytes = encode(&window).await;
+		let text = schema.decode(ty, &bytes, Limits::default()).unwrap();
+		assert_eq!(
+			text,
+			r#"Window { title: "t", size: (3, 4), workspace: None, tags: [Named("n"), Color { red: 0, green: 1, blue: 2 }] }"#
+		);
+
+		// decoded text can be encoded again
+		assert_eq!(schema.encode(ty, &text).unwrap(), bytes);
+
+		assert!(matches!(
+			schema.decode(ty, &bytes[..10], Limits::default()),
+			Err(ValueError::UnexpectedEnd)
+		));
+	}
+
+	#[tokio::test]
+	async fn test_opaque() {
+		struct Manual;
+		let mut types = Types::new();
+		let ty = types.opaque::<Manual>();
+		let schema = Schema {
+			name: "Test".to_owned(),
+			fingerprint: 0,
+			methods: Vec::new(),
+			signals: Vec::new(),
+			types: types.into_vec(),
+		};
+		assert!(matches!(schema.encode(ty, "x"), Err(ValueError::Opaque(_))));
+		assert!(matches!(
+			schema.decode(ty, &[], Limits::default()),
+			Err(ValueError::Opaque(_))
+		));
+	}
+}
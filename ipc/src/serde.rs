@@ -0,0 +1,932 @@
+//! Sending types implementing [`Serialize`] and [`Deserialize`](::serde::Deserialize) instead of [`Read`] and [`Write`]
+//!
+//! Values are encoded like types deriving [`Read`] and [`Write`] would be:
+//! structs and tuples as their fields, enums as a `u64` variant index followed
+//! by their fields, sequences, maps, strings and bytes prefixed by their `u64`
+//! length. Since serde can't read from an async stream, the whole value is
+//! prefixed by its size in bytes, and decoded once it's received.
+//!
+//! Either wrap the type in [`Serde`], or use `#[ipc(with = ipc::serde)]` on a
+//! field of a type deriving [`Read`] and [`Write`].
+//!
+//! The encoding isn't self-describing: attributes skipping fields depending on
+//! their value, like `#[serde(skip_serializing_if)]`, `#[serde(flatten)]` and
+//! untagged enums aren't supported.
+
+use std::{
+	fmt::Display,
+	ops::{Deref, DerefMut},
+	str,
+};
+
+use ::serde::{
+	Serialize,
+	de::{
+		self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess,
+		SeqAccess, VariantAccess, Visitor,
+	},
+	ser::{
+		self, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+		SerializeTupleStruct, SerializeTupleVariant,
+	},
+};
+use anyhow::Context;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{LimitExceededError, Limits, Read, Write};
+
+/// A value sent with its [`Serialize`] and [`Deserialize`](::serde::Deserialize) implementations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Serde<T>(pub T);
+
+impl<T> Serde<T> {
+	pub fn into_inner(self) -> T {
+		self.0
+	}
+}
+
+impl<T> From<T> for Serde<T> {
+	fn from(value: T) -> Self {
+		Self(value)
+	}
+}
+
+impl<T> Deref for Serde<T> {
+	type Target = T;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl<T> DerefMut for Serde<T> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.0
+	}
+}
+
+impl<T: DeserializeOwned + Send> Read for Serde<T> {
+	type Error = anyhow::Error;
+
+	async fn read_limited(
+		stream: &mut (impl AsyncRead + Unpin + Send),
+		limits: Limits,
+	) -> Result<Self, Self::Error>
+	where
+		Self: Sized,
+	{
+		read(stream, limits).await.map(Self)
+	}
+}
+
+impl<T: Serialize + Sync> Write for Serde<T> {
+	type Error = anyhow::Error;
+
+	async fn write(
+		&self,
+		stream: &mut (impl AsyncWrite + Unpin + Send),
+	) -> Result<(), Self::Error> {
+		write(&self.0, stream).await
+	}
+}
+
+/// Read a value with its [`Deserialize`](::serde::Deserialize) implementation
+///
+/// # Errors
+///
+/// This function will return an error if the value can't be read, is invalid
+/// or exceeds `limits`.
+pub async fn read<T: DeserializeOwned>(
+	stream: &mut (impl AsyncRead + Unpin + Send),
+	limits: Limits,
+) -> anyhow::Result<T> {
+	let length = limits.check_frame_bytes(stream.read_u64().await?)?;
+	let mut bytes = vec![0; length];
+	stream.read_exact(&mut bytes).await?;
+	from_bytes(&bytes, limits).map_err(|error| match error {
+		// keep the error downcastable, like for other types
+		SerdeError::LimitExceeded(error) => anyhow::Error::from(error),
+		error => anyhow::Error::from(error),
+	})
+}
+
+/// Write a value with its [`Serialize`] implementation
+///
+/// # Errors
+///
+/// This function will return an error if the value can't be serialized or written.
+pub async fn write<T: Serialize + ?Sized>(
+	value: &T,
+	stream: &mut (impl AsyncWrite + Unpin + Send),
+) -> anyhow::Result<()> {
+	let bytes = to_bytes(value)?;
+	stream.write_u64(bytes.len() as u64).await?;
+	stream
+		.write_all(&bytes)
+		.await
+		.context("while writing a serialized value")
+}
+
+/// A value couldn't be serialized or deserialized
+#[derive(Debug, Error)]
+pub enum SerdeError {
+	#[error("{0}")]
+	Custom(String),
+	#[error(transparent)]
+	LimitExceeded(#[from] LimitExceededError),
+	#[error("Unexpected end of the serialized value")]
+	UnexpectedEnd,
+	#[error("{0} bytes are left after the serialized value")]
+	TrailingBytes(usize),
+	#[error("Invalid {type_name} value: {value}")]
+	InvalidValue { type_name: &'static str, value: u64 },
+	#[error("Cannot deserialize {0}, the encoding isn't self-describing")]
+	NotSelfDescribing(&'static str),
+}
+
+impl ser::Error for SerdeError {
+	fn custom<T: Display>(message: T) -> Self {
+		Self::Custom(message.to_string())
+	}
+}
+
+impl de::Error for SerdeError {
+	fn custom<T: Display>(message: T) -> Self {
+		Self::Custom(message.to_string())
+	}
+}
+
+fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, SerdeError> {
+	let mut serializer = Serializer { output: Vec::new() };
+	value.serialize(&mut serializer)?;
+	Ok(serializer.output)
+}
+
+fn from_bytes<T: DeserializeOwned>(bytes: &[u8], limits: Limits) -> Result<T, SerdeError> {
+	let mut deserializer = Deserializer {
+		input: bytes,
+		limits,
+	};
+	let value = T::deserialize(&mut deserializer)?;
+	if !deserializer.input.is_empty() {
+		return Err(SerdeError::TrailingBytes(deserializer.input.len()));
+	}
+	Ok(value)
+}
+
+struct Serializer {
+	output: Vec<u8>,
+}
+
+impl Serializer {
+	fn write_u64(&mut self, value: u64) {
+		self.output.extend_from_slice(&value.to_be_bytes());
+	}
+
+	fn write_length(&mut self, length: usize) {
+		self.write_u64(length as u64);
+	}
+
+	/// Start a sequence or map, whose length is written once it ends
+	fn compound_with_length(&mut self) -> Compound<'_> {
+		let position = self.output.len();
+		self.write_u64(0);
+		Compound {
+			serializer: self,
+			length: Some((position, 0)),
+		}
+	}
+
+	fn compound(&mut self) -> Compound<'_> {
+		Compound {
+			serializer: self,
+			length: None,
+		}
+	}
+}
+
+macro_rules! serialize_number {
+	($($method:ident: $type:ty,)*) => {
+		$(
+			fn $method(self, value: $type) -> Result<(), SerdeError> {
+				self.output.extend_from_slice(&value.to_be_bytes());
+				Ok(())
+			}
+		)*
+	};
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+	type Ok = ();
+	type Error = SerdeError;
+
+	type SerializeSeq = Compound<'a>;
+	type SerializeTuple = Compound<'a>;
+	type SerializeTupleStruct = Compound<'a>;
+	type SerializeTupleVariant = Compound<'a>;
+	type SerializeMap = Compound<'a>;
+	type SerializeStruct = Compound<'a>;
+	type SerializeStructVariant = Compound<'a>;
+
+	serialize_number! {
+		serialize_u8: u8,
+		serialize_u16: u16,
+		serialize_u32: u32,
+		serialize_u64: u64,
+		serialize_u128: u128,
+		serialize_i8: i8,
+		serialize_i16: i16,
+		serialize_i32: i32,
+		serialize_i64: i64,
+		serialize_i128: i128,
+		serialize_f32: f32,
+		serialize_f64: f64,
+	}
+
+	fn serialize_bool(self, value: bool) -> Result<(), SerdeError> {
+		self.output.push(value.into());
+		Ok(())
+	}
+
+	fn serialize_char(self, value: char) -> Result<(), SerdeError> {
+		self.serialize_u32(value.into())
+	}
+
+	fn serialize_str(self, value: &str) -> Result<(), SerdeError> {
+		self.serialize_bytes(value.as_bytes())
+	}
+
+	fn serialize_bytes(self, value: &[u8]) -> Result<(), SerdeError> {
+		self.write_length(value.len());
+		self.output.extend_from_slice(value);
+		Ok(())
+	}
+
+	/// Like [`Option`] deriving [`Write`], `Some` being the first variant
+	fn serialize_none(self) -> Result<(), SerdeError> {
+		self.write_u64(1);
+		Ok(())
+	}
+
+	fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), SerdeError> {
+		self.write_u64(0);
+		value.serialize(self)
+	}
+
+	fn serialize_unit(self) -> Result<(), SerdeError> {
+		Ok(())
+	}
+
+	fn serialize_unit_struct(self, _name: &'static str) -> Result<(), SerdeError> {
+		Ok(())
+	}
+
+	fn serialize_unit_variant(
+		self,
+		_name: &'static str,
+		variant_index: u32,
+		_variant: &'static str,
+	) -> Result<(), SerdeError> {
+		self.write_u64(variant_index.into());
+		Ok(())
+	}
+
+	fn serialize_newtype_struct<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		value: &T,
+	) -> Result<(), SerdeError> {
+		value.serialize(self)
+	}
+
+	fn serialize_newtype_variant<T: Serialize + ?Sized>(
+		self,
+		_name: &'static str,
+		variant_index: u32,
+		_variant: &'static str,
+		value: &T,
+	) -> Result<(), SerdeError> {
+		self.write_u64(variant_index.into());
+		value.serialize(self)
+	}
+
+	fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>, SerdeError> {
+		Ok(self.compound_with_length())
+	}
+
+	fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>, SerdeError> {
+		Ok(self.compound())
+	}
+
+	fn serialize_tuple_struct(
+		self,
+		_name: &'static str,
+		_len: usize,
+	) -> Result<Compound<'a>, SerdeError> {
+		Ok(self.compound())
+	}
+
+	fn serialize_tuple_variant(
+		self,
+		_name: &'static str,
+		variant_index: u32,
+		_variant: &'static str,
+		_len: usize,
+	) -> Result<Compound<'a>, SerdeError> {
+		self.write_u64(variant_index.into());
+		Ok(self.compound())
+	}
+
+	fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>, SerdeError> {
+		Ok(self.compound_with_length())
+	}
+
+	fn serialize_struct(
+		self,
+		_name: &'static str,
+		_len: usize,
+	) -> Result<Compound<'a>, SerdeError> {
+		Ok(self.compound())
+	}
+
+	fn serialize_struct_variant(
+		self,
+		_name: &'static str,
+		variant_index: u32,
+		_variant: &'static str,
+		_len: usize,
+	) -> Result<Compound<'a>, SerdeError> {
+		self.write_u64(variant_index.into());
+		Ok(self.compound())
+	}
+
+	fn is_human_readable(&self) -> bool {
+		false
+	}
+}
+
+/// Elements of a value being serialized
+struct Compound<'a> {
+	serializer: &'a mut Serializer,
+	/// Position of the length of a sequence or map, and its number of elements
+	length: Option<(usize, u64)>,
+}
+
+impl Compound<'_> {
+	fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		if let Some((_, count)) = &mut self.length {
+			*count += 1;
+		}
+		value.serialize(&mut *self.serializer)
+	}
+
+	fn field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		value.serialize(&mut *self.serializer)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		if let Some((position, count)) = self.length {
+			self.serializer.output[position..position + 8].copy_from_slice(&count.to_be_bytes());
+		}
+		Ok(())
+	}
+}
+
+impl SerializeSeq for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		self.element(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+impl SerializeTuple for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		self.field(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+impl SerializeTupleStruct for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		self.field(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+impl SerializeTupleVariant for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		self.field(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+/// Maps are encoded like a sequence of key-value pairs
+impl SerializeMap for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+		self.element(key)
+	}
+
+	fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+		self.field(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+impl SerializeStruct for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		_key: &'static str,
+		value: &T,
+	) -> Result<(), SerdeError> {
+		self.field(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+impl SerializeStructVariant for Compound<'_> {
+	type Ok = ();
+	type Error = SerdeError;
+
+	fn serialize_field<T: Serialize + ?Sized>(
+		&mut self,
+		_key: &'static str,
+		value: &T,
+	) -> Result<(), SerdeError> {
+		self.field(value)
+	}
+
+	fn end(self) -> Result<(), SerdeError> {
+		Compound::end(self)
+	}
+}
+
+struct Deserializer<'de> {
+	input: &'de [u8],
+	/// Limits of the value being deserialized, nested like [`Read::read_limited`] does
+	limits: Limits,
+}
+
+impl<'de> Deserializer<'de> {
+	fn take(&mut self, length: usize) -> Result<&'de [u8], SerdeError> {
+		let Some((bytes, rest)) = self.input.split_at_checked(length) else {
+			return Err(SerdeError::UnexpectedEnd);
+		};
+		self.input = rest;
+		Ok(bytes)
+	}
+
+	fn take_array<const N: usize>(&mut self) -> Result<[u8; N], SerdeError> {
+		let bytes = self.take(N)?;
+		Ok(bytes.try_into().unwrap_or_else(|_| unreachable!()))
+	}
+
+	fn read_u64(&mut self) -> Result<u64, SerdeError> {
+		self.take_array().map(u64::from_be_bytes)
+	}
+
+	/// Run `f` with the limits of the values contained in the one being read
+	fn nested<T>(
+		&mut self,
+		f: impl FnOnce(&mut Self) -> Result<T, SerdeError>,
+	) -> Result<T, SerdeError> {
+		let limits = self.limits;
+		self.limits = limits.nested()?;
+		let result = f(self);
+		self.limits = limits;
+		result
+	}
+
+	fn visit_elements<V: Visitor<'de>>(
+		&mut self,
+		length: usize,
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		self.nested(|deserializer| {
+			visitor.visit_seq(Elements {
+				deserializer,
+				remaining: length,
+			})
+		})
+	}
+}
+
+macro_rules! deserialize_number {
+	($($method:ident: $type:ty => $visit:ident,)*) => {
+		$(
+			fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+				visitor.$visit(<$type>::from_be_bytes(self.take_array()?))
+			}
+		)*
+	};
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+	type Error = SerdeError;
+
+	deserialize_number! {
+		deserialize_u8: u8 => visit_u8,
+		deserialize_u16: u16 => visit_u16,
+		deserialize_u32: u32 => visit_u32,
+		deserialize_u64: u64 => visit_u64,
+		deserialize_u128: u128 => visit_u128,
+		deserialize_i8: i8 => visit_i8,
+		deserialize_i16: i16 => visit_i16,
+		deserialize_i32: i32 => visit_i32,
+		deserialize_i64: i64 => visit_i64,
+		deserialize_i128: i128 => visit_i128,
+		deserialize_f32: f32 => visit_f32,
+		deserialize_f64: f64 => visit_f64,
+	}
+
+	fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, SerdeError> {
+		Err(SerdeError::NotSelfDescribing("a value of unknown type"))
+	}
+
+	fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, SerdeError> {
+		Err(SerdeError::NotSelfDescribing("an ignored value"))
+	}
+
+	fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, SerdeError> {
+		Err(SerdeError::NotSelfDescribing("an identifier"))
+	}
+
+	fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		match self.take_array::<1>()? {
+			[0] => visitor.visit_bool(false),
+			[1] => visitor.visit_bool(true),
+			[value] => Err(SerdeError::InvalidValue {
+				type_name: "boolean",
+				value: value.into(),
+			}),
+		}
+	}
+
+	fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		let value = u32::from_be_bytes(self.take_array()?);
+		let value = char::from_u32(value).ok_or(SerdeError::InvalidValue {
+			type_name: "char",
+			value: value.into(),
+		})?;
+		visitor.visit_char(value)
+	}
+
+	fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		let length = self.limits.check_string_bytes(self.read_u64()?)?;
+		let value =
+			str::from_utf8(self.take(length)?).map_err(<SerdeError as de::Error>::custom)?;
+		visitor.visit_borrowed_str(value)
+	}
+
+	fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		self.deserialize_str(visitor)
+	}
+
+	/// Like a [`Vec<u8>`]
+	fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		let length = self.limits.check_length(self.read_u64()?)?;
+		visitor.visit_borrowed_bytes(self.take(length)?)
+	}
+
+	fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		self.deserialize_bytes(visitor)
+	}
+
+	fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		match self.read_u64()? {
+			0 => self.nested(|deserializer| visitor.visit_some(deserializer)),
+			1 => visitor.visit_none(),
+			value => Err(SerdeError::InvalidValue {
+				type_name: "option discriminant",
+				value,
+			}),
+		}
+	}
+
+	fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		visitor.visit_unit()
+	}
+
+	fn deserialize_unit_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		visitor.visit_unit()
+	}
+
+	fn deserialize_newtype_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		self.nested(|deserializer| visitor.visit_newtype_struct(deserializer))
+	}
+
+	fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		let length = self.limits.check_length(self.read_u64()?)?;
+		self.visit_elements(length, visitor)
+	}
+
+	fn deserialize_tuple<V: Visitor<'de>>(
+		self,
+		len: usize,
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		self.visit_elements(len, visitor)
+	}
+
+	fn deserialize_tuple_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		len: usize,
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		self.visit_elements(len, visitor)
+	}
+
+	fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+		let length = self.limits.check_length(self.read_u64()?)?;
+		self.nested(|deserializer| {
+			visitor.visit_map(Elements {
+				deserializer,
+				remaining: length,
+			})
+		})
+	}
+
+	fn deserialize_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		fields: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		self.visit_elements(fields.len(), visitor)
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		_variants: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		self.nested(|deserializer| visitor.visit_enum(deserializer))
+	}
+
+	fn is_human_readable(&self) -> bool {
+		false
+	}
+}
+
+/// Elements of a sequence, tuple, struct or map being deserialized
+struct Elements<'a, 'de> {
+	deserializer: &'a mut Deserializer<'de>,
+	remaining: usize,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_, 'de> {
+	type Error = SerdeError;
+
+	fn next_element_seed<T: DeserializeSeed<'de>>(
+		&mut self,
+		seed: T,
+	) -> Result<Option<T::Value>, SerdeError> {
+		if self.remaining == 0 {
+			return Ok(None);
+		}
+		self.remaining -= 1;
+		seed.deserialize(&mut *self.deserializer).map(Some)
+	}
+
+	fn size_hint(&self) -> Option<usize> {
+		Some(self.remaining)
+	}
+}
+
+impl<'de> MapAccess<'de> for Elements<'_, 'de> {
+	type Error = SerdeError;
+
+	fn next_key_seed<K: DeserializeSeed<'de>>(
+		&mut self,
+		seed: K,
+	) -> Result<Option<K::Value>, SerdeError> {
+		self.next_element_seed(seed)
+	}
+
+	fn next_value_seed<V: DeserializeSeed<'de>>(
+		&mut self,
+		seed: V,
+	) -> Result<V::Value, SerdeError> {
+		seed.deserialize(&mut *self.deserializer)
+	}
+
+	fn size_hint(&self) -> Option<usize> {
+		Some(self.remaining)
+	}
+}
+
+impl<'de> EnumAccess<'de> for &mut Deserializer<'de> {
+	type Error = SerdeError;
+	type Variant = Self;
+
+	fn variant_seed<V: DeserializeSeed<'de>>(
+		self,
+		seed: V,
+	) -> Result<(V::Value, Self), SerdeError> {
+		let index = self.read_u64()?;
+		let index = u32::try_from(index).map_err(|_| SerdeError::InvalidValue {
+			type_name: "variant index",
+			value: index,
+		})?;
+		let variant = seed.deserialize(IntoDeserializer::<SerdeError>::into_deserializer(index))?;
+		Ok((variant, self))
+	}
+}
+
+impl<'de> VariantAccess<'de> for &mut Deserializer<'de> {
+	type Error = SerdeError;
+
+	fn unit_variant(self) -> Result<(), SerdeError> {
+		Ok(())
+	}
+
+	fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+		self,
+		seed: T,
+	) -> Result<T::Value, SerdeError> {
+		seed.deserialize(self)
+	}
+
+	fn tuple_variant<V: Visitor<'de>>(
+		self,
+		len: usize,
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		visitor.visit_seq(Elements {
+			deserializer: self,
+			remaining: len,
+		})
+	}
+
+	fn struct_variant<V: Visitor<'de>>(
+		self,
+		fields: &'static [&'static str],
+		visitor: V,
+	) -> Result<V::Value, SerdeError> {
+		visitor.visit_seq(Elements {
+			deserializer: self,
+			remaining: fields.len(),
+		})
+	}
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use ::serde::{Deserialize, Serialize};
+	use tokio::io::{BufReader, BufWriter};
+
+	use super::*;
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize, Read, Write)]
+	enum Shape {
+		Empty,
+		Circle(f32),
+		Rectangle { width: u32, height: u32 },
+	}
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize, Read, Write)]
+	struct Workspace {
+		id: i64,
+		name: String,
+		monitor: Option<String>,
+		windows: Vec<(u64, char)>,
+		shapes: BTreeMap<String, Shape>,
+		fullscreen: bool,
+	}
+
+	fn workspace() -> Workspace {
+		Workspace {
+			id: -3,
+			name: "web".to_owned(),
+			monitor: None,
+			windows: vec![(1, 'a'), (2, 'b')],
+			shapes: BTreeMap::from([
+				("a".to_owned(), Shape::Empty),
+				("b".to_owned(), Shape::Circle(1.5)),
+				(
+					"c".to_owned(),
+					Shape::Rectangle {
+						width: 2,
+						height: 3,
+					},
+				),
+			]),
+			fullscreen: true,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_same_encoding() {
+		// the serialized value is the same as the one written by derived implementations
+		let mut writer = BufWriter::new(Vec::new());
+		workspace().write(&mut writer).await.unwrap();
+		writer.flush().await.unwrap();
+		let written = writer.into_inner();
+		assert_eq!(to_bytes(&workspace()).unwrap(), written);
+
+		let read: Workspace = from_bytes(&written, Limits::default()).unwrap();
+		assert_eq!(read, workspace());
+	}
+
+	#[tokio::test]
+	async fn test_round_trip() {
+		let mut writer = BufWriter::new(Vec::new());
+		Serde(workspace()).write(&mut writer).await.unwrap();
+		writer.flush().await.unwrap();
+		let written = writer.into_inner();
+
+		let mut reader = BufReader::new(&written[..]);
+		let read = <Serde<Workspace>>::read(&mut reader).await.unwrap();
+		assert_eq!(read.into_inner(), workspace());
+	}
+
+	#[tokio::test]
+	async fn test_limits() {
+		let bytes = to_bytes(&vec![vec![1u8]]).unwrap();
+		let error =
+			from_bytes::<Vec<Vec<u8>>>(&bytes, Limits::default().with_max_depth(1)).unwrap_err();
+		assert!(matches!(
+			error,
+			SerdeError::LimitExceeded(LimitExceededError::Depth { max: 1 })
+		));
+
+		let mut writer = BufWriter::new(Vec::new());
+		Serde("Hello").write(&mut writer).await.unwrap();
+		writer.flush().await.unwrap();
+		let written = writer.into_inner();
+		let mut reader = BufReader::new(&written[..]);
+		let limits = Limits::default().with_max_string_bytes(4);
+		let error = <Serde<String>>::read_limited(&mut reader, limits)
+			.await
+			.unwrap_err();
+		assert_eq!(
+			error.downcast_ref::<LimitExceededError>(),
+			Some(&LimitExceededError::StringBytes { length: 5, max: 4 })
+		);
+	}
+
+	#[test]
+	fn test_invalid() {
+		assert!(matches!(
+			from_bytes::<u32>(&[0, 0], Limits::default()),
+			Err(SerdeError::UnexpectedEnd)
+		));
+		assert!(matches!(
+			from_bytes::<u8>(&[0, 0], Limits::default()),
+			Err(SerdeError::TrailingBytes(1))
+		));
+		assert!(matches!(
+			from_bytes::<bool>(&[2], Limits::default()),
+			Err(SerdeError::InvalidValue { value: 2, .. })
+		));
+	}
+}
@@ -0,0 +1,37 @@
+//! In-memory transport, to test protocols without a real socket
+//!
+//! # Example
+//!
+//! ```rs
+//! let (client, server) = ipc::testing::pair();
+//! let app = App::default();
+//!
+//! let client = async {
+//!     let client = Client::new_with_transport(client.0, client.1).await?;
+//!     client.start("module".to_owned()).await
+//! };
+//! let result = tokio::select! {
+//!     result = client => result,
+//!     () = app.serve_connection(server.0, server.1) => unreachable!(),
+//! };
+//! ```
+
+use tokio::io::{DuplexStream, duplex};
+
+/// Maximum number of bytes buffered in each direction
+const BUFFER_SIZE: usize = 64 << 10;
+
+/// Reading and writing halves of one side of an in-memory connection
+///
+/// Each direction uses its own pipe, so that dropping the writing half closes
+/// the connection like a socket would.
+pub type Transport = (DuplexStream, DuplexStream);
+
+/// Create the two sides of an in-memory connection, the first one for the client
+/// and the second one for the server
+#[must_use]
+pub fn pair() -> (Transport, Transport) {
+	let (client_tx, server_rx) = duplex(BUFFER_SIZE);
+	let (server_tx, client_rx) = duplex(BUFFER_SIZE);
+	((client_rx, client_tx), (server_rx, server_tx))
+}
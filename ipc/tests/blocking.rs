@@ -0,0 +1,142 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{
+	env,
+	path::{Path, PathBuf},
+	process,
+	sync::Mutex,
+	thread,
+	time::Duration,
+};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::{CancellationToken, Error, IncomingStream};
+use tokio::{runtime::Runtime, time::sleep};
+
+#[ipc::protocol(
+	path_socket = "$XDG_RUNTIME_DIR/ipc-test-blocking.sock",
+	blocking_client_name = CounterBlockingClient
+)]
+pub trait Counter {
+	/// Add to the counter, and return its new value
+	async fn add(&self, amount: u32) -> u32;
+
+	#[timeout = "50ms"]
+	async fn slow(&self) -> u32;
+
+	/// Values from `start` to the counter
+	#[stream(early_error = String)]
+	async fn count(&self, start: u32) -> u32;
+
+	async fn sum(&self, values: impl Stream<Item = u32>) -> u32;
+
+	#[signal]
+	async fn changed(&self, value: u32);
+}
+
+#[derive(Default)]
+struct App {
+	counter: Mutex<u32>,
+	signals: CounterSignals,
+}
+
+impl CounterServer for App {
+	async fn add(&self, amount: u32) -> u32 {
+		let value = {
+			let mut counter = self.counter.lock().unwrap();
+			*counter += amount;
+			*counter
+		};
+		self.signals.changed(value).await;
+		value
+	}
+
+	async fn slow(&self) -> u32 {
+		sleep(Duration::from_millis(200)).await;
+		0
+	}
+
+	async fn count(&self, start: u32) -> Result<impl Stream<Item = u32> + Send, String> {
+		let end = *self.counter.lock().unwrap();
+		if start > end {
+			return Err(format!("{start} is above the counter"));
+		}
+		Ok(stream::iter(start..=end))
+	}
+
+	async fn sum(&self, values: IncomingStream<u32>) -> u32 {
+		values.fold(0, |sum, x| async move { sum + x }).await
+	}
+
+	fn signals(&self) -> &CounterSignals {
+		&self.signals
+	}
+}
+
+/// Path of a socket file that isn't used by any other test
+fn socket_path(name: &str) -> PathBuf {
+	env::temp_dir().join(format!("ipc-test-{}-{name}.sock", process::id()))
+}
+
+/// Wait until the server has created its socket
+fn wait_for(path: &Path) {
+	while !path.exists() {
+		thread::sleep(Duration::from_millis(10));
+	}
+}
+
+#[test]
+fn blocking_client() {
+	let path = socket_path("blocking");
+	let shutdown = CancellationToken::new();
+	let server = thread::spawn({
+		let path = path.clone();
+		let shutdown = shutdown.clone();
+		move || {
+			Runtime::new()
+				.unwrap()
+				.block_on(App::default().serve_with_path_socket_until(&path, &shutdown))
+				.unwrap();
+		}
+	});
+
+	wait_for(&path);
+	let mut client = CounterBlockingClient::new_with_path_socket(&path).unwrap();
+	let mut observer = CounterBlockingClient::new_with_path_socket(&path).unwrap();
+	let mut changes = observer.receive_changed().unwrap();
+
+	assert_eq!(client.add(2).unwrap(), 2);
+	assert_eq!(client.add(3).unwrap(), 5);
+	assert_eq!(changes.next().unwrap().unwrap(), 2);
+	assert_eq!(changes.next().unwrap().unwrap(), 5);
+	drop(changes);
+
+	let values: Vec<_> = client
+		.count(3)
+		.unwrap()
+		.unwrap()
+		.map(Result::unwrap)
+		.collect();
+	assert_eq!(values, [3, 4, 5]);
+	assert_eq!(
+		client.count(8).unwrap().unwrap_err(),
+		"8 is above the counter"
+	);
+
+	// dropping the stream early cancels the call
+	let mut values = client.count(0).unwrap().unwrap();
+	assert_eq!(values.next().unwrap().unwrap(), 0);
+	drop(values);
+	assert_eq!(client.sum([1, 2, 3]).unwrap(), 6);
+
+	// the late response is skipped
+	assert!(matches!(client.slow(), Err(Error::Timeout)));
+	thread::sleep(Duration::from_millis(200));
+	assert_eq!(client.add(1).unwrap(), 6);
+	assert_eq!(observer.add(0).unwrap(), 6);
+
+	drop((client, observer));
+	shutdown.cancel();
+	server.join().unwrap();
+}
@@ -0,0 +1,120 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{env, fs, process};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::{
+	IncomingStream,
+	capture::{self, CAPTURE_VAR, Direction, Printer, Side},
+};
+use tokio::io::DuplexStream;
+
+#[ipc::protocol]
+pub trait Counter {
+	/// Add to the counter, and return its new value
+	async fn add(&self, amount: u32) -> u32;
+
+	#[stream(early_error = String)]
+	async fn count(&self, start: u32) -> u32;
+
+	async fn sum(&self, values: impl Stream<Item = u32>) -> u32;
+}
+
+type Client = CounterClient<DuplexStream, DuplexStream>;
+
+struct App;
+
+impl CounterServer for App {
+	async fn add(&self, amount: u32) -> u32 {
+		amount + 1
+	}
+
+	async fn count(&self, start: u32) -> Result<impl Stream<Item = u32> + Send, String> {
+		if start > 2 {
+			return Err("too big".to_owned());
+		}
+		Ok(stream::iter(start..=2))
+	}
+
+	async fn sum(&self, values: IncomingStream<u32>) -> u32 {
+		values.fold(0, |sum, x| async move { sum + x }).await
+	}
+}
+
+#[tokio::test]
+async fn capture() {
+	let path = env::temp_dir().join(format!("ipc-test-{}.capture", process::id()));
+	// SAFETY: this is the only test of this binary, and it hasn't started any thread
+	unsafe { env::set_var(CAPTURE_VAR, &path) };
+
+	let (client, server) = ipc::testing::pair();
+	let calls = async {
+		let client = Client::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		assert_eq!(client.add(2).await.unwrap(), 3);
+		let values: Vec<_> = client
+			.count(1)
+			.await
+			.unwrap()
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(values, [1, 2]);
+		assert!(client.count(5).await.unwrap().is_err());
+		assert_eq!(client.sum(stream::iter([4])).await.unwrap(), 4);
+	};
+	tokio::select! {
+		() = calls => {}
+		() = App.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+
+	let records = capture::read(&path).unwrap();
+	fs::remove_file(&path).unwrap();
+
+	// both sides are captured by this process
+	let schema = Client::schema();
+	let mut printer = Printer::new(&schema);
+	let client_lines: Vec<_> = records
+		.iter()
+		.filter(|record| record.side == Side::Client)
+		.map(|record| {
+			let line = printer.print(record);
+			let (_, payload) = line.split_once(" #").unwrap_or(("", &line));
+			// calls are traced when the `tracing` feature is enabled
+			let payload = payload.split(" trace ").next().unwrap();
+			(record.direction, payload.to_owned())
+		})
+		.collect();
+	let lines: Vec<_> = client_lines.iter().map(|(_, line)| line.as_str()).collect();
+	assert!(lines[0].contains("handshake fingerprint"));
+	assert!(lines[1].contains("handshake fingerprint"));
+	assert_eq!(
+		lines[2..],
+		[
+			"0 call add(amount: 2)",
+			"0 add returned 3",
+			"1 call count(start: 1)",
+			"1 count value 1",
+			"1 count value 2",
+			"1 end of count",
+			"2 call count(start: 5)",
+			"2 count failed with \"too big\"",
+			"3 call sum()",
+			"3 input of sum: 4",
+			"3 end of the input of sum",
+			"3 sum returned 4",
+		]
+	);
+	assert_eq!(client_lines[2].0, Direction::Sent);
+	assert_eq!(client_lines[3].0, Direction::Received);
+
+	// the server sees the same frames the other way around
+	let server_records = records
+		.iter()
+		.filter(|record| record.side == Side::Server)
+		.count();
+	assert_eq!(server_records, client_lines.len());
+}
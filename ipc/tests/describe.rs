@@ -0,0 +1,202 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{pin::pin, sync::Mutex};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::{
+	DynamicCallError, DynamicClient, DynamicResponse, IncomingStream, Read, Write,
+	schema::{Fields, Type},
+};
+use tokio::io::DuplexStream;
+
+#[derive(Debug, Clone, PartialEq, Eq, Read, Write)]
+#[ipc(extensible)]
+pub struct Note {
+	title: String,
+	tags: Vec<String>,
+	#[ipc(default)]
+	pinned: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Read, Write)]
+pub enum NotesError {
+	NotFound(u32),
+}
+
+#[ipc::protocol]
+pub trait Notes {
+	/// Add a note, and return its id
+	async fn add(&self, note: Note) -> u32;
+
+	#[timeout = "2s"]
+	async fn get(&self, id: u32) -> Result<Note, NotesError>;
+
+	/// Titles of the notes with a tag
+	#[stream(early_error = NotesError)]
+	async fn tagged(&self, tag: String) -> String;
+
+	async fn import(&self, notes: impl Stream<Item = Note>) -> u32;
+
+	#[signal]
+	async fn added(&self, id: u32, title: String);
+}
+
+type Client = NotesClient<DuplexStream, DuplexStream>;
+
+#[derive(Default)]
+struct App {
+	notes: Mutex<Vec<Note>>,
+	signals: NotesSignals,
+}
+
+impl NotesServer for App {
+	async fn add(&self, note: Note) -> u32 {
+		let title = note.title.clone();
+		let id = {
+			let mut notes = self.notes.lock().unwrap();
+			notes.push(note);
+			notes.len() as u32 - 1
+		};
+		self.signals.added(id, title).await;
+		id
+	}
+
+	async fn get(&self, id: u32) -> Result<Note, NotesError> {
+		self.notes
+			.lock()
+			.unwrap()
+			.get(id as usize)
+			.cloned()
+			.ok_or(NotesError::NotFound(id))
+	}
+
+	async fn tagged(&self, tag: String) -> Result<impl Stream<Item = String> + Send, NotesError> {
+		let titles: Vec<_> = self
+			.notes
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|note| note.tags.contains(&tag))
+			.map(|note| note.title.clone())
+			.collect();
+		if titles.is_empty() {
+			return Err(NotesError::NotFound(0));
+		}
+		Ok(stream::iter(titles))
+	}
+
+	async fn import(&self, notes: IncomingStream<Note>) -> u32 {
+		let notes: Vec<_> = notes.collect().await;
+		let count = notes.len() as u32;
+		self.notes.lock().unwrap().extend(notes);
+		count
+	}
+
+	fn signals(&self) -> &NotesSignals {
+		&self.signals
+	}
+}
+
+#[test]
+fn schema() {
+	let schema = Client::schema();
+	assert_eq!(schema.name, "Notes");
+	assert_eq!(schema.fingerprint, Client::FINGERPRINT);
+
+	let names: Vec<_> = schema.methods.iter().map(|x| x.name.as_str()).collect();
+	assert_eq!(names, ["add", "get", "tagged", "import"]);
+
+	let add = schema.method("add").unwrap();
+	assert_eq!(add.docs, "Add a note, and return its id");
+	assert_eq!(add.arguments[0].name, "note");
+	let Some(Type::Struct {
+		fields: Fields::Named(fields),
+		extensible: true,
+		..
+	}) = schema.ty(add.arguments[0].ty)
+	else {
+		panic!("invalid description of Note");
+	};
+	assert!(fields[2].default);
+	assert_eq!(schema.type_name(add.output), "u32");
+
+	let get = schema.method("get").unwrap();
+	assert_eq!(get.timeout, Some(2000));
+	assert_eq!(schema.type_name(get.output), "Result<Note, NotesError>");
+
+	let tagged = schema.method("tagged").unwrap();
+	assert!(tagged.stream);
+	assert_eq!(schema.type_name(tagged.early_error.unwrap()), "NotesError");
+	assert!(schema.method("import").unwrap().input_stream.is_some());
+
+	let signal = schema.signal("added").unwrap();
+	assert_eq!(signal.arguments.len(), 2);
+}
+
+#[tokio::test]
+async fn dynamic_client() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let calls = async {
+		let client = DynamicClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		assert_eq!(*client.schema(), Client::schema());
+
+		let mut added = pin!(client.subscribe("added").await.unwrap());
+
+		let response = client
+			.call("add", &["{ title: groceries, tags: [todo, home] }"])
+			.await
+			.unwrap();
+		assert!(matches!(response, DynamicResponse::Value(id) if id == "0"));
+		assert_eq!(added.next().await.unwrap().unwrap(), r#"(0, "groceries")"#);
+
+		let DynamicResponse::Value(note) = client.call("get", &["0"]).await.unwrap() else {
+			panic!("get isn't streamed");
+		};
+		assert_eq!(
+			note,
+			r#"Ok(Note { title: "groceries", tags: ["todo", "home"], pinned: false })"#
+		);
+		let DynamicResponse::Value(note) = client.call("get", &["3"]).await.unwrap() else {
+			panic!("get isn't streamed");
+		};
+		assert_eq!(note, "Err(NotFound(3))");
+
+		let DynamicResponse::Stream(titles) = client.call("tagged", &["home"]).await.unwrap()
+		else {
+			panic!("tagged is streamed");
+		};
+		let titles: Vec<_> = titles.map(Result::unwrap).collect().await;
+		assert_eq!(titles, [r#""groceries""#]);
+		assert!(matches!(
+			client.call("tagged", &["work"]).await.unwrap(),
+			DynamicResponse::Error(error) if error == "NotFound(0)"
+		));
+
+		assert!(matches!(
+			client.call("add", &["{ tags: [] }"]).await,
+			Err(DynamicCallError::Argument { .. })
+		));
+		assert!(matches!(
+			client.call("get", &[]).await,
+			Err(DynamicCallError::ArgumentCount { .. })
+		));
+		assert!(matches!(
+			client.call("import", &[]).await,
+			Err(DynamicCallError::InputStream(_))
+		));
+		assert!(matches!(
+			client.call("remove", &["0"]).await,
+			Err(DynamicCallError::UnknownMethod(_))
+		));
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
@@ -0,0 +1,120 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{
+	env,
+	fs::File,
+	io::{Read as _, Write as _, pipe},
+	path::{Path, PathBuf},
+	process,
+	time::Duration,
+};
+
+use ipc::{Fd, Read, Write};
+use tokio::time::sleep;
+
+#[derive(Debug, Read, Write)]
+pub struct Attachment {
+	name: String,
+	file: Fd,
+}
+
+#[ipc::protocol(path_socket = "$XDG_RUNTIME_DIR/ipc-test-fd.sock")]
+pub trait Files {
+	/// Name and content of an attached file
+	async fn read(&self, attachment: Attachment) -> (String, String);
+
+	/// Reading end of a pipe, with `message` written to it
+	async fn pipe(&self, message: String) -> Fd;
+}
+
+struct App;
+
+impl FilesServer for App {
+	async fn read(&self, attachment: Attachment) -> (String, String) {
+		let mut content = String::new();
+		File::from(attachment.file.into_inner())
+			.read_to_string(&mut content)
+			.unwrap();
+		(attachment.name, content)
+	}
+
+	async fn pipe(&self, message: String) -> Fd {
+		let (reader, mut writer) = pipe().unwrap();
+		writer.write_all(message.as_bytes()).unwrap();
+		Fd::new(reader)
+	}
+}
+
+/// Path of a socket file that isn't used by any other test
+fn socket_path(name: &str) -> PathBuf {
+	env::temp_dir().join(format!("ipc-test-{}-{name}.sock", process::id()))
+}
+
+/// Wait until the server has created its socket
+async fn wait_for(path: &Path) {
+	while !path.exists() {
+		sleep(Duration::from_millis(10)).await;
+	}
+}
+
+#[tokio::test]
+async fn send_fds() {
+	let path = socket_path("fd");
+
+	let client = async {
+		wait_for(&path).await;
+		let client = FilesClient::new_with_path_socket(&path).await.unwrap();
+
+		let (reader, mut writer) = pipe().unwrap();
+		writer.write_all(b"sent by the client").unwrap();
+		drop(writer);
+		let attachment = Attachment {
+			name: "pipe".to_owned(),
+			file: Fd::new(reader),
+		};
+		assert_eq!(
+			client.read(attachment).await.unwrap(),
+			("pipe".to_owned(), "sent by the client".to_owned())
+		);
+
+		let mut content = [0; 18];
+		File::from(
+			client
+				.pipe("sent by the server")
+				.await
+				.unwrap()
+				.into_inner(),
+		)
+		.read_exact(&mut content)
+		.unwrap();
+		assert_eq!(&content, b"sent by the server");
+	};
+
+	tokio::select! {
+		() = client => {}
+		result = App.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+}
+
+#[tokio::test]
+async fn transport_without_fds() {
+	let (client, server) = ipc::testing::pair();
+
+	let calls = async {
+		let client = FilesClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		let (reader, _) = pipe().unwrap();
+		let attachment = Attachment {
+			name: "pipe".to_owned(),
+			file: Fd::new(reader),
+		};
+		assert!(client.read(attachment).await.is_err());
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = App.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
@@ -0,0 +1,115 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{fmt::Debug, sync::Mutex};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::{IncomingStream, Read, Write};
+
+/// A value shared between a server and its clients, defined once for every type
+#[ipc::protocol]
+pub trait StateService<T> {
+	async fn get(&self) -> T;
+
+	async fn set(&self, value: T);
+
+	/// Set the value to each value of the stream, in order
+	async fn set_all(&self, values: impl Stream<Item = T>);
+
+	/// Every value the state had, starting from the first one
+	#[stream]
+	async fn history(&self) -> T;
+
+	#[signal]
+	async fn changed(&self, value: T);
+}
+
+struct State<T> {
+	history: Mutex<Vec<T>>,
+	signals: StateServiceSignals<T>,
+}
+
+impl<T> State<T> {
+	fn new(value: T) -> Self {
+		Self {
+			history: Mutex::new(vec![value]),
+			signals: StateServiceSignals::default(),
+		}
+	}
+}
+
+impl<T> StateServiceServer<T> for State<T>
+where
+	T: Read + Write + Debug + Clone + Send + Sync + 'static,
+	<T as Read>::Error: Send + Sync + 'static,
+	<T as Write>::Error: Send + Sync + 'static,
+	ipc::anyhow::Error: From<<T as Read>::Error> + From<<T as Write>::Error>,
+{
+	async fn get(&self) -> T {
+		self.history.lock().unwrap().last().unwrap().clone()
+	}
+
+	async fn set(&self, value: T) {
+		self.history.lock().unwrap().push(value.clone());
+		self.signals.changed(value).await;
+	}
+
+	async fn set_all(&self, values: IncomingStream<T>) {
+		let mut values = Box::pin(values);
+		while let Some(value) = values.next().await {
+			self.set(value).await;
+		}
+	}
+
+	async fn history(&self) -> impl Stream<Item = T> + Send {
+		stream::iter(self.history.lock().unwrap().clone())
+	}
+
+	fn signals(&self) -> &StateServiceSignals<T> {
+		&self.signals
+	}
+}
+
+#[tokio::test]
+async fn generic_protocol() {
+	let (numbers_client, numbers_server) = ipc::testing::pair();
+	let (names_client, names_server) = ipc::testing::pair();
+	let numbers = State::new(1u32);
+	let names = State::new("first".to_owned());
+
+	let calls = async {
+		let numbers: StateServiceClient<u32, _, _> =
+			StateServiceClient::new_with_transport(numbers_client.0, numbers_client.1)
+				.await
+				.unwrap();
+		let names: StateServiceClient<String, _, _> =
+			StateServiceClient::new_with_transport(names_client.0, names_client.1)
+				.await
+				.unwrap();
+		let mut changed = Box::pin(numbers.receive_changed().await.unwrap());
+
+		numbers.set(2).await.unwrap();
+		numbers.set_all(stream::iter([3, 4])).await.unwrap();
+		assert_eq!(numbers.get().await.unwrap(), 4);
+		let history: Vec<_> = numbers
+			.history()
+			.await
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(history, [1, 2, 3, 4]);
+		for value in [2, 3, 4] {
+			assert_eq!(changed.next().await.unwrap().unwrap(), value);
+		}
+
+		names.set("second").await.unwrap();
+		assert_eq!(names.get().await.unwrap(), "second");
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = numbers.serve_connection(numbers_server.0, numbers_server.1) => panic!("server stopped before the client"),
+		() = names.serve_connection(names_server.0, names_server.1) => panic!("server stopped before the client"),
+	}
+}
@@ -0,0 +1,114 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{sync::Mutex, time::Duration};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::interceptor::{Authorize, CallInfo, Interceptor, Log, RateLimit, Rejection};
+
+#[ipc::protocol]
+pub trait Modules {
+	async fn start(&self, module: String);
+
+	async fn stop(&self, module: String);
+
+	#[stream]
+	async fn list(&self) -> String;
+}
+
+#[derive(Default)]
+struct App {
+	/// Method, module and whether the call was accepted, for every call
+	audit: Mutex<Vec<(&'static str, Option<String>, bool)>>,
+	/// Trace of every call, as seen by the interceptors and by `start`
+	traces: Mutex<Vec<u128>>,
+	started_traces: Mutex<Vec<Option<u128>>>,
+}
+
+impl App {
+	fn audit(&self, call: &CallInfo, outcome: Result<Duration, Rejection>) {
+		self.audit.lock().unwrap().push((
+			call.method,
+			call.argument("module").map(str::to_owned),
+			outcome.is_ok(),
+		));
+		self.traces.lock().unwrap().push(call.trace.trace_id);
+	}
+}
+
+impl ModulesServer for App {
+	async fn start(&self, _module: String) {
+		self.started_traces.lock().unwrap().push(ipc::trace_id());
+	}
+
+	async fn stop(&self, _module: String) {}
+
+	async fn list(&self) -> impl Stream<Item = String> + Send {
+		stream::iter(["a".to_owned(), "b".to_owned()])
+	}
+
+	fn interceptor(&self) -> impl Interceptor {
+		(
+			Log(|call: &CallInfo, outcome| self.audit(call, outcome)),
+			Authorize(|call: &CallInfo| call.method != "stop"),
+			RateLimit::new(3, Duration::from_secs(3600)),
+		)
+	}
+}
+
+#[tokio::test]
+async fn interceptors() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let calls = async {
+		let client = ModulesClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		client.start("a".to_owned()).await.unwrap();
+		assert!(matches!(
+			client.stop("a".to_owned()).await,
+			Err(ipc::Error::Unauthorized)
+		));
+		let modules: Vec<_> = client
+			.list()
+			.await
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(modules, ["a", "b"]);
+		client.start("b".to_owned()).await.unwrap();
+		assert!(matches!(
+			client.start("c".to_owned()).await,
+			Err(ipc::Error::RateLimited)
+		));
+		assert!(matches!(client.list().await, Err(ipc::Error::RateLimited)));
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+
+	let module = |name: &str| Some(format!("{name:?}"));
+	assert_eq!(
+		*app.audit.lock().unwrap(),
+		[
+			("start", module("a"), true),
+			("stop", module("a"), false),
+			("list", None, true),
+			("start", module("b"), true),
+			("start", module("c"), false),
+			("list", None, false),
+		]
+	);
+
+	// each call starts a new trace, which `start` runs in
+	let traces = app.traces.lock().unwrap();
+	assert_eq!(
+		*app.started_traces.lock().unwrap(),
+		[Some(traces[0]), Some(traces[3])]
+	);
+	assert_ne!(traces[0], traces[3]);
+}
@@ -0,0 +1,120 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{rc::Rc, sync::Mutex};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::IncomingStream;
+use tokio::task::{LocalSet, spawn_local};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+#[ipc::protocol(local_client_name = CounterLocalClient)]
+pub trait Counter {
+	/// Add to the counter, and return its new value
+	async fn add(&self, amount: u32) -> u32;
+
+	/// Values from `start` to the counter
+	#[stream(early_error = String)]
+	async fn count(&self, start: u32) -> u32;
+
+	async fn sum(&self, values: impl Stream<Item = u32>) -> u32;
+
+	#[signal]
+	async fn changed(&self, value: u32);
+}
+
+#[derive(Default)]
+struct App {
+	counter: Mutex<u32>,
+	signals: CounterSignals,
+}
+
+impl CounterServer for App {
+	async fn add(&self, amount: u32) -> u32 {
+		let value = {
+			let mut counter = self.counter.lock().unwrap();
+			*counter += amount;
+			*counter
+		};
+		self.signals.changed(value).await;
+		value
+	}
+
+	async fn count(&self, start: u32) -> Result<impl Stream<Item = u32> + Send, String> {
+		let end = *self.counter.lock().unwrap();
+		if start > end {
+			return Err(format!("{start} is above the counter"));
+		}
+		Ok(stream::iter(start..=end))
+	}
+
+	async fn sum(&self, values: IncomingStream<u32>) -> u32 {
+		values.fold(0, |sum, x| async move { sum + x }).await
+	}
+
+	fn signals(&self) -> &CounterSignals {
+		&self.signals
+	}
+}
+
+#[tokio::test]
+async fn local_client() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let local = LocalSet::new();
+	let calls = local.run_until(async {
+		let (client, connection) = CounterLocalClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		spawn_local(connection);
+
+		let mut changes = Box::pin(client.receive_changed().await.unwrap());
+		assert_eq!(client.add(2).await.unwrap(), 2);
+		assert_eq!(client.add(3).await.unwrap(), 5);
+		assert_eq!(changes.next().await.unwrap().unwrap(), 2);
+		assert_eq!(changes.next().await.unwrap().unwrap(), 5);
+
+		let values: Vec<_> = client
+			.count(3)
+			.await
+			.unwrap()
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(values, [3, 4, 5]);
+		assert!(client.count(8).await.unwrap().is_err());
+
+		// the input stream doesn't need to be `Send`
+		let factor = Rc::new(2);
+		let doubled = stream::iter([1, 2, 3]).map(move |x| x * *factor);
+		assert_eq!(client.sum(doubled).await.unwrap(), 12);
+	});
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
+
+#[tokio::test]
+async fn futures_io_transport() {
+	let (client, server) = tokio::io::duplex(1024);
+	let (server_rx, server_tx) = tokio::io::split(server);
+	let app = App::default();
+
+	let local = LocalSet::new();
+	let calls = local.run_until(async {
+		let (client, connection) = CounterLocalClient::new_with_io(client.compat())
+			.await
+			.unwrap();
+		spawn_local(connection);
+		assert_eq!(client.add(4).await.unwrap(), 4);
+	});
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server_rx, server_tx) => panic!("server stopped before the client"),
+	}
+}
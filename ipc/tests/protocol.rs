@@ -0,0 +1,396 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{
+	io,
+	sync::{
+		Arc,
+		atomic::{AtomicBool, Ordering},
+	},
+	time::Duration,
+};
+
+use futures::{Stream, StreamExt, stream};
+use ipc::{ConnectionState, IncomingStream, LimitExceededError, Limits, Read, Reconnect, Write};
+use tokio::{
+	io::DuplexStream,
+	sync::{Notify, mpsc},
+	time::timeout,
+};
+
+#[derive(Debug, PartialEq, Eq, Read, Write)]
+pub enum DivideError {
+	DivisionByZero,
+}
+
+#[ipc::protocol]
+pub trait Calculator {
+	async fn divide(&self, a: u32, b: u32) -> Result<u32, DivideError>;
+
+	#[stream]
+	async fn count(&self, to: u8) -> u8;
+
+	async fn sum(&self, values: impl Stream<Item = u32>) -> u32;
+
+	/// Send the sum of the values received so far, after each value
+	#[stream]
+	async fn running_total(&self, values: impl Stream<Item = u32>) -> u32;
+
+	/// Never returns, without reading any value
+	async fn stall(&self, values: impl Stream<Item = u32>);
+
+	/// Never returns, the client gives up first
+	#[timeout = "100ms"]
+	async fn hang(&self);
+
+	/// Whether the client's deadline is known while each item is sent
+	#[stream]
+	#[timeout = "1s"]
+	async fn deadlines(&self, count: u8) -> bool;
+
+	#[signal]
+	async fn computed(&self, value: u32);
+}
+
+#[derive(Default)]
+struct App {
+	signals: CalculatorSignals,
+	limits: Limits,
+	/// Set when the stream of a `running_total` call is dropped
+	running_total_dropped: Arc<AtomicBool>,
+	/// Set when a `hang` call knows when the client will give up
+	hang_has_deadline: AtomicBool,
+}
+
+/// Set a flag when dropped
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+	fn drop(&mut self) {
+		self.0.store(true, Ordering::SeqCst);
+	}
+}
+
+impl CalculatorServer for App {
+	async fn divide(&self, a: u32, b: u32) -> Result<u32, DivideError> {
+		let result = a.checked_div(b).ok_or(DivideError::DivisionByZero)?;
+		self.signals.computed(result).await;
+		Ok(result)
+	}
+
+	async fn count(&self, to: u8) -> impl Stream<Item = u8> + Send {
+		stream::iter(1..=to)
+	}
+
+	async fn sum(&self, values: IncomingStream<u32>) -> u32 {
+		values.fold(0, |acc, x| async move { acc + x }).await
+	}
+
+	async fn running_total(&self, values: IncomingStream<u32>) -> impl Stream<Item = u32> + Send {
+		let flag = DropFlag(Arc::clone(&self.running_total_dropped));
+		values.scan(0, move |total, x| {
+			let _ = &flag;
+			*total += x;
+			futures::future::ready(Some(*total))
+		})
+	}
+
+	async fn stall(&self, _values: IncomingStream<u32>) {
+		std::future::pending().await
+	}
+
+	async fn hang(&self) {
+		self.hang_has_deadline
+			.store(ipc::deadline().is_some(), Ordering::SeqCst);
+		std::future::pending().await
+	}
+
+	async fn deadlines(&self, count: u8) -> impl Stream<Item = bool> + Send {
+		// computed while the stream is polled, after the call returned
+		stream::iter(0..count).map(|_| ipc::deadline().is_some())
+	}
+
+	fn limits(&self) -> Limits {
+		self.limits
+	}
+
+	fn signals(&self) -> &CalculatorSignals {
+		&self.signals
+	}
+}
+
+#[tokio::test]
+async fn serve_connection() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let calls = async {
+		let client = CalculatorClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		let computed = client.receive_computed().await.unwrap();
+
+		assert_eq!(client.divide(12, 4).await.unwrap(), Ok(3));
+		assert_eq!(
+			client.divide(1, 0).await.unwrap(),
+			Err(DivideError::DivisionByZero)
+		);
+
+		let counted: Vec<_> = client
+			.count(3)
+			.await
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(counted, [1, 2, 3]);
+
+		let mut computed = Box::pin(computed);
+		assert_eq!(computed.next().await.unwrap().unwrap(), 3);
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
+
+#[tokio::test]
+async fn client_disconnects() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let client = async {
+		let client = CalculatorClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		assert_eq!(client.divide(10, 5).await.unwrap(), Ok(2));
+	};
+
+	// the server returns once the client is dropped
+	tokio::join!(client, app.serve_connection(server.0, server.1));
+}
+
+#[tokio::test]
+async fn input_streams() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let calls = async {
+		let client = CalculatorClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+
+		assert_eq!(client.sum(stream::iter([1, 2, 3])).await.unwrap(), 6);
+		assert_eq!(client.sum(stream::empty()).await.unwrap(), 0);
+
+		let totals: Vec<_> = client
+			.running_total(stream::iter([1, 2, 3]))
+			.await
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(totals, [1, 3, 6]);
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
+
+#[tokio::test]
+async fn cancel_bidirectional_call() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let calls = async {
+		let client = CalculatorClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+
+		// the input never ends, only the client can stop the call
+		let input = stream::iter([1, 2]).chain(stream::pending());
+		let mut totals = Box::pin(client.running_total(input).await.unwrap());
+		assert_eq!(totals.next().await.unwrap().unwrap(), 1);
+		assert_eq!(totals.next().await.unwrap().unwrap(), 3);
+		drop(totals);
+
+		timeout(Duration::from_secs(5), async {
+			while !app.running_total_dropped.load(Ordering::SeqCst) {
+				tokio::task::yield_now().await;
+			}
+		})
+		.await
+		.unwrap();
+
+		// the connection is still usable
+		assert_eq!(client.sum(stream::iter([4, 5])).await.unwrap(), 9);
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
+
+#[tokio::test]
+async fn too_many_pending_inputs() {
+	let (client, server) = ipc::testing::pair();
+	let app = App {
+		limits: Limits::default().with_max_pending_inputs(4),
+		..App::default()
+	};
+
+	let calls = async {
+		let client = CalculatorClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+
+		let result = client.stall(stream::iter(0..100)).await;
+		assert!(matches!(
+			result,
+			Err(ipc::Error::LimitExceeded(
+				LimitExceededError::PendingInputs { max: 4 }
+			))
+		));
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
+
+#[tokio::test]
+async fn timeouts() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+
+	let calls = async {
+		let client = CalculatorClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap()
+			.with_timeout(Duration::from_millis(50));
+
+		assert!(matches!(client.hang().await, Err(ipc::Error::Timeout)));
+		assert!(app.hang_has_deadline.load(Ordering::SeqCst));
+		let deadlines: Vec<_> = client
+			.deadlines(2)
+			.await
+			.unwrap()
+			.map(Result::unwrap)
+			.collect()
+			.await;
+		assert_eq!(deadlines, [true, true]);
+		assert!(matches!(
+			client.stall(stream::pending()).await,
+			Err(ipc::Error::Timeout)
+		));
+
+		// calls are cancelled when they time out, the connection is still usable
+		assert_eq!(client.divide(4, 2).await.unwrap(), Ok(2));
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
+
+#[tokio::test]
+async fn reconnect() {
+	let app = App::default();
+	let (connections_tx, mut connections) = mpsc::unbounded_channel();
+	let disconnect = Notify::new();
+
+	let connect = move || {
+		let (client, server) = ipc::testing::pair();
+		let sent = connections_tx.send(server).map_err(io::Error::other);
+		async move { sent.map(|()| client) }
+	};
+	// serve one connection at a time, until told to disconnect the client
+	let server = async {
+		while let Some(server) = connections.recv().await {
+			tokio::select! {
+				() = app.serve_connection(server.0, server.1) => {}
+				() = disconnect.notified() => {}
+			}
+		}
+	};
+
+	let calls = async {
+		let options = Reconnect::default()
+			.with_initial_delay(Duration::from_millis(50))
+			.with_resubscribe(true);
+		let client = CalculatorClient::new_with_connector(connect, options)
+			.await
+			.unwrap();
+		let mut states = Box::pin(client.connection_states());
+		assert_eq!(states.next().await, Some(ConnectionState::Connected));
+		let mut computed = Box::pin(client.receive_computed().await.unwrap());
+
+		disconnect.notify_one();
+		assert_eq!(states.next().await, Some(ConnectionState::Disconnected));
+		assert!(matches!(
+			client.divide(1, 1).await,
+			Err(ipc::Error::ConnectionBroken)
+		));
+		assert_eq!(states.next().await, Some(ConnectionState::Connected));
+
+		// the signal was subscribed to again on the new connection
+		assert_eq!(client.divide(12, 4).await.unwrap(), Ok(3));
+		assert_eq!(computed.next().await.unwrap().unwrap(), 3);
+	};
+
+	tokio::select! {
+		result = timeout(Duration::from_secs(5), calls) => result.unwrap(),
+		() = server => panic!("server stopped before the client"),
+	}
+}
+
+#[test]
+fn fingerprint() {
+	mod same {
+		#[ipc::protocol]
+		pub trait Calculator {
+			async fn divide(&self, a: u32, b: u32) -> Result<u32, crate::DivideError>;
+		}
+	}
+
+	mod identical {
+		#[ipc::protocol]
+		pub trait Calculator {
+			async fn divide(&self, a: u32, b: u32) -> Result<u32, crate::DivideError>;
+		}
+	}
+
+	mod renamed {
+		#[ipc::protocol]
+		pub trait Calculator {
+			async fn div(&self, a: u32, b: u32) -> Result<u32, crate::DivideError>;
+		}
+	}
+
+	mod retyped {
+		#[ipc::protocol]
+		pub trait Calculator {
+			async fn divide(&self, a: u64, b: u32) -> Result<u32, crate::DivideError>;
+		}
+	}
+
+	macro_rules! fingerprint {
+		($module:ident) => {
+			$module::CalculatorClient::<DuplexStream, DuplexStream>::FINGERPRINT
+		};
+	}
+
+	assert_eq!(fingerprint!(same), fingerprint!(identical));
+	assert_ne!(fingerprint!(same), fingerprint!(renamed));
+	assert_ne!(fingerprint!(same), fingerprint!(retyped));
+	assert_ne!(
+		fingerprint!(same),
+		CalculatorClient::<DuplexStream, DuplexStream>::FINGERPRINT
+	);
+}
@@ -0,0 +1,191 @@
+#![allow(clippy::unwrap_used)]
+
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	ffi::OsString,
+	fmt::Debug,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr},
+	num::{NonZero, NonZeroI64, NonZeroU8},
+	os::unix::ffi::OsStringExt,
+	path::PathBuf,
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ipc::{Read, Write};
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+
+async fn roundtrip<T>(x: &T) -> T
+where
+	T: Read + Write,
+	<T as Read>::Error: Debug,
+	<T as Write>::Error: Debug,
+{
+	let mut writer = BufWriter::new(Vec::new());
+	x.write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let mut data = &writer.into_inner()[..];
+	let mut reader = BufReader::new(&mut data);
+	T::read(&mut reader).await.unwrap()
+}
+
+#[tokio::test]
+async fn floats() {
+	for x in [0.0, -1.5, f32::MAX, f32::INFINITY] {
+		assert_eq!(roundtrip(&x).await, x);
+	}
+	for x in [0.0, -1.5, f64::MIN_POSITIVE, f64::NEG_INFINITY] {
+		assert_eq!(roundtrip(&x).await, x);
+	}
+	assert!(roundtrip(&f64::NAN).await.is_nan());
+}
+
+#[tokio::test]
+async fn wide_integers() {
+	assert_eq!(roundtrip(&u128::MAX).await, u128::MAX);
+	assert_eq!(roundtrip(&i128::MIN).await, i128::MIN);
+	assert_eq!(roundtrip(&usize::MAX).await, usize::MAX);
+	assert_eq!(roundtrip(&isize::MIN).await, isize::MIN);
+}
+
+#[tokio::test]
+async fn char() {
+	for x in ['a', 'é', '🦀', char::MAX] {
+		assert_eq!(roundtrip(&x).await, x);
+	}
+}
+
+#[tokio::test]
+async fn invalid_char() {
+	// a surrogate, which isn't a valid char
+	let data = 0xD800u32.to_be_bytes();
+	let mut reader = BufReader::new(&data[..]);
+	assert!(char::read(&mut reader).await.is_err());
+}
+
+#[tokio::test]
+async fn boxed() {
+	let x = Box::new("Boxed".to_owned());
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn arc() {
+	let x = Arc::new(vec![1u16, 2, 3]);
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn array() {
+	let x = [1u32, 2, 3, 4];
+	assert_eq!(roundtrip(&x).await, x);
+
+	let empty: [String; 0] = [];
+	assert_eq!(roundtrip(&empty).await, empty);
+}
+
+#[tokio::test]
+async fn array_wrong_length() {
+	let mut writer = BufWriter::new(Vec::new());
+	[1u8, 2, 3].write(&mut writer).await.unwrap();
+	writer.flush().await.unwrap();
+
+	let mut data = &writer.into_inner()[..];
+	let mut reader = BufReader::new(&mut data);
+	assert!(<[u8; 4]>::read(&mut reader).await.is_err());
+}
+
+#[tokio::test]
+async fn hash_map() {
+	let x = HashMap::from([("one".to_owned(), 1u8), ("two".to_owned(), 2)]);
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn btree_map() {
+	let x = BTreeMap::from([(1i32, Some("one".to_owned())), (-2, None)]);
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn hash_set() {
+	let x = HashSet::from([1u64, 2, 3]);
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn btree_set() {
+	let x = BTreeSet::from(['a', 'b', 'c']);
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn duration() {
+	for x in [Duration::ZERO, Duration::new(5, 123_456_789), Duration::MAX] {
+		assert_eq!(roundtrip(&x).await, x);
+	}
+}
+
+#[tokio::test]
+async fn invalid_duration() {
+	let mut data = Vec::new();
+	data.extend(1u64.to_be_bytes());
+	data.extend(1_000_000_000u32.to_be_bytes());
+	let mut reader = BufReader::new(&data[..]);
+	assert!(Duration::read(&mut reader).await.is_err());
+}
+
+#[tokio::test]
+async fn system_time() {
+	for x in [
+		UNIX_EPOCH,
+		SystemTime::now(),
+		UNIX_EPOCH - Duration::new(86_400, 500),
+	] {
+		assert_eq!(roundtrip(&x).await, x);
+	}
+}
+
+#[tokio::test]
+async fn os_string() {
+	// not valid UTF-8
+	let x = OsString::from_vec(vec![0x66, 0x6F, 0x80, 0x6F]);
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn path_buf() {
+	let x = PathBuf::from("/run/user/1000/tryfol");
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn ip_addr() {
+	for x in [
+		IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+		IpAddr::V6(Ipv6Addr::LOCALHOST),
+		IpAddr::V6(Ipv6Addr::new(0x2001, 0xDB8, 0, 0, 0, 0, 0, 1)),
+	] {
+		assert_eq!(roundtrip(&x).await, x);
+	}
+}
+
+#[tokio::test]
+async fn non_zero() {
+	let x = NonZeroU8::new(42).unwrap();
+	assert_eq!(roundtrip(&x).await, x);
+
+	let x = NonZeroI64::new(-7).unwrap();
+	assert_eq!(roundtrip(&x).await, x);
+
+	let x = NonZero::<usize>::MAX;
+	assert_eq!(roundtrip(&x).await, x);
+}
+
+#[tokio::test]
+async fn zero_non_zero() {
+	let data = [0];
+	let mut reader = BufReader::new(&data[..]);
+	assert!(NonZeroU8::read(&mut reader).await.is_err());
+}
@@ -0,0 +1,91 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use ipc::{Read, Serde, Write};
+use serde::{Deserialize, Serialize};
+
+/// A type from another crate, only implementing serde traits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceInfos {
+	id: i64,
+	name: String,
+	monitor: Option<String>,
+	windows: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Read, Write)]
+pub struct Focused {
+	#[ipc(with = ipc::serde)]
+	workspace: WorkspaceInfos,
+	window: Option<String>,
+}
+
+#[ipc::protocol]
+pub trait Workspaces {
+	async fn add(&self, workspace: Serde<WorkspaceInfos>);
+
+	async fn all(&self) -> Serde<Vec<WorkspaceInfos>>;
+
+	async fn focused(&self) -> Option<Focused>;
+}
+
+#[derive(Default)]
+struct App {
+	workspaces: Mutex<Vec<WorkspaceInfos>>,
+}
+
+impl WorkspacesServer for App {
+	async fn add(&self, workspace: Serde<WorkspaceInfos>) {
+		self.workspaces.lock().unwrap().push(workspace.into_inner());
+	}
+
+	async fn all(&self) -> Serde<Vec<WorkspaceInfos>> {
+		Serde(self.workspaces.lock().unwrap().clone())
+	}
+
+	async fn focused(&self) -> Option<Focused> {
+		let workspace = self.workspaces.lock().unwrap().first()?.clone();
+		Some(Focused {
+			workspace,
+			window: Some("term".to_owned()),
+		})
+	}
+}
+
+#[tokio::test]
+async fn serde_types() {
+	let (client, server) = ipc::testing::pair();
+	let app = App::default();
+	let workspace = WorkspaceInfos {
+		id: 1,
+		name: "web".to_owned(),
+		monitor: None,
+		windows: BTreeMap::from([("firefox".to_owned(), 2)]),
+	};
+
+	let calls = async {
+		let client = WorkspacesClient::new_with_transport(client.0, client.1)
+			.await
+			.unwrap();
+		assert_eq!(client.focused().await.unwrap(), None);
+		client.add(Serde(workspace.clone())).await.unwrap();
+		assert_eq!(
+			client.all().await.unwrap().0,
+			std::slice::from_ref(&workspace)
+		);
+		assert_eq!(
+			client.focused().await.unwrap(),
+			Some(Focused {
+				workspace: workspace.clone(),
+				window: Some("term".to_owned()),
+			})
+		);
+	};
+
+	tokio::select! {
+		() = calls => {}
+		() = app.serve_connection(server.0, server.1) => panic!("server stopped before the client"),
+	}
+}
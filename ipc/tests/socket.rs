@@ -0,0 +1,277 @@
+#![feature(never_type)]
+#![allow(clippy::unwrap_used)]
+
+use std::{
+	env, fs,
+	io::ErrorKind,
+	os::unix::fs::{MetadataExt, PermissionsExt},
+	path::{Path, PathBuf},
+	process,
+	sync::atomic::{AtomicUsize, Ordering},
+	time::Duration,
+};
+
+use futures::{StreamExt, future::join_all, stream};
+use ipc::{CancellationToken, IncomingStream, LimitExceededError, PeerCredentials, ServerOptions};
+use tokio::time::{sleep, timeout};
+
+#[ipc::protocol(path_socket = "$XDG_RUNTIME_DIR/ipc-test.sock")]
+pub trait Peer {
+	/// Credentials of the caller, as seen by the server
+	async fn whoami(&self) -> Option<(u32, Option<i32>)>;
+
+	/// Return after some time
+	async fn wait(&self, millis: u64);
+
+	/// Sum of the values
+	async fn sum(&self, values: impl Stream<Item = u32>) -> u32;
+}
+
+#[derive(Default)]
+struct App {
+	/// Reject every client
+	locked: bool,
+	options: ServerOptions,
+	/// Number of `wait` calls running
+	waiting: AtomicUsize,
+	/// Maximum number of `wait` calls that ran at once
+	max_waiting: AtomicUsize,
+}
+
+impl PeerServer for App {
+	async fn whoami(&self) -> Option<(u32, Option<i32>)> {
+		ipc::peer_credentials().map(|x| (x.uid, x.pid))
+	}
+
+	async fn wait(&self, millis: u64) {
+		let waiting = self.waiting.fetch_add(1, Ordering::SeqCst) + 1;
+		self.max_waiting.fetch_max(waiting, Ordering::SeqCst);
+		sleep(Duration::from_millis(millis)).await;
+		self.waiting.fetch_sub(1, Ordering::SeqCst);
+	}
+
+	async fn sum(&self, values: IncomingStream<u32>) -> u32 {
+		values.fold(0, |sum, x| async move { sum + x }).await
+	}
+
+	async fn authorize(&self, _peer: &PeerCredentials) -> bool {
+		!self.locked
+	}
+
+	fn server_options(&self) -> ServerOptions {
+		self.options
+	}
+}
+
+/// Path of a socket file that isn't used by any other test
+fn socket_path(name: &str) -> PathBuf {
+	env::temp_dir().join(format!("ipc-test-{}-{name}.sock", process::id()))
+}
+
+/// Wait until the server has created its socket
+async fn wait_for(path: &Path) {
+	while !path.exists() {
+		sleep(Duration::from_millis(10)).await;
+	}
+}
+
+#[tokio::test]
+async fn peer_credentials() {
+	let path = socket_path("credentials");
+	let app = App::default();
+
+	let client = async {
+		wait_for(&path).await;
+		let client = PeerClient::new_with_path_socket(&path).await.unwrap();
+
+		let metadata = fs::metadata(&path).unwrap();
+		assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+		assert_eq!(
+			client.whoami().await.unwrap(),
+			Some((metadata.uid(), Some(process::id().cast_signed())))
+		);
+	};
+
+	tokio::select! {
+		() = client => {}
+		result = app.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+	// the server was dropped
+	assert!(!path.exists());
+	// so was the directory the socket was created in
+	let private = path.with_file_name(format!(
+		".{}.{}",
+		path.file_name().unwrap().display(),
+		process::id()
+	));
+	assert!(!private.exists());
+}
+
+#[tokio::test]
+async fn unauthorized_client() {
+	let path = socket_path("unauthorized");
+	let app = App {
+		locked: true,
+		..App::default()
+	};
+
+	let client = async {
+		wait_for(&path).await;
+		let client = PeerClient::new_with_path_socket(&path).await.unwrap();
+		assert!(matches!(
+			client.whoami().await,
+			Err(ipc::Error::Unauthorized)
+		));
+	};
+
+	tokio::select! {
+		() = client => {}
+		result = app.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+}
+
+#[tokio::test]
+async fn stale_socket_file() {
+	let path = socket_path("stale");
+	// left by a server that crashed
+	drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+	let app = App::default();
+
+	let client = async {
+		sleep(Duration::from_millis(100)).await;
+		let client = PeerClient::new_with_path_socket(&path).await.unwrap();
+		assert!(client.whoami().await.unwrap().is_some());
+
+		// another server can't take over the socket of a running one
+		let other = App::default();
+		let error = other.serve_with_path_socket(&path).await.unwrap_err();
+		assert_eq!(error.kind(), ErrorKind::AddrInUse);
+	};
+
+	tokio::select! {
+		() = client => {}
+		result = app.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+	let path = socket_path("shutdown");
+	let app = App::default();
+	let shutdown = CancellationToken::new();
+
+	let client = async {
+		wait_for(&path).await;
+		let client = PeerClient::new_with_path_socket(&path).await.unwrap();
+		let call = client.wait(200);
+		let cancel = async {
+			sleep(Duration::from_millis(50)).await;
+			shutdown.cancel();
+			sleep(Duration::from_millis(50)).await;
+			// no new call starts once the server is shut down
+			assert!(matches!(
+				client.wait(0).await,
+				Err(ipc::Error::ShuttingDown)
+			));
+		};
+		// the call in progress ends, even though the server was shut down
+		let (result, ()) = tokio::join!(call, cancel);
+		result.unwrap();
+		client
+	};
+
+	let (client, result) = tokio::join!(client, app.serve_with_path_socket_until(&path, &shutdown));
+	result.unwrap();
+	assert!(!path.exists());
+	assert!(matches!(
+		client.wait(0).await,
+		Err(ipc::Error::ConnectionBroken)
+	));
+}
+
+#[tokio::test]
+async fn max_calls_per_client() {
+	let path = socket_path("max-calls");
+	let app = App {
+		options: ServerOptions::default().with_max_calls_per_client(2),
+		..App::default()
+	};
+
+	let client = async {
+		wait_for(&path).await;
+		let client = PeerClient::new_with_path_socket(&path).await.unwrap();
+		// 2 calls run, 2 wait for them, and the last one is rejected
+		let results = join_all((0..5).map(|_| client.wait(50))).await;
+		assert!(results[..4].iter().all(Result::is_ok));
+		assert!(matches!(
+			results[4],
+			Err(ipc::Error::LimitExceeded(LimitExceededError::QueuedCalls {
+				max: 2
+			}))
+		));
+	};
+
+	tokio::select! {
+		() = client => {}
+		result = app.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+	assert_eq!(app.max_waiting.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn inputs_of_running_calls_while_queued() {
+	let path = socket_path("queued-inputs");
+	let app = App {
+		options: ServerOptions::default().with_max_calls_per_client(1),
+		..App::default()
+	};
+
+	let client = async {
+		wait_for(&path).await;
+		let client = PeerClient::new_with_path_socket(&path).await.unwrap();
+		let values = stream::iter([1, 2, 3]).then(|x| async move {
+			sleep(Duration::from_millis(20)).await;
+			x
+		});
+		// the inputs of the sum are still read while the other call waits for it to end
+		let (sum, wait) = tokio::join!(client.sum(values), client.wait(0));
+		assert_eq!(sum.unwrap(), 6);
+		wait.unwrap();
+	};
+
+	tokio::select! {
+		result = timeout(Duration::from_secs(5), client) => result.unwrap(),
+		result = app.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+}
+
+#[tokio::test]
+async fn max_clients() {
+	let path = socket_path("max-clients");
+	let app = App {
+		options: ServerOptions::default().with_max_clients(1),
+		..App::default()
+	};
+
+	let clients = async {
+		wait_for(&path).await;
+		let first = PeerClient::new_with_path_socket(&path).await.unwrap();
+		// the server doesn't accept the second connection yet
+		let second = PeerClient::new_with_path_socket(&path);
+		tokio::pin!(second);
+		assert!(
+			timeout(Duration::from_millis(100), &mut second)
+				.await
+				.is_err()
+		);
+
+		drop(first);
+		let second = second.await.unwrap();
+		second.wait(0).await.unwrap();
+	};
+
+	tokio::select! {
+		() = clients => {}
+		result = app.serve_with_path_socket(&path) => panic!("server stopped: {:?}", result.unwrap_err()),
+	}
+}
@@ -48,6 +48,7 @@ fn right_modules() -> gtk::Box {
 
 	let modules = gtk::Box::new(Orientation::Horizontal, 0);
 	modules.set_halign(Align::End);
+	modules.append(&daemon::new());
 	modules.append(&connectivity_module);
 	modules.append(&temperatures);
 	modules.append(&memory);
@@ -0,0 +1,54 @@
+use std::{collections::BTreeSet, pin::pin};
+
+use futures::StreamExt;
+use gtk::{
+	Label,
+	glib::{self, clone},
+	prelude::*,
+};
+use gtk4 as gtk;
+use log::error;
+use tryfol_ipc::daemon_control::{LocalClient, ModuleStatus};
+
+/// Show the modules of the daemon that crashed, hidden while there are none
+pub fn new() -> Label {
+	let label = Label::builder()
+		.name("daemon")
+		.css_classes(["module"])
+		.visible(false)
+		.build();
+
+	glib::spawn_future_local(clone!(
+		#[strong]
+		label,
+		async move {
+			if let Err(e) = listen(&label).await {
+				error!("Cannot listen for daemon modules: {e:?}");
+			}
+		}
+	));
+
+	label
+}
+
+async fn listen(label: &Label) -> anyhow::Result<()> {
+	let (client, connection) = LocalClient::new().await?;
+	glib::spawn_future_local(connection);
+
+	let mut changes = pin!(client.receive_module_status_changed().await?);
+	let mut crashed = BTreeSet::new();
+	while let Some((module, status)) = changes.next().await.transpose()? {
+		if matches!(status, ModuleStatus::Crashed) {
+			crashed.insert(module);
+		} else {
+			crashed.remove(&module);
+		}
+
+		let modules = crashed.iter().map(String::as_str).collect::<Vec<_>>();
+		label.set_label(&format!(" {}", modules.join(", ")));
+		label.set_tooltip_text(Some("Crashed daemon modules"));
+		label.set_visible(!crashed.is_empty());
+	}
+
+	Ok(())
+}
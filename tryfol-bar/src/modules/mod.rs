@@ -1,4 +1,5 @@
 pub mod clock;
+pub mod daemon;
 pub mod hyprland;
 pub mod mpris;
 pub mod power;
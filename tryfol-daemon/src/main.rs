@@ -1,24 +1,28 @@
-#![feature(never_type)]
-
 use std::{collections::HashMap, io, time::Duration};
 
 use async_stream::stream;
 use futures::{Stream, StreamExt, stream};
+use ipc::{
+	ServerOptions,
+	interceptor::{CallInfo, Interceptor, Log, RateLimit, Rejection, Tracing},
+};
 use tokio::{
+	select,
+	signal::unix::{SignalKind, signal},
 	spawn,
 	sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, broadcast::error::RecvError},
 	task::JoinHandle,
 	time::timeout,
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{Instrument, error, info_span};
+use tracing::{Instrument, error, info, info_span, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use tryfol_daemon::{
 	modules::{Module, test::TestMod},
 	tracing::LogStore,
 };
 use tryfol_ipc::daemon_control::{
-	self, LogsError, ModuleStatus, Server, StartError, StatusError, StopError,
+	self, LogError, LogsError, ModuleStatus, Server, Signals, StartError, StatusError, StopError,
 };
 
 type ModulesMap = HashMap<String, Box<dyn Module + Send + Sync>>;
@@ -28,6 +32,7 @@ struct App {
 	modules: RwLock<ModulesMap>,
 	running_modules: RwLock<RunningModulesMap>,
 	log_store: LogStore,
+	signals: Signals,
 }
 
 enum ModuleState {
@@ -40,8 +45,31 @@ async fn main() -> () {
 	let app = App::new();
 	app.register(TestMod).await;
 
-	let Err(e) = app.run().await;
-	error!("{e}");
+	let shutdown = CancellationToken::new();
+	spawn(shutdown_on_signal(shutdown.clone()));
+	if let Err(e) = app.run(&shutdown).await {
+		error!("{e}");
+	}
+}
+
+/// Shut down the daemon on SIGTERM or SIGINT
+async fn shutdown_on_signal(shutdown: CancellationToken) {
+	let (mut terminate, mut interrupt) = match (
+		signal(SignalKind::terminate()),
+		signal(SignalKind::interrupt()),
+	) {
+		(Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+		(Err(e), _) | (_, Err(e)) => {
+			error!("Could not listen for termination signals: {e}");
+			return;
+		}
+	};
+	select! {
+		_ = terminate.recv() => {}
+		_ = interrupt.recv() => {}
+	}
+	info!("Shutting down");
+	shutdown.cancel();
 }
 
 impl App {
@@ -56,6 +84,7 @@ impl App {
 			modules: RwLock::default(),
 			running_modules: RwLock::default(),
 			log_store,
+			signals: Signals::default(),
 		}
 	}
 
@@ -66,7 +95,8 @@ impl App {
 			.insert(T::name().to_string(), Box::new(module));
 	}
 
-	pub async fn run(self) -> io::Result<!> {
+	/// Start every module and serve clients until `shutdown` is cancelled, then stop the modules
+	pub async fn run(self, shutdown: &CancellationToken) -> io::Result<()> {
 		// drop RwLock guard at the end of the scope
 		{
 			let modules = self.modules.read().await;
@@ -74,16 +104,29 @@ impl App {
 			for module in modules.keys().cloned() {
 				// the only error that can be returned from start_module is StartError::NotFound,
 				// which isn't possible here
-				let _ = Self::start_module(module, &modules, &mut running_modules);
+				let _ = Self::start_module(module, &modules, &mut running_modules, &self.signals);
+			}
+		}
+		let result = self.serve_until(shutdown).await;
+		self.stop_all().await;
+		result
+	}
+
+	async fn stop_all(&self) {
+		let modules: Vec<_> = self.running_modules.read().await.keys().cloned().collect();
+		for module in modules {
+			match self.stop(module.clone()).await {
+				Ok(()) | Err(StopError::NotRunning) => {}
+				Err(e) => error!("Could not stop module {module}: {e:?}"),
 			}
 		}
-		self.serve().await
 	}
 
 	fn start_module(
 		name: String,
 		modules: &RwLockReadGuard<'_, ModulesMap>,
 		running_modules: &mut RwLockWriteGuard<'_, RunningModulesMap>,
+		signals: &Signals,
 	) -> Result<(), StartError> {
 		modules
 			.get(&name)
@@ -91,10 +134,18 @@ impl App {
 				let token = CancellationToken::new();
 
 				let future = module.run(token.clone());
+				let signals = signals.clone();
+				let module = name.clone();
 				let handle = spawn(
-					async {
+					async move {
+						signals
+							.module_status_changed(module.clone(), ModuleStatus::Running)
+							.await;
 						if let Err(e) = future.await {
 							error!("{e}");
+							signals
+								.module_status_changed(module, ModuleStatus::Crashed)
+								.await;
 							return Err(e);
 						}
 						Ok(())
@@ -105,6 +156,35 @@ impl App {
 				Ok(())
 			})
 	}
+
+	/// Record which client started or stopped a module in the logs of the module
+	fn audit(call: &CallInfo, outcome: Result<Duration, Rejection>) {
+		if !matches!(call.method, "start" | "stop") {
+			return;
+		}
+		let Some(module) = call.argument("module") else {
+			return;
+		};
+		// arguments are formatted with Debug, which quotes strings
+		let _span = info_span!("module", module = module.trim_matches('"')).entered();
+		let client = call.peer.map_or_else(
+			|| "an unknown client".to_owned(),
+			|peer| match peer.pid {
+				Some(pid) => format!("uid {} (pid {pid})", peer.uid),
+				None => format!("uid {}", peer.uid),
+			},
+		);
+		match outcome {
+			Ok(elapsed) => info!(
+				"{} requested by {client}, handled in {elapsed:?}",
+				call.method
+			),
+			Err(rejection) => warn!(
+				"{} requested by {client} was rejected: {rejection}",
+				call.method
+			),
+		}
+	}
 }
 
 impl daemon_control::Server for App {
@@ -123,7 +203,12 @@ impl daemon_control::Server for App {
 		if is_running {
 			return Err(StartError::AlreadyRunning);
 		}
-		Self::start_module(module, &self.modules.read().await, &mut running_modules)
+		Self::start_module(
+			module,
+			&self.modules.read().await,
+			&mut running_modules,
+			&self.signals,
+		)
 	}
 
 	async fn stop(&self, module: String) -> Result<(), StopError> {
@@ -138,7 +223,7 @@ impl daemon_control::Server for App {
 
 			token.cancel();
 			let abort_handle = handle.abort_handle();
-			timeout(Duration::from_secs(10), handle).await.map_or_else(
+			let result = timeout(Duration::from_secs(10), handle).await.map_or_else(
 				|_| {
 					abort_handle.abort();
 					Err(StopError::ForceStopped)
@@ -150,11 +235,21 @@ impl daemon_control::Server for App {
 					)]
 					let result = x.unwrap();
 					if result.is_err() {
-						running_modules.insert(module, ModuleState::Crashed);
+						running_modules.insert(module.clone(), ModuleState::Crashed);
 					}
 					Ok(())
 				},
-			)
+			);
+
+			// a crash was already signaled by the module's task
+			let crashed = matches!(running_modules.get(&module), Some(ModuleState::Crashed));
+			drop(running_modules);
+			if !crashed {
+				self.signals
+					.module_status_changed(module, ModuleStatus::Stopped)
+					.await;
+			}
+			result
 		} else if self.modules.read().await.contains_key(&module) {
 			Err(StopError::NotRunning)
 		} else {
@@ -191,20 +286,43 @@ impl daemon_control::Server for App {
 		&self,
 		module: String,
 		lines: Option<u64>,
-	) -> Result<impl Stream<Item = String>, LogsError> {
+	) -> Result<impl Stream<Item = Result<String, LogError>>, LogsError> {
 		if !self.modules.read().await.contains_key(&module) {
 			return Err(LogsError::NotFound);
 		}
 
 		let (stored, mut rx) = self.log_store.tail(module, lines);
-		Ok(stream::iter(stored).chain(stream! {
+		Ok(stream::iter(stored).map(Ok).chain(stream! {
 			loop {
-				yield match rx.recv().await {
-					Ok(x) => x,
-					Err(RecvError::Lagged(count)) => format!("Receiver lagged ! Missing {count} log lines"),
-					Err(RecvError::Closed) => break,
+				match rx.recv().await {
+					Ok(x) => yield Ok(x),
+					Err(RecvError::Lagged(count)) => yield Ok(format!("Receiver lagged ! Missing {count} log lines")),
+					Err(RecvError::Closed) => {
+						yield Err(LogError::StoreClosed);
+						break;
+					}
 				}
 			}
 		}))
 	}
+
+	fn signals(&self) -> &Signals {
+		&self.signals
+	}
+
+	fn server_options(&self) -> ServerOptions {
+		// so that a misbehaving client can't exhaust the daemon
+		ServerOptions::default()
+			.with_max_clients(64)
+			.with_max_calls_per_client(32)
+	}
+
+	fn interceptor(&self) -> impl Interceptor {
+		(
+			Tracing,
+			Log(Self::audit),
+			// per client, as it's created for each of them
+			RateLimit::new(100, Duration::from_secs(1)),
+		)
+	}
 }
@@ -15,34 +15,54 @@ pub struct ModuleLogLayer(pub(crate) LogStore);
 
 struct ModuleNameExt(String);
 
+/// Trace id of a `call` span of the server, tying the events of a call to the request
+struct TraceIdExt(String);
+
 impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ModuleLogLayer {
 	fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
 		let mut visitor = ModuleFieldVisitor(None);
 		attrs.record(&mut visitor);
+		let mut trace_visitor = TraceIdVisitor(None);
+		attrs.record(&mut trace_visitor);
 
-		if let Some(name) = visitor.0
-			&& let Some(span) = ctx.span(id)
-		{
+		let Some(span) = ctx.span(id) else {
+			return;
+		};
+		if let Some(name) = visitor.0 {
 			span.extensions_mut().insert(ModuleNameExt(name));
 		}
+		if let Some(trace_id) = trace_visitor.0 {
+			span.extensions_mut().insert(TraceIdExt(trace_id));
+		}
 	}
 
 	fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
-		let module_name = (|| {
-			let mut span = ctx.lookup_current();
-			while let Some(p) = span {
-				if let Some(m) = p.extensions().get::<ModuleNameExt>() {
-					return Some(m.0.clone());
-				}
-				span = p.parent();
+		let mut module_name = None;
+		let mut trace_id = None;
+		let mut span = ctx.lookup_current();
+		while let Some(p) = span {
+			let extensions = p.extensions();
+			if module_name.is_none()
+				&& let Some(m) = extensions.get::<ModuleNameExt>()
+			{
+				module_name = Some(m.0.clone());
 			}
-
-			None
-		})();
+			if trace_id.is_none()
+				&& let Some(t) = extensions.get::<TraceIdExt>()
+			{
+				trace_id = Some(t.0.clone());
+			}
+			drop(extensions);
+			span = p.parent();
+		}
 
 		if let Some(module) = module_name {
 			let mut visitor = EventVisitor(String::new());
 			event.record(&mut visitor);
+			if let Some(trace_id) = trace_id {
+				// events of a call are tied to the request that caused it
+				write!(visitor.0, " trace_id={trace_id}").ok();
+			}
 
 			let level_color = match *event.metadata().level() {
 				Level::TRACE => "35",
@@ -78,6 +98,22 @@ impl Visit for ModuleFieldVisitor {
 	}
 }
 
+struct TraceIdVisitor(Option<String>);
+
+impl Visit for TraceIdVisitor {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		if field.name() == "trace_id" {
+			self.0 = Some(value.to_owned());
+		}
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+		if field.name() == "trace_id" {
+			self.0 = Some(format!("{value:?}"));
+		}
+	}
+}
+
 struct EventVisitor(String);
 
 impl Visit for EventVisitor {
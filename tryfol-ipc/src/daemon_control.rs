@@ -1,55 +1,84 @@
+//! Discriminants are pinned so that variants can be reordered, or new ones inserted,
+//! without breaking older `tryfolctl` binaries.
+
 use ipc::{Read, Write};
 
 #[derive(Debug, Read, Write)]
 pub enum StartError {
 	/// Module was not found
+	#[ipc(discriminant = 0)]
 	NotFound,
 	/// Module was already running
+	#[ipc(discriminant = 1)]
 	AlreadyRunning,
 }
 
 #[derive(Debug, Read, Write)]
 pub enum StopError {
 	/// Module was not found
+	#[ipc(discriminant = 0)]
 	NotFound,
 	/// Module wasn't running
+	#[ipc(discriminant = 1)]
 	NotRunning,
 	/// Module had to be force stopped because it was stopping too slowly
+	#[ipc(discriminant = 2)]
 	ForceStopped,
 }
 
 #[derive(Debug, Read, Write)]
 pub enum StatusError {
 	/// Module was not found
+	#[ipc(discriminant = 0)]
 	NotFound,
 }
 
 #[derive(Debug, Read, Write)]
 pub enum LogsError {
 	/// Module was not found
+	#[ipc(discriminant = 0)]
 	NotFound,
 }
 
+#[derive(Debug, Read, Write)]
+pub enum LogError {
+	/// Log storage was closed, no more logs will be received
+	#[ipc(discriminant = 0)]
+	StoreClosed,
+}
+
 #[derive(Debug, Clone, Read, Write)]
 pub enum ModuleStatus {
+	#[ipc(discriminant = 0)]
 	Stopped,
+	#[ipc(discriminant = 1)]
 	Running,
+	#[ipc(discriminant = 2)]
 	Crashed,
 }
 
 #[ipc::protocol(
-    abstract_socket = "tryfol-daemonctl",
+    path_socket = "$XDG_RUNTIME_DIR/tryfol-daemonctl.sock",
     client_name = Client,
-    server_name = Server
+    blocking_client_name = BlockingClient,
+    local_client_name = LocalClient,
+    server_name = Server,
+    signals_name = Signals
 )]
 pub trait DaemonControl {
 	async fn start(&self, module: String) -> Result<(), StartError>;
+	/// Modules are force stopped if they take more than 10 seconds to stop
+	#[timeout = "15s"]
 	async fn stop(&self, module: String) -> Result<(), StopError>;
 	async fn status(&self, module: String) -> Result<ModuleStatus, StatusError>;
 
 	/// Get logs from storage then send live logs
 	///
 	/// If `lines` is [`None`], send everything in storage, otherwise send `lines` lines of logs.
-	#[stream(early_error = LogsError)]
+	#[stream(early_error = LogsError, item_error = LogError)]
 	async fn logs(&self, module: String, lines: Option<u64>) -> String;
+
+	/// A module was started, stopped or has crashed
+	#[signal]
+	async fn module_status_changed(&self, module: String, status: ModuleStatus);
 }
@@ -1,19 +1,27 @@
 use std::{
 	env,
 	io::{self, ErrorKind, Stdout, Write, stdout},
-	path::PathBuf,
+	path::{Path, PathBuf},
 	pin::pin,
 	process::{ChildStdin, Command as StdCommand, Stdio},
+	time::Duration,
 };
 
 use clap::{Parser, Subcommand};
 use futures::StreamExt;
+use ipc::{
+	DynamicClient, DynamicResponse,
+	capture::{self, Printer},
+};
 use terminal_size::terminal_size_of;
 use tryfol_ipc::daemon_control::{
-	DaemonControl, LogsError, ModuleStatus, StartError, StatusError, StopError,
+	DaemonControl, LogError, LogsError, ModuleStatus, StartError, StatusError, StopError,
 };
 use which::which;
 
+/// Maximum time to wait for the daemon to answer a call
+const TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Subcommand)]
 enum Command {
 	/// Start a module
@@ -45,6 +53,38 @@ enum Command {
 		#[arg(short = 'P', long)]
 		no_pager: bool,
 	},
+	/// Show status changes of modules as they happen
+	Watch,
+	/// List the methods and signals of the daemon, or of the protocol served on a socket
+	Methods {
+		/// Socket file of the protocol, the daemon's by default
+		#[arg(short, long)]
+		socket: Option<PathBuf>,
+	},
+	/// Call a method of the daemon, or of the protocol served on a socket
+	///
+	/// Arguments are written like Rust values, strings can be written without quotes and
+	/// options without `Some`. See `methods` for the available methods.
+	Call {
+		/// The name of the method to call
+		method: String,
+		/// The arguments of the method, in order
+		arguments: Vec<String>,
+		/// Socket file of the protocol, the daemon's by default
+		#[arg(short, long)]
+		socket: Option<PathBuf>,
+	},
+	/// Print the frames captured by setting `IPC_CAPTURE` to a file
+	///
+	/// Frames are decoded with the protocol of the daemon, or with the protocol served
+	/// on a socket.
+	IpcDump {
+		/// The capture file
+		file: PathBuf,
+		/// Socket file of the protocol, the daemon's by default
+		#[arg(short, long)]
+		socket: Option<PathBuf>,
+	},
 }
 
 #[derive(Parser)]
@@ -74,15 +114,30 @@ enum Writer {
 async fn main() {
 	let args = Arguments::parse();
 
-	let client = match tryfol_ipc::daemon_control::Client::new() {
-		Ok(x) => x,
+	let command = match args.command {
+		Command::Methods { socket } => return methods(socket.as_deref()).await,
+		Command::Call {
+			method,
+			arguments,
+			socket,
+		} => return call(&method, &arguments, socket.as_deref()).await,
+		Command::IpcDump { file, socket } => return ipc_dump(&file, socket.as_deref()).await,
+		command => command,
+	};
+
+	let client = match tryfol_ipc::daemon_control::Client::new().await {
+		Ok(x) => x.with_timeout(TIMEOUT),
+		Err(ipc::Error::IncompatibleProtocol { .. }) => {
+			println!("tryfol-daemon is outdated, restart it");
+			return;
+		}
 		Err(e) => {
 			println!("Could not connect to tryfol-daemon: {e}");
 			return;
 		}
 	};
 
-	match args.command {
+	match command {
 		Command::Start { module } => match client.start(&module).await {
 			Ok(Ok(())) => println!("Module started succesfully"),
 			Ok(Err(StartError::NotFound)) => println!("No module named {module}"),
@@ -132,7 +187,10 @@ async fn main() {
 					let mut lines = pin!(lines);
 					while let Some(line) = lines.next().await {
 						let res = match line {
-							Ok(line) => writeln!(fd, "{line}"),
+							Ok(Ok(line)) => writeln!(fd, "{line}"),
+							Ok(Err(LogError::StoreClosed)) => {
+								writeln!(fd, "Log storage was closed")
+							}
 							Err(e) => writeln!(fd, "Could not read log line: {e}"),
 						};
 						if let Err(e) = res {
@@ -147,6 +205,138 @@ async fn main() {
 				Err(e) => println!("Could not get module logs: {e}"),
 			}
 		}
+		Command::Watch => match client.receive_module_status_changed().await {
+			Ok(changes) => {
+				let mut changes = pin!(changes);
+				while let Some(change) = changes.next().await {
+					match change {
+						Ok((module, ModuleStatus::Stopped)) => println!("{module} stopped"),
+						Ok((module, ModuleStatus::Running)) => println!("{module} started"),
+						Ok((module, ModuleStatus::Crashed)) => println!("{module} crashed"),
+						Err(e) => {
+							println!("Could not receive status change: {e}");
+							break;
+						}
+					}
+				}
+			}
+			Err(e) => println!("Could not communicate with daemon: {e}"),
+		},
+		Command::Methods { .. } | Command::Call { .. } | Command::IpcDump { .. } => {
+			unreachable!("these commands don't use the daemon client")
+		}
+	}
+}
+
+/// Connect to the protocol served on `socket`, or to the daemon
+async fn connect_dynamic(socket: Option<&Path>) -> Option<DynamicClient> {
+	let path = match socket {
+		Some(path) => path.to_owned(),
+		None => match ipc::expand_path(tryfol_ipc::daemon_control::Client::PATH_SOCKET) {
+			Ok(path) => path,
+			Err(e) => {
+				println!("Could not find the socket of tryfol-daemon: {e}");
+				return None;
+			}
+		},
+	};
+
+	match DynamicClient::new_with_path_socket(&path).await {
+		Ok(client) => Some(client.with_timeout(TIMEOUT)),
+		Err(e) => {
+			println!("Could not connect to {}: {e}", path.display());
+			None
+		}
+	}
+}
+
+async fn methods(socket: Option<&Path>) {
+	let Some(client) = connect_dynamic(socket).await else {
+		return;
+	};
+	let schema = client.schema();
+
+	println!("Protocol {}", schema.name);
+	for method in &schema.methods {
+		let mut arguments: Vec<_> = method
+			.arguments
+			.iter()
+			.map(|x| format!("{}: {}", x.name, schema.type_name(x.ty)))
+			.collect();
+		if let Some(input) = method.input_stream {
+			arguments.push(format!("stream of {}", schema.type_name(input)));
+		}
+		let mut output = schema.type_name(method.output);
+		if method.stream {
+			output = format!("stream of {output}");
+		}
+		if let Some(error) = method.early_error {
+			output = format!("{output}, or {}", schema.type_name(error));
+		}
+
+		println!("\n{}({}) -> {output}", method.name, arguments.join(", "));
+		for line in method.docs.lines() {
+			println!("    {line}");
+		}
+	}
+	for signal in &schema.signals {
+		let arguments: Vec<_> = signal
+			.arguments
+			.iter()
+			.map(|x| format!("{}: {}", x.name, schema.type_name(x.ty)))
+			.collect();
+		println!("\nsignal {}({})", signal.name, arguments.join(", "));
+		for line in signal.docs.lines() {
+			println!("    {line}");
+		}
+	}
+}
+
+async fn ipc_dump(file: &Path, socket: Option<&Path>) {
+	let records = match capture::read(file) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("Could not read {}: {e}", file.display());
+			return;
+		}
+	};
+	let schema = match socket {
+		Some(_) => {
+			let Some(client) = connect_dynamic(socket).await else {
+				return;
+			};
+			client.schema().clone()
+		}
+		None => tryfol_ipc::daemon_control::BlockingClient::schema(),
+	};
+
+	let mut printer = Printer::new(&schema);
+	for record in &records {
+		println!("{}", printer.print(record));
+	}
+}
+
+async fn call(method: &str, arguments: &[String], socket: Option<&Path>) {
+	let Some(client) = connect_dynamic(socket).await else {
+		return;
+	};
+	let arguments: Vec<_> = arguments.iter().map(String::as_str).collect();
+
+	match client.call(method, &arguments).await {
+		Ok(DynamicResponse::Value(value) | DynamicResponse::Error(value)) => println!("{value}"),
+		Ok(DynamicResponse::Stream(values)) => {
+			let mut values = pin!(values);
+			while let Some(value) = values.next().await {
+				match value {
+					Ok(value) => println!("{value}"),
+					Err(e) => {
+						println!("Could not receive value: {e}");
+						break;
+					}
+				}
+			}
+		}
+		Err(e) => println!("Could not call {method}: {e}"),
 	}
 }
 